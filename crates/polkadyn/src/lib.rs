@@ -1,9 +1,13 @@
-use frame_metadata::RuntimeMetadata;
+mod metadata_index;
 mod types_that_should_be_defined_somewhere_else;
+pub use metadata_index::MetadataIndex;
 use parity_scale_codec::Compact;
 use parity_scale_codec::Decode;
+use parity_scale_codec::Encode;
 use scale_value::scale::DecodeError;
-use scale_value::Value;
+use scale_value::scale::EncodeError;
+use scale_borrow::{ErrorKind, ValueBuilder};
+use scale_value::{At, Value, ValueDef};
 pub use types_that_should_be_defined_somewhere_else::Phase;
 
 // pub use frame_metadata::RuntimeMetadataPrefixed::decode as decode_metadata;
@@ -14,51 +18,231 @@ pub fn decode_metadata(
 	frame_metadata::RuntimeMetadataPrefixed::decode(&mut bytes)
 }
 
+/// A decoded `frame_system::EventRecord`. The event is a `scale_value::Value` from
+/// `decode_events` or a `scale_borrow::Value` from `decode_events_borrowed`.
+#[derive(Debug, Clone)]
+pub struct EventRecord<'scale, V = Value<()>> {
+	pub phase: Phase,
+	pub pallet: String,
+	pub variant: String,
+	pub pallet_index: u8,
+	pub variant_index: u8,
+	/// The whole event, i.e. `Pallet(Variant(..))`.
+	pub value: V,
+	/// Each topic is `index.hash_size()` bytes long.
+	pub topics: Vec<&'scale [u8]>,
+	/// The phase and event as encoded (not including the topics).
+	pub raw: &'scale [u8],
+}
+
 pub fn decode_events<'scale>(
-	metadata: &frame_metadata::RuntimeMetadataPrefixed,
+	index: &MetadataIndex,
 	scale_encoded_data: &'scale [u8],
-) -> Result<Vec<(Phase, Value<()>, &'scale [u8])>, ()> {
-	if let RuntimeMetadata::V14(metadata) = &metadata.1 {
-		let mut event_type = None;
-		for r in &metadata.types.types {
-			let segs = &r.ty.path.segments;
-			if segs.len() == 2 {
-				// It got renamed recently:
-				if (segs[1] == "Event" || segs[1] == "RuntimeEvent")
-					&& segs[0].ends_with("_runtime")
-				{
-					event_type = Some(r);
-					break;
-				}
-			}
+) -> Result<Vec<EventRecord<'scale>>, ()> {
+	let (metadata, event_type) = index.metadata().zip(index.event_type()).ok_or(())?;
+	event_records(index, scale_encoded_data, |cursor| {
+		let value = scale_value::scale::decode_as_type(cursor, event_type, &metadata.types)
+			.map_err(|_| ())?
+			.remove_context();
+		let (pallet, variant) = match &value.value {
+			ValueDef::Variant(pallet) => match pallet.values.values().next() {
+				Some(Value { value: ValueDef::Variant(variant), .. }) =>
+					(pallet.name.clone(), variant.name.clone()),
+				_ => (pallet.name.clone(), String::new()),
+			},
+			_ => return Err(()),
+		};
+		Ok(Some((value, pallet, variant)))
+	})
+}
+
+/// Like `decode_events` but straight into `scale_borrow::Value`s, which borrow from the data
+/// and the metadata.
+pub fn decode_events_borrowed<'scale>(
+	index: &'scale MetadataIndex,
+	scale_encoded_data: &'scale [u8],
+) -> Result<Vec<EventRecord<'scale, scale_borrow::Value<'scale>>>, ()> {
+	let (metadata, event_type) = index.metadata().zip(index.event_type()).ok_or(())?;
+	event_records(index, scale_encoded_data, |cursor| {
+		let value = ValueBuilder::parse_prefix(cursor, event_type, &metadata.types)
+			.map_err(|_| ())?;
+		let (pallet, variant) = match value.only3() {
+			Some((pallet, _, variant, _)) => (pallet.to_string(), variant.to_string()),
+			None => (value.only().ok_or(())?.0.to_string(), String::new()),
+		};
+		Ok(Some((value, pallet, variant)))
+	})
+}
+
+/// Like `decode_events_borrowed` but only the events that are `wanted(pallet, variant)`: the
+/// rest are skipped over without being decoded.
+pub fn decode_events_where<'scale>(
+	index: &'scale MetadataIndex,
+	scale_encoded_data: &'scale [u8],
+	mut wanted: impl FnMut(&str, &str) -> bool,
+) -> Result<Vec<EventRecord<'scale, scale_borrow::Value<'scale>>>, ()> {
+	let (metadata, event_type) = index.metadata().zip(index.event_type()).ok_or(())?;
+	event_records(index, scale_encoded_data, |cursor| {
+		let event = scale_borrow::Cursor::new(cursor, event_type, &metadata.types);
+		let pallet = event.variant().map_err(|_| ())?;
+		let variant = match event.items().map_err(|_| ())?.next() {
+			Some(inner) => inner.and_then(|(_, inner)| inner.variant()).map_err(|_| ())?,
+			None => "",
+		};
+		if !wanted(pallet, variant) {
+			scale_borrow::cursor::skip(cursor, event_type, &metadata.types).map_err(|_| ())?;
+			return Ok(None)
 		}
-		if let Some(event_type) = event_type {
-			let cursor = &mut &*scale_encoded_data;
-			let mut num_events = <Compact<u32>>::decode(cursor).unwrap_or(Compact(0)).0;
-
-			let mut results = Vec::with_capacity(num_events as usize);
-			while num_events > 0 {
-				let cursor_original = <&[u8]>::clone(cursor);
-				let phase = Phase::decode(cursor).map_err(|_| ())?;
-				let new_value =
-					scale_value::scale::decode_as_type(cursor, event_type.id, &metadata.types)
-						.map_err(|_| ())?;
-				num_events -= 1;
-				// Return slice of the raw event too.
-				results.push((
-					phase,
-					new_value.remove_context(),
-					&cursor_original[..cursor_original.len() - cursor.len()],
-				));
-				let _topics = Vec::<[u8; 32]>::decode(cursor).map_err(|_| ())?; //TODO don't hardcode hash size
-			}
+		let value = ValueBuilder::parse_prefix(cursor, event_type, &metadata.types)
+			.map_err(|_| ())?;
+		Ok(Some((value, pallet.to_string(), variant.to_string())))
+	})
+}
 
-			Ok(results)
-		} else {
-			Err(())
+/// The records of `frame_system::Events`, with `decode_event` moving the cursor past each
+/// event and naming its pallet and variant (or `None` to leave the record out).
+fn event_records<'scale, V>(
+	index: &MetadataIndex,
+	scale_encoded_data: &'scale [u8],
+	mut decode_event: impl FnMut(&mut &'scale [u8]) -> Result<Option<(V, String, String)>, ()>,
+) -> Result<Vec<EventRecord<'scale, V>>, ()> {
+	let cursor = &mut &*scale_encoded_data;
+	let mut num_events = <Compact<u32>>::decode(cursor).unwrap_or(Compact(0)).0;
+
+	let mut results = Vec::with_capacity(num_events as usize);
+	while num_events > 0 {
+		let cursor_original = <&[u8]>::clone(cursor);
+		let phase = Phase::decode(cursor).map_err(|_| ())?;
+		// The first two bytes of an event are the pallet and variant indexes.
+		let (pallet_index, variant_index) = match **cursor {
+			[pallet_index, variant_index, ..] => (pallet_index, variant_index),
+			_ => return Err(()),
+		};
+		let event = decode_event(cursor)?;
+		num_events -= 1;
+
+		let raw = &cursor_original[..cursor_original.len() - cursor.len()];
+
+		let num_topics = <Compact<u32>>::decode(cursor).map_err(|_| ())?.0 as usize;
+		let topics_len = num_topics * index.hash_size();
+		if cursor.len() < topics_len {
+			return Err(())
+		}
+		let (topics, rest): (&'scale [u8], _) = cursor.split_at(topics_len);
+		let topics = topics.chunks(index.hash_size()).collect();
+		*cursor = rest;
+
+		if let Some((value, pallet, variant)) = event {
+			results.push(EventRecord {
+				phase,
+				pallet,
+				variant,
+				pallet_index,
+				variant_index,
+				value,
+				topics,
+				raw,
+			});
 		}
+	}
+
+	Ok(results)
+}
+
+/// A `DispatchError` made human readable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DispatchError {
+	/// Pallet that raised the error (`None` for errors that aren't `DispatchError::Module`).
+	pub pallet: Option<String>,
+	pub error: String,
+	pub docs: String,
+}
+
+impl core::fmt::Display for DispatchError {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		if let Some(pallet) = &self.pallet {
+			write!(f, "{}.", pallet)?;
+		}
+		write!(f, "{}", self.error)?;
+		if !self.docs.is_empty() {
+			write!(f, ": {}", self.docs)?;
+		}
+		Ok(())
+	}
+}
+
+/// If this is a `System.ExtrinsicFailed` event, why did it fail?
+pub fn extrinsic_failed<V>(
+	index: &MetadataIndex,
+	record: &EventRecord<V>,
+) -> Option<DispatchError> {
+	if record.pallet != "System" || record.variant != "ExtrinsicFailed" {
+		return None
+	}
+	// Rare enough to decode again from the raw event, whatever the record's value is.
+	let metadata = index.metadata()?;
+	let mut raw = record.raw;
+	Phase::decode(&mut raw).ok()?;
+	let value =
+		scale_value::scale::decode_as_type(&mut raw, index.event_type()?, &metadata.types).ok()?;
+	let event = match &value.value {
+		ValueDef::Variant(pallet) => pallet.values.values().next()?,
+		_ => return None,
+	};
+	// `dispatch_error` comes first whether or not the fields are named.
+	let dispatch_error = match &event.value {
+		ValueDef::Variant(event) => event.values.values().next()?,
+		_ => return None,
+	};
+	decode_dispatch_error(index, dispatch_error)
+}
+
+/// Turn a decoded `sp_runtime::DispatchError` into pallet / error names and docs.
+pub fn decode_dispatch_error<T>(
+	index: &MetadataIndex,
+	dispatch_error: &Value<T>,
+) -> Option<DispatchError> {
+	let dispatch_error = match &dispatch_error.value {
+		ValueDef::Variant(dispatch_error) => dispatch_error,
+		_ => return None,
+	};
+	if dispatch_error.name != "Module" {
+		// e.g. `BadOrigin` or `Token(FundsUnavailable)`
+		let error = match dispatch_error.values.values().next() {
+			Some(Value { value: ValueDef::Variant(inner), .. }) =>
+				format!("{}({})", dispatch_error.name, inner.name),
+			_ => dispatch_error.name.clone(),
+		};
+		return Some(DispatchError { pallet: None, error, docs: String::new() })
+	}
+
+	// Older runtimes had `Module { index, error, .. }` rather than `Module(ModuleError)`.
+	let module_error = match dispatch_error.values.at("index") {
+		Some(_) => &dispatch_error.values,
+		None => match &dispatch_error.values.values().next()?.value {
+			ValueDef::Composite(module_error) => module_error,
+			_ => return None,
+		},
+	};
+	let pallet_index = module_error.at("index")?.as_u128()? as u8;
+	// `error` used to be a u8 and is now [u8; 4] of which the first byte is the variant.
+	let error = module_error.at("error")?;
+	let error_index = match &error.value {
+		ValueDef::Composite(bytes) => bytes.values().next()?.as_u128()?,
+		_ => error.as_u128()?,
+	} as u8;
+
+	let pallet = index.pallet_name(pallet_index)?.to_string();
+	let error_type = index.metadata()?.types.resolve(index.error_type(pallet_index)?)?;
+	if let scale_info::TypeDef::Variant(errors) = &error_type.type_def {
+		let variant = errors.variants.iter().find(|v| v.index == error_index)?;
+		Some(DispatchError {
+			pallet: Some(pallet),
+			error: variant.name.clone(),
+			docs: variant.docs.join(" ").trim().to_string(),
+		})
 	} else {
-		Err(())
+		None
 	}
 }
 
@@ -89,146 +273,354 @@ pub fn convert_json_block_response(
 	Err(())
 }
 
+/// Why a pallet constant couldn't be read.
+#[derive(Debug)]
+pub enum ConstantError {
+	/// Constants can only be looked up in V14 metadata.
+	UnsupportedMetadata,
+	/// No such pallet, or no such constant in the pallet.
+	NotFound { pallet: String, name: String },
+	/// The constant is there but its value doesn't decode as its type.
+	Decode(DecodeError),
+}
+
+impl core::fmt::Display for ConstantError {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		match self {
+			ConstantError::UnsupportedMetadata => write!(f, "constants need V14 metadata"),
+			ConstantError::NotFound { pallet, name } =>
+				write!(f, "no constant {}.{}", pallet, name),
+			ConstantError::Decode(err) => write!(f, "can't decode constant: {:?}", err),
+		}
+	}
+}
+
+/// Decode a pallet constant (e.g. `System` / `BlockWeights` or `Babe` / `ExpectedBlockTime`)
+/// straight out of the metadata.
+pub fn constant(
+	index: &MetadataIndex,
+	pallet: &str,
+	name: &str,
+) -> Result<Value<u32>, ConstantError> {
+	let metadata = index.metadata().ok_or(ConstantError::UnsupportedMetadata)?;
+	let constant = metadata
+		.pallets
+		.iter()
+		.find(|p| p.name == pallet)
+		.and_then(|p| p.constants.iter().find(|c| c.name == name))
+		.ok_or_else(|| ConstantError::NotFound { pallet: pallet.into(), name: name.into() })?;
+
+	scale_value::scale::decode_as_type(&mut &constant.value[..], constant.ty.id, &metadata.types)
+		.map_err(ConstantError::Decode)
+}
+
+/// The chain's `ss58Format` for showing account ids as addresses, from `System` /
+/// `SS58Prefix` (if the runtime has it).
+pub fn ss58_format(index: &MetadataIndex) -> Option<u16> {
+	let prefix = constant(index, "System", "SS58Prefix").ok()?;
+	prefix.as_u128().and_then(|prefix| u16::try_from(prefix).ok())
+}
+
+/// Decode a storage value (e.g. `System` / `Account` or `Balances` / `TotalIssuance`). A
+/// missing value is the entry's default (e.g. zero balances for an account that's been
+/// reaped), or `None` if the entry doesn't have one.
+pub fn decode_storage_value(
+	index: &MetadataIndex,
+	pallet: &str,
+	name: &str,
+	scale_encoded_data: Option<&[u8]>,
+) -> Result<Option<Value<u32>>, DecodeError> {
+	use frame_metadata::v14::{StorageEntryModifier, StorageEntryType};
+
+	let metadata = index.metadata().ok_or(DecodeError::NotEnoughInput)?;
+	let entry = index
+		.storage_entry(pallet, name)
+		.ok_or_else(|| DecodeError::TypeIdNotFound(format!("{}.{}", pallet, name)))?;
+	let ty = match &entry.ty {
+		StorageEntryType::Plain(ty) => ty.id,
+		StorageEntryType::Map { value, .. } => value.id,
+	};
+	let bytes = match (scale_encoded_data, &entry.modifier) {
+		(Some(bytes), _) => bytes,
+		(None, StorageEntryModifier::Default) => &entry.default[..],
+		(None, StorageEntryModifier::Optional) => return Ok(None),
+	};
+	scale_value::scale::decode_as_type(&mut &*bytes, ty, &metadata.types).map(Some)
+}
+
 pub fn decode_xcm(
-	meta: &frame_metadata::RuntimeMetadataPrefixed,
+	index: &MetadataIndex,
 	scale_encoded_data: &[u8],
 ) -> Result<Value<u32>, DecodeError> {
-	if let RuntimeMetadata::V14(metadata) = &meta.1 {
-		let mut extrinsic_type = None;
-		for r in &metadata.types.types {
-			let segs = &r.ty.path.segments;
-			if segs.len() == 2 && segs[1] == "VersionedXcm" && segs[0] == "xcm" {
-				extrinsic_type = Some(r);
-				break;
-			}
-		}
-		if extrinsic_type.is_none() {
-			return Err(DecodeError::TypeIdNotFound("Oh dear".to_string()));
-		}
+	if let Some(metadata) = index.metadata() {
+		let xcm_type = index
+			.xcm_type()
+			.ok_or_else(|| DecodeError::TypeIdNotFound("xcm::VersionedXcm".to_string()))?;
 
-		scale_value::scale::decode_as_type(
-			&mut &*scale_encoded_data,
-			extrinsic_type.unwrap().id,
-			&metadata.types,
-		)
+		scale_value::scale::decode_as_type(&mut &*scale_encoded_data, xcm_type, &metadata.types)
 	} else {
 		Err(DecodeError::NotEnoughInput)
 	}
 }
 
 pub fn decode_extrinsic(
-	meta: &frame_metadata::RuntimeMetadataPrefixed,
-	mut scale_encoded_data: &[u8],
+	index: &MetadataIndex,
+	scale_encoded_data: &[u8],
 ) -> Result<Value<u32>, DecodeError> {
-	if let RuntimeMetadata::V14(metadata) = &meta.1 {
-		let mut extrinsic_type = None;
-		for r in &metadata.types.types {
-			let segs = &r.ty.path.segments;
-			if segs.len() == 2 {
-				// it got renamed recently
-				if (segs[1] == "Call" || segs[1] == "RuntimeCall") && segs[0].ends_with("_runtime")
-				{
-					extrinsic_type = Some(r);
-					break;
-				}
-			}
-		}
-		if extrinsic_type.is_none() {
-			return Err(DecodeError::TypeIdNotFound("oh dear".to_string()));
+	let metadata = index.metadata().ok_or(DecodeError::NotEnoughInput)?;
+	let call_type = index
+		.call_type()
+		.ok_or_else(|| DecodeError::TypeIdNotFound("RuntimeCall".to_string()))?;
+	let call = extrinsic_call(index, scale_encoded_data).map_err(|_| DecodeError::NotEnoughInput)?;
+	scale_value::scale::decode_as_type(&mut &*call, call_type, &metadata.types)
+}
+
+/// Like `decode_extrinsic` but straight into a `scale_borrow::Value`, which borrows from the
+/// data and the metadata.
+pub fn decode_extrinsic_borrowed<'scale>(
+	index: &'scale MetadataIndex,
+	scale_encoded_data: &'scale [u8],
+) -> Result<scale_borrow::Value<'scale>, scale_borrow::Error> {
+	let call = extrinsic_call(index, scale_encoded_data)?;
+	decode_call_borrowed(index, call)
+}
+
+/// Decode a call (e.g. one from `extrinsic_call` or `batch_calls`).
+pub fn decode_call_borrowed<'scale>(
+	index: &'scale MetadataIndex,
+	call: &'scale [u8],
+) -> Result<scale_borrow::Value<'scale>, scale_borrow::Error> {
+	let (metadata, call_type) = index
+		.metadata()
+		.zip(index.call_type())
+		.ok_or_else(|| error(0, 0, ErrorKind::Unsupported("metadata without calls".into())))?;
+	ValueBuilder::parse(call, call_type, &metadata.types)
+}
+
+/// Encode a call (e.g. one from `decode_call_borrowed`, maybe with different arguments).
+pub fn encode_call_borrowed(
+	index: &MetadataIndex,
+	call: &scale_borrow::Value,
+) -> Result<Vec<u8>, scale_borrow::Error> {
+	let (metadata, call_type) = index
+		.metadata()
+		.zip(index.call_type())
+		.ok_or_else(|| error(0, 0, ErrorKind::Unsupported("metadata without calls".into())))?;
+	call.encode_as_type(call_type, &metadata.types)
+}
+
+/// The call of an extrinsic, i.e. what follows its length, version, signature and signed
+/// extensions.
+pub fn extrinsic_call<'scale>(
+	index: &'scale MetadataIndex,
+	scale_encoded_data: &'scale [u8],
+) -> Result<&'scale [u8], scale_borrow::Error> {
+	let metadata = index
+		.metadata()
+		.ok_or_else(|| error(0, 0, ErrorKind::Unsupported("metadata before v14".into())))?;
+	let cursor = &mut &*scale_encoded_data;
+	let offset = |cursor: &[u8]| scale_encoded_data.len() - cursor.len();
+
+	let _size = <Compact<u32>>::decode(cursor).map_err(|err| error(0, 0, err.into()))?;
+	let version = *cursor.first().ok_or_else(|| {
+		error(0, offset(cursor), ErrorKind::NotEnoughData { needed: 1, left: 0 })
+	})?;
+	let is_signed = version & 0b1000_0000 != 0;
+	// We only know how to decode V4 extrinsics at the moment
+	if version & 0b0111_1111 != EXTRINSIC_VERSION {
+		let kind = ErrorKind::Unsupported(format!("extrinsic version {}", version & 0b0111_1111));
+		return Err(error(0, offset(cursor), kind))
+	}
+	*cursor = &cursor[1..];
+
+	if is_signed {
+		// TODO assumes a `MultiAddress::Id` and a 64 byte `MultiSignature`. Can we figure this
+		// out from the metadata?
+		const ADDRESS_AND_SIGNATURE_LEN: usize = 1 + 32 + 1 + 64;
+		if cursor.len() < ADDRESS_AND_SIGNATURE_LEN {
+			let kind =
+				ErrorKind::NotEnoughData { needed: ADDRESS_AND_SIGNATURE_LEN, left: cursor.len() };
+			return Err(error(0, offset(cursor), kind))
 		}
+		*cursor = &cursor[ADDRESS_AND_SIGNATURE_LEN..];
 
-		let _size = <Compact<u32>>::decode(&mut scale_encoded_data).unwrap_or(Compact(0)).0;
-		// println!("size {_size}");
-
-		let is_signed = scale_encoded_data[0] & 0b1000_0000 != 0;
-		let version = scale_encoded_data[0] & 0b0111_1111;
-		scale_encoded_data = &scale_encoded_data[1..];
-
-		// We only know how to decode V4 extrinsics at the moment
-		if version != 4 {
-			// eprintln!(
-			//     "not v4 v{} {} {}",
-			//     version,
-			//     is_signed,
-			//     hex::encode(scale_encoded_data)
-			// );
-			return Err(DecodeError::NotEnoughInput);
+		for sig_ext in &metadata.extrinsic.signed_extensions {
+			let start = offset(cursor);
+			scale_borrow::cursor::skip(cursor, sig_ext.ty.id, &metadata.types)
+				.map_err(|err| scale_borrow::Error { offset: start + err.offset, ..err })?;
 		}
-		// println!("is signed {}", is_signed);
-		// If the extrinsic is signed, decode the signature next.
-		let _signature: Option<()> = match is_signed {
-			true => {
-				// skip_decode(meta, &["polkadot_runtime", "Call"], scale_encoded_data);
-				let _address = <[u8; 32]>::decode(&mut scale_encoded_data); // TODO assumed 32 len. Can we figure out this from the metadata?
-				let _sig = <[u8; 64]>::decode(&mut scale_encoded_data); // 1 byte for the discriminant of era.
-				let _maybe_enum_sig_determinant = <[u8; 2]>::decode(&mut scale_encoded_data);
-
-				//find signed extensions,
-				// println!("gobble len {}", scale_encoded_data.len());
-
-				// println!(
-				//     "era decode as type {}",
-				//     hex::encode(&scale_encoded_data[..])
-				// );
-				// println!("gobble len {}", scale_encoded_data.len());
-
-				// let _era = Era::decode(&mut scale_encoded_data).unwrap();
-				// // assert_eq!(_additional_and_extra_params.unwrap()[0], 5);
-				// println!("era {:?}", _era);
-
-				// if include_tip {
-				//     //TODO need a more efficient way!
-				//     //                     sp_runtime::generic::Era enum
-				//         //   - compact encoded u32 (nonce; prior transaction count)
-				// println!("noce bits: {}", hex::encode(&scale_encoded_data[0..4]));
-				// let _nonce = Compact::<u32>::decode(&mut scale_encoded_data);
-
-				// println!("nonce {:?}", _nonce);
-				// let _tip = Compact::<u128>::decode(&mut scale_encoded_data);
-				// println!("tip {:?}", _tip);
-
-				//let _maybe_enum_sig_determinant = <[u8; 1]>::decode(&mut scale_encoded_data);
-
-				for sig_ext in &metadata.extrinsic.signed_extensions {
-					// println!("sigext {}", sig_ext.identifier);
-					// println!("sigext type {:?}", sig_ext.ty);
-					// println!("sigext additional {:?}", sig_ext.additional_signed);
-					//  println!("gobble next {}", hex::encode(&scale_encoded_data[..4]));
-					let _val = scale_value::scale::decode_as_type(
-						&mut scale_encoded_data,
-						sig_ext.ty.id,
-						&metadata.types,
-					)?;
-					// let _val = scale_value::scale::decode_as_type(
-					//     &mut scale_encoded_data,
-					//     sig_ext.additional_signed.id(),
-					//     &metadata.types,
-					// )?;
-					// println!("res= {:?}", _val);
-				}
-				Some(())
-			},
-			false => None,
-		};
+	}
+	Ok(*cursor)
+}
 
-		// let cursor = &mut &*scale_encoded_data;
-		// println!("decode as type {}", hex::encode(&scale_encoded_data[..]));
+/// The calls in a batch (`Utility` / `batch`, `batch_all` or `force_batch`), each as encoded.
+pub fn batch_calls<'scale>(
+	index: &'scale MetadataIndex,
+	call: &'scale [u8],
+) -> Result<Vec<&'scale [u8]>, scale_borrow::Error> {
+	let (metadata, call_type) = index
+		.metadata()
+		.zip(index.call_type())
+		.ok_or_else(|| error(0, 0, ErrorKind::Unsupported("metadata without calls".into())))?;
+	// The pallet and call indexes, then the one argument: `calls: Vec<RuntimeCall>`.
+	let cursor = &mut call.get(2..).unwrap_or_default();
+	let offset = |cursor: &[u8]| call.len() - cursor.len();
+	let len = <Compact<u32>>::decode(cursor).map_err(|err| error(call_type, 2, err.into()))?.0;
+	let mut calls = vec![];
+	for _ in 0..len {
+		let start = *cursor;
+		scale_borrow::cursor::skip(cursor, call_type, &metadata.types)
+			.map_err(|err| scale_borrow::Error { offset: offset(start) + err.offset, ..err })?;
+		calls.push(&start[..start.len() - cursor.len()]);
+	}
+	Ok(calls)
+}
 
-		scale_value::scale::decode_as_type(
-			&mut &*scale_encoded_data,
-			extrinsic_type.unwrap().id,
-			&metadata.types,
-		)
-	} else {
-		Err(DecodeError::NotEnoughInput)
+/// Like `decode_xcm` but straight into a `scale_borrow::Value`.
+pub fn decode_xcm_borrowed<'scale>(
+	index: &'scale MetadataIndex,
+	scale_encoded_data: &'scale [u8],
+) -> Result<scale_borrow::Value<'scale>, scale_borrow::Error> {
+	let (metadata, xcm_type) = index
+		.metadata()
+		.zip(index.xcm_type())
+		.ok_or_else(|| error(0, 0, ErrorKind::Unsupported("metadata without xcm".into())))?;
+	ValueBuilder::parse(scale_encoded_data, xcm_type, &metadata.types)
+}
+
+/// An error with the extrinsic (or whatever's being decoded) itself rather than a value in it.
+fn error(type_id: u32, offset: usize, kind: ErrorKind) -> scale_borrow::Error {
+	scale_borrow::Error { path: String::new(), type_id, offset, kind }
+}
+
+/// Encode the arguments of a runtime API call (for `state_call` of `{api}_{method}`).
+pub fn encode_runtime_api_args<T>(
+	index: &MetadataIndex,
+	api: &str,
+	method: &str,
+	args: &[Value<T>],
+) -> Result<Vec<u8>, EncodeError> {
+	let (method_meta, types) = index
+		.runtime_api(api, method)
+		.ok_or_else(|| EncodeError::custom_string(format!("no runtime api {}_{}", api, method)))?;
+	if method_meta.inputs.len() != args.len() {
+		return Err(EncodeError::custom_string(format!(
+			"{}_{} takes {} args but got {}",
+			api,
+			method,
+			method_meta.inputs.len(),
+			args.len()
+		)))
+	}
+	let mut encoded = vec![];
+	for (input, arg) in method_meta.inputs.iter().zip(args) {
+		scale_value::scale::encode_as_type(arg, input.ty.id, types, &mut encoded)?;
+	}
+	Ok(encoded)
+}
+
+/// Decode what a runtime API call returned.
+pub fn decode_runtime_api_output(
+	index: &MetadataIndex,
+	api: &str,
+	method: &str,
+	scale_encoded_data: &[u8],
+) -> Result<Value<u32>, DecodeError> {
+	let (method_meta, types) = index
+		.runtime_api(api, method)
+		.ok_or_else(|| DecodeError::TypeIdNotFound(format!("{}_{}", api, method)))?;
+	scale_value::scale::decode_as_type(&mut &*scale_encoded_data, method_meta.output.id, types)
+}
+
+/// Extrinsic format version that we know how to encode and decode.
+const EXTRINSIC_VERSION: u8 = 4;
+
+/// Encode a `RuntimeCall` value (e.g. one from `decode_extrinsic`) back into SCALE bytes.
+pub fn encode_call<T>(index: &MetadataIndex, call: &Value<T>) -> Result<Vec<u8>, EncodeError> {
+	let (metadata, call_type) = match (index.metadata(), index.call_type()) {
+		(Some(metadata), Some(call_type)) => (metadata, call_type),
+		_ => return Err(EncodeError::custom_str("no RuntimeCall type in metadata")),
+	};
+	let mut encoded = vec![];
+	scale_value::scale::encode_as_type(call, call_type, &metadata.types, &mut encoded)?;
+	Ok(encoded)
+}
+
+/// Encode the extra data of the signed extensions (era, nonce, tip...) that goes into a signed
+/// extrinsic. One value per signed extension, in metadata order.
+pub fn encode_signed_extra<T>(
+	index: &MetadataIndex,
+	extra: &[Value<T>],
+) -> Result<Vec<u8>, EncodeError> {
+	encode_signed_extensions(index, extra, false)
+}
+
+/// Encode the additional signed data of the signed extensions (spec version, genesis hash...).
+/// This is signed over but not included in the extrinsic.
+pub fn encode_additional_signed<T>(
+	index: &MetadataIndex,
+	additional: &[Value<T>],
+) -> Result<Vec<u8>, EncodeError> {
+	encode_signed_extensions(index, additional, true)
+}
+
+fn encode_signed_extensions<T>(
+	index: &MetadataIndex,
+	values: &[Value<T>],
+	additional: bool,
+) -> Result<Vec<u8>, EncodeError> {
+	let metadata =
+		index.metadata().ok_or_else(|| EncodeError::custom_str("only V14 metadata supported"))?;
+	let signed_extensions = &metadata.extrinsic.signed_extensions;
+	if signed_extensions.len() != values.len() {
+		return Err(EncodeError::custom_string(format!(
+			"expected {} signed extension values but got {}",
+			signed_extensions.len(),
+			values.len()
+		)))
+	}
+	let mut encoded = vec![];
+	for (sig_ext, value) in signed_extensions.iter().zip(values) {
+		let ty = if additional { sig_ext.additional_signed.id } else { sig_ext.ty.id };
+		scale_value::scale::encode_as_type(value, ty, &metadata.types, &mut encoded)?;
+	}
+	Ok(encoded)
+}
+
+/// The bytes a signer signs. (Substrate expects payloads over 256 bytes to be blake2_256
+/// hashed first - that's up to the signer as we have no crypto deps here.)
+pub fn signer_payload(call: &[u8], extra: &[u8], additional_signed: &[u8]) -> Vec<u8> {
+	[call, extra, additional_signed].concat()
+}
+
+/// Wrap an encoded call into an unsigned extrinsic, ready for `PolkaPipe::submit`.
+pub fn encode_unsigned_extrinsic(call: &[u8]) -> Vec<u8> {
+	length_prefixed(&[&[EXTRINSIC_VERSION], call])
+}
+
+/// Wrap an encoded call into a signed extrinsic. `address` and `signature` are the SCALE encoded
+/// `MultiAddress` and `MultiSignature` (of `signer_payload`) and `extra` is from
+/// `encode_signed_extra`.
+pub fn encode_signed_extrinsic(
+	address: &[u8],
+	signature: &[u8],
+	extra: &[u8],
+	call: &[u8],
+) -> Vec<u8> {
+	length_prefixed(&[&[0b1000_0000 | EXTRINSIC_VERSION], address, signature, extra, call])
+}
+
+fn length_prefixed(parts: &[&[u8]]) -> Vec<u8> {
+	let len: usize = parts.iter().map(|part| part.len()).sum();
+	let mut encoded = Compact(len as u32).encode();
+	for part in parts {
+		encoded.extend_from_slice(part);
 	}
+	encoded
 }
 
-pub fn potluck_decode(
-	metadata: &frame_metadata::RuntimeMetadataPrefixed,
-	mut scale_encoded_data: &[u8],
-) {
-	if let RuntimeMetadata::V14(metadata) = &metadata.1 {
+pub fn potluck_decode(index: &MetadataIndex, mut scale_encoded_data: &[u8]) {
+	if let Some(metadata) = index.metadata() {
 		for r in &metadata.types.types {
 			if scale_value::scale::decode_as_type(&mut scale_encoded_data, r.id, &metadata.types)
 				.is_ok()
@@ -241,12 +633,8 @@ pub fn potluck_decode(
 	}
 }
 
-pub fn skip_decode(
-	metadata: &frame_metadata::RuntimeMetadataPrefixed,
-	path: &[&str],
-	scale_encoded_data: &[u8],
-) {
-	if let RuntimeMetadata::V14(metadata) = &metadata.1 {
+pub fn skip_decode(index: &MetadataIndex, path: &[&str], scale_encoded_data: &[u8]) {
+	if let Some(metadata) = index.metadata() {
 		for r in &metadata.types.types {
 			if r.ty.path.segments == path {
 				for i in 0..scale_encoded_data.len() {
@@ -285,23 +673,35 @@ mod tests {
 	// fn get_karura() -> polkapipe::http::Backend {
 	//     polkapipe::http::Backend::new("https://karura-rpc-2.aca-api.network/")
 	// }
-	async fn get_polkadot() -> polkapipe::PolkaPipe<polkapipe::http::Backend> {
-		polkapipe::PolkaPipe {
-			rpc: polkapipe::http::Backend::new(vec![
-				Url::parse("https://dot-rpc.stakeworld.io").unwrap(),
-				Url::parse("https://rpc-polkadot.luckyfriday.io").unwrap(),
-				Url::parse("https://rpc.polkadot.io").unwrap(),
-				].as_slice())				,
-		}
+	type Client = polkapipe::PolkaPipe<polkapipe::mock::Node<polkapipe::http::Backend>>;
+
+	/// Replays `tests/transcripts/<transcript>.json`, see `polkapipe::mock::Node::replay`.
+	async fn node(transcript: &str, urls: &[&str]) -> Client {
+		let path =
+			format!("{}/tests/transcripts/{}.json", env!("CARGO_MANIFEST_DIR"), transcript);
+		let urls: Vec<_> = urls.iter().map(|url| Url::parse(url).unwrap()).collect();
+		let rpc = polkapipe::mock::Node::replay(path, || async {
+			Ok(polkapipe::http::Backend::new(urls.as_slice()))
+		})
+		.await
+		.unwrap();
+		polkapipe::PolkaPipe { rpc }
 	}
 
-	async fn get_statemine() -> polkapipe::PolkaPipe<polkapipe::http::Backend> {
-		polkapipe::PolkaPipe {
-			rpc: polkapipe::http::Backend::new(vec![
-				Url::parse("https://kusama-asset-hub-rpc.polkadot.io").unwrap(),
-			].as_slice())
-				,
-		}
+	async fn get_polkadot(transcript: &str) -> Client {
+		node(
+			transcript,
+			&[
+				"https://dot-rpc.stakeworld.io",
+				"https://rpc-polkadot.luckyfriday.io",
+				"https://rpc.polkadot.io",
+			],
+		)
+		.await
+	}
+
+	async fn get_statemine(transcript: &str) -> Client {
+		node(transcript, &["https://kusama-asset-hub-rpc.polkadot.io"]).await
 	}
 
 	// fn get_interlay() -> polkapipe::http::Backend {
@@ -316,7 +716,7 @@ mod tests {
 
 	async fn test_metadata() {
 		env_logger::init();
-		let client = get_polkadot().await;
+		let client = get_polkadot("metadata").await;
 
 		let metadata = client.query_metadata(None).await.unwrap();
 		let meta = decode_metadata(metadata.as_slice()).unwrap();
@@ -384,11 +784,12 @@ mod tests {
 		let hex_block_hash = hash;
 		let block_hash = hex::decode(hex_block_hash).unwrap();
 
-		let client = get_polkadot().await;
+		let client = get_polkadot(&format!("extrinsics_{}", &hash[..8])).await;
 
 		let metadata = client.query_metadata(Some(&block_hash[..])).await.unwrap();
 		let meta = decode_metadata(metadata.as_slice()).unwrap();
 		assert!(matches!(meta.1, RuntimeMetadata::V14(_)));
+		let index = MetadataIndex::new(meta);
 
 		// let events_key = "26aa394eea5630e07c48ae0c9558cef780d41e5e16056765bc8461851072c9d7";
 		// let key = hex::decode(events_key).unwrap();
@@ -401,8 +802,9 @@ mod tests {
 		assert_eq!(extrinsics.len(), expected_extrinsics);
 		for (_i, ex) in extrinsics.iter().enumerate() {
 			println!("extrinsic #{_i}");
-			let res = decode_extrinsic(&meta, &ex[..]);
+			let res = decode_extrinsic(&index, &ex[..]);
 			assert!(res.is_ok(), "bytes {:?}", hex::encode(&ex[..]));
+			assert_round_trips(&index, &ex[..]);
 			// println!("just finished decoding {} res was {:?}", i, res);
 		}
 		// let val = extrinsics(meta, &block_json).unwrap();
@@ -444,11 +846,12 @@ mod tests {
 		let hex_block_hash = hash;
 		let block_hash = hex::decode(hex_block_hash).unwrap();
 
-		let client = get_statemine().await;
+		let client = get_statemine(&format!("extrinsics_statemine_{}", &hash[..8])).await;
 
 		let metadata = client.query_metadata(Some(&block_hash[..])).await.unwrap();
 		let meta = decode_metadata(metadata.as_slice()).unwrap();
 		assert!(matches!(meta.1, RuntimeMetadata::V14(_)));
+		let index = MetadataIndex::new(meta);
 
 		// let events_key = "26aa394eea5630e07c48ae0c9558cef780d41e5e16056765bc8461851072c9d7";
 		// let key = hex::decode(events_key).unwrap();
@@ -461,14 +864,358 @@ mod tests {
 		assert_eq!(extrinsics.len(), expected_extrinsics);
 		for (_i, ex) in extrinsics.iter().enumerate() {
 			println!("extrinsic #{_i}");
-			let res = decode_extrinsic(&meta, &ex[..]);
+			let res = decode_extrinsic(&index, &ex[..]);
 			assert!(res.is_ok(), "bytes {:?}", hex::encode(&ex[..]));
+			assert_round_trips(&index, &ex[..]);
 			// println!("just finished decoding {} res was {:?}", i, res);
 		}
 		// let val = extrinsics(meta, &block_json).unwrap();
 		// println!("{:#?}", val);
 	}
 
+	/// A real extrinsic's call decodes and encodes back to the same bytes.
+	fn assert_round_trips(index: &MetadataIndex, extrinsic: &[u8]) {
+		let call = extrinsic_call(index, extrinsic).unwrap();
+		let value = decode_call_borrowed(index, call)
+			.unwrap_or_else(|err| panic!("{:?} decoding {}", err, hex::encode(extrinsic)));
+		assert_eq!(encode_call_borrowed(index, &value).unwrap(), call, "{}", hex::encode(extrinsic));
+	}
+
+	fn polkadot_metadata() -> MetadataIndex {
+		decode_metadata(include_bytes!("../../../polkadot_metadata.scale")).unwrap().into()
+	}
+
+	#[test]
+	fn can_index_metadata() {
+		let index = polkadot_metadata();
+
+		assert!(index.event_type().is_some());
+		assert!(index.call_type().is_some());
+		assert!(index.xcm_type().is_some());
+		assert_ne!(index.event_type(), index.call_type());
+
+		assert_eq!(index.pallet_name(0), Some("System"));
+		assert_eq!(index.pallet_index("Balances"), Some(5));
+		assert!(index.error_type(5).is_some());
+		assert_eq!(index.pallet_name(255), None);
+
+		let events = index.storage_entry("System", "Events").unwrap();
+		assert_eq!(events.name, "Events");
+		assert!(index.storage_entry("System", "NotAnEntry").is_none());
+
+		// No events is still a valid set of events.
+		assert_eq!(decode_events(&index, &[0]).unwrap().len(), 0);
+	}
+
+	#[test]
+	fn can_decode_event_records() {
+		let index = polkadot_metadata();
+		assert_eq!(index.hash_size(), 32);
+
+		// One event: Phase::Finalization, System.CodeUpdated, with one topic.
+		let mut events = vec![4, 1, 0, 2, 4];
+		events.extend([7; 32]);
+
+		let records = decode_events(&index, &events).unwrap();
+		assert_eq!(records.len(), 1);
+		let record = &records[0];
+		assert_eq!(record.phase, Phase::Finalization);
+		assert_eq!((record.pallet.as_str(), record.variant.as_str()), ("System", "CodeUpdated"));
+		assert_eq!((record.pallet_index, record.variant_index), (0, 2));
+		assert_eq!(record.raw, &[1, 0, 2]);
+		assert_eq!(record.topics, vec![&[7; 32][..]]);
+
+		// Truncated topic.
+		assert!(decode_events(&index, &events[..events.len() - 1]).is_err());
+
+		// Another before it: ApplyExtrinsic(1), System.ExtrinsicSuccess with no topics.
+		let success = [0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+		let mut events = [&[8][..], &success, &events[1..]].concat();
+		let all = decode_events_borrowed(&index, &events).unwrap();
+		assert_eq!(all.len(), 2);
+		assert_eq!(all[0].variant, "ExtrinsicSuccess");
+		let wanted = decode_events_where(&index, &events, |_, variant| variant == "CodeUpdated");
+		let wanted = wanted.unwrap();
+		assert_eq!(wanted.len(), 1);
+		assert_eq!(wanted[0].variant, "CodeUpdated");
+		assert_eq!(wanted[0].value, all[1].value);
+		assert_eq!(wanted[0].raw, all[1].raw);
+		assert_eq!(decode_events_where(&index, &events, |_, _| true).unwrap().len(), 2);
+		assert_eq!(decode_events_where(&index, &events, |_, _| false).unwrap().len(), 0);
+
+		// Bad data is still an error, even in events that aren't wanted.
+		events[16] = 9; // The `DispatchClass`.
+		assert!(decode_events_where(&index, &events, |_, _| false).is_err());
+	}
+
+	#[test]
+	fn can_encode_extrinsics() {
+		let index = polkadot_metadata();
+		let remark = Value::unnamed_variant(
+			"System",
+			[Value::named_variant("remark", [("remark", Value::from_bytes(b"hello"))])],
+		);
+		let call = encode_call(&index, &remark).unwrap();
+
+		let unsigned = encode_unsigned_extrinsic(&call);
+		let decoded = decode_extrinsic(&index, &unsigned).unwrap();
+		assert_eq!(encode_call(&index, &decoded).unwrap(), call);
+
+		// Immortal era, nonce 0, tip 0 - everything else has no extra data.
+		let extra = [0, 0, 0];
+		let metadata = index.metadata().unwrap();
+		let mut cursor = &extra[..];
+		let extra_values: Vec<_> = metadata
+			.extrinsic
+			.signed_extensions
+			.iter()
+			.map(|sig_ext| {
+				scale_value::scale::decode_as_type(&mut cursor, sig_ext.ty.id, &metadata.types)
+					.unwrap()
+			})
+			.collect();
+		assert!(cursor.is_empty());
+		assert_eq!(encode_signed_extra(&index, &extra_values).unwrap(), extra);
+		assert!(encode_signed_extra(&index, &extra_values[1..]).is_err());
+
+		let address = [&[0][..], &[1; 32]].concat(); // MultiAddress::Id
+		let signature = [&[1][..], &[2; 64]].concat(); // MultiSignature::Sr25519
+		let signed = encode_signed_extrinsic(&address, &signature, &extra, &call);
+		let decoded = decode_extrinsic(&index, &signed).unwrap();
+		assert_eq!(encode_call(&index, &decoded).unwrap(), call);
+	}
+
+	#[test]
+	fn can_decode_borrowed() {
+		let index = polkadot_metadata();
+		let remark = |text: &[u8]| {
+			Value::unnamed_variant(
+				"System",
+				[Value::named_variant("remark", [("remark", Value::from_bytes(text))])],
+			)
+		};
+		let call = encode_call(&index, &remark(b"hello")).unwrap();
+
+		let unsigned = encode_unsigned_extrinsic(&call);
+		assert_eq!(extrinsic_call(&index, &unsigned).unwrap(), call);
+		let decoded = decode_extrinsic_borrowed(&index, &unsigned).unwrap();
+		let args = decoded.expect3("System", "0", "remark").unwrap();
+		assert_eq!(args.get("remark").and_then(|remark| remark.as_bytes()), Some(&b"hello"[..]));
+
+		let address = [&[0][..], &[1; 32]].concat();
+		let signature = [&[1][..], &[2; 64]].concat();
+		let signed = encode_signed_extrinsic(&address, &signature, &[0, 0, 0], &call);
+		assert_eq!(extrinsic_call(&index, &signed).unwrap(), call);
+		assert_eq!(decode_extrinsic_borrowed(&index, &signed).unwrap(), decoded);
+
+		// Errors rather than panics.
+		assert!(decode_extrinsic(&index, &[]).is_err());
+		let err = extrinsic_call(&index, &[4, 5]).unwrap_err();
+		assert_eq!(err.kind, ErrorKind::Unsupported("extrinsic version 5".into()));
+		assert!(decode_extrinsic_borrowed(&index, &signed[..50]).is_err());
+
+		let batch = Value::unnamed_variant(
+			"Utility",
+			[Value::named_variant(
+				"batch_all",
+				[("calls", Value::unnamed_composite([remark(b"a"), remark(b"bc")]))],
+			)],
+		);
+		let batch = encode_call(&index, &batch).unwrap();
+		let calls = batch_calls(&index, &batch).unwrap();
+		assert_eq!(calls, vec![
+			encode_call(&index, &remark(b"a")).unwrap(),
+			encode_call(&index, &remark(b"bc")).unwrap()
+		]);
+		let second = decode_call_borrowed(&index, calls[1]).unwrap();
+		assert_eq!(second.only3().map(|(pallet, _, call, _)| (pallet, call)), Some(("System", "remark")));
+		// And back again.
+		assert_eq!(
+			encode_call_borrowed(&index, &decode_call_borrowed(&index, &batch).unwrap()).unwrap(),
+			batch
+		);
+		let edited = decode_call_borrowed(&index, calls[0]).unwrap();
+		let mut edited = edited.expect3("System", "0", "remark").unwrap().clone();
+		if let scale_borrow::Value::Object(fields) = &mut edited {
+			fields.retain(|(name, _)| *name != "remark");
+			fields.push(("remark", scale_borrow::Value::Scale(b"bc")));
+		}
+		let edited = scale_borrow::Value::Object(Box::new(vec![(
+			"System",
+			scale_borrow::Value::Object(Box::new(vec![(
+				"0",
+				scale_borrow::Value::Object(Box::new(vec![("remark", edited)])),
+			)])),
+		)]));
+		assert_eq!(encode_call_borrowed(&index, &edited).unwrap(), calls[1]);
+
+		// One event: Phase::Finalization, System.CodeUpdated (no fields), with one topic.
+		let mut events = vec![4, 1, 0, 2, 4];
+		events.extend([7; 32]);
+		let records = decode_events_borrowed(&index, &events).unwrap();
+		assert_eq!(records.len(), 1);
+		assert_eq!((records[0].pallet.as_str(), records[0].variant.as_str()), ("System", "CodeUpdated"));
+		assert_eq!(records[0].raw, &[1, 0, 2]);
+		assert_eq!(records[0].topics, vec![&[7; 32][..]]);
+		assert!(records[0].value.expect3("System", "0", "CodeUpdated").is_some());
+	}
+
+	#[test]
+	fn can_round_trip_borrowed() {
+		use frame_metadata::v14::{StorageEntryModifier, StorageEntryType};
+
+		let index = polkadot_metadata();
+		let metadata = index.metadata().unwrap();
+		let mut values = vec![];
+		for pallet in &metadata.pallets {
+			for constant in &pallet.constants {
+				values.push((&constant.name, constant.ty.id, &constant.value));
+			}
+			for entry in pallet.storage.iter().flat_map(|storage| &storage.entries) {
+				let ty = match &entry.ty {
+					StorageEntryType::Plain(ty) => ty.id,
+					StorageEntryType::Map { value, .. } => value.id,
+				};
+				// Optional storage defaults to a `None`, which isn't a value of the type.
+				if let StorageEntryModifier::Optional = entry.modifier {
+					assert_eq!(entry.default, [0], "{}", entry.name);
+					continue
+				}
+				values.push((&entry.name, ty, &entry.default));
+			}
+		}
+
+		// Every constant and storage default decodes and encodes back to the same bytes.
+		for (name, ty, data) in values {
+			let value = ValueBuilder::parse(data, ty, &metadata.types)
+				.unwrap_or_else(|err| panic!("{}: {:?}", name, err));
+			assert_eq!(&value.encode_as_type(ty, &metadata.types).unwrap(), data, "{}", name);
+		}
+	}
+
+	#[test]
+	fn can_call_runtime_apis() {
+		use frame_metadata::v15::*;
+		use scale_info::meta_type;
+
+		let mut index = polkadot_metadata();
+		assert!(index.runtime_api("Core", "version").is_none());
+
+		let apis = RuntimeMetadataV15::new(
+			vec![],
+			ExtrinsicMetadata {
+				version: 4,
+				address_ty: meta_type::<()>(),
+				call_ty: meta_type::<()>(),
+				signature_ty: meta_type::<()>(),
+				extra_ty: meta_type::<()>(),
+				signed_extensions: vec![],
+			},
+			meta_type::<()>(),
+			vec![RuntimeApiMetadata {
+				name: "TransactionPaymentApi",
+				methods: vec![RuntimeApiMethodMetadata {
+					name: "query_info",
+					inputs: vec![
+						RuntimeApiMethodParamMetadata { name: "uxt", ty: meta_type::<Vec<u8>>() },
+						RuntimeApiMethodParamMetadata { name: "len", ty: meta_type::<u32>() },
+					],
+					output: meta_type::<(u64, u128)>(),
+					docs: vec![],
+				}],
+				docs: vec![],
+			}],
+			OuterEnums {
+				call_enum_ty: meta_type::<()>(),
+				event_enum_ty: meta_type::<()>(),
+				error_enum_ty: meta_type::<()>(),
+			},
+			CustomMetadata { map: Default::default() },
+		);
+		let v14 = decode_metadata(include_bytes!("../../../polkadot_metadata.scale")).unwrap();
+		assert!(!index.set_runtime_apis(v14));
+		assert!(index.set_runtime_apis(apis.into()));
+
+		let args = [Value::from_bytes([1, 2, 3]), Value::u128(3)];
+		let encoded =
+			encode_runtime_api_args(&index, "TransactionPaymentApi", "query_info", &args).unwrap();
+		assert_eq!(encoded, [12, 1, 2, 3, 3, 0, 0, 0]);
+		assert!(encode_runtime_api_args(&index, "TransactionPaymentApi", "query_info", &args[..1])
+			.is_err());
+		assert!(encode_runtime_api_args(&index, "Core", "version", &args).is_err());
+
+		let output = (5u64, 7u128).encode();
+		let decoded =
+			decode_runtime_api_output(&index, "TransactionPaymentApi", "query_info", &output)
+				.unwrap();
+		assert_eq!(decoded.at(1).and_then(|fee| fee.as_u128()), Some(7));
+	}
+
+	#[test]
+	fn can_decode_dispatch_errors() {
+		let index = polkadot_metadata();
+
+		// ExtrinsicFailed for extrinsic 1: DispatchError::Module(Balances, error 2). (This
+		// metadata still has a u8 module error and a u64 weight in the DispatchInfo.)
+		let mut events = vec![4, 0, 1, 0, 0, 0, 0, 1, 3, 5, 2];
+		events.extend([0; 11]);
+		let records = decode_events(&index, &events).unwrap();
+		assert_eq!(records[0].phase, Phase::ApplyExtrinsic(1));
+		let error = extrinsic_failed(&index, &records[0]).unwrap();
+		assert_eq!(error.pallet.as_deref(), Some("Balances"));
+		assert_eq!(error.error, "InsufficientBalance");
+		assert!(!error.docs.is_empty());
+		assert!(error.to_string().starts_with("Balances.InsufficientBalance: "));
+
+		// DispatchError::BadOrigin
+		let mut events = vec![4, 0, 1, 0, 0, 0, 0, 1, 2];
+		events.extend([0; 11]);
+		let records = decode_events(&index, &events).unwrap();
+		let error = extrinsic_failed(&index, &records[0]).unwrap();
+		assert_eq!(error, DispatchError { pallet: None, error: "BadOrigin".into(), docs: "".into() });
+		let records = decode_events_borrowed(&index, &events).unwrap();
+		assert_eq!(extrinsic_failed(&index, &records[0]), Some(error));
+	}
+
+	#[test]
+	fn can_decode_storage_values() {
+		let index = polkadot_metadata();
+
+		let issuance = 12_345u128.encode();
+		let total = decode_storage_value(&index, "Balances", "TotalIssuance", Some(&issuance));
+		assert_eq!(total.unwrap().unwrap().as_u128(), Some(12_345));
+
+		// An account that isn't there has the default (nothing).
+		let account = decode_storage_value(&index, "System", "Account", None).unwrap().unwrap();
+		assert_eq!(account.at("nonce").and_then(|nonce| nonce.as_u128()), Some(0));
+		// A stash that isn't bonded has no ledger at all.
+		assert!(decode_storage_value(&index, "Staking", "Ledger", None).unwrap().is_none());
+		assert!(decode_storage_value(&index, "Babe", "NextAuthorities", Some(&[4])).is_err());
+		assert!(decode_storage_value(&index, "System", "NotAnEntry", None).is_err());
+	}
+
+	#[test]
+	fn can_read_constants() {
+		let meta = polkadot_metadata();
+
+		let block_time = constant(&meta, "Babe", "ExpectedBlockTime").unwrap();
+		assert_eq!(block_time.as_u128(), Some(6_000));
+
+		let block_weights = constant(&meta, "System", "BlockWeights").unwrap();
+		assert!(block_weights.at("max_block").is_some());
+
+		assert_eq!(ss58_format(&meta), Some(0));
+
+		assert!(matches!(
+			constant(&meta, "Babe", "NotAConstant"),
+			Err(ConstantError::NotFound { .. })
+		));
+		assert!(matches!(
+			constant(&meta, "NotAPallet", "ExpectedBlockTime"),
+			Err(ConstantError::NotFound { .. })
+		));
+	}
+
 	#[test]
 	fn can_decode_events1() {
 		async_std::task::block_on(test_events1());
@@ -479,11 +1226,12 @@ mod tests {
 			hex::decode("e33568bff8e6f30fee6f217a93523a6b29c31c8fe94c076d818b97b97cfd3a16")
 				.unwrap();
 
-		let client = get_polkadot().await;
+		let client = get_polkadot("events1").await;
 		let metadata = client.query_metadata(Some(&block_hash[..])).await.unwrap();
 		let meta =
 			frame_metadata::RuntimeMetadataPrefixed::decode(&mut metadata.as_slice()).unwrap();
 		assert!(matches!(meta.1, RuntimeMetadata::V14(_)));
+		let index = MetadataIndex::new(meta);
 
 		let events_key = "26aa394eea5630e07c48ae0c9558cef780d41e5e16056765bc8461851072c9d7";
 		let key = hex::decode(events_key).unwrap();
@@ -492,7 +1240,7 @@ mod tests {
 		assert!(as_of_events.len() > 0);
 		// println!("{:?}", as_of_events);
 
-		let _val = decode_events(&meta, &as_of_events[..]).unwrap();
+		let _val = decode_events(&index, &as_of_events[..]).unwrap();
 		// println!("{:#?}", val);
 	}
 
@@ -0,0 +1,199 @@
+use frame_metadata::{
+	v14::{StorageEntryMetadata, StorageEntryType},
+	v15::{RuntimeApiMethodMetadata, RuntimeMetadataV15},
+	RuntimeMetadata, RuntimeMetadataPrefixed,
+};
+use scale_info::{form::PortableForm, PortableRegistry, TypeDef, TypeDefPrimitive};
+use std::collections::{BTreeMap, HashMap};
+
+/// Everything the decode functions would otherwise have to go looking for in the
+/// metadata on every call. Build it once per metadata (i.e. once per runtime upgrade)
+/// and hand it to `decode_events`, `decode_extrinsic`, `decode_xcm` etc.
+pub struct MetadataIndex {
+	pub meta: RuntimeMetadataPrefixed,
+	event_type: Option<u32>,
+	call_type: Option<u32>,
+	xcm_type: Option<u32>,
+	hash_size: usize,
+	pallet_names: BTreeMap<u8, String>,
+	error_types: BTreeMap<u8, u32>,
+	// (pallet, entry) -> (index into pallets, index into storage entries)
+	storage: HashMap<(String, String), (usize, usize)>,
+	// Runtime APIs are only described from V15 metadata onwards.
+	apis: Option<RuntimeMetadataV15>,
+}
+
+impl MetadataIndex {
+	pub fn new(meta: RuntimeMetadataPrefixed) -> Self {
+		let mut index = MetadataIndex {
+			meta,
+			event_type: None,
+			call_type: None,
+			xcm_type: None,
+			hash_size: 32,
+			pallet_names: BTreeMap::new(),
+			error_types: BTreeMap::new(),
+			storage: HashMap::new(),
+			apis: None,
+		};
+
+		if let RuntimeMetadata::V14(metadata) = &index.meta.1 {
+			for r in &metadata.types.types {
+				let segs = &r.ty.path.segments;
+				if segs.len() != 2 {
+					continue
+				}
+				// Event and Call got renamed to RuntimeEvent and RuntimeCall.
+				if segs[0].ends_with("_runtime") {
+					if index.event_type.is_none() &&
+						(segs[1] == "Event" || segs[1] == "RuntimeEvent")
+					{
+						index.event_type = Some(r.id);
+					}
+					if index.call_type.is_none() && (segs[1] == "Call" || segs[1] == "RuntimeCall")
+					{
+						index.call_type = Some(r.id);
+					}
+				}
+				if index.xcm_type.is_none() && segs[0] == "xcm" && segs[1] == "VersionedXcm" {
+					index.xcm_type = Some(r.id);
+				}
+			}
+
+			for (p, pallet) in metadata.pallets.iter().enumerate() {
+				index.pallet_names.insert(pallet.index, pallet.name.clone());
+				if let Some(error) = &pallet.error {
+					index.error_types.insert(pallet.index, error.ty.id);
+				}
+				if let Some(storage) = &pallet.storage {
+					for (e, entry) in storage.entries.iter().enumerate() {
+						index.storage.insert((pallet.name.clone(), entry.name.clone()), (p, e));
+					}
+				}
+			}
+
+			if let Some(hash_size) = index.topic_hash_size() {
+				index.hash_size = hash_size;
+			}
+		}
+		index
+	}
+
+	/// Event topics are `Vec<T::Hash>`, so dig the size of the hash out of the type of
+	/// `System.Events`.
+	fn topic_hash_size(&self) -> Option<usize> {
+		let metadata = self.metadata()?;
+		let sequence_of = |id: u32| match &metadata.types.resolve(id)?.type_def {
+			TypeDef::Sequence(seq) => Some(seq.type_param.id),
+			_ => None,
+		};
+
+		let events = match &self.storage_entry("System", "Events")?.ty {
+			StorageEntryType::Plain(events) => events.id,
+			_ => return None,
+		};
+		let record = match &metadata.types.resolve(sequence_of(events)?)?.type_def {
+			TypeDef::Composite(record) => record,
+			_ => return None,
+		};
+		let topics = record.fields.iter().find(|f| f.name.as_deref() == Some("topics"))?;
+		fixed_size(&metadata.types, sequence_of(topics.ty.id)?)
+	}
+
+	/// The V14 metadata, if that's what we have.
+	pub fn metadata(&self) -> Option<&frame_metadata::v14::RuntimeMetadataV14> {
+		if let RuntimeMetadata::V14(metadata) = &self.meta.1 {
+			Some(metadata)
+		} else {
+			None
+		}
+	}
+
+	/// Type id of the runtime's outer `RuntimeEvent` enum.
+	pub fn event_type(&self) -> Option<u32> {
+		self.event_type
+	}
+
+	/// Type id of the runtime's outer `RuntimeCall` enum.
+	pub fn call_type(&self) -> Option<u32> {
+		self.call_type
+	}
+
+	/// Type id of `xcm::VersionedXcm`.
+	pub fn xcm_type(&self) -> Option<u32> {
+		self.xcm_type
+	}
+
+	/// Size in bytes of the chain's `T::Hash` (32 unless the metadata says otherwise).
+	pub fn hash_size(&self) -> usize {
+		self.hash_size
+	}
+
+	pub fn pallet_name(&self, pallet_index: u8) -> Option<&str> {
+		self.pallet_names.get(&pallet_index).map(|name| name.as_str())
+	}
+
+	pub fn pallet_index(&self, pallet_name: &str) -> Option<u8> {
+		self.pallet_names.iter().find(|(_, name)| *name == pallet_name).map(|(index, _)| *index)
+	}
+
+	/// Type id of a pallet's `Error` enum.
+	pub fn error_type(&self, pallet_index: u8) -> Option<u32> {
+		self.error_types.get(&pallet_index).copied()
+	}
+
+	/// Add the runtime API descriptions from V15 metadata (`Metadata_metadata_at_version(15)`).
+	/// Returns false if it's not V15 metadata.
+	pub fn set_runtime_apis(&mut self, meta: RuntimeMetadataPrefixed) -> bool {
+		if let RuntimeMetadata::V15(metadata) = meta.1 {
+			self.apis = Some(metadata);
+			true
+		} else {
+			false
+		}
+	}
+
+	/// A runtime API method (e.g. `TransactionPaymentApi` / `query_info`) and the registry its
+	/// types live in.
+	pub fn runtime_api(
+		&self,
+		api: &str,
+		method: &str,
+	) -> Option<(&RuntimeApiMethodMetadata<PortableForm>, &PortableRegistry)> {
+		let metadata = self.apis.as_ref()?;
+		let method = metadata
+			.apis
+			.iter()
+			.find(|a| a.name == api)?
+			.methods
+			.iter()
+			.find(|m| m.name == method)?;
+		Some((method, &metadata.types))
+	}
+
+	pub fn storage_entry(
+		&self,
+		pallet: &str,
+		name: &str,
+	) -> Option<&StorageEntryMetadata<PortableForm>> {
+		let (p, e) = self.storage.get(&(pallet.to_string(), name.to_string()))?;
+		self.metadata()?.pallets[*p].storage.as_ref()?.entries.get(*e)
+	}
+}
+
+/// Encoded size of a type that's always the same size (e.g. a hash), in bytes.
+fn fixed_size(types: &PortableRegistry, id: u32) -> Option<usize> {
+	match &types.resolve(id)?.type_def {
+		TypeDef::Primitive(TypeDefPrimitive::U8) => Some(1),
+		TypeDef::Array(array) => Some(array.len as usize * fixed_size(types, array.type_param.id)?),
+		TypeDef::Composite(composite) =>
+			composite.fields.iter().map(|f| fixed_size(types, f.ty.id)).sum(),
+		_ => None,
+	}
+}
+
+impl From<RuntimeMetadataPrefixed> for MetadataIndex {
+	fn from(meta: RuntimeMetadataPrefixed) -> Self {
+		MetadataIndex::new(meta)
+	}
+}
@@ -1,10 +1,12 @@
 use crate::{
+	limit::{Limiter, TOO_MANY_REQUESTS},
 	prelude::*,
-	rpc::{StateChanges, Streamable},
+	rpc::Streamable,
+	Limits, Throttling,
 };
 use core::{convert::TryInto, fmt};
 use jsonrpc::{
-	error::{standard_error, StandardError},
+	error::{standard_error, RpcError, StandardError},
 	serde_json::value::to_raw_value,
 };
 pub use surf::Url;
@@ -12,41 +14,57 @@ pub use surf::Url;
 use crate::rpc::{self, Rpc, RpcResult};
 
 #[derive(Debug)]
-pub struct Backend(Url);
+pub struct Backend {
+	url: Url,
+	limiter: Limiter,
+}
 
 impl Streamable for Backend {
-	async fn stream(
-		&self,
-		method: &str,
-		params: &str,
-	) -> async_std::channel::Receiver<StateChanges> {
-		let _result = self.rpc(method, params).await;
-		panic!("unsupported for now");
-		// let (sender, recv) = async_std::channel::unbounded();
-		// if let Ok(result_subscription) = result {
-		// 	if let Ok(result) = extract_subscription(&result_subscription) {
-		// 		self.streams.lock().await.insert(result.to_owned(), sender);
-		// 	}
-		// }
-
-		// recv
+	async fn subscribe(&self, _method: &str, _params: &str) -> crate::Result<rpc::Subscription> {
+		Err(crate::Error::Node("subscriptions need a websocket, not http".into()))
 	}
 }
 
 impl Backend {
 	pub fn new(urls: &[Url]) -> Self
 	{
-		Backend(urls[0].clone())
+		Backend { url: urls[0].clone(), limiter: Limiter::default() }
+	}
+
+	/// How many requests may be in flight or start each second, `Limits::default()` otherwise.
+	pub fn with_limits(mut self, limits: Limits) -> Self {
+		self.limiter = Limiter::new(limits);
+		self
 	}
 }
 
+/// A 429 answer as the JSON-RPC error it means.
+fn too_many_requests(body: String) -> jsonrpc::Error {
+	let message = if body.is_empty() { "Too Many Requests".into() } else { body };
+	jsonrpc::Error::Rpc(RpcError { code: TOO_MANY_REQUESTS, message, data: None })
+}
+
 // #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
 // #[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 impl Rpc for Backend {
 	/// HTTP based JSON RPC request expecting valid json result.
 	async fn rpc(&self, method: &str, params: &str) -> RpcResult {
+		self.limiter.rpc(|| self.send(method, params)).await
+	}
+
+	async fn rpc_batch(&self, requests: &[(&str, &str)]) -> Vec<RpcResult> {
+		self.limiter.rpc_batch(|| self.send_batch(requests)).await
+	}
+
+	fn throttling(&self) -> Throttling {
+		self.limiter.throttling()
+	}
+}
+
+impl Backend {
+	async fn send(&self, method: &str, params: &str) -> RpcResult {
 		#[cfg(feature = "logging")]
-		log::info!("RPC `{}` to {}", method, &self.0);
+		log::info!("RPC `{}` to {}", method, &self.url);
 		let id = 1_u32;
 		let body = format!(
 			"{{\"id\":{}, \"jsonrpc\": \"2.0\", \"method\":\"{}\", \"params\":{}}}",
@@ -54,7 +72,7 @@ impl Rpc for Backend {
 		);
 		#[cfg(feature = "logging")]
 		log::debug!("outgoing request was: `{}`", body);
-		let req = surf::post(&self.0).content_type("application/json").body(body);
+		let req = surf::post(&self.url).content_type("application/json").body(body);
 		let client = surf::client().with(surf::middleware::Redirect::new(2));
 		let mut res = client
 			.send(req)
@@ -69,43 +87,168 @@ impl Rpc for Backend {
 		log::debug!("outgoing request status: `{}`", status);
 
 		let res = if status.is_success() {
-			res.body_json::<rpc::Response>().await.map_err(|err| {
-				standard_error(StandardError::ParseError, to_raw_value(&err.to_string()).ok())
-			})?
+			let body = res.body_string().await.map_err(|err| {
+				standard_error(StandardError::InternalError, to_raw_value(&err.to_string()).ok())
+			})?;
+			serde_json::from_str::<rpc::Response>(&body)?
+		} else if status == surf::StatusCode::TooManyRequests {
+			return Err(too_many_requests(res.body_string().await.unwrap_or_default()))
 		} else {
 			#[cfg(feature = "logging")]
 			log::debug!("RPC HTTP status: {}", res.status());
 			let err = res.body_string().await.unwrap_or_else(|_| status.canonical_reason().into());
-			let err = to_raw_value(&err).expect("error string");
+			let err = to_raw_value(&err).ok();
 			#[cfg(feature = "logging")]
 			log::debug!("RPC Response: {:?}...", &res);
 
 			return Err(if status.is_client_error() {
-				standard_error(StandardError::InvalidRequest, Some(err)).into()
+				standard_error(StandardError::InvalidRequest, err).into()
 			} else {
-				standard_error(StandardError::InternalError, Some(err)).into()
+				standard_error(StandardError::InternalError, err).into()
 			})
 		};
 
-		// assume the response is a hex encoded string starting with "0x"
-		// let response = hex::decode(&res[2..])
-		// 	.map_err(|_err| standard_error(StandardError::InternalError, None))?;
-		res.result.ok_or(jsonrpc::error::Error::EmptyBatch)
+		rpc::response_result(res)
+	}
+
+	/// Sends the requests as one JSON-RPC batch (array) and matches the responses up by id.
+	async fn send_batch(&self, requests: &[(&str, &str)]) -> Vec<RpcResult> {
+		#[cfg(feature = "logging")]
+		log::info!("RPC batch of {} to {}", requests.len(), &self.url);
+		if requests.is_empty() {
+			return vec![]
+		}
+		let numbered: Vec<_> = requests
+			.iter()
+			.enumerate()
+			.map(|(id, (method, params))| (id, *method, *params))
+			.collect();
+		let body = rpc::batch_request(&numbered);
+		#[cfg(feature = "logging")]
+		log::debug!("outgoing batch was: `{}`", body);
+		let req = surf::post(&self.url).content_type("application/json").body(body);
+		let client = surf::client().with(surf::middleware::Redirect::new(2));
+
+		let responses = match client.send(req).await {
+			Ok(mut res) if res.status().is_success() =>
+				res.body_json::<Vec<rpc::Response>>().await.map_err(|err| err.to_string()),
+			Ok(mut res) if res.status() == surf::StatusCode::TooManyRequests => {
+				let body = res.body_string().await.unwrap_or_default();
+				return requests.iter().map(|_| Err(too_many_requests(body.clone()))).collect()
+			},
+			Ok(res) => Err(res.status().to_string()),
+			Err(err) => Err(err.to_string()),
+		};
+		let mut responses = match responses {
+			Ok(responses) => responses,
+			Err(err) => {
+				#[cfg(feature = "logging")]
+				log::debug!("RPC batch failed: {}", err);
+				return requests
+					.iter()
+					.map(|_| {
+						Err(standard_error(StandardError::InternalError, to_raw_value(&err).ok())
+							.into())
+					})
+					.collect()
+			},
+		};
+
+		(0..requests.len())
+			.map(|id| {
+				let pos = responses.iter().position(|res| res.id.as_u64() == Some(id as u64));
+				match pos {
+					Some(pos) => rpc::response_result(responses.swap_remove(pos)),
+					None => Err(jsonrpc::error::Error::EmptyBatch),
+				}
+			})
+			.collect()
 	}
 }
 
 #[cfg(feature = "http")]
 #[cfg(test)]
 mod tests {
-	use super::Backend;
+	use super::{Backend, Rpc};
+	use crate::{
+		mock::{transcript, Node},
+		Error,
+	};
 	use surf::Url;
 
 	fn init() {
 		let _ = env_logger::builder().is_test(true).try_init();
 	}
 
-	fn polkadot_backend() -> crate::PolkaPipe<Backend> {
-		crate::PolkaPipe::<Backend> { rpc: Backend::new(&vec![Url::parse("http://rpc.polkadot.io").unwrap()]) }
+	/// A node on a local port that answers every request with `body` (`Redirect` sends
+	/// each request twice).
+	fn scripted_node(body: &'static str) -> crate::PolkaPipe<Backend> {
+		scripted_reply("200 OK", body)
+	}
+
+	fn scripted_reply(status: &'static str, body: &'static str) -> crate::PolkaPipe<Backend> {
+		use std::io::{Read, Write};
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}", listener.local_addr().unwrap());
+		std::thread::spawn(move || {
+			while let Ok((mut stream, _)) = listener.accept() {
+				// All of it, answering part way through can reset the connection.
+				let mut request = vec![];
+				let mut buf = [0; 4096];
+				while let Ok(read @ 1..) = stream.read(&mut buf) {
+					request.extend_from_slice(&buf[..read]);
+					if request.ends_with(b"}") || request.ends_with(b"]") {
+						break
+					}
+				}
+				let headers = format!("HTTP/1.1 {}\r\nContent-Type: application/json", status);
+				let _ =
+					write!(stream, "{}\r\nContent-Length: {}\r\n\r\n{}", headers, body.len(), body);
+			}
+		});
+		crate::PolkaPipe { rpc: Backend::new(&[Url::parse(&url).unwrap()]) }
+	}
+
+	#[test]
+	fn malformed_responses_are_errors() {
+		let node = scripted_node(r#"{"jsonrpc": "2.0", "id": 1, "result": "0xzz"}"#);
+		let hash = async_std::task::block_on(node.query_block_hash(&[1]));
+		assert!(matches!(hash, Err(Error::MalformedResponse(_))));
+
+		let node = scripted_node(r#"{"jsonrpc": "2.0", "id": 1, "#);
+		let block = async_std::task::block_on(node.query_block(None));
+		assert!(matches!(block, Err(Error::MalformedResponse(_))));
+
+		let node = scripted_node(
+			r#"{"jsonrpc": "2.0", "id": 1, "error": {"code": 1010, "message": "Invalid"}}"#,
+		);
+		let submitted = async_std::task::block_on(node.submit([0]));
+		assert!(matches!(submitted, Err(Error::Rpc { code: 1010, .. })));
+	}
+
+	#[test]
+	fn retries_too_many_requests() {
+		let node = scripted_reply("429 Too Many Requests", "slow down");
+		let limits = crate::Limits {
+			retries: 2,
+			backoff: std::time::Duration::from_millis(1),
+			..Default::default()
+		};
+		let node = crate::PolkaPipe { rpc: node.rpc.with_limits(limits) };
+		let hash = async_std::task::block_on(node.query_block_hash(&[1]));
+		let slow_down = |message: &str| message == "slow down";
+		assert!(matches!(hash, Err(Error::Rpc { code: 429, ref message }) if slow_down(message)));
+		let throttling = node.rpc.throttling();
+		assert_eq!((throttling.requests, throttling.throttled, throttling.gave_up), (3, 3, 1));
+	}
+
+	/// Replays the test's transcript, see `mock::Node::replay`.
+	fn polkadot_backend() -> crate::PolkaPipe<Node<Backend>> {
+		let rpc = async_std::task::block_on(Node::replay(transcript("http"), || async {
+			Ok(Backend::new(&[Url::parse("https://rpc.polkadot.io").unwrap()]))
+		}))
+		.unwrap();
+		crate::PolkaPipe { rpc }
 	}
 	//{"id":1,"jsonrpc":"2.0","method":"state_getKeys","params":["1234"]}
 	//websocat wss://statemint-rpc-tn.dwellir.com
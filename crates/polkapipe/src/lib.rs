@@ -32,6 +32,12 @@ Creating a client is as simple as instantiating a backend and converting it to a
   Enables the websocket implementation that works in the browser.
 * **smoldot-std**
   Uses light client to answer rpc requests
+* **mock**
+  A backend that replays recorded transcripts and a recorder to make them, for offline tests
+* **metrics**
+  Observers of each call a backend makes, e.g. latency histograms, errors and bytes per method
+* **proof**
+  Verifies the storage values of a read proof (`state_getReadProof`) against a state root
 */
 
 #[macro_use]
@@ -63,7 +69,21 @@ pub mod smoldot_std;
 #[cfg(all(feature = "ws-web", target_arch = "wasm32"))]
 pub mod ws_web;
 
+/// Transcript replaying backend and recorder
+#[cfg(any(feature = "mock", all(test, feature = "std", feature = "async-std")))]
+pub mod mock;
+
+/// Per method latency, errors and bytes of calls to the node
+#[cfg(feature = "metrics")]
+pub mod metrics;
+
+/// Checking storage read proofs against a block's state root
+#[cfg(feature = "proof")]
+pub mod proof;
+
+mod limit;
 mod rpc;
+mod rpc_v1;
 
 #[derive(Clone, Debug)]
 pub enum Error {
@@ -71,6 +91,12 @@ pub enum Error {
 	BadInput,
 	BadKey,
 	Node(String),
+	/// The node answered with a JSON-RPC error.
+	Rpc { code: i32, message: String },
+	/// The node's answer wasn't what we asked for (not JSON, not hex...).
+	MalformedResponse(String),
+	/// The node didn't answer in time.
+	Timeout,
 	ParseStorageItem,
 	StorageKeyNotFound,
 }
@@ -79,11 +105,24 @@ impl fmt::Display for Error {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		match self {
 			Self::Node(e) => write!(f, "{:}", e),
+			Self::Rpc { code, message } => write!(f, "RPC error {}: {}", code, message),
+			Self::MalformedResponse(response) => write!(f, "Malformed response: {}", response),
 			_ => write!(f, "{:?}", self),
 		}
 	}
 }
 
+impl From<jsonrpc::Error> for Error {
+	fn from(err: jsonrpc::Error) -> Self {
+		match err {
+			jsonrpc::Error::Rpc(err) => Error::Rpc { code: err.code, message: err.message },
+			jsonrpc::Error::Transport(err) if err.is::<rpc::TimedOut>() => Error::Timeout,
+			jsonrpc::Error::Json(err) => Error::MalformedResponse(err.to_string()),
+			err => Error::Node(err.to_string()),
+		}
+	}
+}
+
 #[cfg(all(feature = "ws", not(target_arch = "wasm32")))]
 impl From<async_tungstenite::tungstenite::Error> for Error {
 	fn from(_err: async_tungstenite::tungstenite::Error) -> Self {
@@ -94,4 +133,6 @@ impl From<async_tungstenite::tungstenite::Error> for Error {
 #[cfg(feature = "std")]
 impl std::error::Error for Error {}
 
-pub use rpc::PolkaPipe;
+pub use limit::{is_too_many_requests, Limits, Throttling};
+pub use rpc::{PolkaPipe, ReadProof, StateChanges, StorageKeys, TransactionStatus};
+pub use rpc_v1::{ChainHead, FollowEvent};
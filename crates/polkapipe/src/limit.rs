@@ -0,0 +1,325 @@
+//! Keeping to what public nodes put up with: a cap on the requests in flight and on the
+//! requests started per second, and retrying with backoff when the node says there have been
+//! too many.
+#[cfg(not(target_arch = "wasm32"))]
+extern crate std;
+
+use crate::rpc::{error, OnDrop, RpcResult};
+use async_std::channel;
+use core::{
+	future::Future,
+	sync::atomic::{AtomicU64, Ordering},
+	time::Duration,
+};
+
+/// How hard a backend may use its node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+	/// Requests waiting for an answer at once (a batch counts as one).
+	pub max_in_flight: usize,
+	/// Requests started per second, `None` for as many as we like.
+	pub requests_per_second: Option<u32>,
+	/// How many times to retry a request the node turned away for being one too many.
+	pub retries: u32,
+	/// How long to wait before the first retry, it doubles for each one after.
+	pub backoff: Duration,
+}
+
+impl Default for Limits {
+	fn default() -> Self {
+		Limits {
+			max_in_flight: 32,
+			requests_per_second: None,
+			retries: 5,
+			backoff: Duration::from_millis(500),
+		}
+	}
+}
+
+/// How much a backend has been held back, e.g. to show that a chain is being throttled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Throttling {
+	/// Requests sent, including retries.
+	pub requests: u64,
+	/// Requests that had to wait to keep within the limits.
+	pub delayed: u64,
+	/// Answers saying there have been too many requests.
+	pub throttled: u64,
+	/// Requests still turned away after all the retries.
+	pub gave_up: u64,
+	pub in_flight: usize,
+}
+
+/// What an HTTP 429 answer becomes, as a JSON-RPC error code.
+pub(crate) const TOO_MANY_REQUESTS: i32 = 429;
+
+/// Whether the node turned the request away for being one too many (HTTP 429 or a JSON-RPC
+/// "too many requests" error).
+pub fn is_too_many_requests(err: &error::Error) -> bool {
+	match err {
+		error::Error::Rpc(err) => {
+			let message = err.message.to_lowercase();
+			err.code == TOO_MANY_REQUESTS ||
+				message.contains("too many requests") ||
+				message.contains("rate limit")
+		},
+		_ => false,
+	}
+}
+
+#[derive(Debug)]
+pub(crate) struct Limiter {
+	limits: Limits,
+	// Holds a message for each request in flight.
+	in_flight: (channel::Sender<()>, channel::Receiver<()>),
+	// When the next request may start, in microseconds.
+	next_start: AtomicU64,
+	started: Stopwatch,
+	requests: AtomicU64,
+	delayed: AtomicU64,
+	throttled: AtomicU64,
+	gave_up: AtomicU64,
+}
+
+impl Default for Limiter {
+	fn default() -> Self {
+		Limiter::new(Limits::default())
+	}
+}
+
+impl Limiter {
+	pub fn new(limits: Limits) -> Self {
+		Limiter {
+			limits,
+			in_flight: channel::bounded(limits.max_in_flight.max(1)),
+			next_start: AtomicU64::new(0),
+			started: Stopwatch::start(),
+			requests: AtomicU64::new(0),
+			delayed: AtomicU64::new(0),
+			throttled: AtomicU64::new(0),
+			gave_up: AtomicU64::new(0),
+		}
+	}
+
+	pub fn throttling(&self) -> Throttling {
+		Throttling {
+			requests: self.requests.load(Ordering::Relaxed),
+			delayed: self.delayed.load(Ordering::Relaxed),
+			throttled: self.throttled.load(Ordering::Relaxed),
+			gave_up: self.gave_up.load(Ordering::Relaxed),
+			in_flight: self.in_flight.0.len(),
+		}
+	}
+
+	/// Make the request within the limits, again (after a while) for as long as
+	/// `too_many` says the node turned it away.
+	pub async fn run<T, F, R, M>(&self, mut request: R, too_many: M) -> T
+	where
+		R: FnMut() -> F,
+		F: Future<Output = T>,
+		M: Fn(&T) -> bool,
+	{
+		let mut backoff = self.limits.backoff;
+		let mut retries = 0;
+		loop {
+			let res = {
+				let _in_flight = self.start().await;
+				request().await
+			};
+			if !too_many(&res) {
+				return res
+			}
+			self.throttled.fetch_add(1, Ordering::Relaxed);
+			if retries == self.limits.retries {
+				self.gave_up.fetch_add(1, Ordering::Relaxed);
+				return res
+			}
+			#[cfg(feature = "logging")]
+			log::debug!("Too many requests, trying again in {:?}", backoff);
+			sleep(backoff).await;
+			backoff *= 2;
+			retries += 1;
+		}
+	}
+
+	/// A request, retried while the node answers with too many requests.
+	pub async fn rpc<F>(&self, request: impl FnMut() -> F) -> RpcResult
+	where
+		F: Future<Output = RpcResult>,
+	{
+		self.run(request, |res| matches!(res, Err(err) if is_too_many_requests(err))).await
+	}
+
+	/// A batch, all of it retried while the node answers any of it with too many requests.
+	pub async fn rpc_batch<F>(&self, batch: impl FnMut() -> F) -> Vec<RpcResult>
+	where
+		F: Future<Output = Vec<RpcResult>>,
+	{
+		let too_many = |results: &Vec<RpcResult>| {
+			results.iter().any(|res| matches!(res, Err(err) if is_too_many_requests(err)))
+		};
+		self.run(batch, too_many).await
+	}
+
+	/// Wait for a place in flight (held until what's returned is dropped) and a time slot.
+	async fn start(&self) -> OnDrop<impl FnMut() + '_> {
+		let mut delayed = false;
+		if self.in_flight.0.try_send(()).is_err() {
+			delayed = true;
+			let _ = self.in_flight.0.send(()).await;
+		}
+		if let Some(per_second) = self.limits.requests_per_second {
+			let interval = 1_000_000 / per_second.max(1) as u64;
+			let now = self.now();
+			let start = self
+				.next_start
+				.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| {
+					Some(next.max(now) + interval)
+				})
+				.unwrap_or(now)
+				.max(now);
+			if start > now {
+				delayed = true;
+				sleep(Duration::from_micros(start - now)).await;
+			}
+		}
+		if delayed {
+			self.delayed.fetch_add(1, Ordering::Relaxed);
+		}
+		self.requests.fetch_add(1, Ordering::Relaxed);
+		let in_flight = &self.in_flight.1;
+		OnDrop(move || {
+			let _ = in_flight.try_recv();
+		})
+	}
+
+	/// Microseconds since the limiter was made.
+	fn now(&self) -> u64 {
+		self.started.elapsed().as_micros() as u64
+	}
+}
+
+/// Time since it was started, natively and in the browser.
+#[derive(Debug)]
+pub(crate) struct Stopwatch {
+	#[cfg(not(target_arch = "wasm32"))]
+	started: std::time::Instant,
+	// Milliseconds since the epoch.
+	#[cfg(target_arch = "wasm32")]
+	started: f64,
+}
+
+impl Stopwatch {
+	pub fn start() -> Self {
+		#[cfg(not(target_arch = "wasm32"))]
+		let started = std::time::Instant::now();
+		#[cfg(target_arch = "wasm32")]
+		let started = js_sys::Date::now();
+		Stopwatch { started }
+	}
+
+	pub fn elapsed(&self) -> Duration {
+		#[cfg(not(target_arch = "wasm32"))]
+		let elapsed = self.started.elapsed();
+		#[cfg(target_arch = "wasm32")]
+		let elapsed = Duration::from_secs_f64((js_sys::Date::now() - self.started).max(0.) / 1000.);
+		elapsed
+	}
+}
+
+pub(crate) async fn sleep(duration: Duration) {
+	#[cfg(target_arch = "wasm32")]
+	gloo_timers::future::sleep(duration).await;
+	#[cfg(not(target_arch = "wasm32"))]
+	async_std::task::sleep(duration).await;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use jsonrpc::error::RpcError;
+
+	fn too_many(res: &Result<(), error::Error>) -> bool {
+		matches!(res, Err(err) if is_too_many_requests(err))
+	}
+
+	#[test]
+	fn knows_too_many_requests() {
+		let rpc_error = |code, message: &str| {
+			error::Error::Rpc(RpcError { code, message: message.into(), data: None })
+		};
+		assert!(is_too_many_requests(&rpc_error(TOO_MANY_REQUESTS, "")));
+		assert!(is_too_many_requests(&rpc_error(-32999, "Too Many Requests")));
+		assert!(is_too_many_requests(&rpc_error(-32000, "RPC rate limit exceeded")));
+		assert!(!is_too_many_requests(&rpc_error(-32602, "Invalid params")));
+		assert!(!is_too_many_requests(&error::Error::EmptyBatch));
+	}
+
+	#[async_std::test]
+	async fn retries_with_backoff() {
+		let limiter = Limiter::new(Limits {
+			retries: 2,
+			backoff: Duration::from_millis(1),
+			..Limits::default()
+		});
+		let turned_away = || async {
+			Err::<(), _>(error::Error::Rpc(RpcError {
+				code: TOO_MANY_REQUESTS,
+				message: "Too Many Requests".into(),
+				data: None,
+			}))
+		};
+		assert!(limiter.run(turned_away, too_many).await.is_err());
+		let throttling = limiter.throttling();
+		assert_eq!((throttling.requests, throttling.throttled, throttling.gave_up), (3, 3, 1));
+
+		let mut answers = vec![Ok(()), Err(error::Error::Rpc(RpcError {
+			code: TOO_MANY_REQUESTS,
+			message: String::new(),
+			data: None,
+		}))];
+		let res = limiter.run(|| core::future::ready(answers.pop().unwrap()), too_many).await;
+		assert!(res.is_ok());
+		assert_eq!(limiter.throttling().requests, 5);
+		assert_eq!(limiter.throttling().gave_up, 1);
+	}
+
+	#[async_std::test]
+	async fn keeps_within_the_limits() {
+		use alloc::sync::Arc;
+		use core::sync::atomic::AtomicUsize;
+		let limiter = Arc::new(Limiter::new(Limits {
+			max_in_flight: 2,
+			requests_per_second: Some(100),
+			..Limits::default()
+		}));
+		let started = std::time::Instant::now();
+		let in_flight = Arc::new(AtomicUsize::new(0));
+		let most_in_flight = Arc::new(AtomicUsize::new(0));
+		let requests: Vec<_> = (0..6)
+			.map(|_| {
+				let (limiter, in_flight, most_in_flight) =
+					(limiter.clone(), in_flight.clone(), most_in_flight.clone());
+				async_std::task::spawn(async move {
+					let request = || async {
+						let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+						most_in_flight.fetch_max(now, Ordering::SeqCst);
+						sleep(Duration::from_millis(5)).await;
+						in_flight.fetch_sub(1, Ordering::SeqCst);
+						Ok(())
+					};
+					limiter.run(request, too_many).await
+				})
+			})
+			.collect();
+		for request in requests {
+			assert!(request.await.is_ok());
+		}
+		assert!(most_in_flight.load(Ordering::SeqCst) <= 2);
+		// Six starts, 10ms apart.
+		assert!(started.elapsed() >= Duration::from_millis(50));
+		let throttling = limiter.throttling();
+		assert_eq!((throttling.requests, throttling.in_flight), (6, 0));
+		assert!(throttling.delayed >= 4);
+	}
+}
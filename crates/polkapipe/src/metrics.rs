@@ -0,0 +1,289 @@
+//! Instrumentation: wrap a backend in `Observed` and an `Observer` hears about every call it
+//! makes, e.g. `Metrics` keeps latency histograms, error counts and bytes per method.
+extern crate std;
+
+use crate::{
+	limit::Stopwatch,
+	prelude::*,
+	rpc::{Rpc, RpcResult, Streamable, Subscription},
+	Throttling,
+};
+use alloc::{collections::BTreeMap, sync::Arc};
+use core::{fmt, time::Duration};
+use std::sync::Mutex;
+
+/// One call to the node, once it has been answered (or not).
+pub struct Call<'a> {
+	pub method: &'a str,
+	pub latency: Duration,
+	/// Method name and params.
+	pub bytes_sent: usize,
+	/// The result, not counting notifications of a subscription.
+	pub bytes_received: usize,
+	pub error: Option<&'a dyn fmt::Display>,
+}
+
+/// Hears about each call a backend wrapped in `Observed` makes.
+pub trait Observer {
+	fn observe(&self, call: &Call<'_>);
+}
+
+impl<O: Observer + ?Sized> Observer for Arc<O> {
+	fn observe(&self, call: &Call<'_>) {
+		(**self).observe(call)
+	}
+}
+
+impl<A: Observer, B: Observer> Observer for (A, B) {
+	fn observe(&self, call: &Call<'_>) {
+		self.0.observe(call);
+		self.1.observe(call);
+	}
+}
+
+/// Logs each call with how long it took.
+#[cfg(feature = "logging")]
+pub struct Log;
+
+#[cfg(feature = "logging")]
+impl Observer for Log {
+	fn observe(&self, call: &Call<'_>) {
+		match call.error {
+			None => log::debug!(
+				"`{}` took {:?} ({} bytes out, {} in)",
+				call.method,
+				call.latency,
+				call.bytes_sent,
+				call.bytes_received
+			),
+			Some(err) => log::debug!("`{}` failed after {:?}: {}", call.method, call.latency, err),
+		}
+	}
+}
+
+/// A backend whose calls are told to an `Observer`.
+pub struct Observed<R, O> {
+	inner: R,
+	observer: O,
+}
+
+impl<R, O: Observer> Observed<R, O> {
+	pub fn new(inner: R, observer: O) -> Self {
+		Observed { inner, observer }
+	}
+
+	pub fn observer(&self) -> &O {
+		&self.observer
+	}
+
+	pub fn inner(&self) -> &R {
+		&self.inner
+	}
+}
+
+impl<R: Rpc, O: Observer> Rpc for Observed<R, O> {
+	async fn rpc(&self, method: &str, params: &str) -> RpcResult {
+		let stopwatch = Stopwatch::start();
+		let res = self.inner.rpc(method, params).await;
+		self.observer.observe(&Call {
+			method,
+			latency: stopwatch.elapsed(),
+			bytes_sent: method.len() + params.len(),
+			bytes_received: res.as_ref().map_or(0, |result| result.get().len()),
+			error: res.as_ref().err().map(|err| err as &dyn fmt::Display),
+		});
+		res
+	}
+
+	/// Each request of the batch is observed as taking as long as the whole batch.
+	async fn rpc_batch(&self, requests: &[(&str, &str)]) -> Vec<RpcResult> {
+		let stopwatch = Stopwatch::start();
+		let results = self.inner.rpc_batch(requests).await;
+		let latency = stopwatch.elapsed();
+		for ((method, params), res) in requests.iter().zip(&results) {
+			self.observer.observe(&Call {
+				method,
+				latency,
+				bytes_sent: method.len() + params.len(),
+				bytes_received: res.as_ref().map_or(0, |result| result.get().len()),
+				error: res.as_ref().err().map(|err| err as &dyn fmt::Display),
+			});
+		}
+		results
+	}
+
+	fn throttling(&self) -> Throttling {
+		self.inner.throttling()
+	}
+}
+
+impl<R: Streamable, O: Observer> Streamable for Observed<R, O> {
+	async fn subscribe(&self, method: &str, params: &str) -> crate::Result<Subscription> {
+		let stopwatch = Stopwatch::start();
+		let subscription = self.inner.subscribe(method, params).await;
+		self.observer.observe(&Call {
+			method,
+			latency: stopwatch.elapsed(),
+			bytes_sent: method.len() + params.len(),
+			bytes_received: subscription.as_ref().map_or(0, |subscription| subscription.id.len()),
+			error: subscription.as_ref().err().map(|err| err as &dyn fmt::Display),
+		});
+		subscription
+	}
+}
+
+/// Upper bounds of the latency buckets, there's one more for anything slower.
+pub const LATENCY_BUCKETS_MS: [u64; 8] = [10, 50, 100, 250, 500, 1_000, 2_500, 5_000];
+
+/// How long calls took, in `LATENCY_BUCKETS_MS`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Histogram {
+	pub counts: [u64; LATENCY_BUCKETS_MS.len() + 1],
+	pub total: Duration,
+	pub max: Duration,
+}
+
+impl Histogram {
+	pub fn record(&mut self, latency: Duration) {
+		let bucket = LATENCY_BUCKETS_MS
+			.iter()
+			.position(|ms| latency <= Duration::from_millis(*ms))
+			.unwrap_or(LATENCY_BUCKETS_MS.len());
+		self.counts[bucket] += 1;
+		self.total += latency;
+		self.max = self.max.max(latency);
+	}
+
+	/// Add in the calls `other` has seen, e.g. to sum up several methods.
+	pub fn merge(&mut self, other: &Histogram) {
+		for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+			*count += other;
+		}
+		self.total += other.total;
+		self.max = self.max.max(other.max);
+	}
+
+	pub fn count(&self) -> u64 {
+		self.counts.iter().sum()
+	}
+
+	pub fn mean(&self) -> Option<Duration> {
+		match self.count() {
+			0 => None,
+			count => Some(self.total / count as u32),
+		}
+	}
+
+	/// The bucket bound that `quantile` (e.g. 0.95) of calls were quicker than, or the
+	/// slowest call if that's beyond the last bucket.
+	pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+		let count = self.count();
+		if count == 0 {
+			return None
+		}
+		let wanted = ((count as f64 * quantile).ceil() as u64).clamp(1, count);
+		let mut seen = 0;
+		for (bucket, n) in self.counts.iter().enumerate() {
+			seen += n;
+			if seen >= wanted {
+				return Some(match LATENCY_BUCKETS_MS.get(bucket) {
+					Some(ms) => Duration::from_millis(*ms).min(self.max),
+					None => self.max,
+				})
+			}
+		}
+		Some(self.max)
+	}
+}
+
+/// What the calls to one method came to.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MethodMetrics {
+	pub calls: u64,
+	pub errors: u64,
+	pub bytes_sent: u64,
+	pub bytes_received: u64,
+	pub latency: Histogram,
+}
+
+/// Keeps `MethodMetrics` for each method called.
+#[derive(Debug, Default)]
+pub struct Metrics {
+	methods: Mutex<BTreeMap<String, MethodMetrics>>,
+}
+
+impl Metrics {
+	/// The metrics so far, by method.
+	pub fn snapshot(&self) -> BTreeMap<String, MethodMetrics> {
+		self.methods.lock().unwrap_or_else(|e| e.into_inner()).clone()
+	}
+}
+
+impl Observer for Metrics {
+	fn observe(&self, call: &Call<'_>) {
+		let mut methods = self.methods.lock().unwrap_or_else(|e| e.into_inner());
+		let metrics = match methods.get_mut(call.method) {
+			Some(metrics) => metrics,
+			None => methods.entry(call.method.to_string()).or_default(),
+		};
+		metrics.calls += 1;
+		if call.error.is_some() {
+			metrics.errors += 1;
+		}
+		metrics.bytes_sent += call.bytes_sent as u64;
+		metrics.bytes_received += call.bytes_received as u64;
+		metrics.latency.record(call.latency);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[cfg(feature = "mock")]
+	use crate::{mock, PolkaPipe};
+
+	#[test]
+	fn histogram_quantiles() {
+		let mut histogram = Histogram::default();
+		assert_eq!(histogram.mean(), None);
+		for ms in [5, 20, 30, 40, 200, 7_000] {
+			histogram.record(Duration::from_millis(ms));
+		}
+		assert_eq!(histogram.counts, [1, 3, 0, 1, 0, 0, 0, 0, 1]);
+		assert_eq!(histogram.mean(), Some(Duration::from_millis(7_295) / 6));
+		assert_eq!(histogram.quantile(0.5), Some(Duration::from_millis(50)));
+		assert_eq!(histogram.quantile(0.8), Some(Duration::from_millis(250)));
+		assert_eq!(histogram.quantile(1.), Some(Duration::from_millis(7_000)));
+
+		let mut merged = Histogram::default();
+		merged.record(Duration::from_millis(1));
+		merged.merge(&histogram);
+		assert_eq!(merged.counts, [2, 3, 0, 1, 0, 0, 0, 0, 1]);
+		assert_eq!(merged.max, Duration::from_millis(7_000));
+	}
+
+	#[cfg(feature = "mock")]
+	#[async_std::test]
+	async fn metrics_by_method() {
+		let transcript = mock::Transcript::from_json(concat!(
+			r#"[{"method":"chain_getBlockHash","params":[1],"response":{"result":"0x01"}},"#,
+			r#"{"method":"state_getStorage","params":["0x00"],"#,
+			r#""response":{"error":{"code":-32602,"message":"Invalid params"}}}]"#,
+		))
+		.unwrap();
+		let metrics = Arc::new(Metrics::default());
+		let rpc = Observed::new(mock::Backend::new(transcript), metrics.clone());
+		let node = PolkaPipe { rpc };
+		assert!(node.query_block_hash(&[1]).await.is_ok());
+		assert!(node.query_block_hash(&[1]).await.is_ok());
+		assert!(node.query_storage(&[0], None).await.is_err());
+
+		let metrics = metrics.snapshot();
+		let hashes = &metrics["chain_getBlockHash"];
+		assert_eq!((hashes.calls, hashes.errors, hashes.latency.count()), (2, 0, 2));
+		assert_eq!(hashes.bytes_received, 2 * "\"0x01\"".len() as u64);
+		assert_eq!(hashes.bytes_sent, 2 * ("chain_getBlockHash".len() + "[1]".len()) as u64);
+		let storage = &metrics["state_getStorage"];
+		assert_eq!((storage.calls, storage.errors, storage.bytes_received), (1, 1, 0));
+	}
+}
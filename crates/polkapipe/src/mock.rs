@@ -0,0 +1,514 @@
+//! Stand-ins for a node so that tests can run offline. `Backend` answers from a transcript
+//! of requests and responses, `Recorder` writes one while talking to a real node and `Node`
+//! does whichever is needed, so a test only needs the network the first time it's run.
+use crate::{
+	prelude::*,
+	rpc::{self, Rpc, RpcResult, Streamable, Subscription, Subscriptions},
+};
+use alloc::sync::Arc;
+use core::future::Future;
+use jsonrpc::error::{Error as RpcError, RpcError as ErrorObject};
+use serde_json::{json, Value};
+use std::{
+	path::{Path, PathBuf},
+	sync::Mutex,
+};
+
+/// A request and what the node said to it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Exchange {
+	pub method: String,
+	pub params: Value,
+	/// `{"result": ..}` or `{"error": {"code": .., "message": ..}}`.
+	pub response: Value,
+	/// The `result` of each notification, if it was a subscription.
+	pub notifications: Vec<Value>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Transcript(pub Vec<Exchange>);
+
+impl Transcript {
+	pub fn from_json(json: &str) -> crate::Result<Self> {
+		let exchanges: Vec<Value> = serde_json::from_str(json).map_err(|_| crate::Error::BadInput)?;
+		exchanges
+			.into_iter()
+			.map(|exchange| {
+				Some(Exchange {
+					method: exchange.get("method")?.as_str()?.to_string(),
+					params: exchange.get("params")?.clone(),
+					response: exchange.get("response")?.clone(),
+					notifications: match exchange.get("notifications") {
+						Some(notifications) => notifications.as_array()?.clone(),
+						None => vec![],
+					},
+				})
+			})
+			.collect::<Option<Vec<_>>>()
+			.map(Transcript)
+			.ok_or(crate::Error::BadInput)
+	}
+
+	/// One exchange per line so that changes to a transcript diff well.
+	pub fn to_json(&self) -> String {
+		let exchanges: Vec<String> = self
+			.0
+			.iter()
+			.map(|exchange| {
+				json!({
+					"method": exchange.method,
+					"params": exchange.params,
+					"response": exchange.response,
+					"notifications": exchange.notifications,
+				})
+				.to_string()
+			})
+			.collect();
+		format!("[\n{}\n]\n", exchanges.join(",\n"))
+	}
+}
+
+/// Params as JSON so that they compare regardless of whitespace.
+fn params(params: &str) -> Value {
+	serde_json::from_str(params).unwrap_or_else(|_| Value::String(params.to_string()))
+}
+
+fn error(code: i32, message: String) -> Value {
+	json!({ "error": { "code": code, "message": message } })
+}
+
+fn response(res: &RpcResult) -> Value {
+	match res {
+		Ok(result) => json!({ "result": rpc::to_value(result).unwrap_or(Value::Null) }),
+		Err(RpcError::Rpc(err)) => error(err.code, err.message.clone()),
+		Err(err) => error(-32603, err.to_string()),
+	}
+}
+
+fn answer(response: &Value) -> RpcResult {
+	match response.get("result") {
+		Some(result) => Ok(serde_json::value::to_raw_value(result)?),
+		None => Err(RpcError::Rpc(ErrorObject {
+			code: response["error"]["code"].as_i64().unwrap_or(-32603) as i32,
+			message: response["error"]["message"].as_str().unwrap_or_default().to_string(),
+			data: None,
+		})),
+	}
+}
+
+/// Answers from a transcript rather than a node. A request gets the first answer recorded
+/// for it that hasn't been given yet, or the last one once they all have.
+pub struct Backend {
+	// Whether it's been given yet, and the exchange.
+	exchanges: Mutex<Vec<(bool, Exchange)>>,
+}
+
+impl Backend {
+	pub fn new(transcript: Transcript) -> Self {
+		let exchanges = transcript.0.into_iter().map(|exchange| (false, exchange)).collect();
+		Backend { exchanges: Mutex::new(exchanges) }
+	}
+
+	fn exchange(&self, method: &str, params_: &str) -> core::result::Result<Exchange, RpcError> {
+		let params = params(params_);
+		let mut exchanges = self.exchanges.lock().unwrap_or_else(|e| e.into_inner());
+		let matching: Vec<usize> = (0..exchanges.len())
+			.filter(|i| exchanges[*i].1.method == method && exchanges[*i].1.params == params)
+			.collect();
+		let index = matching
+			.iter()
+			.find(|i| !exchanges[**i].0)
+			.or_else(|| matching.last())
+			.ok_or_else(|| {
+				RpcError::Rpc(ErrorObject {
+					code: -32601,
+					message: format!("nothing recorded for {} {}", method, params_),
+					data: None,
+				})
+			})?;
+		let (given, exchange) = &mut exchanges[*index];
+		*given = true;
+		Ok(exchange.clone())
+	}
+}
+
+impl Rpc for Backend {
+	async fn rpc(&self, method: &str, params: &str) -> RpcResult {
+		answer(&self.exchange(method, params)?.response)
+	}
+}
+
+impl Streamable for Backend {
+	async fn subscribe(&self, method: &str, params: &str) -> crate::Result<Subscription> {
+		let exchange = self.exchange(method, params)?;
+		let res = answer(&exchange.response)?;
+		let id = rpc::extract_subscription(&res)?;
+		// The notifications are all there is, so the subscription ends once they've been
+		// received. Unsubscribing has nowhere to go.
+		let mut streams = Subscriptions::default();
+		let subscription = streams.add(method, id, async_std::channel::unbounded().0);
+		for result in exchange.notifications {
+			streams.notify(json!({ "params": { "subscription": id, "result": result } }));
+		}
+		Ok(subscription)
+	}
+}
+
+/// Passes everything on to a real backend and keeps a transcript of it.
+pub struct Recorder<R> {
+	inner: R,
+	exchanges: Arc<Mutex<Vec<Exchange>>>,
+	/// Where the transcript is written when the recorder is dropped.
+	path: Option<PathBuf>,
+}
+
+impl<R> Recorder<R> {
+	pub fn new(inner: R) -> Self {
+		Recorder { inner, exchanges: Arc::default(), path: None }
+	}
+
+	/// Write the transcript to `path` when dropped.
+	pub fn saving_to(mut self, path: impl Into<PathBuf>) -> Self {
+		self.path = Some(path.into());
+		self
+	}
+
+	pub fn transcript(&self) -> Transcript {
+		Transcript(self.exchanges.lock().unwrap_or_else(|e| e.into_inner()).clone())
+	}
+
+	/// Returns where it's been recorded.
+	fn record(&self, method: &str, params_: &str, response: Value) -> usize {
+		let mut exchanges = self.exchanges.lock().unwrap_or_else(|e| e.into_inner());
+		exchanges.push(Exchange {
+			method: method.to_string(),
+			params: params(params_),
+			response,
+			notifications: vec![],
+		});
+		exchanges.len() - 1
+	}
+}
+
+impl<R> Drop for Recorder<R> {
+	fn drop(&mut self) {
+		// A test that failed part way through would leave a transcript that's missing answers.
+		if std::thread::panicking() {
+			return
+		}
+		if let Some(path) = &self.path {
+			if let Some(dir) = path.parent() {
+				let _ = std::fs::create_dir_all(dir);
+			}
+			if let Err(_e) = std::fs::write(path, self.transcript().to_json()) {
+				#[cfg(feature = "logging")]
+				log::warn!("Couldn't save transcript to {}: {}", path.display(), _e);
+			}
+		}
+	}
+}
+
+impl<R: Rpc> Rpc for Recorder<R> {
+	async fn rpc(&self, method: &str, params: &str) -> RpcResult {
+		let res = self.inner.rpc(method, params).await;
+		self.record(method, params, response(&res));
+		res
+	}
+
+	async fn rpc_batch(&self, requests: &[(&str, &str)]) -> Vec<RpcResult> {
+		let results = self.inner.rpc_batch(requests).await;
+		for ((method, params), res) in requests.iter().zip(&results) {
+			self.record(method, params, response(res));
+		}
+		results
+	}
+
+	fn throttling(&self) -> crate::Throttling {
+		self.inner.throttling()
+	}
+}
+
+impl<R: Streamable> Streamable for Recorder<R> {
+	async fn subscribe(&self, method: &str, params: &str) -> crate::Result<Subscription> {
+		let subscription = self.inner.subscribe(method, params).await;
+		let recorded = match &subscription {
+			Ok(subscription) => json!({ "result": subscription.id }),
+			Err(crate::Error::Rpc { code, message }) => error(*code, message.clone()),
+			Err(err) => error(-32603, err.to_string()),
+		};
+		let index = self.record(method, params, recorded);
+		let exchanges = self.exchanges.clone();
+		Ok(subscription?.tap(move |result| {
+			if let Some(exchange) =
+				exchanges.lock().unwrap_or_else(|e| e.into_inner()).get_mut(index)
+			{
+				exchange.notifications.push(result.clone());
+			}
+		}))
+	}
+}
+
+/// Replays the transcript at `path` if there is one, otherwise talks to the real node and
+/// records a transcript there.
+pub enum Node<R> {
+	Replay(Backend),
+	Record(Recorder<R>),
+}
+
+impl<R> Node<R> {
+	/// `connect` to the real node is only called if there's no transcript yet.
+	pub async fn new<F>(path: impl AsRef<Path>, connect: impl FnOnce() -> F) -> crate::Result<Self>
+	where
+		F: Future<Output = crate::Result<R>>,
+	{
+		let path = path.as_ref();
+		match std::fs::read_to_string(path) {
+			Ok(json) => Ok(Node::Replay(Backend::new(Transcript::from_json(&json)?))),
+			Err(_) => Ok(Node::Record(Recorder::new(connect().await?).saving_to(path))),
+		}
+	}
+
+	/// Like `new` for a transcript that's checked in: it being missing is an error unless
+	/// `RECORD_TRANSCRIPTS` is set, so that tests don't quietly go to the network.
+	pub async fn replay<F>(
+		path: impl AsRef<Path>,
+		connect: impl FnOnce() -> F,
+	) -> crate::Result<Self>
+	where
+		F: Future<Output = crate::Result<R>>,
+	{
+		let path = path.as_ref();
+		if !path.exists() && std::env::var_os("RECORD_TRANSCRIPTS").is_none() {
+			let message = format!(
+				"no transcript at {}; run with RECORD_TRANSCRIPTS=1 to record it",
+				path.display()
+			);
+			return Err(crate::Error::Node(message))
+		}
+		Node::new(path, connect).await
+	}
+}
+
+/// Where the running test's checked in transcript for `backend` is:
+/// `tests/transcripts/<backend>_<test>.json`.
+#[cfg(test)]
+pub(crate) fn transcript(backend: &str) -> PathBuf {
+	let thread = std::thread::current();
+	let test = thread.name().unwrap_or("main").rsplit("::").next().unwrap_or_default();
+	format!("{}/tests/transcripts/{}_{}.json", env!("CARGO_MANIFEST_DIR"), backend, test).into()
+}
+
+impl<R: Rpc> Rpc for Node<R> {
+	async fn rpc(&self, method: &str, params: &str) -> RpcResult {
+		match self {
+			Node::Replay(backend) => backend.rpc(method, params).await,
+			Node::Record(recorder) => recorder.rpc(method, params).await,
+		}
+	}
+
+	async fn rpc_batch(&self, requests: &[(&str, &str)]) -> Vec<RpcResult> {
+		match self {
+			Node::Replay(backend) => backend.rpc_batch(requests).await,
+			Node::Record(recorder) => recorder.rpc_batch(requests).await,
+		}
+	}
+
+	fn throttling(&self) -> crate::Throttling {
+		match self {
+			Node::Replay(backend) => backend.throttling(),
+			Node::Record(recorder) => recorder.throttling(),
+		}
+	}
+}
+
+impl<R: Streamable> Streamable for Node<R> {
+	async fn subscribe(&self, method: &str, params: &str) -> crate::Result<Subscription> {
+		match self {
+			Node::Replay(backend) => backend.subscribe(method, params).await,
+			Node::Record(recorder) => recorder.subscribe(method, params).await,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::rpc::PolkaPipe;
+	use async_std::stream::{Stream, StreamExt};
+
+	const POLKADOT: &str = r#"[
+{"method":"chain_getBlockHash","params":[1],"response":{"result":"0xc0096358534ec8d21d01d34b836eed476a1c343f8724fa2153dc0725ad797a90"}},
+{"method":"state_getStorage","params":["0x1234"],"response":{"result":"0xe8030000"}},
+{"method":"state_getStorage","params":["0x1234"],"response":{"result":"0xd0070000"}},
+{"method":"state_getStorage","params":["0x5678"],"response":{"error":{"code":-32602,"message":"Invalid params"}}},
+{"method":"state_subscribeStorage","params":[["1234"]],"response":{"result":"sub1"},"notifications":[
+	{"block":"0xe33568bff8e6f30fee6f217a93523a6b29c31c8fe94c076d818b97b97cfd3a16","changes":[["0x1234","0xe8030000"]]},
+	{"block":"0xc0096358534ec8d21d01d34b836eed476a1c343f8724fa2153dc0725ad797a90","changes":[["0x1234","0xd0070000"]]}
+]}
+]"#;
+
+	fn polkadot() -> PolkaPipe<Backend> {
+		PolkaPipe { rpc: Backend::new(Transcript::from_json(POLKADOT).unwrap()) }
+	}
+
+	async fn all<T>(stream: impl Stream<Item = T>) -> Vec<T> {
+		let mut stream = Box::pin(stream);
+		let mut items = vec![];
+		while let Some(item) = stream.next().await {
+			items.push(item);
+		}
+		items
+	}
+
+	#[async_std::test]
+	async fn replays_a_transcript() {
+		let node = polkadot();
+		assert_eq!(
+			hex::encode(node.query_block_hash(&[1]).await.unwrap()),
+			"c0096358534ec8d21d01d34b836eed476a1c343f8724fa2153dc0725ad797a90"
+		);
+
+		// Answers are given in order, then the last is repeated.
+		let key = hex::decode("1234").unwrap();
+		assert_eq!(node.query_storage(&key, None).await.unwrap(), vec![0xe8, 3, 0, 0]);
+		assert_eq!(node.query_storage(&key, None).await.unwrap(), vec![0xd0, 7, 0, 0]);
+		assert_eq!(node.query_storage(&key, None).await.unwrap(), vec![0xd0, 7, 0, 0]);
+
+		let key = hex::decode("5678").unwrap();
+		assert!(matches!(
+			node.query_storage(&key, None).await,
+			Err(crate::Error::Rpc { code: -32602, .. })
+		));
+		assert!(matches!(
+			node.query_block_hash(&[2]).await,
+			Err(crate::Error::Rpc { code: -32601, .. })
+		));
+	}
+
+	#[async_std::test]
+	async fn replays_subscriptions() {
+		let node = polkadot();
+		let key = hex::decode("1234").unwrap();
+		let changes: Vec<_> = all(node.subscribe_storage(&[&key], None).await).await;
+		assert_eq!(changes.len(), 2);
+		assert_eq!(changes[0].block[..2], [0xe3, 0x35]);
+		assert_eq!(changes[1].changes, vec![(key, Some(vec![0xd0, 7, 0, 0]))]);
+	}
+
+	#[async_std::test]
+	async fn queries_storage_over_blocks() {
+		let node = PolkaPipe {
+			rpc: Backend::new(
+				Transcript::from_json(
+					r#"[
+{"method":"state_queryStorage","params":[["0x1234","0x5678"],"0x01","0x03"],"response":{"result":[
+	{"block":"0x01","changes":[["0x1234","0xe8030000"],["0x5678",null]]},
+	{"block":"0x03","changes":[["0x1234",null]]}
+]}},
+{"method":"state_queryStorageAt","params":[["0x1234","0x5678"],"0x01"],"response":{"result":[
+	{"block":"0x01","changes":[["0x5678","0x01"],["0x1234","0xe8030000"]]}
+]}},
+{"method":"state_queryStorageAt","params":[["0x1234"]],"response":{"result":[]}}
+]"#,
+				)
+				.unwrap(),
+			),
+		};
+		let keys = [&[0x12, 0x34][..], &[0x56, 0x78]];
+		let changes = node.query_storage_range(&keys, &[1], Some(&[3])).await.unwrap();
+		assert_eq!(changes.len(), 2);
+		assert_eq!(changes[0].changes[0], (keys[0].to_vec(), Some(vec![0xe8, 3, 0, 0])));
+		assert_eq!(changes[0].changes[1], (keys[1].to_vec(), None));
+		assert_eq!(changes[1].block, vec![3]);
+		assert_eq!(changes[1].changes, vec![(keys[0].to_vec(), None)]);
+
+		// In the order asked for.
+		let values = node.query_storage_at(&keys, Some(&[1])).await.unwrap();
+		assert_eq!(values, vec![Some(vec![0xe8, 3, 0, 0]), Some(vec![1])]);
+		assert_eq!(node.query_storage_at(&keys[..1], None).await.unwrap(), vec![None]);
+	}
+
+	#[async_std::test]
+	async fn pages_through_keys() {
+		let node = PolkaPipe {
+			rpc: Backend::new(
+				Transcript::from_json(
+					r#"[
+{"method":"state_getKeysPaged","params":["0x12",2,null,"0xff"],"response":{"result":["0x1201","0x1202"]}},
+{"method":"state_getKeysPaged","params":["0x12",2,"0x1202","0xff"],"response":{"result":["0x1203"]}},
+{"method":"state_queryStorageAt","params":[["0x1201","0x1202"],"0xff"],"response":{"result":[
+	{"block":"0xff","changes":[["0x1201","0x01"],["0x1202","0x02"]]}
+]}},
+{"method":"state_queryStorageAt","params":[["0x1203"],"0xff"],"response":{"result":[
+	{"block":"0xff","changes":[["0x1203",null]]}
+]}}
+]"#,
+				)
+				.unwrap(),
+			),
+		};
+		let mut keys = node.storage_keys(&[0x12], 2, Some(&[0xff]));
+		assert_eq!(keys.next_page().await.unwrap(), Some(vec![vec![0x12, 1], vec![0x12, 2]]));
+		assert_eq!(keys.next_page().await.unwrap(), Some(vec![vec![0x12, 3]]));
+		assert_eq!(keys.next_page().await.unwrap(), None);
+
+		let mut entries = node.storage_keys(&[0x12], 2, Some(&[0xff]));
+		let page = entries.next_entries().await.unwrap().unwrap();
+		assert_eq!(page, vec![(vec![0x12, 1], Some(vec![1])), (vec![0x12, 2], Some(vec![2]))]);
+		let page = entries.next_entries().await.unwrap().unwrap();
+		assert_eq!(page, vec![(vec![0x12, 3], None)]);
+		assert_eq!(entries.next_entries().await.unwrap(), None);
+	}
+
+	#[async_std::test]
+	async fn recordings_replay_the_same() {
+		let node = PolkaPipe { rpc: Recorder::new(polkadot().rpc) };
+		let key = hex::decode("1234").unwrap();
+		let block_hash = node.query_block_hash(&[1]).await.unwrap();
+		let missing = node.query_storage(&hex::decode("5678").unwrap(), None).await;
+		let changes: Vec<_> = all(node.subscribe_storage(&[&key], None).await).await;
+
+		let transcript = node.rpc.transcript();
+		assert_eq!(transcript.0.len(), 3);
+		assert_eq!(Transcript::from_json(&transcript.to_json()).unwrap(), transcript);
+
+		let node = PolkaPipe { rpc: Backend::new(transcript) };
+		assert_eq!(node.query_block_hash(&[1]).await.unwrap(), block_hash);
+		assert_eq!(
+			format!("{:?}", node.query_storage(&hex::decode("5678").unwrap(), None).await),
+			format!("{:?}", missing)
+		);
+		let replayed: Vec<_> = all(node.subscribe_storage(&[&key], None).await).await;
+		assert_eq!(format!("{:?}", replayed), format!("{:?}", changes));
+	}
+
+	#[async_std::test]
+	async fn node_records_then_replays() {
+		let path = std::env::temp_dir().join(format!("polkapipe-{}.json", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+
+		let node = Node::new(&path, || async { Ok(polkadot().rpc) }).await.unwrap();
+		assert!(matches!(node, Node::Record(_)));
+		let block_hash = PolkaPipe { rpc: node }.query_block_hash(&[1]).await.unwrap();
+
+		let node = Node::<Backend>::new(&path, || async { Err(crate::Error::ChainUnavailable) })
+			.await
+			.unwrap();
+		assert!(matches!(node, Node::Replay(_)));
+		assert_eq!(PolkaPipe { rpc: node }.query_block_hash(&[1]).await.unwrap(), block_hash);
+		let _ = std::fs::remove_file(&path);
+	}
+
+	#[async_std::test]
+	async fn only_records_when_asked_to() {
+		let path =
+			std::env::temp_dir().join(format!("polkapipe-missing-{}.json", std::process::id()));
+		let node = Node::replay(&path, || async { Ok(polkadot().rpc) }).await;
+		match std::env::var_os("RECORD_TRANSCRIPTS") {
+			Some(_) => assert!(matches!(node, Ok(Node::Record(_)))),
+			None => assert!(matches!(node, Err(crate::Error::Node(_)))),
+		}
+		drop(node);
+		let _ = std::fs::remove_file(&path);
+	}
+}
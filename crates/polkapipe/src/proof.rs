@@ -0,0 +1,431 @@
+//! Checking what a node says against a block's state root: the nodes of the storage trie that
+//! `state_getReadProof` returns are looked up by their hashes from the root down to the key,
+//! so a value that's not really in the state can't be made to fit.
+use crate::prelude::*;
+use alloc::collections::BTreeMap;
+use blake2::{digest::consts::U32, Blake2b, Digest};
+use core::fmt;
+
+pub type Hash = [u8; 32];
+
+pub fn blake2_256(data: &[u8]) -> Hash {
+	Blake2b::<U32>::digest(data).into()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProofError {
+	/// A node (or hashed value) on the way to the key isn't in the proof.
+	MissingNode(Hash),
+	/// A node in the proof doesn't decode as a trie node.
+	BadNode(Hash),
+}
+
+impl fmt::Display for ProofError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::MissingNode(hash) => write!(f, "Proof is missing node 0x{}", hex::encode(hash)),
+			Self::BadNode(hash) => write!(f, "Proof has a bad node 0x{}", hex::encode(hash)),
+		}
+	}
+}
+
+/// The value of `key` in the trie with `state_root`, `None` if the proof shows it's not
+/// there. It's an error if the proof doesn't go all the way.
+pub fn read(
+	state_root: &Hash,
+	proof: &[Vec<u8>],
+	key: &[u8],
+) -> Result<Option<Vec<u8>>, ProofError> {
+	let nodes: BTreeMap<Hash, &[u8]> =
+		proof.iter().map(|node| (blake2_256(node), node.as_slice())).collect();
+	let get = |hash: &Hash| nodes.get(hash).copied().ok_or(ProofError::MissingNode(*hash));
+
+	let nibbles: Vec<u8> = key.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]).collect();
+	let mut key = nibbles.as_slice();
+	let mut hash = *state_root;
+	let mut encoded = get(&hash)?;
+	loop {
+		let node = Node::decode(encoded).ok_or(ProofError::BadNode(hash))?;
+		let (partial, value, children) = match node {
+			Node::Empty => return Ok(None),
+			Node::Leaf { partial, value } => (partial, Some(value), None),
+			Node::Branch { partial, value, children } => (partial, value, Some(children)),
+		};
+		if !key.starts_with(&partial) {
+			return Ok(None)
+		}
+		key = &key[partial.len()..];
+		let child = match (key.split_first(), children) {
+			(None, _) => {
+				return match value {
+					Some(Value::Inline(value)) => Ok(Some(value.to_vec())),
+					Some(Value::Hashed(value)) => get(&value).map(|value| Some(value.to_vec())),
+					None => Ok(None),
+				}
+			},
+			(Some((nibble, rest)), Some(children)) => {
+				key = rest;
+				children[*nibble as usize]
+			},
+			(Some(_), None) => return Ok(None),
+		};
+		encoded = match child {
+			Some(Child::Hash(child)) => {
+				hash = child;
+				get(&hash)?
+			},
+			Some(Child::Inline(node)) => node,
+			None => return Ok(None),
+		};
+	}
+}
+
+enum Value<'a> {
+	Inline(&'a [u8]),
+	Hashed(Hash),
+}
+
+#[derive(Clone, Copy)]
+enum Child<'a> {
+	Hash(Hash),
+	/// Nodes shorter than a hash are kept in their parent.
+	Inline(&'a [u8]),
+}
+
+/// A node as substrate's trie encodes it (`NodeCodec` of `sp-trie`), with the partial key
+/// as nibbles.
+enum Node<'a> {
+	Empty,
+	Leaf { partial: Vec<u8>, value: Value<'a> },
+	Branch { partial: Vec<u8>, value: Option<Value<'a>>, children: Box<[Option<Child<'a>>; 16]> },
+}
+
+impl<'a> Node<'a> {
+	fn decode(mut input: &'a [u8]) -> Option<Self> {
+		let input = &mut input;
+		let header = take(input, 1)?[0];
+		// leaf, branch, has a value, the value is hashed, bits of the header the size is in
+		let (leaf, branch, has_value, hashed, size_bits) = match header >> 6 {
+			0b01 => (true, false, true, false, 6),
+			0b10 => (false, true, false, false, 6),
+			0b11 => (false, true, true, false, 6),
+			_ if header == 0 => return Some(Node::Empty),
+			_ if header >> 5 == 0b001 => (true, false, true, true, 5),
+			_ if header >> 4 == 0b0001 => (false, true, true, true, 4),
+			_ => return None,
+		};
+		let nibble_count = decode_size(header, size_bits, input)?;
+		let partial = take(input, nibble_count.div_ceil(2))?;
+		// An odd number of nibbles is padded at the front.
+		if nibble_count % 2 == 1 && partial[0] & 0xf0 != 0 {
+			return None
+		}
+		let skip = nibble_count % 2;
+		let partial: Vec<u8> =
+			partial.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]).skip(skip).collect();
+
+		let bitmap = if branch {
+			let bitmap = take(input, 2)?;
+			u16::from_le_bytes([bitmap[0], bitmap[1]])
+		} else {
+			0
+		};
+		let value = match (has_value, hashed) {
+			(false, _) => None,
+			(true, false) => {
+				let len = decode_compact(input)?;
+				Some(Value::Inline(take(input, usize::try_from(len).ok()?)?))
+			},
+			(true, true) => Some(Value::Hashed(take(input, 32)?.try_into().ok()?)),
+		};
+		if leaf {
+			return match input.is_empty() {
+				true => Some(Node::Leaf { partial, value: value? }),
+				false => None,
+			}
+		}
+		let mut children = Box::new([None; 16]);
+		for (i, child) in children.iter_mut().enumerate() {
+			if bitmap & (1 << i) != 0 {
+				let len = usize::try_from(decode_compact(input)?).ok()?;
+				let data = take(input, len)?;
+				*child = Some(match len {
+					32 => Child::Hash(data.try_into().ok()?),
+					_ => Child::Inline(data),
+				});
+			}
+		}
+		match input.is_empty() {
+			true => Some(Node::Branch { partial, value, children }),
+			false => None,
+		}
+	}
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+	if input.len() < len {
+		return None
+	}
+	let (taken, rest) = input.split_at(len);
+	*input = rest;
+	Some(taken)
+}
+
+/// Sizes that don't fit in the header carry on in the bytes after it, while they're 255.
+fn decode_size(header: u8, size_bits: u32, input: &mut &[u8]) -> Option<usize> {
+	let max = 255u8 >> (8 - size_bits);
+	let mut size = (header & max) as usize;
+	if size < max as usize {
+		return Some(size)
+	}
+	loop {
+		let byte = take(input, 1)?[0];
+		size += byte as usize;
+		if byte < 255 {
+			return Some(size)
+		}
+	}
+}
+
+/// SCALE compact encoding.
+pub(crate) fn decode_compact(input: &mut &[u8]) -> Option<u64> {
+	let first = *input.first()?;
+	let value = match first & 0b11 {
+		0b00 => (take(input, 1)?[0] >> 2) as u64,
+		0b01 => (u16::from_le_bytes(take(input, 2)?.try_into().ok()?) >> 2) as u64,
+		0b10 => (u32::from_le_bytes(take(input, 4)?.try_into().ok()?) >> 2) as u64,
+		_ => {
+			let len = (first >> 2) as usize + 4;
+			let bytes = take(input, len + 1)?;
+			if len > 8 {
+				return None
+			}
+			let mut value = [0; 8];
+			value[..len].copy_from_slice(&bytes[1..]);
+			u64::from_le_bytes(value)
+		},
+	};
+	Some(value)
+}
+
+pub(crate) fn encode_compact(value: u64, out: &mut Vec<u8>) {
+	match value {
+		0..=0x3f => out.push((value as u8) << 2),
+		0x40..=0x3fff => out.extend(((value as u16) << 2 | 0b01).to_le_bytes()),
+		0x4000..=0x3fff_ffff => out.extend(((value as u32) << 2 | 0b10).to_le_bytes()),
+		_ => {
+			let len = (8 - value.leading_zeros() as usize / 8).max(4);
+			out.push(((len - 4) as u8) << 2 | 0b11);
+			out.extend(&value.to_le_bytes()[..len]);
+		},
+	}
+}
+
+/// A block header, e.g. to get at the state root of a block having checked it's the block
+/// with the hash we asked for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+	pub parent_hash: Hash,
+	pub number: u32,
+	pub state_root: Hash,
+	pub extrinsics_root: Hash,
+	/// Encoded digest items.
+	pub logs: Vec<Vec<u8>>,
+}
+
+impl Header {
+	/// From the JSON of `chain_getHeader` (or the header of `chain_getBlock`).
+	pub fn from_json(header: &serde_json::Value) -> Option<Self> {
+		let bytes =
+			|field: &serde_json::Value| hex::decode(field.as_str()?.trim_start_matches("0x")).ok();
+		let hash = |field: &str| bytes(header.get(field)?)?.try_into().ok();
+		let number = header.get("number")?.as_str()?.trim_start_matches("0x");
+		let logs = header.get("digest")?.get("logs")?.as_array()?;
+		Some(Header {
+			parent_hash: hash("parentHash")?,
+			number: u32::from_str_radix(number, 16).ok()?,
+			state_root: hash("stateRoot")?,
+			extrinsics_root: hash("extrinsicsRoot")?,
+			logs: logs.iter().map(bytes).collect::<Option<_>>()?,
+		})
+	}
+
+	pub fn encode(&self) -> Vec<u8> {
+		let mut out = self.parent_hash.to_vec();
+		encode_compact(self.number.into(), &mut out);
+		out.extend(self.state_root);
+		out.extend(self.extrinsics_root);
+		encode_compact(self.logs.len() as u64, &mut out);
+		for log in &self.logs {
+			out.extend(log);
+		}
+		out
+	}
+
+	/// The block hash.
+	pub fn hash(&self) -> Hash {
+		blake2_256(&self.encode())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[cfg(feature = "mock")]
+	use crate::{mock, PolkaPipe};
+
+	/// Encode a node the way substrate does: the partial key is in nibbles.
+	fn node(partial: &[u8], value: Option<&[u8]>, children: &[(u8, &[u8])]) -> Vec<u8> {
+		let (mut out, size_bits) = match (children.is_empty(), value) {
+			(true, _) => (vec![0b01 << 6], 6),
+			(false, None) => (vec![0b10 << 6], 6),
+			(false, Some(_)) => (vec![0b11 << 6], 6),
+		};
+		let max = 255 >> (8 - size_bits);
+		if partial.len() < max {
+			out[0] |= partial.len() as u8;
+		} else {
+			out[0] |= max as u8;
+			let mut rest = partial.len() - max;
+			while rest >= 255 {
+				out.push(255);
+				rest -= 255;
+			}
+			out.push(rest as u8);
+		}
+		let mut nibbles = partial.to_vec();
+		if nibbles.len() % 2 == 1 {
+			nibbles.insert(0, 0);
+		}
+		out.extend(nibbles.chunks(2).map(|pair| pair[0] << 4 | pair[1]));
+		if !children.is_empty() {
+			let bitmap = children.iter().fold(0u16, |bitmap, (i, _)| bitmap | 1 << i);
+			out.extend(bitmap.to_le_bytes());
+		}
+		if let Some(value) = value {
+			encode_compact(value.len() as u64, &mut out);
+			out.extend(value);
+		}
+		for (_, child) in children {
+			encode_compact(child.len() as u64, &mut out);
+			out.extend(*child);
+		}
+		out
+	}
+
+	#[test]
+	fn blake2_256_of_nothing() {
+		assert_eq!(
+			hex::encode(blake2_256(b"")),
+			"0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8"
+		);
+	}
+
+	#[test]
+	fn compact_round_trips() {
+		for value in [0, 1, 63, 64, 16_383, 16_384, 1 << 30, u32::MAX as u64, u64::MAX] {
+			let mut out = vec![];
+			encode_compact(value, &mut out);
+			assert_eq!(decode_compact(&mut out.as_slice()), Some(value));
+		}
+		let mut out = vec![];
+		encode_compact(10504599, &mut out);
+		assert_eq!(hex::encode(out), "5e268102");
+	}
+
+	#[test]
+	fn reads_a_leaf() {
+		let leaf = node(&[1, 2, 3, 4], Some(b"value"), &[]);
+		let root = blake2_256(&leaf);
+		let proof = vec![leaf];
+		assert_eq!(read(&root, &proof, &[0x12, 0x34]), Ok(Some(b"value".to_vec())));
+		assert_eq!(read(&root, &proof, &[0x12, 0x35]), Ok(None));
+		assert_eq!(read(&root, &proof, &[0x12]), Ok(None));
+		assert_eq!(read(&[0; 32], &proof, &[0x12, 0x34]), Err(ProofError::MissingNode([0; 32])));
+	}
+
+	#[test]
+	fn reads_through_branches() {
+		// 0x1234 => "long value that gets hashed...", 0x1256 => "inline", 0x12 => "branch"
+		let long = node(&[4], Some(&[7; 40]), &[]);
+		let inline = node(&[6], Some(b"hi"), &[]);
+		assert!(inline.len() < 32);
+		let branch =
+			node(&[1, 2], Some(b"branch"), &[(3, &blake2_256(&long)), (5, inline.as_slice())]);
+		let root = blake2_256(&branch);
+		let proof = vec![branch.clone(), long.clone()];
+
+		assert_eq!(read(&root, &proof, &[0x12, 0x34]), Ok(Some(vec![7; 40])));
+		assert_eq!(read(&root, &proof, &[0x12, 0x56]), Ok(Some(b"hi".to_vec())));
+		assert_eq!(read(&root, &proof, &[0x12]), Ok(Some(b"branch".to_vec())));
+		assert_eq!(read(&root, &proof, &[0x12, 0x78]), Ok(None));
+
+		// Without the node the value is in there's no telling.
+		let missing = Err(ProofError::MissingNode(blake2_256(&long)));
+		assert_eq!(read(&root, core::slice::from_ref(&branch), &[0x12, 0x34]), missing);
+		// A value that's not what the root says is nowhere to be found.
+		let forged = node(&[4], Some(&[8; 40]), &[]);
+		assert!(read(&root, &[branch, forged], &[0x12, 0x34]).is_err());
+	}
+
+	#[test]
+	fn reads_hashed_values() {
+		let value = vec![9; 100];
+		// A leaf of one nibble with the value's hash in place of the value.
+		let mut leaf = vec![0b001 << 5 | 1, 0x05];
+		leaf.extend(blake2_256(&value));
+		let root = blake2_256(&leaf);
+		assert_eq!(read(&root, &[leaf.clone(), value.clone()], &[0x05]), Ok(None));
+		let mut leaf = vec![0b001 << 5 | 2, 0x05];
+		leaf.extend(blake2_256(&value));
+		let root = blake2_256(&leaf);
+		assert_eq!(read(&root, &[leaf.clone(), value.clone()], &[0x05]), Ok(Some(value)));
+		let missing = ProofError::MissingNode(blake2_256(&[9; 100]));
+		assert_eq!(read(&root, &[leaf], &[0x05]), Err(missing));
+	}
+
+	#[test]
+	fn long_partial_keys() {
+		let key = [0xab; 40];
+		let nibbles: Vec<u8> = key.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]).collect();
+		let leaf = node(&nibbles, Some(b"x"), &[]);
+		assert_eq!(leaf[..2], [0b01 << 6 | 63, 80 - 63]);
+		assert_eq!(read(&blake2_256(&leaf), &[leaf], &key), Ok(Some(b"x".to_vec())));
+	}
+
+	#[test]
+	fn header_hash() {
+		let header = serde_json::json!({
+			"parentHash": format!("0x{}", "11".repeat(32)),
+			"number": "0xa04997",
+			"stateRoot": format!("0x{}", "22".repeat(32)),
+			"extrinsicsRoot": format!("0x{}", "33".repeat(32)),
+			"digest": { "logs": ["0x0601020304"] }
+		});
+		let header = Header::from_json(&header).unwrap();
+		assert_eq!(header.number, 10504599);
+		let encoded = header.encode();
+		assert_eq!(encoded[32..36], hex_literal::hex!("5e268102"));
+		assert_eq!(encoded[100..], hex_literal::hex!("04 0601020304"));
+		assert_eq!(header.hash(), blake2_256(&encoded));
+	}
+
+	#[cfg(feature = "mock")]
+	#[async_std::test]
+	async fn checks_what_the_node_says() {
+		let leaf = node(&[1, 2, 3, 4], Some(b"value"), &[]);
+		let transcript = mock::Transcript::from_json(&format!(
+			concat!(
+				r#"[{{"method":"state_getReadProof","params":[["0x1234"],"0x01"],"#,
+				r#""response":{{"result":{{"at":"0x01","proof":["0x{}"]}}}}}}]"#,
+			),
+			hex::encode(&leaf)
+		))
+		.unwrap();
+		let node = PolkaPipe { rpc: mock::Backend::new(transcript) };
+		let proof = node.query_read_proof(&[&[0x12, 0x34]], Some(&[1])).await.unwrap();
+		assert_eq!(proof.at, vec![1]);
+		let value = read(&blake2_256(&leaf), &proof.proof, &[0x12, 0x34]);
+		assert_eq!(value, Ok(Some(b"value".to_vec())));
+	}
+}
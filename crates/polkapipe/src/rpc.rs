@@ -2,14 +2,32 @@ use crate::prelude::*;
 // use async_trait::async_trait;
 pub use jsonrpc::{error, Response};
 pub type RpcResult = Result<Box<serde_json::value::RawValue>, error::Error>;
-use async_std::stream::Stream;
-use core::str::FromStr;
+use alloc::{
+	borrow::ToOwned,
+	collections::{BTreeMap, VecDeque},
+};
+use async_std::stream::{Stream, StreamExt};
+use core::{
+	pin::Pin,
+	str::FromStr,
+	task::{Context, Poll},
+	time::Duration,
+};
 
 /// Scale state changes
 #[derive(Debug)]
 pub struct StateChanges {
 	pub block: Vec<u8>,
-	pub changes: Vec<(Vec<u8>, Vec<u8>)>,
+	/// `None` where the value was deleted.
+	pub changes: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+/// Trie nodes that prove storage values as of a block.
+#[derive(Debug)]
+pub struct ReadProof {
+	/// Hash of the block.
+	pub at: Vec<u8>,
+	pub proof: Vec<Vec<u8>>,
 }
 
 /// Rpc defines types of backends that are remote and talk JSONRpc
@@ -17,14 +35,344 @@ pub struct StateChanges {
 // #[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 pub trait Rpc {
 	async fn rpc(&self, method: &str, params: &str) -> RpcResult;
+
+	/// Several `(method, params)` requests in one round trip. Results are in the same order
+	/// as the requests. Backends that can't batch just send them one after another.
+	async fn rpc_batch(&self, requests: &[(&str, &str)]) -> Vec<RpcResult> {
+		let mut results = Vec::with_capacity(requests.len());
+		for (method, params) in requests {
+			results.push(self.rpc(method, params).await);
+		}
+		results
+	}
+
+	/// How much the backend has been held back, for backends that keep to `Limits`.
+	fn throttling(&self) -> crate::Throttling {
+		crate::Throttling::default()
+	}
+}
+
+/// A JSON-RPC batch (array) of `(id, method, params)` requests.
+pub(crate) fn batch_request<Id: core::fmt::Display>(requests: &[(Id, &str, &str)]) -> String {
+	let mut msg = String::from("[");
+	for (id, method, params) in requests {
+		if msg.len() > 1 {
+			msg.push(',');
+		}
+		msg.push_str(&format!(
+			"{{\"id\":{}, \"jsonrpc\": \"2.0\", \"method\":\"{}\", \"params\":{}}}",
+			id, method, params
+		));
+	}
+	msg.push(']');
+	msg
 }
 
+/// The result of a response, or the error the node gave instead.
+pub(crate) fn response_result(res: Response) -> RpcResult {
+	res.result.ok_or_else(|| {
+		error::Error::Rpc(
+			res.error.unwrap_or_else(|| {
+				error::standard_error(error::StandardError::InternalError, None)
+			}),
+		)
+	})
+}
+
+/// How long to wait for the node to answer a request unless told otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Backends that can hold a subscription open.
 pub trait Streamable {
-	async fn stream(
-		&self,
+	async fn subscribe(&self, method: &str, params: &str) -> crate::Result<Subscription>;
+}
+
+/// The `result` of each notification for a subscription, it's up to the caller to make
+/// sense of them. Dropping it unsubscribes.
+pub struct Subscription {
+	/// Needed by methods that act on the subscription (e.g. `chainHead_v1_body`).
+	pub id: String,
+	unsubscribe_method: String,
+	notifications: async_std::channel::Receiver<serde_json::Value>,
+	// Unsubscribe requests for the backend to send.
+	unsubscribe: async_std::channel::Sender<String>,
+	// Sees each notification on its way out (e.g. to record it).
+	tap: Option<Box<Tap>>,
+}
+
+type Tap = dyn FnMut(&serde_json::Value) + Send + Sync;
+
+impl Subscription {
+	/// A subscription that's already over, e.g. because subscribing failed.
+	pub(crate) fn ended() -> Self {
+		Subscription {
+			id: String::new(),
+			unsubscribe_method: String::new(),
+			notifications: async_std::channel::unbounded().1,
+			// Nobody is listening so there's no unsubscribing.
+			unsubscribe: async_std::channel::unbounded().0,
+			tap: None,
+		}
+	}
+
+	/// Call `f` with each notification as it's received.
+	#[cfg(feature = "mock")]
+	pub(crate) fn tap(mut self, f: impl FnMut(&serde_json::Value) + Send + Sync + 'static) -> Self {
+		self.tap = Some(Box::new(f));
+		self
+	}
+
+	fn tapped(&mut self, notification: Option<serde_json::Value>) -> Option<serde_json::Value> {
+		if let (Some(tap), Some(notification)) = (self.tap.as_mut(), notification.as_ref()) {
+			tap(notification);
+		}
+		notification
+	}
+
+	pub async fn recv(&mut self) -> Option<serde_json::Value> {
+		let notification = self.notifications.recv().await.ok();
+		self.tapped(notification)
+	}
+
+	/// The next notification if there is one already.
+	pub fn try_recv(&mut self) -> Option<serde_json::Value> {
+		let notification = self.notifications.try_recv().ok();
+		self.tapped(notification)
+	}
+}
+
+impl Stream for Subscription {
+	type Item = serde_json::Value;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		let notification = Pin::new(&mut self.notifications).poll_next(cx);
+		notification.map(|notification| self.tapped(notification))
+	}
+}
+
+impl Drop for Subscription {
+	fn drop(&mut self) {
+		// Nobody waits for the answer so it uses the id that's never given to a request.
+		let _ = self.unsubscribe.try_send(format!(
+			"{{\"id\":0, \"jsonrpc\": \"2.0\", \"method\":\"{}\", \"params\":[\"{}\"]}}",
+			self.unsubscribe_method, self.id
+		));
+	}
+}
+
+/// The method that ends a subscription made with `method`.
+fn unsubscribe_method(method: &str) -> String {
+	match method {
+		"author_submitAndWatchExtrinsic" => "author_unwatchExtrinsic".to_string(),
+		"chainHead_v1_follow" => "chainHead_v1_unfollow".to_string(),
+		"archive_v1_storage" => "archive_v1_stopStorage".to_string(),
+		_ => method.replacen("subscribe", "unsubscribe", 1),
+	}
+}
+
+/// Runs the closure when dropped, e.g. to clean up after a request that's given up on.
+pub(crate) struct OnDrop<F: FnMut()>(pub F);
+
+impl<F: FnMut()> Drop for OnDrop<F> {
+	fn drop(&mut self) {
+		(self.0)()
+	}
+}
+
+/// The node didn't answer in time, becomes `crate::Error::Timeout`.
+#[derive(Debug)]
+pub(crate) struct TimedOut;
+
+impl core::fmt::Display for TimedOut {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		write!(f, "Timed out waiting for the node")
+	}
+}
+
+impl core::error::Error for TimedOut {}
+
+pub(crate) fn timed_out() -> error::Error {
+	error::Error::Transport(Box::new(TimedOut))
+}
+
+/// What a request gets back: the node's response or, if it can't be made sense of, why not.
+pub(crate) type Answer = Result<Response, error::Error>;
+
+/// A message from the node.
+pub(crate) enum Incoming {
+	/// Answers to requests, with their ids.
+	Responses(Vec<(u64, Answer)>),
+	Notification(serde_json::Value),
+}
+
+/// `None` if it's not JSON-RPC we can make sense of and there's no telling which request it's
+/// for. Answers without a (numeric) id are dropped.
+pub(crate) fn parse_incoming(msg: &str) -> Option<Incoming> {
+	let answer = |res: serde_json::Value| {
+		let id = res.get("id")?.as_u64()?;
+		Some((id, serde_json::from_value(res).map_err(error::Error::Json)))
+	};
+	match serde_json::from_str(msg) {
+		// The responses to a batch come back together as an array.
+		Ok(serde_json::Value::Array(responses)) =>
+			Some(Incoming::Responses(responses.into_iter().filter_map(answer).collect())),
+		Ok(res @ serde_json::Value::Object(_)) if res.get("id").is_some() =>
+			Some(Incoming::Responses(answer(res).into_iter().collect())),
+		Ok(notification @ serde_json::Value::Object(_)) =>
+			Some(Incoming::Notification(notification)),
+		Ok(_) => None,
+		// e.g. cut short, the request it's for still shouldn't wait for an answer that won't come.
+		Err(err) =>
+			Some(Incoming::Responses(vec![(salvage_id(msg)?, Err(error::Error::Json(err)))])),
+	}
+}
+
+/// The `id` of a response that isn't valid JSON, if it can still be made out.
+fn salvage_id(msg: &str) -> Option<u64> {
+	let bytes = msg.as_bytes();
+	if bytes.first() != Some(&b'{') {
+		return None
+	}
+	let (mut depth, mut i) = (0, 0);
+	while i < bytes.len() {
+		match bytes[i] {
+			b'"' => {
+				let start = i;
+				i += 1;
+				while i < bytes.len() && bytes[i] != b'"' {
+					i += if bytes[i] == b'\\' { 2 } else { 1 };
+				}
+				let key = bytes.get(start..=i) == Some(&b"\"id\""[..]);
+				let value = msg.get(i + 1..).and_then(|rest| rest.trim_start().strip_prefix(':'));
+				if let (1, true, Some(value)) = (depth, key, value) {
+					let value = value.trim_start();
+					let end = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+					return value[..end].parse().ok()
+				}
+			},
+			b'{' | b'[' => depth += 1,
+			b'}' | b']' => depth -= 1,
+			_ => {},
+		}
+		i += 1;
+	}
+	None
+}
+
+/// Status updates of a submitted extrinsic (`author_extrinsicUpdate` notifications).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransactionStatus {
+	/// In the future queue (e.g. waiting on an earlier nonce).
+	Future,
+	/// In the ready queue.
+	Ready,
+	/// Gossiped to these peers.
+	Broadcast(Vec<String>),
+	/// Included in the block with this hash.
+	InBlock(Vec<u8>),
+	/// The block it was in got retracted.
+	Retracted(Vec<u8>),
+	/// Waited too long for finality of the block it was in, no longer watching.
+	FinalityTimeout(Vec<u8>),
+	/// Finalized in the block with this hash.
+	Finalized(Vec<u8>),
+	/// Replaced by another extrinsic with the same sender and nonce (hash of that one).
+	Usurped(Vec<u8>),
+	/// Dropped from the pool (e.g. it was full).
+	Dropped,
+	/// Invalid, it will never be included.
+	Invalid,
+}
+
+impl TransactionStatus {
+	/// No more updates will follow this one.
+	pub fn is_final(&self) -> bool {
+		matches!(
+			self,
+			Self::FinalityTimeout(_) |
+				Self::Finalized(_) |
+				Self::Usurped(_) |
+				Self::Dropped |
+				Self::Invalid
+		)
+	}
+}
+
+/// Most notifications held on to for a subscription that hasn't been added yet.
+const MAX_EARLY_NOTIFICATIONS: usize = 16;
+/// Most subscriptions notifications are held on to for before they're added. Any more and the
+/// longest waiting is given up on.
+const MAX_EARLY_SUBSCRIPTIONS: usize = 16;
+/// How many dropped subscriptions to remember. Anything still on its way for them turns up soon
+/// after they're dropped.
+const MAX_CLOSED: usize = 64;
+
+/// Subscription id -> stream. Notifications can turn up before the response with the
+/// subscription id has been seen, so (a bounded number of) those are held on to until the
+/// stream is added.
+#[derive(Default)]
+pub(crate) struct Subscriptions {
+	streams: BTreeMap<String, async_std::channel::Sender<serde_json::Value>>,
+	/// Oldest first.
+	early: VecDeque<(String, Vec<serde_json::Value>)>,
+	// Recently dropped, so anything still on its way for them can be ignored. Oldest first.
+	closed: VecDeque<String>,
+}
+
+impl Subscriptions {
+	/// `unsubscribe` is where the subscription sends its unsubscribe request when dropped.
+	pub fn add(
+		&mut self,
 		method: &str,
-		params: &str,
-	) -> async_std::channel::Receiver<StateChanges>;
+		subscription_id: &str,
+		unsubscribe: async_std::channel::Sender<String>,
+	) -> Subscription {
+		let (sender, notifications) = async_std::channel::unbounded();
+		if let Some(pos) = self.early.iter().position(|(id, _)| id == subscription_id) {
+			for result in self.early.remove(pos).map(|(_, early)| early).unwrap_or_default() {
+				let _ = sender.try_send(result);
+			}
+		}
+		self.streams.insert(subscription_id.to_owned(), sender);
+		Subscription {
+			id: subscription_id.to_owned(),
+			unsubscribe_method: unsubscribe_method(method),
+			notifications,
+			unsubscribe,
+			tap: None,
+		}
+	}
+
+	/// Forward a notification (a message without an id) to its stream.
+	pub fn notify(&mut self, notification: serde_json::Value) {
+		if let Some((subscription_id, result)) = parse_notification(notification) {
+			if let Some(sender) = self.streams.get(&subscription_id) {
+				if sender.try_send(result).is_err() {
+					// Nobody is listening any more.
+					self.streams.remove(&subscription_id);
+					if self.closed.len() == MAX_CLOSED {
+						self.closed.pop_front();
+					}
+					self.closed.push_back(subscription_id);
+				}
+			} else if !self.closed.contains(&subscription_id) {
+				self.hold_early(subscription_id, result);
+			}
+		}
+	}
+
+	fn hold_early(&mut self, subscription_id: String, result: serde_json::Value) {
+		if let Some((_, early)) = self.early.iter_mut().find(|(id, _)| *id == subscription_id) {
+			if early.len() < MAX_EARLY_NOTIFICATIONS {
+				early.push(result);
+			}
+			return
+		}
+		if self.early.len() == MAX_EARLY_SUBSCRIPTIONS {
+			self.early.pop_front();
+		}
+		self.early.push_back((subscription_id, vec![result]));
+	}
 }
 
 fn convert_params_raw(params: &[&str]) -> String {
@@ -40,51 +388,64 @@ fn convert_params_raw(params: &[&str]) -> String {
 	msg
 }
 
+/// `["0x..", ..]`
+fn hex_array(items: &[&[u8]]) -> String {
+	let items: Vec<_> = items.iter().map(|item| format!("\"0x{}\"", hex::encode(item))).collect();
+	convert_params_raw(&items.iter().map(|item| item.as_str()).collect::<Vec<_>>())
+}
+
+/// Skip over the compact encoded length at the start of a `Vec<u8>`.
+fn skip_compact_prefix(bytes: &[u8]) -> Option<&[u8]> {
+	let prefix_len = match bytes.first()? & 0b11 {
+		0b00 => 1,
+		0b01 => 2,
+		0b10 => 4,
+		_ => 1 + (bytes[0] >> 2) as usize + 4,
+	};
+	bytes.get(prefix_len..)
+}
+
+/// A `result` that isn't what we asked for, cut short so as not to flood the logs.
+pub(crate) fn malformed(response: &str) -> crate::Error {
+	let end = (0..=response.len().min(150)).rev().find(|end| response.is_char_boundary(*end));
+	crate::Error::MalformedResponse(response[..end.unwrap_or(0)].to_owned())
+}
+
+pub(crate) fn to_value(val: &serde_json::value::RawValue) -> crate::Result<serde_json::Value> {
+	serde_json::Value::from_str(val.get()).map_err(|_| malformed(val.get()))
+}
+
+fn hex_bytes(val: &str) -> crate::Result<Vec<u8>> {
+	hex::decode(val.trim_start_matches("0x")).map_err(|_| malformed(val))
+}
+
 fn extract_bytes(val: &serde_json::value::RawValue) -> crate::Result<Vec<u8>> {
-	let val2 = serde_json::Value::from_str(val.get());
-	if let Some(result_val) = val2.unwrap().get("result") {
-		if let serde_json::Value::String(meta) = result_val {
-			Ok(hex::decode(&meta[(1 + "0x".len())..meta.len() - 1])
-				.unwrap_or_else(|_| panic!("shoudl be hex: {}", meta)))
-		} else {
+	let val2 = to_value(val)?;
+	match val2.get("result") {
+		Some(serde_json::Value::String(meta)) => hex_bytes(meta),
+		Some(result_val) => {
 			#[cfg(feature = "logging")]
 			log::warn!("RPC failure : {:?}", &result_val);
 			Err(crate::Error::Node(format!("{:?}", result_val)))
-		}
-	} else {
-		let meta = val.get();
-		Ok(hex::decode(&meta[(1 + "0x".len())..meta.len() - 1])
-			.unwrap_or_else(|_| panic!("should be hex: {}", meta)))
+		},
+		None => match &val2 {
+			serde_json::Value::String(meta) => hex_bytes(meta),
+			// Nothing there.
+			serde_json::Value::Null => Ok(vec![]),
+			_ => Err(malformed(val.get())),
+		},
 	}
 }
 
-pub fn parse_changes(value: &serde_json::Value) -> Option<(&str, StateChanges)> {
-	if let serde_json::Value::Object(map) = value {
-		if let Some(serde_json::Value::Object(params_map)) = map.get("params") {
-			if let Some(serde_json::Value::String(subscription_id)) = params_map.get("subscription")
+/// Split a notification into its subscription id and result.
+pub fn parse_notification(value: serde_json::Value) -> Option<(String, serde_json::Value)> {
+	if let serde_json::Value::Object(mut map) = value {
+		if let Some(serde_json::Value::Object(mut params_map)) = map.remove("params") {
+			if let Some(serde_json::Value::String(subscription_id)) =
+				params_map.remove("subscription")
 			{
-				if let Some(serde_json::Value::Object(result)) = params_map.get("result") {
-					if let Some(serde_json::Value::String(block)) = result.get("block") {
-						if let Some(serde_json::Value::Array(changes)) = result.get("changes") {
-							debug_assert!(block.starts_with("0x"));
-							let block = hex::decode(&block[2..]).unwrap();
-							let mut state_changes = StateChanges { block, changes: vec![] };
-
-							for change in changes {
-								if let serde_json::Value::Array(key_val) = change {
-									debug_assert!(key_val.len() == 2);
-									if let serde_json::Value::String(key) = &change[0] {
-										let key = hex::decode(&key[2..]).unwrap();
-										if let serde_json::Value::String(value) = &change[1] {
-											let value = hex::decode(&value[2..]).unwrap();
-											state_changes.changes.push((key, value));
-										}
-									}
-								}
-							}
-							return Some((subscription_id.as_str(), state_changes))
-						}
-					}
+				if let Some(result) = params_map.remove("result") {
+					return Some((subscription_id, result))
 				}
 			}
 		}
@@ -92,14 +453,74 @@ pub fn parse_changes(value: &serde_json::Value) -> Option<(&str, StateChanges)>
 	None
 }
 
+/// Result of a `state_storage` notification (or one block's worth of `state_queryStorage`),
+/// `None` if it's malformed.
+pub fn parse_changes(result: &serde_json::Value) -> Option<StateChanges> {
+	if let serde_json::Value::Object(result) = result {
+		if let Some(serde_json::Value::String(block)) = result.get("block") {
+			if let Some(serde_json::Value::Array(changes)) = result.get("changes") {
+				let block = hex_bytes(block).ok()?;
+				let mut state_changes = StateChanges { block, changes: vec![] };
+
+				for change in changes {
+					let (key, value) = match change.as_array()?.as_slice() {
+						[serde_json::Value::String(key), serde_json::Value::String(value)] =>
+							(hex_bytes(key).ok()?, Some(hex_bytes(value).ok()?)),
+						// A deleted value is null.
+						[serde_json::Value::String(key), serde_json::Value::Null] =>
+							(hex_bytes(key).ok()?, None),
+						_ => return None,
+					};
+					state_changes.changes.push((key, value));
+				}
+				return Some(state_changes)
+			}
+		}
+	}
+	None
+}
+
+/// Result of an `author_extrinsicUpdate` notification.
+pub fn parse_transaction_status(result: &serde_json::Value) -> Option<TransactionStatus> {
+	let hash = |hash: &serde_json::Value| hex::decode(hash.as_str()?.trim_start_matches("0x")).ok();
+	match result {
+		serde_json::Value::String(status) => match status.as_str() {
+			"future" => Some(TransactionStatus::Future),
+			"ready" => Some(TransactionStatus::Ready),
+			"dropped" => Some(TransactionStatus::Dropped),
+			"invalid" => Some(TransactionStatus::Invalid),
+			_ => None,
+		},
+		serde_json::Value::Object(status) => {
+			let (status, detail) = status.iter().next()?;
+			match status.as_str() {
+				"broadcast" => Some(TransactionStatus::Broadcast(
+					detail
+						.as_array()?
+						.iter()
+						.filter_map(|peer| peer.as_str().map(|peer| peer.to_owned()))
+						.collect(),
+				)),
+				"inBlock" => Some(TransactionStatus::InBlock(hash(detail)?)),
+				"retracted" => Some(TransactionStatus::Retracted(hash(detail)?)),
+				"finalityTimeout" => Some(TransactionStatus::FinalityTimeout(hash(detail)?)),
+				"finalized" => Some(TransactionStatus::Finalized(hash(detail)?)),
+				"usurped" => Some(TransactionStatus::Usurped(hash(detail)?)),
+				_ => None,
+			}
+		},
+		_ => None,
+	}
+}
+
 // subscription id used to unsubscribe
 pub(crate) fn extract_subscription(val: &serde_json::value::RawValue) -> crate::Result<&str> {
-	let val2 = serde_json::Value::from_str(val.get());
-	if let Some(_result_val) = val2.unwrap().get("result") {
-		panic!("unexpected");
-	} else {
-		let meta = val.get();
-		Ok(&meta[1..meta.len() - 1])
+	let id = val.get();
+	match to_value(val)? {
+		serde_json::Value::String(_) => Ok(&id[1..id.len() - 1]),
+		// Some nodes number their subscriptions.
+		serde_json::Value::Number(_) => Ok(id),
+		_ => Err(malformed(id)),
 	}
 }
 
@@ -110,6 +531,11 @@ pub struct PolkaPipe<R: Rpc> {
 // #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
 // #[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 impl<R: Rpc + Streamable> PolkaPipe<R> {
+	/// How much the backend has been held back by its `Limits` and by the node.
+	pub fn throttling(&self) -> crate::Throttling {
+		self.rpc.throttling()
+	}
+
 	pub async fn subscribe_storage(
 		&self,
 		keys: &[&[u8]],
@@ -136,7 +562,12 @@ impl<R: Rpc + Streamable> PolkaPipe<R> {
 			buf = format!("\"{}\"", hex::encode(block_hash));
 			params.push(&buf);
 		}
-		self.rpc.stream("state_subscribeStorage", &convert_params_raw(&params)).await
+		let params = convert_params_raw(&params);
+		let updates = match self.rpc.subscribe("state_subscribeStorage", &params).await {
+			Ok(updates) => updates,
+			Err(_) => Subscription::ended(),
+		};
+		updates.filter_map(|result| parse_changes(&result))
 	}
 
 	//state_queryStorage for multiple keys over a hash range.
@@ -162,7 +593,7 @@ impl<R: Rpc + Streamable> PolkaPipe<R> {
 				.map_err(|e| {
 					#[cfg(feature = "logging")]
 					log::debug!("RPC failure: {}", &e);
-					crate::Error::Node(e.to_string())
+					crate::Error::from(e)
 				})
 		} else {
 			self.rpc
@@ -171,7 +602,7 @@ impl<R: Rpc + Streamable> PolkaPipe<R> {
 				.map_err(|e| {
 					#[cfg(feature = "logging")]
 					log::debug!("RPC failure: {:?}", &e);
-					crate::Error::Node(format!("{}", e))
+					crate::Error::from(e)
 				})
 		};
 		let val = val?;
@@ -205,7 +636,7 @@ impl<R: Rpc + Streamable> PolkaPipe<R> {
 			self.rpc.rpc("state_call", &convert_params_raw(&params)).await.map_err(|e| {
 				#[cfg(feature = "logging")]
 				log::debug!("RPC failure: {}", &e);
-				crate::Error::Node(e.to_string())
+				crate::Error::from(e)
 			})
 		} else {
 			self.rpc
@@ -214,7 +645,7 @@ impl<R: Rpc + Streamable> PolkaPipe<R> {
 				.map_err(|e| {
 					#[cfg(feature = "logging")]
 					log::debug!("RPC failure: {:?}", &e);
-					crate::Error::Node(format!("{}", e))
+					crate::Error::from(e)
 				})
 		};
 		let val = val?;
@@ -228,7 +659,7 @@ impl<R: Rpc + Streamable> PolkaPipe<R> {
 		let res = self.rpc.rpc("chain_getBlockHash", &convert_params_raw(&n)).await.map_err(|e| {
 			#[cfg(feature = "logging")]
 			log::warn!("RPC failure: {}", &e);
-			crate::Error::Node(e.to_string())
+			crate::Error::from(e)
 		});
 		let val = res?;
 		extract_bytes(&val)
@@ -240,23 +671,62 @@ impl<R: Rpc + Streamable> PolkaPipe<R> {
 	) -> crate::Result<serde_json::value::Value> {
 		if let Some(block_hash_in_hex) = block_hash_in_hex {
 			let res = self.rpc.rpc("chain_getBlock", &format!("[\"{}\"]", block_hash_in_hex)).await;
-			res.map(|raw_val| serde_json::Value::from_str(raw_val.get()).unwrap())
-				.map_err(|e| {
-					#[cfg(feature = "logging")]
-					log::warn!("RPC failure: {:?}", &e);
-					crate::Error::Node(format!("{}", e))
-				})
+			res.map_err(|e| {
+				#[cfg(feature = "logging")]
+				log::warn!("RPC failure: {:?}", &e);
+				crate::Error::from(e)
+			})
+			.and_then(|raw_val| to_value(&raw_val))
 		} else {
 			self.rpc
 				.rpc("chain_getBlock", "[]")
 				.await
-				.map(|raw_val| serde_json::Value::from_str(raw_val.get()).unwrap())
 				.map_err(|e| {
 					#[cfg(feature = "logging")]
 					log::warn!("RPC failure: {:?}", &e);
-					crate::Error::Node(format!("{}", e))
+					crate::Error::from(e)
 				})
+				.and_then(|raw_val| to_value(&raw_val))
+		}
+	}
+
+	/// A block (`chain_getBlock` JSON) along with storage items as of that block, all in
+	/// one batch. Storage comes back in the order of `keys`.
+	pub async fn query_block_with_storage(
+		&self,
+		block_hash: &[u8],
+		keys: &[&[u8]],
+	) -> crate::Result<(serde_json::value::Value, Vec<Option<Vec<u8>>>)> {
+		let block_hash = format!("\"0x{}\"", hex::encode(block_hash));
+		let mut params = vec![convert_params_raw(&[&block_hash])];
+		for key in keys {
+			let key = format!("\"0x{}\"", hex::encode(key));
+			params.push(convert_params_raw(&[&key, &block_hash]));
+		}
+		let mut requests = vec![("chain_getBlock", params[0].as_str())];
+		requests.extend(params[1..].iter().map(|params| ("state_getStorage", params.as_str())));
+
+		let mut results = self.rpc.rpc_batch(&requests).await.into_iter().map(|res| {
+			res.map_err(|e| {
+				#[cfg(feature = "logging")]
+				log::warn!("RPC failure: {:?}", &e);
+				crate::Error::from(e)
+			})
+		});
+		let block = results.next().ok_or(crate::Error::ChainUnavailable)??;
+		let block = to_value(&block)?;
+		let mut storage = Vec::with_capacity(keys.len());
+		for res in results {
+			storage.push(match serde_json::Value::from_str(res?.get()) {
+				Ok(serde_json::Value::String(value)) => Some(
+					hex::decode(value.trim_start_matches("0x"))
+						.map_err(|_| crate::Error::ParseStorageItem)?,
+				),
+				Ok(serde_json::Value::Null) => None,
+				_ => return Err(crate::Error::ParseStorageItem),
+			});
 		}
+		Ok((block, storage))
 	}
 
 	pub async fn state_get_keys(
@@ -272,22 +742,22 @@ impl<R: Rpc + Streamable> PolkaPipe<R> {
 			self.rpc
 				.rpc("state_getKeys", &convert_params_raw(&params))
 				.await
-				.map(|raw_val| serde_json::Value::from_str(raw_val.get()).unwrap())
 				.map_err(|e| {
 					#[cfg(feature = "logging")]
 					log::debug!("RPC failure: {}", &e);
-					crate::Error::Node(e.to_string())
+					crate::Error::from(e)
 				})
+				.and_then(|raw_val| to_value(&raw_val))
 		} else {
 			self.rpc
 				.rpc("state_getKeys", &format!("[\"{}\"]", key))
 				.await
-				.map(|raw_val| serde_json::Value::from_str(raw_val.get()).unwrap())
 				.map_err(|e| {
 					#[cfg(feature = "logging")]
 					log::warn!("RPC failure: {:?}", &e);
-					crate::Error::Node(format!("{}", e))
+					crate::Error::from(e)
 				})
+				.and_then(|raw_val| to_value(&raw_val))
 		}
 	}
 
@@ -301,39 +771,175 @@ impl<R: Rpc + Streamable> PolkaPipe<R> {
 			let buf = hex::encode(as_of);
 			let buf = format!("\"0x{}\"", buf);
 			let count = count.to_string();
-			let params = vec![key, &count, buf.as_str()];
+			// No start key.
+			let params = vec![key, &count, "null", buf.as_str()];
 
 			self.rpc
 				.rpc("state_getKeysPaged", &convert_params_raw(&params))
 				.await
-				.map(|raw_val| serde_json::Value::from_str(raw_val.get()).unwrap())
 				.map_err(|e| {
 					#[cfg(feature = "logging")]
 					log::debug!("RPC failure: {}", &e);
-					crate::Error::Node(e.to_string())
+					crate::Error::from(e)
 				})
+				.and_then(|raw_val| to_value(&raw_val))
 		} else {
 			self.rpc
 				.rpc("state_getKeysPaged", &format!("[\"{}\", {}]", key, count))
 				.await
-				.map(|raw_val| serde_json::Value::from_str(raw_val.get()).unwrap())
 				.map_err(|e| {
 					#[cfg(feature = "logging")]
 					log::warn!("RPC failure: {:?}", &e);
-					crate::Error::Node(format!("{}", e))
+					crate::Error::from(e)
 				})
+				.and_then(|raw_val| to_value(&raw_val))
+		}
+	}
+
+	/// How `keys` changed over the blocks from `from` to `to` (the best block if `None`), one
+	/// `StateChanges` for each block where any of them did. The first has them as of `from`.
+	/// Nodes limit how many blocks can be asked about at once.
+	pub async fn query_storage_range(
+		&self,
+		keys: &[&[u8]],
+		from: &[u8],
+		to: Option<&[u8]>,
+	) -> crate::Result<Vec<StateChanges>> {
+		let keys = hex_array(keys);
+		let from = format!("\"0x{}\"", hex::encode(from));
+		let to = to.map(|to| format!("\"0x{}\"", hex::encode(to)));
+		let mut params = vec![keys.as_str(), from.as_str()];
+		params.extend(to.as_deref());
+		let res = self.rpc.rpc("state_queryStorage", &convert_params_raw(&params)).await;
+		let val = res.map_err(|e| {
+			#[cfg(feature = "logging")]
+			log::warn!("RPC failure: {:?}", &e);
+			crate::Error::from(e)
+		})?;
+		match to_value(&val)? {
+			serde_json::Value::Array(blocks) => blocks
+				.iter()
+				.map(|changes| parse_changes(changes).ok_or_else(|| malformed(val.get())))
+				.collect(),
+			_ => Err(malformed(val.get())),
+		}
+	}
+
+	/// `keys` as of a block (the best block if `None`) in one request, in the order they were
+	/// asked for.
+	pub async fn query_storage_at(
+		&self,
+		keys: &[&[u8]],
+		as_of: Option<&[u8]>,
+	) -> crate::Result<Vec<Option<Vec<u8>>>> {
+		let keys_param = hex_array(keys);
+		let as_of = as_of.map(|as_of| format!("\"0x{}\"", hex::encode(as_of)));
+		let mut params = vec![keys_param.as_str()];
+		params.extend(as_of.as_deref());
+		let res = self.rpc.rpc("state_queryStorageAt", &convert_params_raw(&params)).await;
+		let val = res.map_err(|e| {
+			#[cfg(feature = "logging")]
+			log::warn!("RPC failure: {:?}", &e);
+			crate::Error::from(e)
+		})?;
+		let mut changes = match to_value(&val)? {
+			// There's only the one block.
+			serde_json::Value::Array(blocks) => match blocks.first() {
+				Some(changes) => parse_changes(changes).ok_or_else(|| malformed(val.get()))?,
+				None => return Ok(vec![None; keys.len()]),
+			},
+			_ => return Err(malformed(val.get())),
+		};
+		Ok(keys
+			.iter()
+			.map(|key| {
+				let change = changes.changes.iter_mut().find(|(changed, _)| changed == key);
+				change.and_then(|(_, value)| value.take())
+			})
+			.collect())
+	}
+
+	/// The trie nodes that prove the values of `keys` as of a block (the best block if
+	/// `None`), to check them against its state root.
+	pub async fn query_read_proof(
+		&self,
+		keys: &[&[u8]],
+		as_of: Option<&[u8]>,
+	) -> crate::Result<ReadProof> {
+		let keys = hex_array(keys);
+		let as_of = as_of.map(|as_of| format!("\"0x{}\"", hex::encode(as_of)));
+		let mut params = vec![keys.as_str()];
+		params.extend(as_of.as_deref());
+		let res = self.rpc.rpc("state_getReadProof", &convert_params_raw(&params)).await;
+		let val = res.map_err(|e| {
+			#[cfg(feature = "logging")]
+			log::warn!("RPC failure: {:?}", &e);
+			crate::Error::from(e)
+		})?;
+		let proof = to_value(&val)?;
+		let hex = |item: &serde_json::Value| item.as_str().map(hex_bytes);
+		let at = proof.get("at").and_then(hex).ok_or_else(|| malformed(val.get()))??;
+		let nodes = proof.get("proof").and_then(|proof| proof.as_array());
+		let nodes = nodes.ok_or_else(|| malformed(val.get()))?;
+		let proof = nodes
+			.iter()
+			.map(|node| hex(node).unwrap_or_else(|| Err(malformed(val.get()))))
+			.collect::<crate::Result<_>>()?;
+		Ok(ReadProof { at, proof })
+	}
+
+	/// The keys starting with `prefix` (e.g. all the entries of a storage map), fetched
+	/// `page_size` at a time as they're asked for.
+	pub fn storage_keys(
+		&self,
+		prefix: &[u8],
+		page_size: u32,
+		as_of: Option<&[u8]>,
+	) -> StorageKeys<'_, R> {
+		StorageKeys {
+			pipe: self,
+			prefix: prefix.to_vec(),
+			page_size,
+			as_of: as_of.map(|as_of| as_of.to_vec()),
+			start: None,
+			done: false,
 		}
 	}
 
 	pub async fn query_metadata(&self, as_of: Option<&[u8]>) -> crate::Result<Vec<u8>> {
-		self.query_state_call("Metadata_metadata", b"", as_of).await.map(|mut v| {
-			//TODO find a more efficient way
-			v.remove(0);
-			v.remove(0);
-			v.remove(0);
-			v.remove(0);
-			v
-		})
+		let res = self.query_state_call("Metadata_metadata", b"", as_of).await?;
+		// Skip the (4 byte) compact length prefix.
+		res.get(4..).map(<[u8]>::to_vec).ok_or_else(|| malformed(&hex::encode(&res)))
+	}
+
+	/// Metadata of a given version (e.g. 15 to get the runtime API descriptions).
+	/// `None` if the runtime doesn't support that version.
+	pub async fn query_metadata_at_version(
+		&self,
+		version: u32,
+		as_of: Option<&[u8]>,
+	) -> crate::Result<Option<Vec<u8>>> {
+		let res = self
+			.query_state_call("Metadata_metadata_at_version", &version.to_le_bytes(), as_of)
+			.await?;
+		// Option<OpaqueMetadata>
+		match res.split_first() {
+			Some((1, opaque)) => skip_compact_prefix(opaque)
+				.map(|metadata| Some(metadata.to_vec()))
+				.ok_or(crate::Error::ParseStorageItem),
+			_ => Ok(None),
+		}
+	}
+
+	/// Call a runtime API method (e.g. `Core` / `version`) with SCALE encoded args.
+	pub async fn query_runtime_api(
+		&self,
+		api: &str,
+		method: &str,
+		args: &[u8],
+		as_of: Option<&[u8]>,
+	) -> crate::Result<Vec<u8>> {
+		self.query_state_call(&format!("{}_{}", api, method), args, as_of).await
 	}
 
 	pub async fn submit(&self, ext: impl AsRef<[u8]> + Send) -> crate::Result<()> {
@@ -342,13 +948,274 @@ impl<R: Rpc + Streamable> PolkaPipe<R> {
 		log::debug!("Extrinsic: {}", extrinsic);
 
 		let _res = self
-			.rpc //could do author_submitAndWatchExtrinsic
+			.rpc
 			.rpc("author_submitExtrinsic", &convert_params_raw(&[&extrinsic]))
 			.await
-			.map_err(|e| crate::Error::Node(e.to_string()))?;
+			.map_err(crate::Error::from)?;
 
 		#[cfg(feature = "logging")]
 		log::debug!("Extrinsic {:x?}", _res);
 		Ok(())
 	}
+
+	/// Submit an extrinsic and follow it through the pool and into a (finalized) block.
+	/// An extrinsic the node rejects outright is an error rather than an `Invalid` update.
+	pub async fn submit_and_watch(
+		&self,
+		ext: impl AsRef<[u8]> + Send,
+	) -> crate::Result<impl Stream<Item = TransactionStatus>> {
+		let extrinsic = format!("\"0x{}\"", hex::encode(ext.as_ref()));
+		#[cfg(feature = "logging")]
+		log::debug!("Extrinsic: {}", extrinsic);
+
+		let updates = self
+			.rpc
+			.subscribe("author_submitAndWatchExtrinsic", &convert_params_raw(&[&extrinsic]))
+			.await?;
+		Ok(updates.filter_map(|result| parse_transaction_status(&result)))
+	}
+}
+
+/// Pages of storage keys, see `PolkaPipe::storage_keys`.
+pub struct StorageKeys<'a, R: Rpc + Streamable> {
+	pipe: &'a PolkaPipe<R>,
+	prefix: Vec<u8>,
+	page_size: u32,
+	as_of: Option<Vec<u8>>,
+	// The last key of the page before.
+	start: Option<Vec<u8>>,
+	done: bool,
+}
+
+impl<R: Rpc + Streamable> StorageKeys<'_, R> {
+	/// The next page of keys, `None` once there are no more.
+	pub async fn next_page(&mut self) -> crate::Result<Option<Vec<Vec<u8>>>> {
+		if self.done {
+			return Ok(None)
+		}
+		let hex_param = |bytes: &[u8]| format!("\"0x{}\"", hex::encode(bytes));
+		let prefix = hex_param(&self.prefix);
+		let count = self.page_size.to_string();
+		let start = self.start.as_deref().map_or_else(|| "null".to_string(), hex_param);
+		let as_of = self.as_of.as_deref().map(hex_param);
+		let mut params = vec![prefix.as_str(), count.as_str(), start.as_str()];
+		params.extend(as_of.as_deref());
+
+		let res = self.pipe.rpc.rpc("state_getKeysPaged", &convert_params_raw(&params)).await;
+		let val = res.map_err(|e| {
+			#[cfg(feature = "logging")]
+			log::warn!("RPC failure: {:?}", &e);
+			crate::Error::from(e)
+		})?;
+		let keys = match to_value(&val)? {
+			serde_json::Value::Array(keys) => keys
+				.iter()
+				.map(|key| key.as_str().ok_or_else(|| malformed(val.get())).and_then(hex_bytes))
+				.collect::<crate::Result<Vec<_>>>()?,
+			_ => return Err(malformed(val.get())),
+		};
+		// A page that isn't full is the last.
+		self.done = keys.is_empty() || keys.len() < self.page_size as usize;
+		self.start = keys.last().cloned();
+		Ok(if keys.is_empty() { None } else { Some(keys) })
+	}
+
+	/// The next page of keys along with their values (as of the same block).
+	pub async fn next_entries(
+		&mut self,
+	) -> crate::Result<Option<Vec<(Vec<u8>, Option<Vec<u8>>)>>> {
+		let keys = match self.next_page().await? {
+			Some(keys) => keys,
+			None => return Ok(None),
+		};
+		let key_refs: Vec<&[u8]> = keys.iter().map(|key| key.as_slice()).collect();
+		let values = self.pipe.query_storage_at(&key_refs, self.as_of.as_deref()).await?;
+		Ok(Some(keys.into_iter().zip(values).collect()))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn can_parse_transaction_status() {
+		let notification = serde_json::json!({
+			"jsonrpc": "2.0",
+			"method": "author_extrinsicUpdate",
+			"params": {
+				"subscription": "abc",
+				"result": { "inBlock": "0x0102" }
+			}
+		});
+		let (subscription_id, result) = parse_notification(notification).unwrap();
+		assert_eq!(subscription_id, "abc");
+		assert_eq!(parse_transaction_status(&result), Some(TransactionStatus::InBlock(vec![1, 2])));
+
+		let ready = serde_json::json!("ready");
+		assert_eq!(parse_transaction_status(&ready), Some(TransactionStatus::Ready));
+		let broadcast = serde_json::json!({ "broadcast": ["peer1", "peer2"] });
+		assert_eq!(
+			parse_transaction_status(&broadcast),
+			Some(TransactionStatus::Broadcast(vec!["peer1".into(), "peer2".into()]))
+		);
+		let finalized = parse_transaction_status(&serde_json::json!({ "finalized": "0xff" }));
+		assert!(finalized.unwrap().is_final());
+	}
+
+	#[test]
+	fn can_build_batch_request() {
+		let batch =
+			batch_request(&[(1, "chain_getBlock", "[]"), (2, "state_getStorage", "[\"0x00\"]")]);
+		let batch: serde_json::Value = serde_json::from_str(&batch).unwrap();
+		assert_eq!(batch[1]["id"], 2);
+		assert_eq!(batch[1]["method"], "state_getStorage");
+		assert_eq!(batch[1]["params"][0], "0x00");
+	}
+
+	#[test]
+	fn early_notifications_are_kept() {
+		let mut subscriptions = Subscriptions::default();
+		subscriptions.notify(serde_json::json!({
+			"params": { "subscription": "abc", "result": "ready" }
+		}));
+		let unsubscribe = async_std::channel::unbounded().0;
+		let mut subscription = subscriptions.add("state_subscribeStorage", "abc", unsubscribe);
+		assert_eq!(subscription.try_recv(), Some(serde_json::json!("ready")));
+	}
+
+	#[test]
+	fn dropping_a_subscription_unsubscribes() {
+		let mut subscriptions = Subscriptions::default();
+		let (unsubscribe, unsubscribes) = async_std::channel::unbounded();
+		let subscription = subscriptions.add("chain_subscribeNewHeads", "abc", unsubscribe);
+		assert!(unsubscribes.try_recv().is_err());
+
+		drop(subscription);
+		let request: serde_json::Value =
+			serde_json::from_str(&unsubscribes.try_recv().unwrap()).unwrap();
+		assert_eq!(request["method"], "chain_unsubscribeNewHeads");
+		assert_eq!(request["params"], serde_json::json!(["abc"]));
+
+		// Anything still on its way isn't held on to.
+		for _ in 0..2 {
+			subscriptions.notify(serde_json::json!({
+				"params": { "subscription": "abc", "result": "0x01" }
+			}));
+		}
+		assert!(subscriptions.streams.is_empty() && subscriptions.early.is_empty());
+	}
+
+	#[test]
+	fn early_notifications_are_bounded() {
+		let mut subscriptions = Subscriptions::default();
+		let notify = |subscriptions: &mut Subscriptions, id: &str| {
+			subscriptions.notify(serde_json::json!({
+				"params": { "subscription": id, "result": "0x01" }
+			}));
+		};
+		for _ in 0..MAX_EARLY_NOTIFICATIONS * 2 {
+			notify(&mut subscriptions, "abc");
+		}
+		for id in 0..MAX_EARLY_SUBSCRIPTIONS {
+			notify(&mut subscriptions, &id.to_string());
+		}
+		assert_eq!(subscriptions.early.len(), MAX_EARLY_SUBSCRIPTIONS);
+		// The longest waiting is given up on.
+		assert!(subscriptions.early.iter().all(|(id, _)| id != "abc"));
+		assert!(subscriptions.early.iter().all(|(_, early)| early.len() == 1));
+
+		let unsubscribe = async_std::channel::unbounded().0;
+		for _ in 0..MAX_EARLY_NOTIFICATIONS * 2 {
+			notify(&mut subscriptions, "def");
+		}
+		let mut subscription = subscriptions.add("state_subscribeStorage", "def", unsubscribe);
+		let mut held = 0;
+		while subscription.try_recv().is_some() {
+			held += 1;
+		}
+		assert_eq!(held, MAX_EARLY_NOTIFICATIONS);
+	}
+
+	#[test]
+	fn closed_subscriptions_are_bounded() {
+		let mut subscriptions = Subscriptions::default();
+		for id in 0..MAX_CLOSED * 2 {
+			let id = id.to_string();
+			let unsubscribe = async_std::channel::unbounded().0;
+			drop(subscriptions.add("chain_subscribeNewHeads", &id, unsubscribe));
+			subscriptions.notify(serde_json::json!({
+				"params": { "subscription": id, "result": "0x01" }
+			}));
+		}
+		assert!(subscriptions.streams.is_empty() && subscriptions.early.is_empty());
+		assert_eq!(subscriptions.closed.len(), MAX_CLOSED);
+	}
+
+	#[test]
+	fn malformed_results_are_errors() {
+		let raw = |json: &str| serde_json::value::RawValue::from_string(json.into()).unwrap();
+		assert_eq!(extract_bytes(&raw("\"0x0102\"")).unwrap(), vec![1, 2]);
+		for malformed in ["\"0xnothex\"", "[1, 2]", "{}"] {
+			let bytes = extract_bytes(&raw(malformed));
+			assert!(matches!(bytes, Err(crate::Error::MalformedResponse(_))));
+		}
+
+		assert_eq!(extract_subscription(&raw("\"abc\"")).unwrap(), "abc");
+		assert_eq!(extract_subscription(&raw("7")).unwrap(), "7");
+		assert!(extract_subscription(&raw("{\"result\": \"abc\"}")).is_err());
+
+		let changes = |change| serde_json::json!({ "block": "0x01", "changes": [change] });
+		assert!(parse_changes(&changes(serde_json::json!(["0x02", "0x03"]))).is_some());
+		assert!(parse_changes(&changes(serde_json::json!(["0x02", "0xzz"]))).is_none());
+		assert!(parse_changes(&changes(serde_json::json!("0x02"))).is_none());
+		let deleted = parse_changes(&changes(serde_json::json!(["0x02", null]))).unwrap();
+		assert_eq!(deleted.changes, vec![(vec![2], None)]);
+	}
+
+	#[test]
+	fn malformed_messages_are_ignored() {
+		for msg in ["", "}{", "\"0x01\"", "{\"result\": ", "[1, 2]", "{\"result\": {\"id\": 1"] {
+			match parse_incoming(msg) {
+				None => {},
+				Some(Incoming::Responses(responses)) => assert!(responses.is_empty(), "{}", msg),
+				Some(Incoming::Notification(_)) => panic!("{} isn't a notification", msg),
+			}
+		}
+		// No telling who it's for.
+		let answer = r#"{"jsonrpc": "2.0", "id": null, "error": {"code": -32700, "message": ""}}"#;
+		assert!(matches!(parse_incoming(answer), Some(Incoming::Responses(r)) if r.is_empty()));
+
+		let batch = r#"[{"jsonrpc": "2.0", "id": 2, "result": "0x"}, "junk"]"#;
+		assert!(matches!(parse_incoming(batch), Some(Incoming::Responses(r)) if r[0].0 == 2));
+	}
+
+	#[test]
+	fn malformed_responses_are_answered() {
+		let answers = |msg| match parse_incoming(msg) {
+			Some(Incoming::Responses(responses)) => responses,
+			_ => panic!("{} should answer a request", msg),
+		};
+		for msg in [
+			r#"{"jsonrpc": "2.0", "id": 3, "result": "#,
+			r#"{"jsonrpc": "2.0", "result": {"id": 2, "x": "}{"}, "id" :3, "#,
+		] {
+			let answer = answers(msg);
+			assert!(matches!(answer[..], [(3, Err(error::Error::Json(_)))]), "{}", msg);
+		}
+		// Something that's not a response at all still fails the request.
+		let answer = answers(r#"{"jsonrpc": "2.0", "id": 3, "result": 1, "error": 2}"#);
+		assert!(matches!(answer[..], [(3, Err(error::Error::Json(_)))]));
+
+		let batch = r#"[{"jsonrpc": "2.0", "id": 2, "result": "0x"}, {"id": 4, "error": 5}]"#;
+		let answer = answers(batch);
+		assert!(matches!(answer[..], [(2, Ok(_)), (4, Err(_))]));
+	}
+
+	#[test]
+	fn knows_how_to_unsubscribe() {
+		assert_eq!(unsubscribe_method("state_subscribeStorage"), "state_unsubscribeStorage");
+		assert_eq!(unsubscribe_method("author_submitAndWatchExtrinsic"), "author_unwatchExtrinsic");
+		assert_eq!(unsubscribe_method("chainHead_v1_follow"), "chainHead_v1_unfollow");
+	}
 }
@@ -0,0 +1,350 @@
+//! The new JSON-RPC spec (https://paritytech.github.io/json-rpc-interface-spec/):
+//! `chainHead_v1_*` to follow the head of the chain and `archive_v1_*` for history.
+use crate::{
+	prelude::*,
+	rpc::{malformed, to_value, Rpc, Streamable, Subscription},
+	PolkaPipe,
+};
+use alloc::collections::VecDeque;
+use serde_json::{json, Value};
+
+/// A `chainHead_v1_follow` subscription. Every block it reports stays pinned on the node
+/// (so it can be queried) until it's unpinned. Dropping it unfollows.
+pub struct ChainHead {
+	events: Subscription,
+	// Events that turned up while waiting on an operation.
+	backlog: VecDeque<Value>,
+}
+
+/// What happened to the head of the chain. Hashes of finalized blocks are oldest first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FollowEvent {
+	/// The finalized block(s) we start from.
+	Initialized(Vec<Vec<u8>>),
+	NewBlock { hash: Vec<u8>, parent: Vec<u8> },
+	BestBlockChanged(Vec<u8>),
+	/// Newly finalized blocks, and blocks on abandoned forks that can now be unpinned.
+	Finalized { finalized: Vec<Vec<u8>>, pruned: Vec<Vec<u8>> },
+	/// The node has given up on the subscription, follow again.
+	Stop,
+}
+
+impl ChainHead {
+	pub async fn next_event(&mut self) -> Option<FollowEvent> {
+		loop {
+			let event = match self.backlog.pop_front() {
+				Some(event) => event,
+				None => self.events.recv().await?,
+			};
+			// Otherwise it's left over from an operation nobody is waiting on any more.
+			if let Some(event) = parse_follow_event(&event) {
+				return Some(event)
+			}
+		}
+	}
+
+	/// Like `next_event` but doesn't wait for one.
+	pub fn try_next_event(&mut self) -> Option<FollowEvent> {
+		loop {
+			let event = match self.backlog.pop_front() {
+				Some(event) => event,
+				None => self.events.try_recv()?,
+			};
+			if let Some(event) = parse_follow_event(&event) {
+				return Some(event)
+			}
+		}
+	}
+
+	/// Next event of an operation (body / storage / call), keeping hold of any others.
+	async fn operation_event(&mut self, operation_id: &str) -> crate::Result<Value> {
+		let is_ours = |event: &Value| event["operationId"].as_str() == Some(operation_id);
+		if let Some(pos) = self.backlog.iter().position(is_ours) {
+			return self.backlog.remove(pos).ok_or(crate::Error::ChainUnavailable)
+		}
+		loop {
+			let event = self.events.recv().await.ok_or(crate::Error::ChainUnavailable)?;
+			if is_ours(&event) {
+				return Ok(event)
+			}
+			self.backlog.push_back(event);
+		}
+	}
+}
+
+fn parse_follow_event(event: &Value) -> Option<FollowEvent> {
+	let hashes = |hashes: &Value| -> Option<Vec<_>> {
+		hashes.as_array()?.iter().map(from_hex).collect()
+	};
+	match event["event"].as_str()? {
+		"initialized" => Some(FollowEvent::Initialized(hashes(&event["finalizedBlockHashes"])?)),
+		"newBlock" => Some(FollowEvent::NewBlock {
+			hash: from_hex(&event["blockHash"])?,
+			parent: from_hex(&event["parentBlockHash"])?,
+		}),
+		"bestBlockChanged" =>
+			Some(FollowEvent::BestBlockChanged(from_hex(&event["bestBlockHash"])?)),
+		"finalized" => Some(FollowEvent::Finalized {
+			finalized: hashes(&event["finalizedBlockHashes"])?,
+			pruned: hashes(&event["prunedBlockHashes"])?,
+		}),
+		"stop" => Some(FollowEvent::Stop),
+		_ => None,
+	}
+}
+
+fn from_hex(value: &Value) -> Option<Vec<u8>> {
+	hex::decode(value.as_str()?.trim_start_matches("0x")).ok()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+	format!("0x{}", hex::encode(bytes))
+}
+
+/// `[{"key": .., "type": "value"}, ..]`
+fn storage_items(keys: &[&[u8]]) -> Value {
+	keys.iter().map(|key| json!({ "key": to_hex(key), "type": "value" })).collect()
+}
+
+/// Put the value of a storage item against the key it's for.
+fn store_item(keys: &[&[u8]], values: &mut [Option<Vec<u8>>], item: &Value) {
+	if let Some(key) = from_hex(&item["key"]) {
+		if let Some(pos) = keys.iter().position(|k| *k == key.as_slice()) {
+			values[pos] = from_hex(&item["value"]);
+		}
+	}
+}
+
+fn operation_failed(event: &Value) -> crate::Error {
+	crate::Error::Node(match event["error"].as_str() {
+		Some(error) => error.to_string(),
+		None => event["event"].as_str().unwrap_or("operation failed").to_string(),
+	})
+}
+
+impl<R: Rpc + Streamable> PolkaPipe<R> {
+	async fn call_v1(&self, method: &str, params: Value) -> crate::Result<Value> {
+		let res = self.rpc.rpc(method, &params.to_string()).await.map_err(|e| {
+			#[cfg(feature = "logging")]
+			log::debug!("RPC failure: {}", &e);
+			crate::Error::from(e)
+		})?;
+		to_value(&res)
+	}
+
+	/// Names of the methods the node supports, to find out if it has these ones.
+	pub async fn rpc_methods(&self) -> crate::Result<Vec<String>> {
+		let res = self.call_v1("rpc_methods", json!([])).await?;
+		res["methods"]
+			.as_array()
+			.map(|methods| methods.iter().filter_map(|m| m.as_str().map(String::from)).collect())
+			.ok_or_else(|| malformed(&res.to_string()))
+	}
+
+	pub async fn chain_head_follow(&self) -> crate::Result<ChainHead> {
+		let events = self.rpc.subscribe("chainHead_v1_follow", "[false]").await?;
+		Ok(ChainHead { events, backlog: VecDeque::new() })
+	}
+
+	/// Operations answer with an id, the outcome turns up as follow events.
+	async fn start_operation(&self, method: &str, params: Value) -> crate::Result<String> {
+		let res = self.call_v1(method, params).await?;
+		match (res["result"].as_str(), res["operationId"].as_str()) {
+			(Some("started"), Some(operation_id)) => Ok(operation_id.to_string()),
+			_ => Err(crate::Error::Node(format!("{} not started: {}", method, res))),
+		}
+	}
+
+	/// SCALE encoded header of a pinned block.
+	pub async fn chain_head_header(
+		&self,
+		head: &ChainHead,
+		hash: &[u8],
+	) -> crate::Result<Option<Vec<u8>>> {
+		let res =
+			self.call_v1("chainHead_v1_header", json!([head.events.id, to_hex(hash)])).await?;
+		Ok(from_hex(&res))
+	}
+
+	/// Extrinsics of a pinned block.
+	pub async fn chain_head_body(
+		&self,
+		head: &mut ChainHead,
+		hash: &[u8],
+	) -> crate::Result<Vec<Vec<u8>>> {
+		let operation_id = self
+			.start_operation("chainHead_v1_body", json!([head.events.id, to_hex(hash)]))
+			.await?;
+		let event = head.operation_event(&operation_id).await?;
+		if event["event"] != "operationBodyDone" {
+			return Err(operation_failed(&event))
+		}
+		event["value"]
+			.as_array()
+			.and_then(|extrinsics| extrinsics.iter().map(from_hex).collect())
+			.ok_or_else(|| malformed(&event.to_string()))
+	}
+
+	/// Storage values as of a pinned block, in the order of `keys`.
+	pub async fn chain_head_storage(
+		&self,
+		head: &mut ChainHead,
+		hash: &[u8],
+		keys: &[&[u8]],
+	) -> crate::Result<Vec<Option<Vec<u8>>>> {
+		let params = json!([head.events.id, to_hex(hash), storage_items(keys), null]);
+		let operation_id = self.start_operation("chainHead_v1_storage", params).await?;
+		let mut values = vec![None; keys.len()];
+		loop {
+			let event = head.operation_event(&operation_id).await?;
+			match event["event"].as_str() {
+				Some("operationStorageItems") =>
+					for item in event["items"].as_array().into_iter().flatten() {
+						store_item(keys, &mut values, item);
+					},
+				Some("operationWaitingForContinue") => {
+					self.call_v1("chainHead_v1_continue", json!([head.events.id, operation_id]))
+						.await?;
+				},
+				Some("operationStorageDone") => return Ok(values),
+				_ => return Err(operation_failed(&event)),
+			}
+		}
+	}
+
+	/// Call a runtime function (e.g. `Metadata_metadata`) as of a pinned block.
+	pub async fn chain_head_call(
+		&self,
+		head: &mut ChainHead,
+		hash: &[u8],
+		function: &str,
+		args: &[u8],
+	) -> crate::Result<Vec<u8>> {
+		let params = json!([head.events.id, to_hex(hash), function, to_hex(args)]);
+		let operation_id = self.start_operation("chainHead_v1_call", params).await?;
+		let event = head.operation_event(&operation_id).await?;
+		if event["event"] != "operationCallDone" {
+			return Err(operation_failed(&event))
+		}
+		from_hex(&event["output"]).ok_or_else(|| malformed(&event.to_string()))
+	}
+
+	/// Let the node forget about blocks we're done with.
+	pub async fn chain_head_unpin(&self, head: &ChainHead, hashes: &[&[u8]]) -> crate::Result<()> {
+		let hashes: Vec<_> = hashes.iter().map(|hash| to_hex(hash)).collect();
+		self.call_v1("chainHead_v1_unpin", json!([head.events.id, hashes])).await.map(|_| ())
+	}
+
+	pub async fn archive_finalized_height(&self) -> crate::Result<u32> {
+		let res = self.call_v1("archive_v1_finalizedHeight", json!([])).await?;
+		res.as_u64().map(|height| height as u32).ok_or_else(|| malformed(&res.to_string()))
+	}
+
+	/// Hashes of the blocks at a height (more than one if it's not finalized yet).
+	pub async fn archive_hash_by_height(&self, height: u32) -> crate::Result<Vec<Vec<u8>>> {
+		let res = self.call_v1("archive_v1_hashByHeight", json!([height])).await?;
+		res.as_array()
+			.and_then(|hashes| hashes.iter().map(from_hex).collect())
+			.ok_or_else(|| malformed(&res.to_string()))
+	}
+
+	/// SCALE encoded header.
+	pub async fn archive_header(&self, hash: &[u8]) -> crate::Result<Option<Vec<u8>>> {
+		let res = self.call_v1("archive_v1_header", json!([to_hex(hash)])).await?;
+		Ok(from_hex(&res))
+	}
+
+	pub async fn archive_body(&self, hash: &[u8]) -> crate::Result<Option<Vec<Vec<u8>>>> {
+		let res = self.call_v1("archive_v1_body", json!([to_hex(hash)])).await?;
+		Ok(res.as_array().and_then(|extrinsics| extrinsics.iter().map(from_hex).collect()))
+	}
+
+	/// Call a runtime function (e.g. `Metadata_metadata`) as of a block.
+	pub async fn archive_call(
+		&self,
+		hash: &[u8],
+		function: &str,
+		args: &[u8],
+	) -> crate::Result<Vec<u8>> {
+		let res = self
+			.call_v1("archive_v1_call", json!([to_hex(hash), function, to_hex(args)]))
+			.await?;
+		if res["success"] == true {
+			from_hex(&res["value"]).ok_or_else(|| malformed(&res.to_string()))
+		} else {
+			Err(operation_failed(&res))
+		}
+	}
+
+	/// Storage values as of a block, in the order of `keys`.
+	pub async fn archive_storage(
+		&self,
+		hash: &[u8],
+		keys: &[&[u8]],
+	) -> crate::Result<Vec<Option<Vec<u8>>>> {
+		let params = json!([to_hex(hash), storage_items(keys), null]).to_string();
+		let mut events = self.rpc.subscribe("archive_v1_storage", &params).await?;
+		let mut values = vec![None; keys.len()];
+		while let Some(event) = events.recv().await {
+			match event["event"].as_str() {
+				Some("storage") => store_item(keys, &mut values, &event),
+				Some("storageDone") => return Ok(values),
+				_ => return Err(operation_failed(&event)),
+			}
+		}
+		Err(crate::Error::ChainUnavailable)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::rpc::Subscriptions;
+
+	#[test]
+	fn can_parse_follow_events() {
+		let event = json!({
+			"event": "finalized",
+			"finalizedBlockHashes": ["0x01", "0x02"],
+			"prunedBlockHashes": ["0x03"]
+		});
+		let finalized =
+			FollowEvent::Finalized { finalized: vec![vec![1], vec![2]], pruned: vec![vec![3]] };
+		assert_eq!(parse_follow_event(&event), Some(finalized));
+		let event = json!({ "event": "newBlock", "blockHash": "0x02", "parentBlockHash": "0x01" });
+		assert_eq!(
+			parse_follow_event(&event),
+			Some(FollowEvent::NewBlock { hash: vec![2], parent: vec![1] })
+		);
+		// Operation events aren't follow events.
+		let event = json!({ "event": "operationBodyDone", "operationId": "1", "value": [] });
+		assert_eq!(parse_follow_event(&event), None);
+	}
+
+	#[test]
+	fn operation_events_dont_get_lost() {
+		let mut subscriptions = Subscriptions::default();
+		let unsubscribe = async_std::channel::unbounded().0;
+		let events = subscriptions.add("chainHead_v1_follow", "sub", unsubscribe);
+		let mut head = ChainHead { events, backlog: VecDeque::new() };
+		for result in [
+			json!({ "event": "bestBlockChanged", "bestBlockHash": "0x01" }),
+			json!({ "event": "operationCallDone", "operationId": "7" }),
+		] {
+			subscriptions.notify(json!({ "params": { "subscription": "sub", "result": result } }));
+		}
+
+		let done = async_std::task::block_on(head.operation_event("7")).unwrap();
+		assert_eq!(done["event"], "operationCallDone");
+		assert_eq!(head.try_next_event(), Some(FollowEvent::BestBlockChanged(vec![1])));
+		assert_eq!(head.try_next_event(), None);
+	}
+
+	#[test]
+	fn storage_items_match_their_keys() {
+		let keys: [&[u8]; 2] = [&[1], &[2]];
+		let mut values = vec![None; 2];
+		store_item(&keys, &mut values, &json!({ "key": "0x02", "value": "0xff" }));
+		assert_eq!(values, vec![None, Some(vec![0xff])]);
+	}
+}
@@ -1,20 +1,26 @@
 use crate::{
+	limit::Limiter,
 	prelude::*,
-	rpc::{self, Rpc, RpcResult, StateChanges, Streamable},
+	rpc::{
+		self, extract_subscription, Incoming, OnDrop, Rpc, RpcResult, Streamable, Subscription,
+		Subscriptions,
+	},
+	Limits, Throttling,
 };
-use async_std::{sync::Mutex, task};
+use async_std::{channel, future::timeout, sync::Mutex, task};
 use futures::{channel::mpsc, prelude::*};
 use futures_channel::oneshot;
-use jsonrpc::{
-	error::{result_to_response, standard_error, RpcError, StandardError},
-	serde_json,
-};
+use jsonrpc::error::{standard_error, StandardError};
 use lazy_static::lazy_static;
 use smoldot_light::ChainId;
 use std::{
 	collections::{hash_map::DefaultHasher, BTreeMap},
 	hash::{Hash, Hasher},
-	sync::Arc,
+	sync::{
+		atomic::{AtomicU8, Ordering},
+		Arc,
+	},
+	time::Duration,
 };
 
 type Id = u8;
@@ -32,25 +38,20 @@ lazy_static! {
 #[derive(Clone)]
 pub struct Backend {
 	chain_id: ChainId,
-	messages: Arc<Mutex<BTreeMap<Id, oneshot::Sender<rpc::Response>>>>,
+	messages: Arc<Mutex<BTreeMap<Id, oneshot::Sender<rpc::Answer>>>>,
+	streams: Arc<Mutex<Subscriptions>>,
+	last_id: Arc<AtomicU8>,
+	timeout: Duration,
+	limiter: Arc<Limiter>,
+	/// Dropped subscriptions queue their unsubscribe requests here.
+	unsubscribe: channel::Sender<String>,
 }
 
 impl Streamable for Backend {
-	async fn stream(
-		&self,
-		method: &str,
-		params: &str,
-	) -> async_std::channel::Receiver<StateChanges> {
-		let _result = self.rpc(method, params).await;
-		panic!("unsupported for now");
-		// let (sender, recv) = async_std::channel::unbounded();
-		// if let Ok(result_subscription) = result {
-		// 	if let Ok(result) = extract_subscription(&result_subscription) {
-		// 		self.streams.lock().await.insert(result.to_owned(), sender);
-		// 	}
-		// }
-
-		// recv
+	async fn subscribe(&self, method: &str, params: &str) -> crate::Result<Subscription> {
+		let result_subscription = self.rpc(method, params).await?;
+		let subscription_id = extract_subscription(&result_subscription)?;
+		Ok(self.streams.lock().await.add(method, subscription_id, self.unsubscribe.clone()))
 	}
 }
 
@@ -117,17 +118,60 @@ impl Backend {
 					// In this example, this feature isn't used. The chain simply has `()`.
 					user_data: (),
 				})
-				.unwrap();
-
-			let backend = Backend { chain_id, messages: Arc::new(Mutex::new(BTreeMap::new())) };
+				.map_err(|_| crate::Error::ChainUnavailable)?;
+
+			let (unsubscribe, unsubscribes) = channel::unbounded();
+			let backend = Backend {
+				chain_id,
+				messages: Arc::new(Mutex::new(BTreeMap::new())),
+				streams: Default::default(),
+				last_id: Default::default(),
+				timeout: rpc::DEFAULT_TIMEOUT,
+				limiter: Default::default(),
+				unsubscribe,
+			};
 			backend.process_incoming_messages(json_rpc_responses_rx);
+			backend.process_unsubscribes(unsubscribes);
 			chains.push((hash, backend.clone()));
 			Ok(backend)
 		}
 	}
 
-	async fn next_id(&self) -> Id {
-		self.messages.lock().await.keys().last().unwrap_or(&0) + 1
+	/// How long to wait for each answer, `rpc::DEFAULT_TIMEOUT` otherwise.
+	pub fn with_timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = timeout;
+		self
+	}
+
+	/// How many requests may be in flight or start each second, `Limits::default()` otherwise.
+	pub fn with_limits(mut self, limits: Limits) -> Self {
+		self.limiter = Arc::new(Limiter::new(limits));
+		self
+	}
+
+	/// Ids aren't reused straight away so a late answer can't be taken for another one's.
+	/// 0 is left for requests nobody waits on.
+	fn next_id(&self) -> Id {
+		loop {
+			let id = self.last_id.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+			if id != 0 {
+				return id
+			}
+		}
+	}
+
+	/// Sends the unsubscribe requests of dropped subscriptions.
+	fn process_unsubscribes(&self, unsubscribes: channel::Receiver<String>) {
+		let chain_id = self.chain_id;
+		task::spawn(async move {
+			while let Ok(msg) = unsubscribes.recv().await {
+				#[cfg(feature = "logging")]
+				log::debug!("Unsubscribing {}", msg);
+				if let Some(client) = CLIENT.lock().await.as_mut() {
+					let _ = client.json_rpc_request(msg, chain_id);
+				}
+			}
+		});
 	}
 
 	fn process_incoming_messages(
@@ -135,26 +179,11 @@ impl Backend {
 		mut rx: futures_channel::mpsc::Receiver<std::string::String>,
 	) {
 		let messages = self.messages.clone();
+		let streams = self.streams.clone();
 
 		task::spawn(async move {
 			while let Some(msg) = rx.next().await {
-				let res: rpc::Response = serde_json::from_str(&msg).unwrap_or_else(|_| {
-					result_to_response(
-						Err(standard_error(StandardError::ParseError, None)),
-						().into(),
-					)
-				});
-				if res.id.is_u64() {
-					let id = res.id.as_u64().unwrap() as Id;
-					#[cfg(feature = "logging")]
-					log::trace!("Answering request {}", id);
-					let mut messages = messages.lock().await;
-					if let Some(channel) = messages.remove(&id) {
-						channel.send(res).expect("receiver waiting");
-						#[cfg(feature = "logging")]
-						log::debug!("Answered request id: {}", id);
-					}
-				}
+				dispatch(&msg, &messages, &streams).await;
 			}
 			#[cfg(feature = "logging")]
 			log::warn!("WS connection closed");
@@ -162,18 +191,73 @@ impl Backend {
 	}
 }
 
+/// Hand a message from the node to whoever is waiting on it.
+async fn dispatch(
+	msg: &str,
+	messages: &Mutex<BTreeMap<Id, oneshot::Sender<rpc::Answer>>>,
+	streams: &Mutex<Subscriptions>,
+) {
+	match rpc::parse_incoming(msg) {
+		Some(Incoming::Responses(responses)) =>
+			for (id, res) in responses {
+				let id = match Id::try_from(id) {
+					Ok(id) => id,
+					// Not an id we gave out, so nobody is waiting on it.
+					Err(_) => {
+						#[cfg(feature = "logging")]
+						log::warn!("Ignoring answer to unknown id {}", id);
+						continue
+					},
+				};
+				#[cfg(feature = "logging")]
+				log::trace!("Answering request {}", id);
+				let mut messages = messages.lock().await;
+				if let Some(channel) = messages.remove(&id) {
+					// Whoever asked might have given up already.
+					let _ = channel.send(res);
+					#[cfg(feature = "logging")]
+					log::debug!("Answered request id: {}", id);
+				}
+			},
+		Some(Incoming::Notification(notification)) => streams.lock().await.notify(notification),
+		None => {
+			#[cfg(feature = "logging")]
+			log::warn!("Ignoring malformed JSON-RPC message: {}", msg);
+		},
+	}
+}
+
 // #[async_trait]
 impl Rpc for Backend {
 	/// HTTP based JSONRpc request expecting an hex encoded result
 	async fn rpc(&self, method: &str, params: &str) -> RpcResult {
-		let id = self.next_id().await;
+		self.limiter.rpc(|| self.send(method, params)).await
+	}
+
+	fn throttling(&self) -> Throttling {
+		self.limiter.throttling()
+	}
+}
+
+impl Backend {
+	async fn send(&self, method: &str, params: &str) -> RpcResult {
+		let id = self.next_id();
 		#[cfg(feature = "logging")]
 		log::debug!("RPC `{}` (ID={})", method, id);
 
 		// Store a sender that will notify our receiver when a matching message arrives
-		let (sender, recv) = oneshot::channel::<rpc::Response>();
+		let (sender, recv) = oneshot::channel::<rpc::Answer>();
 		let messages = self.messages.clone();
 		messages.lock().await.insert(id, sender);
+		// Stop listening for the answer if we give up on it.
+		let _pending = OnDrop(|| {
+			if let Some(mut waiting) = messages.try_lock() {
+				waiting.remove(&id);
+			} else {
+				let messages = messages.clone();
+				task::spawn(async move { messages.lock().await.remove(&id) });
+			}
+		});
 
 		let msg = format!(
 			"{{\"id\":{}, \"jsonrpc\": \"2.0\", \"method\":\"{}\", \"params\":{}}}",
@@ -193,52 +277,52 @@ impl Rpc for Backend {
 			})?;
 
 		// wait for the matching response to arrive
-		let res = recv.await;
+		let res = timeout(self.timeout, recv).await.map_err(|_| rpc::timed_out())?;
 		// println!("RPC response: {:?}", &res);
 		let res = res.map_err(|_| standard_error(StandardError::InternalError, None))?;
-		if let Some(result) = res.result {
-			Ok(result)
-		} else {
-			Err(jsonrpc::Error::Rpc(RpcError {
-				code: 42,
-				message: format!("Error result: {:?}", &res),
-				data: None,
-			}))
-		}
+		res.and_then(rpc::response_result)
 	}
 }
 
 /// For smoldot tests we just check that we can retrieve the latest bits.
 #[cfg(test)]
 mod tests {
+	use super::*;
+	use crate::mock::{transcript, Node};
+
 	fn init() {
 		let _ = env_logger::builder().is_test(true).try_init();
 	}
 
-	async fn polkadot_backend() -> crate::PolkaPipe<super::Backend> {
-		if cfg!(debug_assertions) {
-			panic!("This is not the mode you are looking for. Smoldot is slow (minutes) in debug mode.");
-		}
-		crate::PolkaPipe {
-			rpc: super::Backend::new(include_str!("../chainspecs/polkadot.json"), None)
-				.await
-				.unwrap(),
-		}
+	/// Replays the test's transcript, see `mock::Node::replay`.
+	async fn polkadot_backend() -> crate::PolkaPipe<Node<super::Backend>> {
+		let rpc = Node::replay(transcript("smoldot"), || {
+			light_client(include_str!("../chainspecs/polkadot.json"), None)
+		})
+		.await
+		.unwrap();
+		crate::PolkaPipe { rpc }
+	}
+
+	async fn statemint_backend() -> crate::PolkaPipe<Node<super::Backend>> {
+		let rpc = Node::replay(transcript("smoldot_statemint"), || async {
+			let relay = light_client(include_str!("../chainspecs/polkadot.json"), None).await?;
+			light_client(include_str!("../chainspecs/statemint.json"), Some(relay)).await
+		})
+		.await
+		.unwrap();
+		crate::PolkaPipe { rpc }
 	}
 
-	async fn statemint_backend() -> crate::PolkaPipe<super::Backend> {
+	/// Only needed to record a transcript.
+	async fn light_client(
+		chainspec: &str,
+		relay: Option<super::Backend>,
+	) -> crate::Result<super::Backend> {
 		if cfg!(debug_assertions) {
 			panic!("This is not the mode you are looking for. Smoldot is slow (minutes) in debug mode.");
 		}
-		let relay_backend = polkadot_backend().await;
-		crate::PolkaPipe {
-			rpc: super::Backend::new(
-                include_str!("../chainspecs/statemint.json"),
-                Some(relay_backend.rpc),
-			)
-			.await
-			.unwrap(),
-		}
+		super::Backend::new(chainspec, relay).await
 	}
 
 	#[test]
@@ -329,6 +413,20 @@ mod tests {
 	// 	assert!(as_of_events.len() > 0);
 	// }
 
+	#[test]
+	fn malformed_responses_are_errors() {
+		let messages = Mutex::new(BTreeMap::new());
+		let streams = Mutex::new(Subscriptions::default());
+		let (sender, answer) = oneshot::channel();
+		task::block_on(messages.lock()).insert(3, sender);
+		task::block_on(dispatch(r#"{"jsonrpc": "2.0", "id": 3, "result": 1"#, &messages, &streams));
+		let answer = task::block_on(answer).unwrap().and_then(rpc::response_result);
+		assert!(matches!(
+			answer.map_err(crate::Error::from),
+			Err(crate::Error::MalformedResponse(_))
+		));
+	}
+
 	#[test]
 	fn can_get_storage_latest() {
 		init();
@@ -1,29 +1,39 @@
 use crate::{
-	rpc::{self, extract_subscription, parse_changes, Rpc, RpcResult, StateChanges, Streamable},
-	Error,
+	prelude::*,
+	limit::Limiter,
+	rpc::{
+		self, extract_subscription, Incoming, OnDrop, Rpc, RpcResult, Streamable, Subscription,
+		Subscriptions,
+	},
+	Error, Limits, Throttling,
 };
 use alloc::{collections::BTreeMap, sync::Arc};
 use async_mutex::Mutex;
-use async_std::task;
+use async_std::{channel, future::timeout, task};
 use async_tungstenite::tungstenite::{Error as WsError, Message};
-use core::time::Duration;
+use core::{
+	sync::atomic::{AtomicU16, Ordering},
+	time::Duration,
+};
 use futures_channel::oneshot;
 use futures_util::{
 	sink::{Sink, SinkExt},
 	stream::SplitSink,
 	Stream, StreamExt,
 };
-use jsonrpc::{
-	error::{result_to_response, standard_error, StandardError},
-	serde_json,
-};
+use jsonrpc::error::{standard_error, StandardError};
 
 type Id = u16;
 
 pub struct Backend<Tx> {
-	tx: Mutex<Tx>,
-	messages: Arc<Mutex<BTreeMap<Id, oneshot::Sender<rpc::Response>>>>,
-	streams: Arc<Mutex<BTreeMap<String, async_std::channel::Sender<StateChanges>>>>,
+	tx: Arc<Mutex<Tx>>,
+	messages: Arc<Mutex<BTreeMap<Id, oneshot::Sender<rpc::Answer>>>>,
+	streams: Arc<Mutex<Subscriptions>>,
+	last_id: AtomicU16,
+	timeout: Duration,
+	limiter: Limiter,
+	/// Dropped subscriptions queue their unsubscribe requests here.
+	unsubscribe: channel::Sender<String>,
 }
 
 // impl<Tx> BackendParent for Backend<Tx> where Tx: Sink<Message, Error = Error> + Unpin + Send {}
@@ -32,20 +42,10 @@ impl<Tx> Streamable for Backend<Tx>
 where
 	Tx: Sink<Message, Error = Error> + Unpin + Send,
 {
-	async fn stream(
-		&self,
-		method: &str,
-		params: &str,
-	) -> async_std::channel::Receiver<StateChanges> {
-		let result = self.rpc(method, params).await;
-		let (sender, recv) = async_std::channel::unbounded();
-		if let Ok(result_subscription) = result {
-			if let Ok(result) = extract_subscription(&result_subscription) {
-				self.streams.lock().await.insert(result.to_owned(), sender);
-			}
-		}
-
-		recv
+	async fn subscribe(&self, method: &str, params: &str) -> crate::Result<Subscription> {
+		let result_subscription = self.rpc(method, params).await?;
+		let subscription_id = extract_subscription(&result_subscription)?;
+		Ok(self.streams.lock().await.add(method, subscription_id, self.unsubscribe.clone()))
 	}
 }
 
@@ -54,14 +54,31 @@ where
 	Tx: Sink<Message, Error = Error> + Unpin + Send,
 {
 	async fn rpc(&self, method: &str, params: &str) -> RpcResult {
-		let id = self.next_id().await;
+		self.limiter.rpc(|| self.send(method, params)).await
+	}
+
+	async fn rpc_batch(&self, requests: &[(&str, &str)]) -> Vec<RpcResult> {
+		self.limiter.rpc_batch(|| self.send_batch(requests)).await
+	}
+
+	fn throttling(&self) -> Throttling {
+		self.limiter.throttling()
+	}
+}
+
+impl<Tx> Backend<Tx>
+where
+	Tx: Sink<Message, Error = Error> + Unpin + Send,
+{
+	async fn send(&self, method: &str, params: &str) -> RpcResult {
+		let id = self.next_id();
 		#[cfg(feature = "logging")]
 		log::trace!("RPC `{}` (ID={})", method, id);
 
 		// Store a sender that will notify our receiver when a matching message arrives
-		let (sender, recv) = oneshot::channel::<rpc::Response>();
-		let messages = self.messages.clone();
-		messages.lock().await.insert(id, sender);
+		let (sender, recv) = oneshot::channel::<rpc::Answer>();
+		self.messages.lock().await.insert(id, sender);
+		let _pending = self.forget_on_drop(vec![id]);
 
 		let msg = format!(
 			"{{\"id\":{}, \"jsonrpc\": \"2.0\", \"method\":\"{}\", \"params\":{}}}",
@@ -72,18 +89,92 @@ where
 		let _ = self.tx.lock().await.send(Message::Text(msg)).await;
 
 		// wait for the matching response to arrive
-		let res = recv.await;
+		let res = timeout(self.timeout, recv).await.map_err(|_| rpc::timed_out())?;
 		// println!("RPC response: {:?}", &res);
 		let res = res.map_err(|_| standard_error(StandardError::InternalError, None))?;
-		res.result.ok_or(jsonrpc::Error::Rpc(
-			res.error.unwrap_or(standard_error(StandardError::InternalError, None)),
-		))
+		res.and_then(rpc::response_result)
+	}
+	/// Sends the requests as one JSON-RPC batch (array), the incoming loop hands each
+	/// response to its waiting request by id.
+	async fn send_batch(&self, requests: &[(&str, &str)]) -> Vec<RpcResult> {
+		#[cfg(feature = "logging")]
+		log::trace!("RPC batch of {}", requests.len());
+		if requests.is_empty() {
+			return vec![]
+		}
+		let mut numbered = Vec::with_capacity(requests.len());
+		let mut waiting = Vec::with_capacity(requests.len());
+		{
+			let mut messages = self.messages.lock().await;
+			for (method, params) in requests {
+				let id = self.next_id();
+				let (sender, recv) = oneshot::channel::<rpc::Answer>();
+				messages.insert(id, sender);
+				numbered.push((id, *method, *params));
+				waiting.push(recv);
+			}
+		}
+		let _pending = self.forget_on_drop(numbered.iter().map(|(id, ..)| *id).collect());
+
+		let msg = rpc::batch_request(&numbered);
+		#[cfg(feature = "logging")]
+		log::debug!("RPC Batch {} ...", &msg[..msg.len().min(150)]);
+		let _ = self.tx.lock().await.send(Message::Text(msg)).await;
+
+		let mut results = Vec::with_capacity(waiting.len());
+		for recv in waiting {
+			results.push(match timeout(self.timeout, recv).await {
+				Ok(Ok(res)) => res.and_then(rpc::response_result),
+				Ok(Err(_)) => Err(standard_error(StandardError::InternalError, None).into()),
+				Err(_) => Err(rpc::timed_out()),
+			});
+		}
+		results
 	}
 }
 
 impl<Tx> Backend<Tx> {
-	async fn next_id(&self) -> Id {
-		self.messages.lock().await.keys().last().unwrap_or(&0) + 1
+	/// How long to wait for each answer, `rpc::DEFAULT_TIMEOUT` otherwise.
+	pub fn with_timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = timeout;
+		self
+	}
+
+	/// How many requests may be in flight or start each second, `Limits::default()` otherwise.
+	pub fn with_limits(mut self, limits: Limits) -> Self {
+		self.limiter = Limiter::new(limits);
+		self
+	}
+
+	/// Ids aren't reused straight away so a late answer can't be taken for another one's.
+	/// 0 is left for requests nobody waits on.
+	fn next_id(&self) -> Id {
+		loop {
+			let id = self.last_id.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+			if id != 0 {
+				return id
+			}
+		}
+	}
+
+	/// Stop listening for answers to these requests when we give up on them.
+	fn forget_on_drop(&self, ids: Vec<Id>) -> OnDrop<impl FnMut()> {
+		let messages = self.messages.clone();
+		OnDrop(move || {
+			if let Some(mut waiting) = messages.try_lock() {
+				for id in &ids {
+					waiting.remove(id);
+				}
+			} else {
+				let (messages, ids) = (messages.clone(), ids.clone());
+				task::spawn(async move {
+					let mut waiting = messages.lock().await;
+					for id in &ids {
+						waiting.remove(id);
+					}
+				});
+			}
+		})
 	}
 }
 
@@ -128,16 +219,46 @@ impl Backend<WS2> {
 		};
 
 		let (tx, rx) = stream.split();
+		Ok(Backend::with_connection(tx.sink_err_into(), rx))
+	}
+}
+
+impl<Tx> Backend<Tx>
+where
+	Tx: Sink<Message, Error = Error> + Unpin + Send + 'static,
+{
+	fn with_connection<Rx>(tx: Tx, rx: Rx) -> Self
+	where
+		Rx: Stream<Item = core::result::Result<Message, WsError>> + Unpin + Send + 'static,
+	{
+		let (unsubscribe, unsubscribes) = channel::unbounded();
 
 		let backend = Backend {
-			tx: Mutex::new(tx.sink_err_into()),
+			tx: Arc::new(Mutex::new(tx)),
 			messages: Arc::new(Mutex::new(BTreeMap::new())),
-			streams: Arc::new(Mutex::new(BTreeMap::new())),
+			streams: Default::default(),
+			last_id: AtomicU16::new(0),
+			timeout: rpc::DEFAULT_TIMEOUT,
+			limiter: Limiter::default(),
+			unsubscribe,
 		};
 
 		backend.spawn_process_incoming_message_loop(rx);
+		backend.spawn_unsubscribe_loop(unsubscribes);
+		backend
+	}
 
-		Ok(backend)
+	/// Sends the unsubscribe requests of dropped subscriptions, until the backend and all
+	/// of its subscriptions are gone.
+	fn spawn_unsubscribe_loop(&self, unsubscribes: channel::Receiver<String>) {
+		let tx = self.tx.clone();
+		task::spawn(async move {
+			while let Ok(msg) = unsubscribes.recv().await {
+				#[cfg(feature = "logging")]
+				log::debug!("Unsubscribing {}", msg);
+				let _ = tx.lock().await.send(Message::Text(msg)).await;
+			}
+		});
 	}
 
 	fn spawn_process_incoming_message_loop<Rx>(&self, mut rx: Rx)
@@ -154,38 +275,34 @@ impl Backend<WS2> {
 						#[cfg(feature = "logging")]
 						log::trace!("Got WS message {}", msg);
 						if let Ok(msg) = msg.to_text() {
-							let res: rpc::Response =
-								serde_json::from_str(msg).unwrap_or_else(|_| {
-									result_to_response(
-										Err(standard_error(StandardError::ParseError, None)),
-										().into(),
-									)
-								});
-							if res.id.is_u64() {
-								let id = res.id.as_u64().unwrap() as Id;
-								#[cfg(feature = "logging")]
-								log::trace!("Answering request {}", id);
-								let mut messages = messages.lock().await;
-								if let Some(channel) = messages.remove(&id) {
-									channel.send(res).expect("receiver waiting");
+							match rpc::parse_incoming(msg) {
+								Some(Incoming::Responses(responses)) =>
+									for (id, res) in responses {
+										let id = match Id::try_from(id) {
+											Ok(id) => id,
+											// Not an id we gave out, so nobody is waiting on it.
+											Err(_) => {
+												#[cfg(feature = "logging")]
+												log::warn!("Ignoring answer to unknown id {}", id);
+												continue
+											},
+										};
+										#[cfg(feature = "logging")]
+										log::trace!("Answering request {}", id);
+										let mut messages = messages.lock().await;
+										if let Some(channel) = messages.remove(&id) {
+											// Whoever asked might have given up already.
+											let _ = channel.send(res);
+											#[cfg(feature = "logging")]
+											log::debug!("Answered request id: {}", id);
+										}
+									},
+								Some(Incoming::Notification(notification)) =>
+									streams.lock().await.notify(notification),
+								None => {
 									#[cfg(feature = "logging")]
-									log::debug!("Answered request id: {}", id);
-								}
-							} else {
-								#[cfg(feature = "logging")]
-								log::debug!("Got WS message without id: {}", msg);
-
-								let res: Result<serde_json::Value, _> = serde_json::from_str(msg);
-
-								if let Ok(res) = res {
-									if let Some((subscription_id, state_changes)) =
-										parse_changes(&res)
-									{
-										let mut streams = streams.lock().await;
-										let sender = streams.get_mut(subscription_id).unwrap();
-										sender.send(state_changes).await.expect("receiver waiting");
-									}
-								}
+									log::warn!("Ignoring malformed WS message: {}", msg);
+								},
 							}
 						}
 					},
@@ -213,17 +330,23 @@ mod tests {
 #[cfg(feature = "wss")]
 #[cfg(test)]
 mod tests {
-	use crate::ws::{Backend, WS2};
+	use crate::{
+		mock::{transcript, Node},
+		ws::{Backend, WS2},
+	};
 	use async_std::stream::StreamExt;
 
 	fn init() {
 		let _ = env_logger::builder().is_test(true).try_init();
 	}
 
-	fn polkadot_backend() -> crate::PolkaPipe<Backend<WS2>> {
-		let backend =
-			async_std::task::block_on(crate::ws::Backend::new("wss://rpc.polkadot.io")).unwrap();
-		crate::PolkaPipe { rpc: backend }
+	/// Replays the test's transcript, see `mock::Node::replay`.
+	fn polkadot_backend() -> crate::PolkaPipe<Node<Backend<WS2>>> {
+		let rpc = async_std::task::block_on(Node::replay(transcript("ws"), || async {
+			Backend::new("wss://rpc.polkadot.io").await.map_err(crate::Error::from)
+		}))
+		.unwrap();
+		crate::PolkaPipe { rpc }
 	}
 
 	#[test]
@@ -354,3 +477,69 @@ mod tests {
 	// 	println!("{:?}", n);
 	// }
 }
+
+/// A node that answers every request with whatever it's told to.
+#[cfg(test)]
+mod scripted_tests {
+	use super::*;
+	use crate::PolkaPipe;
+
+	fn scripted_node(
+		answer: fn(&serde_json::Value) -> String,
+	) -> Backend<impl Sink<Message, Error = Error> + Unpin + Send> {
+		let (answers, rx) = channel::unbounded();
+		let tx = futures_util::sink::unfold(answers, move |answers, request: Message| async move {
+			let request: serde_json::Value =
+				serde_json::from_str(request.to_text().unwrap_or_default()).unwrap_or_default();
+			let _ = answers.send(Ok(Message::Text(answer(&request["id"])))).await;
+			Ok::<_, Error>(answers)
+		});
+		Backend::with_connection(Box::pin(tx), rx)
+	}
+
+	#[test]
+	fn malformed_results_are_errors() {
+		let node = scripted_node(|id| format!(r#"{{"jsonrpc":"2.0","id":{},"result":"0x?"}}"#, id));
+		let node = PolkaPipe { rpc: node };
+		let hash = task::block_on(node.query_block_hash(&[1]));
+		assert!(matches!(hash, Err(Error::MalformedResponse(_))));
+
+		// Not even JSON, but it's still an answer.
+		let node = scripted_node(|id| format!(r#"{{"jsonrpc": "2.0", "id": {}, "result": 1"#, id));
+		let node = PolkaPipe { rpc: node.with_timeout(Duration::from_secs(5)) };
+		let block = task::block_on(node.query_block(None));
+		assert!(matches!(block, Err(Error::MalformedResponse(_))));
+	}
+
+	#[test]
+	fn answers_to_ids_we_never_gave_out_are_ignored() {
+		// Would be taken for the request's id if it were truncated.
+		let node = scripted_node(|id| {
+			let id = id.as_u64().unwrap_or_default() + (1 << 16);
+			format!(r#"{{"jsonrpc":"2.0","id":{},"result":"0x01"}}"#, id)
+		});
+		let node = PolkaPipe { rpc: node.with_timeout(Duration::from_millis(50)) };
+		let hash = task::block_on(node.query_block_hash(&[1]));
+		assert!(matches!(hash, Err(Error::Timeout)));
+	}
+
+	#[test]
+	fn rpc_errors_keep_their_code() {
+		let node = PolkaPipe {
+			rpc: scripted_node(|id| {
+				let error = r#"{"code": -32601, "message": "Method not found"}"#;
+				format!(r#"{{"jsonrpc": "2.0", "id": {}, "error": {}}}"#, id, error)
+			}),
+		};
+		let storage = task::block_on(node.query_storage(&[1], None));
+		assert!(matches!(storage, Err(Error::Rpc { code: -32601, .. })));
+	}
+
+	#[test]
+	fn subscriptions_need_an_id() {
+		let node =
+			scripted_node(|id| format!(r#"{{"jsonrpc": "2.0", "id": {}, "result": {{}}}}"#, id));
+		let subscription = task::block_on(node.subscribe("chain_subscribeNewHeads", "[]"));
+		assert!(matches!(subscription, Err(Error::MalformedResponse(_))));
+	}
+}
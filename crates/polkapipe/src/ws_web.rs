@@ -1,12 +1,22 @@
 use crate::{
-	alloc::borrow::ToOwned,
-	rpc::{self, extract_subscription, parse_changes, Rpc, RpcResult, StateChanges, Streamable},
-	Error,
+	limit::{sleep, Limiter},
+	prelude::*,
+	rpc::{
+		self, extract_subscription, Incoming, OnDrop, Rpc, RpcResult, Streamable, Subscription,
+		Subscriptions,
+	},
+	Error, Limits, Throttling,
 };
-use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc};
 use async_mutex::Mutex;
-use core::time::Duration;
-use jsonrpc::serde_json;
+use async_std::channel;
+use core::{
+	future::Future,
+	pin::Pin,
+	sync::atomic::{AtomicU8, Ordering},
+	task::Poll,
+	time::Duration,
+};
 #[cfg(feature = "logging")]
 use log::info;
 use wasm_bindgen::{prelude::*, JsCast};
@@ -17,25 +27,26 @@ type Id = u8;
 #[derive(Clone)]
 pub struct Backend {
 	stream: WebSocket,
-	messages: Arc<Mutex<BTreeMap<Id, async_oneshot::Sender<rpc::Response>>>>,
-	streams: Arc<Mutex<BTreeMap<String, async_std::channel::Sender<StateChanges>>>>,
+	messages: Arc<Mutex<BTreeMap<Id, async_oneshot::Sender<rpc::Answer>>>>,
+	streams: Arc<Mutex<Subscriptions>>,
+	last_id: Arc<AtomicU8>,
+	timeout: Duration,
+	limiter: Arc<Limiter>,
+	/// Dropped subscriptions queue their unsubscribe requests here, they're sent the next
+	/// time we're sending or receiving anything.
+	unsubscribe: channel::Sender<String>,
+	unsubscribes: channel::Receiver<String>,
+	/// Requests given up on while `messages` was locked, they're forgotten the next time it's
+	/// locked.
+	forget: channel::Sender<Id>,
+	forgotten: channel::Receiver<Id>,
 }
 
 impl Streamable for Backend {
-	async fn stream(
-		&self,
-		method: &str,
-		params: &str,
-	) -> async_std::channel::Receiver<StateChanges> {
-		let result = self.rpc(method, params).await;
-		let (sender, recv) = async_std::channel::unbounded();
-		if let Ok(result_subscription) = result {
-			if let Ok(result) = extract_subscription(&result_subscription) {
-				self.streams.lock().await.insert(result.to_owned(), sender);
-			}
-		}
-
-		recv
+	async fn subscribe(&self, method: &str, params: &str) -> crate::Result<Subscription> {
+		let result_subscription = self.rpc(method, params).await?;
+		let subscription_id = extract_subscription(&result_subscription)?;
+		Ok(self.streams.lock().await.add(method, subscription_id, self.unsubscribe.clone()))
 	}
 }
 
@@ -43,14 +54,36 @@ impl Streamable for Backend {
 // #[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 impl Rpc for Backend {
 	async fn rpc(&self, method: &str, params: &str) -> RpcResult {
-		let id = self.next_id().await;
+		self.limiter.rpc(|| self.send(method, params)).await
+	}
+
+	fn throttling(&self) -> Throttling {
+		self.limiter.throttling()
+	}
+}
+
+impl Backend {
+	async fn send(&self, method: &str, params: &str) -> RpcResult {
+		let id = self.next_id();
 		#[cfg(feature = "logging")]
 		log::trace!("RPC normal `{}`", method);
 
 		// Store a sender that will notify our receiver when a matching message arrives
-		let (sender, recv) = async_oneshot::oneshot::<rpc::Response>();
+		let (sender, mut recv) = async_oneshot::oneshot::<rpc::Answer>();
 		let messages = self.messages.clone();
-		messages.lock().await.insert(id, sender);
+		{
+			let mut waiting = messages.lock().await;
+			forget_given_up(&mut waiting, &self.forgotten);
+			waiting.insert(id, sender);
+		}
+		// Stop listening for the answer if we give up on it.
+		let _pending = OnDrop(|| {
+			if let Some(mut waiting) = messages.try_lock() {
+				waiting.remove(&id);
+			} else {
+				let _ = self.forget.try_send(id);
+			}
+		});
 
 		// send rpc request
 		let msg = format!(
@@ -66,14 +99,9 @@ impl Rpc for Backend {
 			log::trace!("RPC got lock now sending {} ...", &msg[..50]);
 			while lock.ready_state() < web_sys::WebSocket::OPEN {
 				let delay = 300;
-				#[cfg(target_arch="wasm32")]
-				{
-					use gloo_timers::future::sleep;
-					sleep(Duration::from_millis(delay as u64)).await;
-				}
-				#[cfg(not(target_arch="wasm32"))]
-				async_std::task::sleep(Duration::from_millis(delay as u64)).await;
+				sleep(Duration::from_millis(delay as u64)).await;
 			}
+			send_unsubscribes(lock, &self.unsubscribes);
 
 			let res = lock.send_with_str(&msg);
 			if let Result::Err(_err) = res {
@@ -83,13 +111,19 @@ impl Rpc for Backend {
 		#[cfg(feature = "logging")]
 		log::trace!("RPC now waiting for response ...");
 
-		match recv.await {
-			Ok(msg) => {
-				match msg.result {
-					Some(val) => Ok(val),
-					None => {Err(jsonrpc::Error::EmptyBatch)},
-				}
-			},
+		let mut timeout = Box::pin(sleep(self.timeout));
+		let answer = core::future::poll_fn(|cx| match Pin::new(&mut recv).poll(cx) {
+			Poll::Ready(answer) => Poll::Ready(Some(answer)),
+			Poll::Pending => timeout.as_mut().poll(cx).map(|_| None),
+		})
+		.await;
+		let answer = match answer {
+			Some(answer) => answer,
+			None => return Err(rpc::timed_out()),
+		};
+
+		match answer {
+			Ok(msg) => msg.and_then(rpc::response_result),
 			Err(_err) => Err(jsonrpc::Error::EmptyBatch),
 		}
 	}
@@ -105,42 +139,33 @@ impl Backend {
 				#[cfg(feature = "logging")]
 				info!("Connection successfully created");
 
-				let backend =
-					Backend { stream, messages: Default::default(), streams: Default::default() };
+				let (unsubscribe, unsubscribes) = channel::unbounded();
+				let (forget, forgotten) = channel::unbounded();
+				let backend = Backend {
+					stream,
+					messages: Default::default(),
+					streams: Default::default(),
+					last_id: Default::default(),
+					timeout: rpc::DEFAULT_TIMEOUT,
+					limiter: Default::default(),
+					unsubscribe,
+					unsubscribes,
+					forget,
+					forgotten,
+				};
 
 				let messages = backend.messages.clone();
 				let streams = backend.streams.clone();
+				let stream = backend.stream.clone();
+				let unsubscribes = backend.unsubscribes.clone();
+				let forgotten = backend.forgotten.clone();
 				let onmessage_callback: Closure<dyn Fn(MessageEvent)> =
 					Closure::wrap(Box::new(move |e: MessageEvent| {
-						use pollster::FutureExt as _;
 						if let Ok(txt) = e.data().dyn_into::<js_sys::JsString>() {
 							let msg: alloc::string::String = txt.into();
-							let res: rpc::Response = serde_json::from_str(&msg).unwrap();
-							if res.id.is_u64() {
-								let id = res.id.as_u64().unwrap() as Id;
-
-								let mut messages = messages.lock().block_on();
-								if let Some(mut channel) = messages.remove(&id) {
-									channel.send(res).expect("receiver waiting");
-									#[cfg(feature = "logging")]
-									log::debug!("Answered request id: {}", id);
-								}
-							} else {
-								let res: Result<serde_json::Value, _> = serde_json::from_str(&msg);
-								if let Ok(res) = res {
-									if let Some((subscription_id, state_changes)) =
-										parse_changes(&res)
-									{
-										let mut streams = streams.lock().block_on();
-										let sender = streams.get_mut(subscription_id).unwrap();
-										sender
-											.send(state_changes)
-											.block_on()
-											.expect("receiver waiting");
-									}
-								}
-							}
+							dispatch(&msg, &messages, &streams, &forgotten);
 						}
+						send_unsubscribes(&stream, &unsubscribes);
 					}));
 
 				// forget the callback to keep it alive
@@ -153,8 +178,81 @@ impl Backend {
 		Err(Error::ChainUnavailable)
 	}
 
-	async fn next_id(&self) -> Id {
-		self.messages.lock().await.keys().last().unwrap_or(&0) + 1
+	/// How long to wait for each answer, `rpc::DEFAULT_TIMEOUT` otherwise.
+	pub fn with_timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = timeout;
+		self
+	}
+
+	/// How many requests may be in flight or start each second, `Limits::default()` otherwise.
+	pub fn with_limits(mut self, limits: Limits) -> Self {
+		self.limiter = Arc::new(Limiter::new(limits));
+		self
+	}
+
+	/// Ids aren't reused straight away so a late answer can't be taken for another one's.
+	/// 0 is left for requests nobody waits on.
+	fn next_id(&self) -> Id {
+		loop {
+			let id = self.last_id.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+			if id != 0 {
+				return id
+			}
+		}
+	}
+}
+
+/// Hand a message from the node to whoever is waiting on it.
+fn dispatch(
+	msg: &str,
+	messages: &Mutex<BTreeMap<Id, async_oneshot::Sender<rpc::Answer>>>,
+	streams: &Mutex<Subscriptions>,
+	forgotten: &channel::Receiver<Id>,
+) {
+	use pollster::FutureExt as _;
+	match rpc::parse_incoming(msg) {
+		Some(Incoming::Responses(responses)) =>
+			for (id, res) in responses {
+				let id = match Id::try_from(id) {
+					Ok(id) => id,
+					// Not an id we gave out, so nobody is waiting on it.
+					Err(_) => {
+						#[cfg(feature = "logging")]
+						log::warn!("Ignoring answer to unknown id {}", id);
+						continue
+					},
+				};
+				let mut messages = messages.lock().block_on();
+				forget_given_up(&mut messages, forgotten);
+				if let Some(mut channel) = messages.remove(&id) {
+					// Whoever asked might have given up already.
+					let _ = channel.send(res);
+					#[cfg(feature = "logging")]
+					log::debug!("Answered request id: {}", id);
+				}
+			},
+		Some(Incoming::Notification(notification)) => streams.lock().block_on().notify(notification),
+		None => {
+			#[cfg(feature = "logging")]
+			log::warn!("Ignoring malformed WS message: {}", msg);
+		},
+	}
+}
+
+/// Stop waiting on the requests that were given up on while the waiting ones were locked.
+fn forget_given_up<T>(waiting: &mut BTreeMap<Id, T>, forgotten: &channel::Receiver<Id>) {
+	while let Ok(id) = forgotten.try_recv() {
+		waiting.remove(&id);
+	}
+}
+
+/// Send the unsubscribe requests of subscriptions that have been dropped.
+fn send_unsubscribes(stream: &WebSocket, unsubscribes: &channel::Receiver<String>) {
+	if stream.ready_state() != WebSocket::OPEN {
+		return
+	}
+	while let Ok(msg) = unsubscribes.try_recv() {
+		let _ = stream.send_with_str(&msg);
 	}
 }
 
@@ -168,6 +266,20 @@ mod tests {
 	#[wasm_bindgen_test]
 	fn no_op() {}
 
+	#[wasm_bindgen_test]
+	fn malformed_responses_are_errors() {
+		use pollster::FutureExt as _;
+		let messages = Mutex::new(BTreeMap::new());
+		let streams = Mutex::new(Subscriptions::default());
+		let (_forget, forgotten) = channel::unbounded();
+		let (sender, answer) = async_oneshot::oneshot();
+		messages.lock().block_on().insert(3, sender);
+		let msg = r#"{"jsonrpc": "2.0", "id": 3, "result": 1"#;
+		dispatch(msg, &messages, &streams, &forgotten);
+		let answer = answer.block_on().unwrap().and_then(rpc::response_result);
+		assert!(matches!(answer.map_err(Error::from), Err(Error::MalformedResponse(_))));
+	}
+
 	#[cfg(target_arch = "wasm32")]
 	pub fn set_panic_hook() {
 		// When the `console_error_panic_hook` feature is enabled, we can call the
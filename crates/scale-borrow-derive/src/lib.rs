@@ -0,0 +1,277 @@
+//! `#[derive(Descale)]` for `scale-borrow`: typed views over scale data.
+//!
+//! Each field is found at `#[descale(path = "a.b")]` (relative to the type), or else at the
+//! field's name (or index for tuple fields). An enum variant is matched by its name or
+//! `#[descale(path = "Name")]`, its fields are relative to it.
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, spanned::Spanned, Attribute, Data, DeriveInput, Fields, GenericParam,
+    Lifetime, LitStr,
+};
+
+#[proc_macro_derive(Descale, attributes(descale))]
+pub fn derive_descale(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match descale(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// A field to fill in: where to find it and what it's called in the partial.
+struct Field {
+    segments: Vec<String>,
+    ty: syn::Type,
+    partial: syn::Ident,
+    /// `None` for tuple fields.
+    name: Option<syn::Ident>,
+}
+
+fn descale(input: DeriveInput) -> syn::Result<TokenStream> {
+    let lifetime = scale_lifetime(&input)?;
+    let name = &input.ident;
+    let vis = &input.vis;
+    let partial_name = format_ident!("{}DescalePartial", name);
+    let ty_generics = if input.generics.params.is_empty() {
+        quote!()
+    } else {
+        quote!(<#lifetime>)
+    };
+
+    let (fields, visit, finish) = match &input.data {
+        Data::Struct(data) => {
+            let fields = fields(&data.fields, "")?;
+            let visit = visit_fields(&fields, &lifetime);
+            let construct = construct(quote!(#name), &data.fields, &fields, &[], &lifetime);
+            let finish = quote!(Ok(#construct));
+            (fields, visit, finish)
+        }
+        Data::Enum(data) => {
+            let mut all_fields = vec![];
+            let mut arms = vec![];
+            let mut finishes = vec![];
+            for (i, variant) in data.variants.iter().enumerate() {
+                let variant_name = match path(&variant.attrs)? {
+                    Some(path) if path.value().contains('.') => {
+                        let msg = "a variant's path is just its name";
+                        return Err(syn::Error::new(path.span(), msg));
+                    }
+                    Some(path) => path.value(),
+                    None => variant.ident.to_string(),
+                };
+                let fields = fields(&variant.fields, &format!("v{}_", i))?;
+                let visit = visit_fields(&fields, &lifetime);
+                arms.push(quote! {
+                    #variant_name => {
+                        if path.is_empty() {
+                            partial.variant = Some(#i);
+                        }
+                        #visit
+                    }
+                });
+                let ident = &variant.ident;
+                let within = [variant_name];
+                let construct = construct(
+                    quote!(#name::#ident),
+                    &variant.fields,
+                    &fields,
+                    &within,
+                    &lifetime,
+                );
+                finishes.push(quote!(Some(#i) => Ok(#construct),));
+                all_fields.extend(fields);
+            }
+            let visit = quote! {
+                if let Some((head, path)) = path.split_first() {
+                    match *head {
+                        #(#arms)*
+                        other => {
+                            if path.is_empty() && found == ::scale_borrow::Found::Variant {
+                                return Err(::scale_borrow::ErrorKind::Unsupported(
+                                    format!("variant `{}`", other),
+                                ));
+                            }
+                        }
+                    }
+                }
+            };
+            let finish = quote! {
+                match partial.variant {
+                    #(#finishes)*
+                    _ => Err(::scale_borrow::ErrorKind::Missing(String::new())),
+                }
+            };
+            (all_fields, visit, finish)
+        }
+        Data::Union(_) => {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "unions can't be descaled",
+            ));
+        }
+    };
+
+    let is_enum = matches!(input.data, Data::Enum(_));
+    // Nothing to fill in, but no unused variable warnings either.
+    let unused = (!is_enum && fields.is_empty()).then(|| quote!(let _ = (&partial, path, found);));
+    let variant_field = is_enum.then(|| quote!(variant: Option<usize>,));
+    let variant_default = is_enum.then(|| quote!(variant: None,));
+    let partial_fields = fields.iter().map(|field| {
+        let (partial, ty) = (&field.partial, &field.ty);
+        quote!(#partial: <#ty as ::scale_borrow::Descale<#lifetime>>::Partial,)
+    });
+    let partial_defaults = fields.iter().map(|field| {
+        let partial = &field.partial;
+        quote!(#partial: Default::default(),)
+    });
+
+    Ok(quote! {
+        #[doc(hidden)]
+        #vis struct #partial_name<#lifetime> {
+            #variant_field
+            #(#partial_fields)*
+            _scale: ::core::marker::PhantomData<&#lifetime ()>,
+        }
+
+        #[automatically_derived]
+        impl<#lifetime> Default for #partial_name<#lifetime> {
+            fn default() -> Self {
+                #partial_name {
+                    #variant_default
+                    #(#partial_defaults)*
+                    _scale: ::core::marker::PhantomData,
+                }
+            }
+        }
+
+        #[automatically_derived]
+        impl<#lifetime> ::scale_borrow::Descale<#lifetime> for #name #ty_generics {
+            type Partial = #partial_name<#lifetime>;
+
+            fn visit(
+                partial: &mut Self::Partial,
+                path: &[&#lifetime str],
+                found: ::scale_borrow::Found<#lifetime>,
+            ) -> Result<(), ::scale_borrow::ErrorKind> {
+                #unused
+                #visit
+                Ok(())
+            }
+
+            fn finish(partial: Self::Partial) -> Result<Self, ::scale_borrow::ErrorKind> {
+                #finish
+            }
+        }
+    })
+}
+
+/// The lifetime of the borrowed data: the type's own if it has one.
+fn scale_lifetime(input: &DeriveInput) -> syn::Result<Lifetime> {
+    let mut lifetimes = vec![];
+    for param in &input.generics.params {
+        match param {
+            GenericParam::Lifetime(param) => lifetimes.push(param.lifetime.clone()),
+            _ => {
+                let msg = "only a lifetime (of the scale data) is supported";
+                return Err(syn::Error::new(param.span(), msg));
+            }
+        }
+    }
+    match lifetimes.len() {
+        0 => Ok(Lifetime::new("'scale", Span::call_site())),
+        1 => Ok(lifetimes.remove(0)),
+        _ => {
+            let msg = "only one lifetime (of the scale data) is supported";
+            Err(syn::Error::new(input.generics.span(), msg))
+        }
+    }
+}
+
+/// The `#[descale(path = "...")]` of a field or variant.
+fn path(attrs: &[Attribute]) -> syn::Result<Option<LitStr>> {
+    let mut path = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("descale")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("path") {
+                path = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `path = \"...\"`"))
+            }
+        })?;
+    }
+    Ok(path)
+}
+
+fn fields(fields: &Fields, prefix: &str) -> syn::Result<Vec<Field>> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let path = match path(&field.attrs)? {
+                Some(path) => path.value(),
+                None => match &field.ident {
+                    Some(ident) => ident.to_string(),
+                    None => i.to_string(),
+                },
+            };
+            // Precompiled: the path is only split the once, here.
+            let segments = match path.as_str() {
+                "" => vec![],
+                path => path.split('.').map(String::from).collect(),
+            };
+            Ok(Field {
+                segments,
+                ty: field.ty.clone(),
+                partial: format_ident!("{}f{}", prefix, i),
+                name: field.ident.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Pass on anything under a field's path to it.
+fn visit_fields(fields: &[Field], lifetime: &Lifetime) -> TokenStream {
+    let visits = fields.iter().map(|field| {
+        let (partial, ty, segments) = (&field.partial, &field.ty, &field.segments);
+        let len = segments.len();
+        quote! {
+            if path.starts_with(&[#(#segments),*]) {
+                <#ty as ::scale_borrow::Descale<#lifetime>>::visit(
+                    &mut partial.#partial,
+                    &path[#len..],
+                    found,
+                )?;
+            }
+        }
+    });
+    quote!(#(#visits)*)
+}
+
+/// Build the struct or variant from the finished fields.
+fn construct(
+    path: TokenStream,
+    shape: &Fields,
+    fields: &[Field],
+    within: &[String],
+    lifetime: &Lifetime,
+) -> TokenStream {
+    let values = fields.iter().map(|field| {
+        let (partial, ty) = (&field.partial, &field.ty);
+        let segments = within.iter().chain(&field.segments);
+        let value = quote! {
+            <#ty as ::scale_borrow::Descale<#lifetime>>::finish(partial.#partial)
+                .map_err(|kind| kind.within(&[#(#segments),*]))?
+        };
+        match &field.name {
+            Some(name) => quote!(#name: #value),
+            None => value,
+        }
+    });
+    match shape {
+        Fields::Named(_) => quote!(#path { #(#values),* }),
+        Fields::Unnamed(_) => quote!(#path ( #(#values),* )),
+        Fields::Unit => path,
+    }
+}
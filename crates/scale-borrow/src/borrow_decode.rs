@@ -1,18 +1,19 @@
+use crate::ErrorKind;
 use parity_scale_codec::{Compact, Decode};
 
-pub trait BorrowDecode<'scale> {
-    fn borrow_decode(data: &'scale [u8]) -> Self;
+pub trait BorrowDecode<'scale>: Sized {
+    fn borrow_decode(data: &'scale [u8]) -> Result<Self, ErrorKind>;
 }
 
 impl<'scale> BorrowDecode<'scale> for &'scale str {
-    fn borrow_decode(data: &'scale [u8]) -> Self {
-        std::str::from_utf8(data).unwrap()
+    fn borrow_decode(data: &'scale [u8]) -> Result<Self, ErrorKind> {
+        std::str::from_utf8(data).map_err(|_| ErrorKind::InvalidUtf8)
     }
 }
 
 impl<'scale> BorrowDecode<'scale> for &'scale [u8] {
-    fn borrow_decode(data: &'scale [u8]) -> Self {
-        data
+    fn borrow_decode(data: &'scale [u8]) -> Result<Self, ErrorKind> {
+        Ok(data)
     }
 }
 
@@ -20,9 +21,9 @@ macro_rules! impl_borrow_decode {
     ($($t:ty)+) => {
         $(
             impl<'scale> BorrowDecode<'scale> for $t {
-                fn borrow_decode(mut data: &'scale [u8]) -> Self {
+                fn borrow_decode(mut data: &'scale [u8]) -> Result<Self, ErrorKind> {
                     let d = &mut data;
-                    <$t>::decode(d).unwrap()
+                    Ok(<$t>::decode(d)?)
                 }
             }
         )+
@@ -30,4 +31,12 @@ macro_rules! impl_borrow_decode {
 }
 
 // Just delegate to standard scale decode
-impl_borrow_decode!(bool u8 u16 u32 u64 u128 Compact<u32> Compact<u64> Compact<u128>);
+impl_borrow_decode!(bool u8 u16 u32 u64 u128 i8 i16 i32 i64 i128);
+impl_borrow_decode!(Compact<u8> Compact<u16> Compact<u32> Compact<u64> Compact<u128>);
+
+impl<'scale> BorrowDecode<'scale> for char {
+    fn borrow_decode(data: &'scale [u8]) -> Result<Self, ErrorKind> {
+        let code = u32::borrow_decode(data)?;
+        char::from_u32(code).ok_or_else(|| ErrorKind::Codec(format!("{:#x} is not a char", code)))
+    }
+}
@@ -0,0 +1,545 @@
+//! Walk a value lazily: step through the items of a (possibly huge) sequence or the fields of a
+//! struct one at a time, only decoding the ones asked for. Everything else is skipped over by
+//! its size, so e.g. picking a few events out of a block's worth allocates nothing for the rest.
+
+use crate::{primitive_len, take, Error, ErrorKind, Value, ValueBuilder};
+use parity_scale_codec::{Compact, Decode};
+use scale_info::{
+    form::PortableForm, interner::UntrackedSymbol, prelude::any::TypeId, Field, PortableRegistry,
+    Type, TypeDef,
+};
+
+/// A value of some type at the start of some scale that hasn't been decoded (yet).
+///
+/// Errors are about the value itself (paths are relative to it) but their offsets are in the
+/// data the first cursor was made from.
+#[derive(Clone, Copy, Debug)]
+pub struct Cursor<'scale> {
+    /// From the start of the value on to the end of the data.
+    data: &'scale [u8],
+    ty_id: u32,
+    offset: usize,
+    types: &'scale PortableRegistry,
+}
+
+impl<'scale> Cursor<'scale> {
+    pub fn new(data: &'scale [u8], ty_id: u32, types: &'scale PortableRegistry) -> Self {
+        Cursor {
+            data,
+            ty_id,
+            offset: 0,
+            types,
+        }
+    }
+
+    pub fn type_id(&self) -> u32 {
+        self.ty_id
+    }
+
+    /// Where the value starts in the data.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The value's own bytes (found by skipping over it).
+    pub fn bytes(&self) -> Result<&'scale [u8], Error> {
+        let mut rest = self.data;
+        skip(&mut rest, self.ty_id, self.types).map_err(|err| self.moved(err))?;
+        Ok(&self.data[..self.data.len() - rest.len()])
+    }
+
+    /// Decode the whole value.
+    pub fn value(&self) -> Result<Value<'scale>, Error> {
+        ValueBuilder::parse_prefix(&mut &*self.data, self.ty_id, self.types)
+            .map_err(|err| self.moved(err))
+    }
+
+    /// The name of the enum variant the value is.
+    pub fn variant(&self) -> Result<&'scale str, Error> {
+        match &self.ty().map_err(|kind| self.error(kind))?.type_def {
+            TypeDef::Variant(var) => {
+                let discriminant = take(&mut &*self.data, 1).map_err(|kind| self.error(kind))?[0];
+                var.variants
+                    .iter()
+                    .find(|v| v.index == discriminant)
+                    .map(|v| v.name.as_str())
+                    .ok_or_else(|| self.error(ErrorKind::UnknownVariant(discriminant)))
+            }
+            other => Err(self.error(ErrorKind::Unsupported(format!("variant of {:?}", other)))),
+        }
+    }
+
+    /// The fields of a struct, tuple or enum variant, or the items of a sequence or array, one
+    /// at a time. A byte sequence is a sequence of `u8` items here.
+    pub fn items(&self) -> Result<Items<'scale>, Error> {
+        let mut rest = self.data;
+        let at = |kind| self.error(kind);
+        let (fields, len) = match &self.ty().map_err(at)?.type_def {
+            TypeDef::Composite(composite) => {
+                (Fields::Named(&composite.fields), composite.fields.len())
+            }
+            TypeDef::Tuple(tuple) => (Fields::Unnamed(&tuple.fields), tuple.fields.len()),
+            TypeDef::Variant(var) => {
+                let discriminant = take(&mut rest, 1).map_err(at)?[0];
+                let variant = var
+                    .variants
+                    .iter()
+                    .find(|v| v.index == discriminant)
+                    .ok_or_else(|| at(ErrorKind::UnknownVariant(discriminant)))?;
+                (Fields::Named(&variant.fields), variant.fields.len())
+            }
+            TypeDef::Sequence(seq) => {
+                let len: u64 = Compact::<u64>::decode(&mut rest)
+                    .map_err(|err| at(err.into()))?
+                    .into();
+                let id = seq.type_param.id;
+                let fixed = fixed_size(id, self.types);
+                (
+                    Fields::Repeat(id, fixed),
+                    usize::try_from(len).unwrap_or(usize::MAX),
+                )
+            }
+            TypeDef::Array(arr) => {
+                let id = arr.type_param.id;
+                let fixed = fixed_size(id, self.types);
+                (Fields::Repeat(id, fixed), arr.len as usize)
+            }
+            other => return Err(at(ErrorKind::Unsupported(format!("items of {:?}", other)))),
+        };
+        Ok(Items {
+            offset: self.offset + (self.data.len() - rest.len()),
+            rest,
+            fields,
+            next: 0,
+            len,
+            names: &[],
+            types: self.types,
+        })
+    }
+
+    /// The field (or item) called `name`, skipping over the ones before it.
+    pub fn field(&self, name: &str) -> Result<Option<Cursor<'scale>>, Error> {
+        for item in self.items()? {
+            let (field, cursor) = item?;
+            if field == name {
+                return Ok(Some(cursor));
+            }
+        }
+        Ok(None)
+    }
+
+    fn ty(&self) -> Result<&'scale Type<PortableForm>, ErrorKind> {
+        self.types
+            .resolve(self.ty_id)
+            .ok_or(ErrorKind::UnknownType(self.ty_id))
+    }
+
+    fn error(&self, kind: ErrorKind) -> Error {
+        Error::new(&[], self.ty_id, self.offset, kind)
+    }
+
+    /// An error from decoding just this value, with its offset in all the data.
+    fn moved(&self, mut err: Error) -> Error {
+        err.offset += self.offset;
+        err
+    }
+}
+
+enum Fields<'scale> {
+    Named(&'scale [Field<PortableForm>]),
+    Unnamed(&'scale [UntrackedSymbol<TypeId>]),
+    /// Items of this type, and its size if it always has the same one.
+    Repeat(u32, Option<usize>),
+}
+
+/// The fields or items of a value, from `Cursor::items`: `(name, cursor)` pairs where unnamed
+/// fields and items are called "0", "1", "2"...
+///
+/// Each item is skipped over as it's returned, so cursors can be kept or dropped as wanted.
+/// After an error there are no more items.
+pub struct Items<'scale> {
+    /// From the next item on.
+    rest: &'scale [u8],
+    offset: usize,
+    fields: Fields<'scale>,
+    next: usize,
+    len: usize,
+    /// Names for (at least) the items so far.
+    names: &'static [&'static str],
+    types: &'scale PortableRegistry,
+}
+
+impl<'scale> Items<'scale> {
+    /// How many items there are still to come.
+    pub fn remaining(&self) -> usize {
+        self.len - self.next
+    }
+}
+
+impl<'scale> Iterator for Items<'scale> {
+    type Item = Result<(&'scale str, Cursor<'scale>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.len {
+            return None;
+        }
+        let i = self.next;
+        self.next += 1;
+        if i == self.names.len() {
+            self.names = crate::nums(i + 1);
+        }
+        let num = self.names[i];
+        let (name, ty_id, fixed) = match &self.fields {
+            Fields::Named(fields) => {
+                let field = &fields[i];
+                let name = field.name.as_deref().unwrap_or(num);
+                (name, field.ty.id, fixed_size(field.ty.id, self.types))
+            }
+            Fields::Unnamed(fields) => (num, fields[i].id, fixed_size(fields[i].id, self.types)),
+            Fields::Repeat(id, fixed) => (num, *id, *fixed),
+        };
+        let item = Cursor {
+            data: self.rest,
+            ty_id,
+            offset: self.offset,
+            types: self.types,
+        };
+        let skipped = match fixed {
+            Some(size) => take(&mut self.rest, size)
+                .map(drop)
+                .map_err(|kind| item.error(kind)),
+            None => skip(&mut self.rest, ty_id, self.types).map_err(|err| item.moved(err)),
+        };
+        if let Err(mut err) = skipped {
+            self.next = self.len;
+            err.path = if err.path.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}.{}", name, err.path)
+            };
+            return Some(Err(err));
+        }
+        self.offset = item.offset + (item.data.len() - self.rest.len());
+        Some(Ok((name, item)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining()))
+    }
+}
+
+/// Move `data` past a value of the type without decoding it. Values that are always the same
+/// size (numbers, arrays and structs of them...) are jumped over in one go, and nothing is named
+/// so sequences can be any length.
+pub fn skip<'scale>(
+    data: &mut &'scale [u8],
+    ty_id: u32,
+    types: &'scale PortableRegistry,
+) -> Result<(), Error> {
+    skip_aux(data, ty_id, types, data.len())
+}
+
+/// `total` is the length of all the data, to know how far in we are.
+fn skip_aux<'scale>(
+    data: &mut &'scale [u8],
+    ty_id: u32,
+    types: &'scale PortableRegistry,
+    total: usize,
+) -> Result<(), Error> {
+    // Errors are reported at the start of the value.
+    let offset = total - data.len();
+    let error = |kind| Error::new(&[], ty_id, offset, kind);
+    if let Some(size) = fixed_size(ty_id, types) {
+        return take(data, size).map(|_| ()).map_err(error);
+    }
+    let ty = types
+        .resolve(ty_id)
+        .ok_or(ErrorKind::UnknownType(ty_id))
+        .map_err(error)?;
+    match &ty.type_def {
+        TypeDef::Composite(composite) => {
+            for (i, field) in composite.fields.iter().enumerate() {
+                skip_aux(data, field.ty.id, types, total).map_err(|err| within(err, field, i))?;
+            }
+        }
+        TypeDef::Tuple(tuple) => {
+            for (i, field) in tuple.fields.iter().enumerate() {
+                skip_aux(data, field.id, types, total).map_err(|err| within_item(err, i))?;
+            }
+        }
+        TypeDef::Variant(var) => {
+            let discriminant = take(data, 1).map_err(error)?[0];
+            let variant = var
+                .variants
+                .iter()
+                .find(|v| v.index == discriminant)
+                .ok_or(ErrorKind::UnknownVariant(discriminant))
+                .map_err(error)?;
+            for (i, field) in variant.fields.iter().enumerate() {
+                skip_aux(data, field.ty.id, types, total)
+                    .map_err(|err| within(err, field, i))
+                    .map_err(|err| prefixed(err, &variant.name))?;
+            }
+        }
+        TypeDef::Sequence(seq) => {
+            let len = Compact::<u32>::decode(data)
+                .map_err(|err| error(err.into()))?
+                .0;
+            skip_items(data, seq.type_param.id, len, types, total).map_err(error)??;
+        }
+        TypeDef::Array(arr) => {
+            skip_items(data, arr.type_param.id, arr.len, types, total).map_err(error)??;
+        }
+        TypeDef::Primitive(_) => {
+            // Only strings aren't a fixed size.
+            let len = Compact::<u32>::decode(data)
+                .map_err(|err| error(err.into()))?
+                .0;
+            take(data, len as usize).map_err(error)?;
+        }
+        TypeDef::Compact(_) => {
+            crate::take_compact_of(data, ty, types).map_err(error)?;
+        }
+        TypeDef::BitSequence(seq) => {
+            crate::take_bits(data, seq, types).map_err(error)?;
+        }
+    }
+    Ok(())
+}
+
+/// Move past `len` items of the type, in one go if they're all the same size. The outer error
+/// is about the items as a whole, the inner one about an item.
+fn skip_items<'scale>(
+    data: &mut &'scale [u8],
+    ty_id: u32,
+    len: u32,
+    types: &'scale PortableRegistry,
+    total: usize,
+) -> Result<Result<(), Error>, ErrorKind> {
+    if let Some(size) = fixed_size(ty_id, types) {
+        take(data, size.saturating_mul(len as usize))?;
+        return Ok(Ok(()));
+    }
+    for i in 0..len as usize {
+        if let Err(err) = skip_aux(data, ty_id, types, total) {
+            return Ok(Err(within_item(err, i)));
+        }
+    }
+    Ok(Ok(()))
+}
+
+/// An error about a field, from the containing value.
+fn within(err: Error, field: &Field<PortableForm>, i: usize) -> Error {
+    match &field.name {
+        Some(name) => prefixed(err, name),
+        None => within_item(err, i),
+    }
+}
+
+fn within_item(err: Error, i: usize) -> Error {
+    prefixed(err, &i.to_string())
+}
+
+fn prefixed(mut err: Error, name: &str) -> Error {
+    err.path = if err.path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", name, err.path)
+    };
+    err
+}
+
+/// The size of every value of the type, if they're all the same.
+fn fixed_size(ty_id: u32, types: &PortableRegistry) -> Option<usize> {
+    match &types.resolve(ty_id)?.type_def {
+        TypeDef::Primitive(primitive) => primitive_len(primitive),
+        TypeDef::Composite(composite) => composite
+            .fields
+            .iter()
+            .map(|field| fixed_size(field.ty.id, types))
+            .sum(),
+        TypeDef::Tuple(tuple) => tuple.fields.iter().map(|id| fixed_size(id.id, types)).sum(),
+        TypeDef::Array(arr) => fixed_size(arr.type_param.id, types)?.checked_mul(arr.len as usize),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{skip, Cursor};
+    use crate::{ErrorKind, Value, ValueBuilder};
+    use parity_scale_codec::*;
+    use scale_info::PortableRegistry;
+
+    fn make_type<T: scale_info::TypeInfo + 'static>() -> (u32, PortableRegistry) {
+        let mut types = scale_info::Registry::new();
+        let id = types.register_type(&scale_info::MetaType::new::<T>());
+        (id.id, types.into())
+    }
+
+    #[derive(Encode, scale_info::TypeInfo)]
+    enum Event {
+        Deposit { who: [u8; 32], amount: u128 },
+        Remark(Vec<u8>),
+        Included { para: u32, head: Vec<u8> },
+    }
+
+    #[derive(Encode, scale_info::TypeInfo)]
+    struct Record {
+        phase: (u32, bool),
+        event: Event,
+        topics: Vec<[u8; 32]>,
+    }
+
+    fn records(n: u32) -> Vec<Record> {
+        (0..n)
+            .map(|i| Record {
+                phase: (i, i % 2 == 0),
+                event: match i % 3 {
+                    0 => Event::Deposit {
+                        who: [i as u8; 32],
+                        amount: i as u128,
+                    },
+                    1 => Event::Remark(vec![1; i as usize]),
+                    _ => Event::Included {
+                        para: 2000 + i,
+                        head: vec![i as u8; 3],
+                    },
+                },
+                topics: vec![[7; 32]; (i % 2) as usize],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn filter_items() {
+        // Lots, to show only the ones wanted are decoded.
+        let encoded = records(20_000).encode();
+        let (id, types) = make_type::<Vec<Record>>();
+
+        let mut items = Cursor::new(&encoded, id, &types).items().unwrap();
+        assert_eq!(items.remaining(), 20_000);
+        let mut paras = vec![];
+        let mut last = None;
+        for item in &mut items {
+            let (name, record) = item.unwrap();
+            last = Some(name);
+            let event = record.field("event").unwrap().unwrap();
+            if event.variant().unwrap() != "Included" {
+                continue;
+            }
+            let para = event.field("para").unwrap().unwrap();
+            paras.push(para.value().unwrap());
+        }
+        assert_eq!(last, Some("19999"));
+        assert_eq!(paras.len(), 6666);
+        assert_eq!(paras[0], Value::U32(2002));
+        assert_eq!(paras[6665], Value::U32(2000 + 19_997));
+    }
+
+    #[test]
+    fn items_are_values() {
+        let recs = records(4);
+        let encoded = recs.encode();
+        let (id, types) = make_type::<Vec<Record>>();
+
+        let whole = ValueBuilder::parse(&encoded, id, &types).unwrap();
+        for (i, item) in Cursor::new(&encoded, id, &types)
+            .items()
+            .unwrap()
+            .enumerate()
+        {
+            let (name, record) = item.unwrap();
+            assert_eq!(name, i.to_string());
+            assert_eq!(record.bytes().unwrap(), &recs[i].encode()[..]);
+            assert_eq!(record.offset(), recs[..i].encode().len());
+            let value = record.value().unwrap();
+            assert_eq!(whole.get(name), Some(&value));
+
+            let names: Vec<_> = record.items().unwrap().map(|f| f.unwrap().0).collect();
+            assert_eq!(names, ["phase", "event", "topics"]);
+            let phase = record.field("phase").unwrap().unwrap();
+            let pair: Vec<_> = phase
+                .items()
+                .unwrap()
+                .map(|f| f.unwrap().1.value())
+                .collect();
+            assert_eq!(
+                pair,
+                [Ok(Value::U32(i as u32)), Ok(Value::Bool(i % 2 == 0))]
+            );
+        }
+        assert!(Cursor::new(&encoded, id, &types)
+            .field("4")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn skip_test() {
+        let (id, types) = make_type::<([u16; 3], (u8, u64))>();
+        let encoded = (([1u16, 2, 3], (4u8, 5u64)), 6u8).encode();
+        let mut data = &encoded[..];
+        skip(&mut data, id, &types).unwrap();
+        assert_eq!(data, [6]);
+
+        let (id, types) = make_type::<Record>();
+        let mut recs = records(3);
+        recs[2].topics = vec![[9; 32]; 2];
+        let encoded = recs.encode();
+        let mut data = &encoded[1..];
+        for record in &recs {
+            let start = data;
+            skip(&mut data, id, &types).unwrap();
+            assert_eq!(&start[..start.len() - data.len()], &record.encode()[..]);
+        }
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn skip_any_length() {
+        // More items than can be named, and not all the same size.
+        let (id, types) = make_type::<Vec<Record>>();
+        let encoded = (records(20_000), 6u8).encode();
+        let mut data = &encoded[..];
+        skip(&mut data, id, &types).unwrap();
+        assert_eq!(data, [6]);
+
+        use bitvec::prelude::*;
+        let (id, types) = make_type::<(BitVec<u32, Msb0>, Vec<BitVec<u8, Lsb0>>)>();
+        let encoded = (
+            (bitvec![u32, Msb0; 1; 40], vec![bitvec![u8, Lsb0; 1; 9]; 3]),
+            6u8,
+        )
+            .encode();
+        let mut data = &encoded[..];
+        skip(&mut data, id, &types).unwrap();
+        assert_eq!(data, [6]);
+    }
+
+    #[test]
+    fn bad_data() {
+        let encoded = records(3).encode();
+        let (id, types) = make_type::<Vec<Record>>();
+
+        // The last record's head is cut short.
+        let short = &encoded[..encoded.len() - 2];
+        let items: Vec<_> = Cursor::new(short, id, &types).items().unwrap().collect();
+        assert_eq!(items.len(), 3);
+        let err = items[2].as_ref().unwrap_err();
+        assert_eq!(err.path, "2.event.Included.head");
+        assert_eq!(err.kind, ErrorKind::NotEnoughData { needed: 3, left: 2 });
+        assert_eq!(err.offset, short.len() - 3);
+
+        let cursor = Cursor::new(&encoded, id, &types);
+        let err = cursor.variant().unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Unsupported(_)));
+        let (_, first) = cursor.items().unwrap().next().unwrap().unwrap();
+        let phase = first.field("phase").unwrap().unwrap();
+        let (_, num) = phase.items().unwrap().next().unwrap().unwrap();
+        assert!(matches!(
+            num.items().err().unwrap().kind,
+            ErrorKind::Unsupported(_)
+        ));
+    }
+}
@@ -0,0 +1,224 @@
+//! Typed views over scale: pick out just the fields you want into your own types.
+//!
+//! Usually via `#[derive(Descale)]` (feature `derive`):
+//!
+//! ```ignore
+//! #[derive(Descale)]
+//! struct CandidateIncluded<'scale> {
+//!     #[descale(path = "0.descriptor.para_id")]
+//!     para_id: u32,
+//!     #[descale(path = "0.commitments_hash")]
+//!     commitments: &'scale [u8],
+//! }
+//!
+//! let event = CandidateIncluded::parse(&data, type_id, &types)?;
+//! ```
+//!
+//! Without a path a field is found at its name (or index). Paths are relative to the type
+//! they're in, so views can be nested. `Vec<T>` has an item per index found under its path,
+//! `Option<T>` is `None` if nothing was found (over a scale `Option` it's `None` for `None`, and
+//! `T` is found at `Some.0`). An enum is decided by the variant's name (or its `path`) and its
+//! fields' paths are relative to that variant. For bytes use `&'scale [u8]`.
+use crate::{borrow_decode::BorrowDecode, skeleton_decode, Error, ErrorKind, VisitScale};
+use parity_scale_codec::Compact;
+use scale_info::{form::PortableForm, PortableRegistry, Type};
+
+/// What's at a path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Found<'scale> {
+    /// The bytes of a value (a number, string, bytes...).
+    Value(&'scale [u8]),
+    /// The path ends with the name of the enum variant chosen.
+    Variant,
+}
+
+/// A type that can be filled in from the values found as scale is walked.
+pub trait Descale<'scale>: Sized {
+    /// What has been found so far.
+    type Partial: Default;
+
+    /// Something was found at `path`, relative to this value.
+    fn visit(
+        partial: &mut Self::Partial,
+        path: &[&'scale str],
+        found: Found<'scale>,
+    ) -> Result<(), ErrorKind>;
+
+    /// Everything has been visited.
+    fn finish(partial: Self::Partial) -> Result<Self, ErrorKind>;
+
+    /// Whether what was found at `path` is any of this value. An `Option` stays `None` until
+    /// something is.
+    fn accepts(_path: &[&'scale str], _found: Found<'scale>) -> bool {
+        true
+    }
+
+    fn parse(
+        data: &'scale [u8],
+        top_type_id: u32,
+        types: &'scale PortableRegistry,
+    ) -> Result<Self, Error> {
+        let mut visitor = Visitor::<Self> {
+            partial: Default::default(),
+            names: vec![],
+        };
+        skeleton_decode(data, top_type_id, &mut visitor, types)?;
+        Self::finish(visitor.partial).map_err(|kind| {
+            let path = match &kind {
+                ErrorKind::Missing(path) => path.clone(),
+                _ => String::new(),
+            };
+            Error {
+                path,
+                type_id: top_type_id,
+                offset: data.len(),
+                kind,
+            }
+        })
+    }
+}
+
+struct Visitor<'scale, T: Descale<'scale>> {
+    partial: T::Partial,
+    /// Reused so there's no allocation per value.
+    names: Vec<&'scale str>,
+}
+
+impl<'scale, T: Descale<'scale>> Visitor<'scale, T> {
+    fn found(
+        &mut self,
+        path: &[(&'scale str, u32)],
+        found: Found<'scale>,
+    ) -> Result<(), ErrorKind> {
+        self.names.clear();
+        self.names.extend(path.iter().map(|(name, _)| *name));
+        T::visit(&mut self.partial, &self.names, found)
+    }
+}
+
+impl<'scale, T: Descale<'scale>> VisitScale<'scale> for Visitor<'scale, T> {
+    fn visit(
+        &mut self,
+        path: &[(&'scale str, u32)],
+        data: &'scale [u8],
+        _ty: &'scale Type<PortableForm>,
+        _types: &'scale PortableRegistry,
+    ) -> Result<(), ErrorKind> {
+        self.found(path, Found::Value(data))
+    }
+
+    fn visit_variant(&mut self, path: &[(&'scale str, u32)]) -> Result<(), ErrorKind> {
+        self.found(path, Found::Variant)
+    }
+}
+
+macro_rules! impl_descale_leaf {
+    ($($t:ty)+) => {
+        $(
+            impl<'scale> Descale<'scale> for $t {
+                type Partial = Option<$t>;
+
+                fn visit(
+                    partial: &mut Self::Partial,
+                    path: &[&'scale str],
+                    found: Found<'scale>,
+                ) -> Result<(), ErrorKind> {
+                    if let (true, Found::Value(data)) = (path.is_empty(), found) {
+                        *partial = Some(<$t as BorrowDecode>::borrow_decode(data)?);
+                    }
+                    Ok(())
+                }
+
+                fn finish(partial: Self::Partial) -> Result<Self, ErrorKind> {
+                    partial.ok_or_else(|| ErrorKind::Missing(String::new()))
+                }
+
+                fn accepts(path: &[&'scale str], found: Found<'scale>) -> bool {
+                    path.is_empty() && matches!(found, Found::Value(_))
+                }
+            }
+        )+
+    };
+}
+
+impl_descale_leaf!(&'scale str &'scale [u8] bool u8 u16 u32 u64 u128 Compact<u32> Compact<u64> Compact<u128>);
+
+impl<'scale, T: Descale<'scale>> Descale<'scale> for Option<T> {
+    type Partial = Option<T::Partial>;
+
+    fn visit(
+        partial: &mut Self::Partial,
+        path: &[&'scale str],
+        found: Found<'scale>,
+    ) -> Result<(), ErrorKind> {
+        let path = match within_option(path, found) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if partial.is_none() && !T::accepts(path, found) {
+            return Ok(());
+        }
+        T::visit(partial.get_or_insert_with(Default::default), path, found)
+    }
+
+    fn finish(partial: Self::Partial) -> Result<Self, ErrorKind> {
+        partial.map(T::finish).transpose()
+    }
+
+    fn accepts(path: &[&'scale str], found: Found<'scale>) -> bool {
+        within_option(path, found).is_some_and(|path| T::accepts(path, found))
+    }
+}
+
+/// Where `path` is in the value of an `Option`, if anywhere. Over a scale `Option` its variant
+/// is nothing and `Some.0` is the value, otherwise it's the value as it is.
+fn within_option<'a, 'scale>(
+    path: &'a [&'scale str],
+    found: Found<'scale>,
+) -> Option<&'a [&'scale str]> {
+    match (path, found) {
+        (["None"] | ["Some"], Found::Variant) => None,
+        (["Some", "0", rest @ ..], _) => Some(rest),
+        _ => Some(path),
+    }
+}
+
+impl<'scale, T: Descale<'scale>> Descale<'scale> for Vec<T> {
+    type Partial = Vec<T::Partial>;
+
+    fn visit(
+        partial: &mut Self::Partial,
+        path: &[&'scale str],
+        found: Found<'scale>,
+    ) -> Result<(), ErrorKind> {
+        let (index, rest) = match path.split_first() {
+            Some(split) => split,
+            // A sequence of bytes, or a mistaken path.
+            None => return Err(ErrorKind::Unsupported("use &[u8] for bytes".into())),
+        };
+        let index: usize = match index.parse() {
+            Ok(index) => index,
+            Err(_) => return Err(ErrorKind::Unsupported(format!("`{}` as an index", index))),
+        };
+        if partial.len() <= index {
+            partial.resize_with(index + 1, Default::default);
+        }
+        T::visit(&mut partial[index], rest, found)
+    }
+
+    fn finish(partial: Self::Partial) -> Result<Self, ErrorKind> {
+        partial
+            .into_iter()
+            .enumerate()
+            .map(|(i, item)| T::finish(item).map_err(|kind| kind.within(&[&i.to_string()])))
+            .collect()
+    }
+
+    fn accepts(path: &[&'scale str], found: Found<'scale>) -> bool {
+        match path.split_first() {
+            Some((index, rest)) => index.parse::<usize>().is_ok() && T::accepts(rest, found),
+            // So that `visit` says what's wrong.
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,497 @@
+//! Back to SCALE: encode a `Value` (as `ValueBuilder` makes them, possibly edited) as a type.
+//!
+//! The value has to have the shape `ValueBuilder` gives that type:
+//!
+//!    * structs and tuples are objects with their fields (`0`, `1`... for unnamed ones),
+//!    * enums are an object with one field named after the variant, holding its fields,
+//!    * sequences and arrays are objects with items `0`, `1`... or bytes if they're of `u8`.
+//!
+//! Numbers can be any size as long as the value fits in the type. `_ty` fields are ignored.
+//! A missing field is fine if `ValueBuilder` leaves it out too, e.g. an empty `Vec`.
+use crate::{Error, ErrorKind, Value};
+use parity_scale_codec::{Compact, Encode};
+use scale_info::form::PortableForm;
+use scale_info::{PortableRegistry, Type, TypeDef, TypeDefPrimitive};
+
+impl<'scale> Value<'scale> {
+    /// The SCALE encoding of this value as type `type_id`.
+    pub fn encode_as_type(&self, type_id: u32, types: &PortableRegistry) -> Result<Vec<u8>, Error> {
+        let mut out = vec![];
+        self.encode_as_type_to(type_id, types, &mut out)?;
+        Ok(out)
+    }
+
+    /// Like `encode_as_type` but appending to `out`. Error offsets are from the start of `out`.
+    pub fn encode_as_type_to(
+        &self,
+        type_id: u32,
+        types: &PortableRegistry,
+        out: &mut Vec<u8>,
+    ) -> Result<(), Error> {
+        encode_aux(&mut vec![], Some(self), type_id, types, out)
+    }
+}
+
+/// Encode `value` (`None` if it's missing) as type `id`.
+fn encode_aux<'t>(
+    stack: &mut Vec<(&'t str, u32)>,
+    value: Option<&Value>,
+    id: u32,
+    types: &'t PortableRegistry,
+    out: &mut Vec<u8>,
+) -> Result<(), Error> {
+    // Errors are reported at the start of the value.
+    let start = out.len();
+    let at = |stack: &[(&str, u32)], kind| Error::new(stack, id, start, kind);
+    let ty = types
+        .resolve(id)
+        .ok_or_else(|| at(stack, ErrorKind::UnknownType(id)))?;
+
+    match &ty.type_def {
+        TypeDef::Composite(composite) => {
+            for (i, field) in composite.fields.iter().enumerate() {
+                let name = match field.name.as_ref() {
+                    Some(name) => name.as_str(),
+                    None => crate::item_name(i),
+                };
+                stack.push((name, field.ty.id));
+                encode_aux(stack, field_of(value, name), field.ty.id, types, out)?;
+                stack.pop();
+            }
+        }
+        TypeDef::Tuple(tuple) => {
+            for (i, field) in tuple.fields.iter().enumerate() {
+                let name = crate::item_name(i);
+                stack.push((name, field.id));
+                encode_aux(stack, field_of(value, name), field.id, types, out)?;
+                stack.pop();
+            }
+        }
+        TypeDef::Variant(var) => {
+            let (name, fields) = value
+                .ok_or_else(|| at(stack, ErrorKind::Missing(String::new())))?
+                .only()
+                .ok_or_else(|| at(stack, mismatch("an enum variant", value)))?;
+            let variant = var
+                .variants
+                .iter()
+                .find(|variant| variant.name == name)
+                .ok_or_else(|| at(stack, ErrorKind::Mismatch(format!("no variant `{}`", name))))?;
+            out.push(variant.index);
+
+            stack.push((&variant.name, id));
+            for (i, field) in variant.fields.iter().enumerate() {
+                let name = match field.name.as_ref() {
+                    Some(name) => name.as_str(),
+                    None => crate::item_name(i),
+                };
+                stack.push((name, field.ty.id));
+                encode_aux(stack, field_of(Some(fields), name), field.ty.id, types, out)?;
+                stack.pop();
+            }
+            stack.pop();
+        }
+        TypeDef::Sequence(seq) => {
+            let inner = seq.type_param.id;
+            match value.and_then(|value| value.as_bytes()) {
+                Some(bytes) if is_u8(inner, types) => {
+                    Compact(bytes.len() as u32).encode_to(out);
+                    out.extend_from_slice(bytes);
+                }
+                _ => {
+                    let items: Vec<_> = items(value).map_err(|kind| at(stack, kind))?;
+                    Compact(items.len() as u32).encode_to(out);
+                    encode_items(stack, &items, inner, types, out)?;
+                }
+            }
+        }
+        TypeDef::Array(arr) => {
+            let inner = arr.type_param.id;
+            match value.and_then(|value| value.as_bytes()) {
+                Some(bytes) if is_u8(inner, types) => {
+                    if bytes.len() != arr.len as usize {
+                        let kind = ErrorKind::Mismatch(format!(
+                            "{} bytes for an array of {}",
+                            bytes.len(),
+                            arr.len
+                        ));
+                        return Err(at(stack, kind));
+                    }
+                    out.extend_from_slice(bytes);
+                }
+                _ => {
+                    let items: Vec<_> = items(value).map_err(|kind| at(stack, kind))?;
+                    if items.len() != arr.len as usize {
+                        let kind = ErrorKind::Mismatch(format!(
+                            "{} items for an array of {}",
+                            items.len(),
+                            arr.len
+                        ));
+                        return Err(at(stack, kind));
+                    }
+                    encode_items(stack, &items, inner, types, out)?;
+                }
+            }
+        }
+        TypeDef::Primitive(primitive) => {
+            let value = value.ok_or_else(|| at(stack, ErrorKind::Missing(String::new())))?;
+            encode_primitive(value, primitive, out).map_err(|kind| at(stack, kind))?;
+        }
+        TypeDef::Compact(_) => {
+            encode_compact(value, ty, types, out).map_err(|kind| at(stack, kind))?;
+        }
+        #[cfg_attr(not(feature = "bitvec"), allow(unused_variables))]
+        TypeDef::BitSequence(seq) => match value {
+            // As `ValueBuilder` decodes them: the bytes as they were.
+            Some(Value::Scale(bytes)) => out.extend_from_slice(bytes),
+            Some(Value::ScaleOwned(bytes)) => out.extend_from_slice(bytes),
+            #[cfg(feature = "bitvec")]
+            Some(Value::Bits(bits)) => {
+                let format = crate::bit_format(seq, types).map_err(|kind| at(stack, kind))?;
+                scale_bits::scale::encode_using_format_to(bits.iter(), format, out);
+            }
+            _ => return Err(at(stack, mismatch("bits", value))),
+        },
+    }
+    Ok(())
+}
+
+/// The items of a sequence or array, in order. A missing one has none.
+fn items<'a, 'scale>(
+    value: Option<&'a Value<'scale>>,
+) -> Result<Vec<Option<&'a Value<'scale>>>, ErrorKind> {
+    match value {
+        None => Ok(vec![]),
+        Some(Value::Object(fields)) => {
+            // Go by their names as `ValueBuilder` leaves out empty items.
+            let index = |name: &str| name.parse::<usize>().ok();
+            let len = fields
+                .iter()
+                .filter_map(|(name, _)| index(name))
+                .max()
+                .map_or(0, |last| last + 1);
+            // Lengths are encoded as a compact `u32`.
+            if len > u32::MAX as usize {
+                return Err(ErrorKind::TooManyItems(len));
+            }
+            let mut items = vec![None; len];
+            for (name, item) in fields.iter() {
+                if let Some(i) = index(name) {
+                    items[i] = Some(item);
+                }
+            }
+            Ok(items)
+        }
+        Some(_) => Err(mismatch("items", value)),
+    }
+}
+
+fn encode_items<'t>(
+    stack: &mut Vec<(&'t str, u32)>,
+    items: &[Option<&Value>],
+    inner: u32,
+    types: &'t PortableRegistry,
+    out: &mut Vec<u8>,
+) -> Result<(), Error> {
+    for (i, item) in items.iter().enumerate() {
+        let name = crate::item_name(i);
+        stack.push((name, inner));
+        encode_aux(stack, *item, inner, types, out)?;
+        stack.pop();
+    }
+    Ok(())
+}
+
+/// The field called `name` of an object (not of anything else).
+fn field_of<'a, 'scale>(value: Option<&'a Value<'scale>>, name: &str) -> Option<&'a Value<'scale>> {
+    value?
+        .into_iter()
+        .find(|(field, _)| *field == name)
+        .map(|(_, value)| value)
+}
+
+fn is_u8(id: u32, types: &PortableRegistry) -> bool {
+    types
+        .resolve(id)
+        .is_some_and(|ty| ty.type_def == TypeDef::Primitive(TypeDefPrimitive::U8))
+}
+
+fn mismatch(expected: &str, value: Option<&Value>) -> ErrorKind {
+    match value {
+        Some(value) => ErrorKind::Mismatch(format!("expected {}, got {:?}", expected, value)),
+        None => ErrorKind::Missing(String::new()),
+    }
+}
+
+fn encode_primitive(
+    value: &Value,
+    primitive: &TypeDefPrimitive,
+    out: &mut Vec<u8>,
+) -> Result<(), ErrorKind> {
+    // A number of type `$t`, from any size of number if it fits.
+    macro_rules! num {
+        ($t:ty, $as:ident) => {
+            value
+                .$as()
+                .and_then(|val| <$t>::try_from(val).ok())
+                .ok_or_else(|| mismatch(stringify!($t), Some(value)))?
+                .encode_to(out)
+        };
+    }
+    match primitive {
+        TypeDefPrimitive::Bool => match value {
+            Value::Bool(val) => val.encode_to(out),
+            _ => return Err(mismatch("bool", Some(value))),
+        },
+        TypeDefPrimitive::Char => match value {
+            Value::Char(val) => (*val as u32).encode_to(out),
+            _ => return Err(mismatch("char", Some(value))),
+        },
+        TypeDefPrimitive::Str => match value {
+            Value::Str(val) => val.encode_to(out),
+            _ => return Err(mismatch("str", Some(value))),
+        },
+        TypeDefPrimitive::U8 => num!(u8, as_u128),
+        TypeDefPrimitive::U16 => num!(u16, as_u128),
+        TypeDefPrimitive::U32 => num!(u32, as_u128),
+        TypeDefPrimitive::U64 => num!(u64, as_u128),
+        TypeDefPrimitive::U128 => num!(u128, as_u128),
+        TypeDefPrimitive::I8 => num!(i8, as_i128),
+        TypeDefPrimitive::I16 => num!(i16, as_i128),
+        TypeDefPrimitive::I32 => num!(i32, as_i128),
+        TypeDefPrimitive::I64 => num!(i64, as_i128),
+        TypeDefPrimitive::I128 => num!(i128, as_i128),
+        TypeDefPrimitive::U256 => match value {
+            Value::U256(bytes) => out.extend_from_slice(&bytes[..]),
+            _ => {
+                let val = value
+                    .as_u128()
+                    .ok_or_else(|| mismatch("u256", Some(value)))?;
+                out.extend_from_slice(&val.to_le_bytes());
+                out.extend_from_slice(&[0; 16]);
+            }
+        },
+        TypeDefPrimitive::I256 => match value {
+            Value::I256(bytes) => out.extend_from_slice(&bytes[..]),
+            _ => {
+                let val = value
+                    .as_i128()
+                    .ok_or_else(|| mismatch("i256", Some(value)))?;
+                out.extend_from_slice(&val.to_le_bytes());
+                // Sign extended.
+                out.extend_from_slice(&[if val < 0 { 0xff } else { 0 }; 16]);
+            }
+        },
+    }
+    Ok(())
+}
+
+fn encode_compact(
+    value: Option<&Value>,
+    ty: &Type<PortableForm>,
+    types: &PortableRegistry,
+    out: &mut Vec<u8>,
+) -> Result<(), ErrorKind> {
+    let inner = crate::compact_inner(ty, types)?;
+    let primitive = match &inner.type_def {
+        // `Compact<()>` is nothing at all.
+        TypeDef::Tuple(tuple) if tuple.fields.is_empty() => return Ok(()),
+        TypeDef::Primitive(primitive) => primitive,
+        _ => {
+            return Err(ErrorKind::Unsupported(format!(
+                "compact {:?}",
+                inner.type_def
+            )))
+        }
+    };
+    let value = value.ok_or(ErrorKind::Missing(String::new()))?;
+    let val = value
+        .as_u128()
+        .ok_or_else(|| mismatch("an unsigned number", Some(value)))?;
+    let too_big = || mismatch(&format!("a compact {:?}", primitive), Some(value));
+    match primitive {
+        TypeDefPrimitive::U8 => Compact(u8::try_from(val).map_err(|_| too_big())?).encode_to(out),
+        TypeDefPrimitive::U16 => Compact(u16::try_from(val).map_err(|_| too_big())?).encode_to(out),
+        TypeDefPrimitive::U32 => Compact(u32::try_from(val).map_err(|_| too_big())?).encode_to(out),
+        TypeDefPrimitive::U64 => Compact(u64::try_from(val).map_err(|_| too_big())?).encode_to(out),
+        TypeDefPrimitive::U128 => Compact(val).encode_to(out),
+        _ => return Err(ErrorKind::Unsupported(format!("compact {:?}", primitive))),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ErrorKind, Value, ValueBuilder};
+    use parity_scale_codec::*;
+    use scale_info::PortableRegistry;
+
+    fn make_type<T: scale_info::TypeInfo + 'static>() -> (u32, PortableRegistry) {
+        let mut types = scale_info::Registry::new();
+        let id = types.register_type(&scale_info::MetaType::new::<T>());
+        (id.id, types.into())
+    }
+
+    fn obj<'scale>(fields: Vec<(&'scale str, Value<'scale>)>) -> Value<'scale> {
+        Value::Object(Box::new(fields))
+    }
+
+    #[derive(Decode, Encode, CompactAs, scale_info::TypeInfo, Clone, Debug, PartialEq)]
+    struct Perbill(u32);
+
+    #[derive(Decode, Encode, scale_info::TypeInfo, Clone, Debug, PartialEq)]
+    struct Unit;
+
+    #[derive(Decode, Encode, scale_info::TypeInfo, Clone, Debug, PartialEq)]
+    enum Kind {
+        Empty,
+        Full(u8),
+        Named { text: String, flag: bool },
+    }
+
+    #[derive(Decode, Encode, scale_info::TypeInfo, Clone, Debug, PartialEq)]
+    struct X {
+        pair: (u8, i16),
+        #[codec(compact)]
+        small: u16,
+        #[codec(compact)]
+        commission: Perbill,
+        #[codec(compact)]
+        big: u128,
+        kinds: Vec<Kind>,
+        many: Vec<i64>,
+        none: Vec<u32>,
+        bytes: Vec<u8>,
+        hash: [u8; 4],
+        maybe: Option<u64>,
+        unit: Unit,
+        signed: i128,
+        wide: [u16; 2],
+        nested: Vec<Vec<u32>>,
+    }
+
+    fn x() -> X {
+        X {
+            pair: (1, -2),
+            small: 300,
+            commission: Perbill(50),
+            big: u128::MAX,
+            kinds: vec![
+                Kind::Empty,
+                Kind::Full(7),
+                Kind::Named {
+                    text: "hi".into(),
+                    flag: true,
+                },
+            ],
+            many: (0..12).collect(),
+            none: vec![],
+            bytes: vec![9; 40],
+            hash: [1, 2, 3, 4],
+            maybe: Some(1 << 40),
+            unit: Unit,
+            signed: -5,
+            wide: [1, 2],
+            nested: vec![vec![], vec![1]],
+        }
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let encoded = x().encode();
+        let (id, types) = make_type::<X>();
+
+        let val = ValueBuilder::parse(&encoded, id, &types).unwrap();
+        assert_eq!(val.encode_as_type(id, &types).unwrap(), encoded);
+
+        // Primitives on their own too.
+        let (id, types) = make_type::<u32>();
+        let val = ValueBuilder::parse(&[1, 2, 3, 4], id, &types).unwrap();
+        assert_eq!(val.encode_as_type(id, &types).unwrap(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn edited_test() {
+        let (id, types) = make_type::<(Option<u16>, Kind, Vec<u8>)>();
+        // Any size of number that fits, variants by name and `_ty` fields ignored.
+        let val = obj(vec![
+            ("_ty", Value::U32(99)),
+            (
+                "0",
+                obj(vec![("Some", obj(vec![("0", Value::U128(Box::new(3)))]))]),
+            ),
+            (
+                "1",
+                obj(vec![(
+                    "Named",
+                    obj(vec![
+                        ("flag", Value::Bool(false)),
+                        ("text", Value::Str("yo")),
+                    ]),
+                )]),
+            ),
+            ("2", obj(vec![("0", Value::U64(5)), ("1", Value::U8(6))])),
+        ]);
+        let expected = (
+            Some(3u16),
+            Kind::Named {
+                text: "yo".into(),
+                flag: false,
+            },
+            vec![5u8, 6],
+        );
+        assert_eq!(val.encode_as_type(id, &types).unwrap(), expected.encode());
+    }
+
+    #[test]
+    fn encode_errors_test() {
+        let (id, types) = make_type::<X>();
+        let encoded = x().encode();
+        let val = ValueBuilder::parse(&encoded, id, &types).unwrap();
+
+        let mut edited = val.clone();
+        if let Value::Object(fields) = &mut edited {
+            fields.retain(|(name, _)| *name != "maybe");
+        }
+        let err = edited.encode_as_type(id, &types).unwrap_err();
+        assert_eq!(err.path, "maybe");
+        assert_eq!(err.kind, ErrorKind::Missing(String::new()));
+
+        let mut edited = val.clone();
+        if let Value::Object(fields) = &mut edited {
+            for (name, field) in fields.iter_mut() {
+                match *name {
+                    "pair" => *field = obj(vec![("0", Value::U16(256)), ("1", Value::I16(0))]),
+                    "kinds" => *field = obj(vec![("0", obj(vec![("Nope", obj(vec![]))]))]),
+                    _ => {}
+                }
+            }
+        }
+        let err = edited.encode_as_type(id, &types).unwrap_err();
+        assert_eq!(err.path, "pair.0");
+        assert_eq!(
+            err.kind,
+            ErrorKind::Mismatch("expected u8, got U16(256)".into())
+        );
+        // Where it would have gone.
+        assert_eq!(err.offset, 0);
+
+        if let Value::Object(fields) = &mut edited {
+            fields.retain(|(name, _)| *name != "pair");
+            fields.insert(
+                0,
+                ("pair", obj(vec![("0", Value::U8(1)), ("1", Value::I16(0))])),
+            );
+        }
+        let err = edited.encode_as_type(id, &types).unwrap_err();
+        assert_eq!(err.path, "kinds.0");
+        assert_eq!(err.kind, ErrorKind::Mismatch("no variant `Nope`".into()));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "value doesn't fit: no variant `Nope` at `kinds.0` (type {}, offset 24)",
+                err.type_id
+            )
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use core::fmt::{Display, Formatter};
+
+/// Why some scale couldn't be decoded, and where it went wrong.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    /// Field names down to the value that couldn't be decoded, e.g. `outer.1.name`.
+    pub path: String,
+    /// The type of that value.
+    pub type_id: u32,
+    /// Where that value starts in the data (or would have in the output, when encoding).
+    pub offset: usize,
+    pub kind: ErrorKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The type isn't in the registry.
+    UnknownType(u32),
+    /// The data ran out.
+    NotEnoughData { needed: usize, left: usize },
+    /// The enum has no variant with this index.
+    UnknownVariant(u8),
+    InvalidUtf8,
+    /// The bytes aren't valid for the type (e.g. a bool of 2, a bad compact).
+    Codec(String),
+    /// A sequence too long for its length to be encoded.
+    TooManyItems(usize),
+    /// Types that can't be decoded (yet) - send PR please.
+    Unsupported(String),
+    /// A field of a `Descale` view wasn't in the data (the path says which), or a value to
+    /// encode is missing a field.
+    Missing(String),
+    /// The value to encode doesn't fit the type (e.g. 256 as a `u8`, an unknown variant).
+    Mismatch(String),
+}
+
+impl Error {
+    pub(crate) fn new(path: &[(&str, u32)], type_id: u32, offset: usize, kind: ErrorKind) -> Self {
+        let path: Vec<_> = path.iter().map(|(name, _)| *name).collect();
+        Error {
+            path: path.join("."),
+            type_id,
+            offset,
+            kind,
+        }
+    }
+}
+
+impl ErrorKind {
+    /// Put the path of a `Missing` field under `prefix`, for the view containing it.
+    #[doc(hidden)]
+    pub fn within(self, prefix: &[&str]) -> Self {
+        match self {
+            Self::Missing(path) => {
+                let mut full = prefix.join(".");
+                if !path.is_empty() {
+                    if !full.is_empty() {
+                        full.push('.');
+                    }
+                    full.push_str(&path);
+                }
+                Self::Missing(full)
+            }
+            other => other,
+        }
+    }
+}
+
+impl From<parity_scale_codec::Error> for ErrorKind {
+    fn from(err: parity_scale_codec::Error) -> Self {
+        ErrorKind::Codec(err.to_string())
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::UnknownType(id) => write!(f, "type {} is not in the registry", id),
+            Self::NotEnoughData { needed, left } => {
+                write!(f, "needed {} bytes but only {} left", needed, left)
+            }
+            Self::UnknownVariant(index) => write!(f, "no variant with index {}", index),
+            Self::InvalidUtf8 => write!(f, "string is not utf8"),
+            Self::Codec(err) => write!(f, "{}", err),
+            Self::TooManyItems(len) => write!(f, "too many items to encode ({})", len),
+            Self::Unsupported(what) => write!(f, "unsupported: {}", what),
+            Self::Missing(_) => write!(f, "nothing found"),
+            Self::Mismatch(what) => write!(f, "value doesn't fit: {}", what),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} at `{}` (type {}, offset {})",
+            self.kind, self.path, self.type_id, self.offset
+        )
+    }
+}
+
+impl std::error::Error for Error {}
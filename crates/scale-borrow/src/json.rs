@@ -0,0 +1,224 @@
+//! `serde::Serialize` for `Value`, so it can be shown, exported or served as JSON. The mapping:
+//!
+//!    * `Object` - an object, fields in order (including `_ty`). Tuples have fields "0", "1"...
+//!    * `Bool` - `true` / `false`.
+//!    * `Char`, `Str` - a string.
+//!    * `U8`..`U64`, `I8`..`I64` - a number.
+//!    * `U128`, `I128`, `U256`, `I256` - a decimal string, as JSON numbers can't hold them.
+//!    * `Scale`, `ScaleOwned` (bytes) - a `0x` prefixed hex string.
+//!    * `Bits` - an array of bools.
+//!
+//! `Value::with_ss58` (feature `ss58`) shows the bytes of account ids as SS58 addresses instead
+//! (`{"_ty": 0, "0": "5Grw..."}`), knowing them by their type.
+use crate::Value;
+use scale_info::PortableRegistry;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+impl<'scale> Serialize for Value<'scale> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Json {
+            value: self,
+            ss58: None,
+            in_account: false,
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "ss58")]
+impl<'a, 'scale> Serialize for crate::ss58::WithSs58<'a, 'scale> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Json {
+            value: self.value,
+            ss58: Some((self.format, self.types)),
+            in_account: self.value.is_account_id(self.types),
+        }
+        .serialize(serializer)
+    }
+}
+
+struct Json<'a, 'scale> {
+    value: &'a Value<'scale>,
+    ss58: Option<(u16, &'a PortableRegistry)>,
+    /// Whether the value is the inside of an account id.
+    #[cfg_attr(not(feature = "ss58"), allow(dead_code))]
+    in_account: bool,
+}
+
+impl<'a, 'scale> Json<'a, 'scale> {
+    fn bytes(&self, bytes: &[u8]) -> String {
+        #[cfg(feature = "ss58")]
+        if let (Some((format, _)), true) = (self.ss58, self.in_account) {
+            if let Some(address) = crate::ss58::account(format, bytes) {
+                return address;
+            }
+        }
+        format!("0x{}", hex::encode(bytes))
+    }
+}
+
+impl<'a, 'scale> Serialize for Json<'a, 'scale> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.value {
+            Value::Object(fields) => {
+                let in_account = self
+                    .ss58
+                    .is_some_and(|(_, types)| self.value.is_account_id(types));
+                let mut map = serializer.serialize_map(Some(fields.len()))?;
+                for (field, value) in fields.iter() {
+                    let value = Json {
+                        value,
+                        ss58: self.ss58,
+                        in_account,
+                    };
+                    map.serialize_entry(field, &value)?;
+                }
+                map.end()
+            }
+            Value::Bool(val) => serializer.serialize_bool(*val),
+            Value::Char(val) => serializer.serialize_char(*val),
+            Value::Str(val) => serializer.serialize_str(val),
+            Value::Scale(bytes) => serializer.serialize_str(&self.bytes(bytes)),
+            Value::ScaleOwned(bytes) => serializer.serialize_str(&self.bytes(bytes)),
+            Value::U8(val) => serializer.serialize_u8(*val),
+            Value::U16(val) => serializer.serialize_u16(*val),
+            Value::U32(val) => serializer.serialize_u32(*val),
+            Value::U64(val) => serializer.serialize_u64(*val),
+            Value::U128(val) => serializer.collect_str(val),
+            Value::I8(val) => serializer.serialize_i8(*val),
+            Value::I16(val) => serializer.serialize_i16(*val),
+            Value::I32(val) => serializer.serialize_i32(*val),
+            Value::I64(val) => serializer.serialize_i64(*val),
+            Value::I128(val) => serializer.collect_str(val),
+            Value::U256(val) => serializer.serialize_str(&decimal(val, false)),
+            Value::I256(val) => serializer.serialize_str(&decimal(val, true)),
+            #[cfg(feature = "bitvec")]
+            Value::Bits(bits) => serializer.collect_seq(bits.iter()),
+        }
+    }
+}
+
+/// A little endian 256 bit number in decimal.
+fn decimal(le_bytes: &[u8; 32], signed: bool) -> String {
+    let mut num = *le_bytes;
+    let negative = signed && num[31] & 0x80 != 0;
+    if negative {
+        // Two's complement: flip and add one.
+        let mut carry = true;
+        for byte in num.iter_mut() {
+            let (sum, overflow) = (!*byte).overflowing_add(carry as u8);
+            *byte = sum;
+            carry = overflow;
+        }
+    }
+    let mut digits = vec![];
+    while num.iter().any(|byte| *byte != 0) {
+        // Long division by 10 from the most significant byte.
+        let mut rem = 0u16;
+        for byte in num.iter_mut().rev() {
+            let acc = (rem << 8) | *byte as u16;
+            *byte = (acc / 10) as u8;
+            rem = acc % 10;
+        }
+        digits.push(b'0' + rem as u8);
+    }
+    if digits.is_empty() {
+        digits.push(b'0');
+    }
+    if negative {
+        digits.push(b'-');
+    }
+    digits.reverse();
+    String::from_utf8(digits).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_mapping() {
+        let mut u256 = [0; 32];
+        u256[..16].copy_from_slice(&u128::MAX.to_le_bytes());
+        let val = Value::Object(Box::new(vec![
+            ("_ty", Value::U32(1)),
+            ("flag", Value::Bool(true)),
+            ("name", Value::Str("hi")),
+            ("bytes", Value::Scale(&[1, 2, 255])),
+            ("owned", Value::ScaleOwned(Box::default())),
+            ("small", Value::I16(-3)),
+            ("big", Value::U128(Box::new(u128::MAX))),
+            ("neg", Value::I128(Box::new(i128::MIN))),
+            ("huge", Value::U256(&u256)),
+            (
+                "nested",
+                Value::Object(Box::new(vec![("0", Value::U64(7))])),
+            ),
+        ]));
+        assert_eq!(
+            serde_json::to_string(&val).unwrap(),
+            concat!(
+                r#"{"_ty":1,"flag":true,"name":"hi","bytes":"0x0102ff","owned":"0x","small":-3,"#,
+                r#""big":"340282366920938463463374607431768211455","#,
+                r#""neg":"-170141183460469231731687303715884105728","#,
+                r#""huge":"340282366920938463463374607431768211455","nested":{"0":7}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn u256_decimal() {
+        assert_eq!(decimal(&[0; 32], false), "0");
+        assert_eq!(decimal(&[0xff; 32], true), "-1");
+        let max = decimal(&[0xff; 32], false);
+        assert_eq!(
+            max,
+            "115792089237316195423570985008687907853269984665640564039457584007913129639935"
+        );
+        let mut min = [0; 32];
+        min[31] = 0x80;
+        assert_eq!(
+            decimal(&min, true),
+            "-57896044618658097711785492504343953926634992332820282019728792003956564819968"
+        );
+    }
+
+    #[cfg(feature = "ss58")]
+    #[test]
+    fn ss58() {
+        use parity_scale_codec::Encode;
+
+        #[derive(Encode, scale_info::TypeInfo)]
+        struct AccountId32([u8; 32]);
+        #[derive(Encode, scale_info::TypeInfo)]
+        struct Included {
+            who: AccountId32,
+            relay_parent: [u8; 32],
+        }
+        let id = [
+            0xd4, 0x35, 0x93, 0xc7, 0x15, 0xfd, 0xd3, 0x1c, 0x61, 0x14, 0x1a, 0xbd, 0x04, 0xa9,
+            0x9f, 0xd6, 0x82, 0x2c, 0x85, 0x58, 0x85, 0x4c, 0xcd, 0xe3, 0x9a, 0x56, 0x84, 0xe7,
+            0xa5, 0x6d, 0xa2, 0x7d,
+        ];
+        let mut types = scale_info::Registry::new();
+        let ty = types
+            .register_type(&scale_info::MetaType::new::<Included>())
+            .id;
+        let types: PortableRegistry = types.into();
+        let encoded = Included {
+            who: AccountId32(id),
+            relay_parent: id,
+        }
+        .encode();
+        let val = crate::ValueBuilder::parse(&encoded, ty, &types).unwrap();
+
+        let json = serde_json::to_value(val.with_ss58(42, &types)).unwrap();
+        assert_eq!(
+            json["who"]["0"],
+            "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY"
+        );
+        assert_eq!(json["relay_parent"], format!("0x{}", hex::encode(id)));
+        let json = serde_json::to_value(&val).unwrap();
+        assert_eq!(json["who"]["0"], format!("0x{}", hex::encode(id)));
+    }
+}
@@ -3,22 +3,51 @@ use parity_scale_codec::Decode;
 use scale_info::form::PortableForm;
 use scale_info::PortableRegistry;
 use scale_info::Type;
-use scale_info::{TypeDef, TypeDefPrimitive};
+use scale_info::{TypeDef, TypeDefBitSequence, TypeDefPrimitive};
+use std::sync::{PoisonError, RwLock};
 pub trait VisitScale<'scale> {
-    // Visit value on current object
+    // Visit value on current object. An error stops the decoding (the path, type and offset
+    // of the value are added to it).
     fn visit(
         &mut self,
         path: &[(&'scale str, u32)],
         data: &'scale [u8],
         ty: &'scale Type<PortableForm>,
         types: &'scale PortableRegistry,
-    );
+    ) -> Result<(), ErrorKind>;
+
+    // An enum variant was chosen, `path` ends with its name. Called before any of its fields
+    // are visited, so variants without fields are seen too.
+    fn visit_variant(&mut self, _path: &[(&'scale str, u32)]) -> Result<(), ErrorKind> {
+        Ok(())
+    }
 }
 pub mod borrow_decode;
+pub mod cursor;
+pub mod descale;
+mod encode;
+mod error;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod query;
+#[cfg(feature = "ss58")]
+pub mod ss58;
 pub mod value;
+pub use cursor::{Cursor, Items};
+pub use descale::{Descale, Found};
+pub use error::{Error, ErrorKind};
+pub use query::{Match, Query};
+#[cfg(feature = "derive")]
+pub use scale_borrow_derive::Descale;
 pub use value::{Value, ValueBuilder};
+
+// So derived code can say `::scale_borrow` here too.
+extern crate self as scale_borrow;
 // use scale_decode::visitor::{self, TypeId};
 
+/// Superseded by `#[derive(Descale)]`: this splits each path on every visit and needs every
+/// field to be `Default` (which is what it is when it isn't found).
+#[deprecated(note = "use `#[derive(Descale)]`, which splits paths at compile time")]
 #[macro_export]
 macro_rules! descale {
     (struct $n:ident <$scale:lifetime> {
@@ -31,15 +60,15 @@ macro_rules! descale {
         }
 
         impl <$scale> $n<$scale> {
-            fn parse(data: &'scale [u8], top_type: UntrackedSymbol<TypeId>, types: &'scale scale_info::PortableRegistry) -> $n<$scale> {
+            fn parse(data: &'scale [u8], top_type: UntrackedSymbol<TypeId>, types: &'scale scale_info::PortableRegistry) -> Result<$n<$scale>, $crate::Error> {
                 let mut slf = $n::<$scale>::default();
-                crate::skeleton_decode(data, top_type.id, &mut slf, types);
-                slf
+                $crate::skeleton_decode(data, top_type.id, &mut slf, types)?;
+                Ok(slf)
             }
         }
 
         impl <'scale> VisitScale<'scale> for $n<$scale> {
-            fn visit(&mut self, current_path: &[(&'scale str,u32)], data: &'scale [u8], _ty: &'scale scale_info::Type<scale_info::form::PortableForm>, _types: &'scale PortableRegistry) {
+            fn visit(&mut self, current_path: &[(&'scale str,u32)], data: &'scale [u8], _ty: &'scale scale_info::Type<scale_info::form::PortableForm>, _types: &'scale PortableRegistry) -> Result<(), $crate::ErrorKind> {
                 $(
                     let p: Vec<_> = $path.split('.').collect();//TODO: do earlier.
                     // println!("visited path {:?} == {:?}", current_path, p);
@@ -47,10 +76,11 @@ macro_rules! descale {
                         let same = current_path.iter().zip(p).all(|((seg,_), p_seg)| *seg == p_seg);
                         if same {
                             // println!("visited path found");
-                            self.$fieldname = <$t as crate::borrow_decode::BorrowDecode>::borrow_decode(data);
+                            self.$fieldname = <$t as $crate::borrow_decode::BorrowDecode>::borrow_decode(data)?;
                         }
                     }
                 )+
+                Ok(())
             }
         }
     };
@@ -63,12 +93,24 @@ pub fn skeleton_decode<'scale>(
     ty_id: u32,
     visitor: &mut impl VisitScale<'scale>,
     types: &'scale PortableRegistry,
-) {
+) -> Result<(), Error> {
+    skeleton_decode_prefix(&mut &*data, ty_id, visitor, types)
+}
+
+/// Like `skeleton_decode` for a value at the start of `data` (followed by who knows what):
+/// `data` is moved on past it.
+pub fn skeleton_decode_prefix<'scale>(
+    data: &mut &'scale [u8],
+    ty_id: u32,
+    visitor: &mut impl VisitScale<'scale>,
+    types: &'scale PortableRegistry,
+) -> Result<(), Error> {
     let id = ty_id;
-    let ty = types.resolve(id).unwrap();
-    let vec: Vec<(&'scale str, u32)> = vec![];
-    let cursor = &mut &*data;
-    semi_decode_aux(vec, cursor, ty, id, visitor, types);
+    let ty = types
+        .resolve(id)
+        .ok_or_else(|| Error::new(&[], id, 0, ErrorKind::UnknownType(id)))?;
+    let total = data.len();
+    semi_decode_aux(&mut vec![], data, ty, id, visitor, types, total)
 }
 
 // struct BorrowVisitor<'scale> {
@@ -175,175 +217,335 @@ pub fn skeleton_decode<'scale>(
 // }
 
 
-static NUMS: &[&str] = &["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"];
+/// "0", "1", "2"... made as they're first needed and then kept, so that values can borrow them.
+/// Returns at least `len` of them.
+fn nums(len: usize) -> &'static [&'static str] {
+    static NUMS: RwLock<&'static [&'static str]> = RwLock::new(&[]);
+    let nums = *NUMS.read().unwrap_or_else(PoisonError::into_inner);
+    if nums.len() >= len {
+        return nums;
+    }
+    let mut nums = NUMS.write().unwrap_or_else(PoisonError::into_inner);
+    if nums.len() < len {
+        // Doubling up, so a long sequence only makes more a few times.
+        let more = nums.len()..len.max(nums.len() * 2).max(1024);
+        let all: String = more.clone().map(|i| i.to_string()).collect();
+        let mut all: &'static str = Box::leak(all.into_boxed_str());
+        let mut grown = nums.to_vec();
+        grown.extend(more.map(|i| {
+            let digits = if i == 0 { 1 } else { i.ilog10() as usize + 1 };
+            let (num, rest) = all.split_at(digits);
+            all = rest;
+            num
+        }));
+        *nums = Box::leak(grown.into_boxed_slice());
+    }
+    *nums
+}
+
+/// The name of the `i`th unnamed field or item: `i` as a string that lives forever.
+pub fn item_name(i: usize) -> &'static str {
+    nums(i + 1)[i]
+}
+
+/// The type a compact encodes: its own type param, or the number inside that if it's a
+/// newtype (e.g. `Compact<Perbill>`).
+pub(crate) fn compact_inner<'scale>(
+    ty: &'scale Type<PortableForm>,
+    types: &'scale PortableRegistry,
+) -> Result<&'scale Type<PortableForm>, ErrorKind> {
+    let resolve = |id: u32| types.resolve(id).ok_or(ErrorKind::UnknownType(id));
+    let mut inner = match &ty.type_def {
+        TypeDef::Compact(compact) => resolve(compact.type_param.id)?,
+        _ => ty,
+    };
+    loop {
+        inner = match &inner.type_def {
+            TypeDef::Composite(composite) if composite.fields.len() == 1 => {
+                resolve(composite.fields[0].ty.id)?
+            }
+            TypeDef::Tuple(tuple) if tuple.fields.len() == 1 => resolve(tuple.fields[0].id)?,
+            _ => return Ok(inner),
+        };
+    }
+}
+
+/// How long a primitive is, unless it's a `str`.
+fn primitive_len(primitive: &TypeDefPrimitive) -> Option<usize> {
+    match primitive {
+        TypeDefPrimitive::Bool | TypeDefPrimitive::U8 | TypeDefPrimitive::I8 => Some(1),
+        TypeDefPrimitive::U16 | TypeDefPrimitive::I16 => Some(2),
+        TypeDefPrimitive::U32 | TypeDefPrimitive::I32 | TypeDefPrimitive::Char => Some(4),
+        TypeDefPrimitive::U64 | TypeDefPrimitive::I64 => Some(8),
+        TypeDefPrimitive::U128 | TypeDefPrimitive::I128 => Some(16),
+        TypeDefPrimitive::U256 | TypeDefPrimitive::I256 => Some(32),
+        TypeDefPrimitive::Str => None,
+    }
+}
+
+fn take<'scale>(data: &mut &'scale [u8], len: usize) -> Result<&'scale [u8], ErrorKind> {
+    if data.len() < len {
+        return Err(ErrorKind::NotEnoughData {
+            needed: len,
+            left: data.len(),
+        });
+    }
+    let (taken, rest) = data.split_at(len);
+    *data = rest;
+    Ok(taken)
+}
+
+/// Move past a compact `T`, returning its bytes.
+fn take_compact<'scale, T>(data: &mut &'scale [u8]) -> Result<&'scale [u8], ErrorKind>
+where
+    Compact<T>: Decode,
+{
+    let start = *data;
+    Compact::<T>::skip(data)?;
+    Ok(&start[..start.len() - data.len()])
+}
+
+/// Move past a compact of type `ty`, returning its bytes (none for a `Compact<()>`).
+pub(crate) fn take_compact_of<'scale>(
+    data: &mut &'scale [u8],
+    ty: &'scale Type<PortableForm>,
+    types: &'scale PortableRegistry,
+) -> Result<Option<&'scale [u8]>, ErrorKind> {
+    let ty_inner = compact_inner(ty, types)?;
+    let bytes = match &ty_inner.type_def {
+        TypeDef::Tuple(tuple) if tuple.fields.is_empty() => return Ok(None),
+        TypeDef::Primitive(TypeDefPrimitive::U8) => take_compact::<u8>(data),
+        TypeDef::Primitive(TypeDefPrimitive::U16) => take_compact::<u16>(data),
+        TypeDef::Primitive(TypeDefPrimitive::U32) => take_compact::<u32>(data),
+        TypeDef::Primitive(TypeDefPrimitive::U64) => take_compact::<u64>(data),
+        TypeDef::Primitive(TypeDefPrimitive::U128) => take_compact::<u128>(data),
+        _ => Err(ErrorKind::Unsupported(format!(
+            "compact {:?}",
+            ty_inner.type_def
+        ))),
+    }?;
+    Ok(Some(bytes))
+}
+
+/// The size in bytes of the store of a bit sequence (`u8` to `u64`).
+fn bit_store_len(
+    seq: &TypeDefBitSequence<PortableForm>,
+    types: &PortableRegistry,
+) -> Result<usize, ErrorKind> {
+    let id = seq.bit_store_type.id;
+    let store = types.resolve(id).ok_or(ErrorKind::UnknownType(id))?;
+    match store.type_def {
+        TypeDef::Primitive(TypeDefPrimitive::U8) => Ok(1),
+        TypeDef::Primitive(TypeDefPrimitive::U16) => Ok(2),
+        TypeDef::Primitive(TypeDefPrimitive::U32) => Ok(4),
+        TypeDef::Primitive(TypeDefPrimitive::U64) => Ok(8),
+        _ => Err(ErrorKind::Unsupported("bitvec store".into())),
+    }
+}
+
+/// Move past a bit sequence, returning its bytes: the compact number of bits followed by
+/// as many stores as it takes to hold them.
+pub(crate) fn take_bits<'scale>(
+    data: &mut &'scale [u8],
+    seq: &TypeDefBitSequence<PortableForm>,
+    types: &PortableRegistry,
+) -> Result<&'scale [u8], ErrorKind> {
+    let store_len = bit_store_len(seq, types)?;
+    let start = *data;
+    let bits = Compact::<u32>::decode(data)?.0 as usize;
+    take(data, bits.div_ceil(store_len * 8) * store_len)?;
+    Ok(&start[..start.len() - data.len()])
+}
+
+/// How the bits of a bit sequence are laid out.
+#[cfg(feature = "bitvec")]
+pub(crate) fn bit_format(
+    seq: &TypeDefBitSequence<PortableForm>,
+    types: &PortableRegistry,
+) -> Result<scale_bits::scale::Format, ErrorKind> {
+    use scale_bits::scale::format::{Format, OrderFormat, StoreFormat};
+    let store = match bit_store_len(seq, types)? {
+        1 => StoreFormat::U8,
+        2 => StoreFormat::U16,
+        4 => StoreFormat::U32,
+        _ => StoreFormat::U64,
+    };
+    let id = seq.bit_order_type.id;
+    let order = types.resolve(id).ok_or(ErrorKind::UnknownType(id))?;
+    let order = match order.path.segments.last().map(String::as_str) {
+        Some("Lsb0") => OrderFormat::Lsb0,
+        Some("Msb0") => OrderFormat::Msb0,
+        _ => return Err(ErrorKind::Unsupported("bitvec order".into())),
+    };
+    Ok(Format::new(store, order))
+}
+
+/// `total` is the length of all the data, to know how far in we are.
 fn semi_decode_aux<'scale, V: VisitScale<'scale>>(
-    mut stack: Vec<(&'scale str, u32)>,
+    stack: &mut Vec<(&'scale str, u32)>,
     data: &mut &'scale [u8],
     ty: &'scale Type<PortableForm>,
     id: u32,
     visitor: &mut V,
     types: &'scale PortableRegistry,
-) -> Vec<(&'scale str, u32)> {
-    println!("decode {:#?} - left {}", ty.type_def, data.len());
-    let original_len = data.len();
+    total: usize,
+) -> Result<(), Error> {
+    // println!("decode {:#?} - left {}", ty.type_def, data.len());
+    // Errors are reported at the start of the value.
+    let start = data.len();
+    let at = |stack: &[(&str, u32)], kind| Error::new(stack, id, total - start, kind);
+    let resolve = |id: u32| types.resolve(id).ok_or(ErrorKind::UnknownType(id));
+    let fixed_len = match &ty.type_def {
+        TypeDef::Primitive(primitive) => primitive_len(primitive),
+        _ => None,
+    };
+    if let Some(len) = fixed_len {
+        let bytes = take(data, len).map_err(|kind| at(stack, kind))?;
+        return visitor
+            .visit(stack, bytes, ty, types)
+            .map_err(|kind| at(stack, kind));
+    }
     match &ty.type_def {
         TypeDef::Composite(inner) => {
             for (i, field) in inner.fields.iter().enumerate() {
                 let id = field.ty.id;
-                let field_ty = types.resolve(id).unwrap();
-                let s: &'scale str = NUMS[i];
-                let fieldname: &'scale str = field.name.as_ref().map(|s| s.as_str()).unwrap_or(s);
+                let field_ty = resolve(id).map_err(|kind| at(stack, kind))?;
+                let fieldname: &'scale str = match field.name.as_ref() {
+                    Some(name) => name.as_str(),
+                    None => item_name(i),
+                };
                 stack.push((fieldname, id));
-                stack = semi_decode_aux(stack, data, field_ty, id, visitor, types);
+                semi_decode_aux(stack, data, field_ty, id, visitor, types, total)?;
+                stack.pop();
+            }
+        }
+        TypeDef::Tuple(tuple) => {
+            for (i, field) in tuple.fields.iter().enumerate() {
+                let id = field.id;
+                let field_ty = resolve(id).map_err(|kind| at(stack, kind))?;
+                let fieldname = item_name(i);
+                stack.push((fieldname, id));
+                semi_decode_aux(stack, data, field_ty, id, visitor, types, total)?;
                 stack.pop();
             }
         }
         TypeDef::Variant(var) => {
-            let (&discriminant, data_new) = data.split_first().unwrap();
-            *data = data_new;
+            let discriminant = take(data, 1).map_err(|kind| at(stack, kind))?[0];
             let variant = var
                 .variants
                 .iter()
                 .find(|v| v.index == discriminant)
-                .unwrap();
+                .ok_or_else(|| at(stack, ErrorKind::UnknownVariant(discriminant)))?;
 
             stack.push((&variant.name, id));
+            visitor
+                .visit_variant(stack)
+                .map_err(|kind| at(stack, kind))?;
             for (i, field) in variant.fields.iter().enumerate() {
                 let id = field.ty.id;
-                let field_ty = types.resolve(id).unwrap();
-                let s: &'scale str = NUMS[i];
-
+                let field_ty = resolve(id).map_err(|kind| at(stack, kind))?;
                 let fieldname: &'scale str = if let Some(ref name) = field.name {
                     name.as_str()
                 } else {
-                    s
+                    item_name(i)
                 };
                 stack.push((fieldname, id));
-                stack = semi_decode_aux(stack, data, field_ty, id, visitor, types);
+                semi_decode_aux(stack, data, field_ty, id, visitor, types, total)?;
                 stack.pop();
             }
             stack.pop();
         }
         TypeDef::Primitive(TypeDefPrimitive::Str) => {
-            let len: u32 = Compact::<u32>::decode(data).unwrap().into();
-            let len = len as usize;
-            visitor.visit(&stack, &data[..len], ty, types);
-            *data = &data[len..];
-        }
-        TypeDef::Primitive(TypeDefPrimitive::Bool) => {
-            // let size = ty..encoded_fixed_size().unwrap();
-            visitor.visit(&stack, &data[..1], ty, types);
-            *data = &data[1..];
-        }
-        TypeDef::Primitive(TypeDefPrimitive::U8) => {
-            const LEN: usize = 1;
-            visitor.visit(&stack, &data[..LEN], ty, types);
-            *data = &data[LEN..];
-        }
-        TypeDef::Primitive(TypeDefPrimitive::U16) => {
-            const LEN: usize = 2;
-            visitor.visit(&stack, &data[..LEN], ty, types);
-            *data = &data[LEN..];
-        }
-        TypeDef::Primitive(TypeDefPrimitive::U32) => {
-            const LEN: usize = 4;
-            visitor.visit(&stack, &data[..LEN], ty, types);
-            *data = &data[LEN..];
-        }
-        TypeDef::Primitive(TypeDefPrimitive::U64) => {
-            const LEN: usize = 8;
-            visitor.visit(&stack, &data[..LEN], ty, types);
-            *data = &data[LEN..];
-        }
-        TypeDef::Primitive(TypeDefPrimitive::U128) => {
-            const LEN: usize = 16;
-            visitor.visit(&stack, &data[..LEN], ty, types);
-            *data = &data[LEN..];
+            let bytes = Compact::<u32>::decode(data)
+                .map_err(ErrorKind::from)
+                .and_then(|len| take(data, len.0 as usize))
+                .map_err(|kind| at(stack, kind))?;
+            visitor
+                .visit(stack, bytes, ty, types)
+                .map_err(|kind| at(stack, kind))?;
         }
         TypeDef::Sequence(seq) => {
-            let len: u64 = Compact::<u64>::decode(data).unwrap().into();
-            let ty_id = seq.type_param;
-            let ty_inner = types.resolve(ty_id.id).unwrap();
+            let len: u64 = Compact::<u64>::decode(data)
+                .map_err(|err| at(stack, err.into()))?
+                .into();
+            let len = usize::try_from(len).unwrap_or(usize::MAX);
+            let ty_inner = resolve(seq.type_param.id).map_err(|kind| at(stack, kind))?;
             if ty_inner.type_def == TypeDef::Primitive(TypeDefPrimitive::U8) {
-                visitor.visit(&stack, &data[..len as usize], ty, types);
-                *data = &data[usize::try_from(len).unwrap()..];
+                let bytes = take(data, len).map_err(|kind| at(stack, kind))?;
+                visitor
+                    .visit(stack, bytes, ty, types)
+                    .map_err(|kind| at(stack, kind))?;
             } else {
-                println!("seq len = {}", len);
-                for i in NUMS.iter().take(len as usize) {
-                    // println!("i = {}", i);println!("bytes left to decode start: {:?}", &data);
-                    stack.push((i, ty_id.id));
-                    // NB: this call must move the data slice onwards.
-                    stack = semi_decode_aux(stack, data, ty_inner, ty_id.id, visitor, types);
-                    // println!("bytes left to decode end  : {:?}", &data);
-                    stack.pop();
-                }
+                // println!("seq len = {}", len);
+                decode_items(stack, data, ty_inner, seq.type_param.id, len, visitor, types, total)?;
             }
         }
         TypeDef::Array(arr) => {
-            let len: u32 = arr.len;
-            let ty_id = arr.type_param;
-            let ty_inner = types.resolve(ty_id.id).unwrap();
+            let len = arr.len as usize;
+            let ty_inner = resolve(arr.type_param.id).map_err(|kind| at(stack, kind))?;
             if ty_inner.type_def == TypeDef::Primitive(TypeDefPrimitive::U8) {
-                visitor.visit(&stack, &data[..len as usize], ty, types);
-                *data = &data[len as usize..];
+                let bytes = take(data, len).map_err(|kind| at(stack, kind))?;
+                visitor
+                    .visit(stack, bytes, ty, types)
+                    .map_err(|kind| at(stack, kind))?;
             } else {
-                println!("seq len = {}", len);
-                for i in NUMS.iter().take(len as usize) {
-                    // println!("i = {}", i);println!("bytes left to decode start: {:?}", &data);
-                    stack.push((i, ty_id.id));
-                    // NB: this call must move the data slice onwards.
-                    stack = semi_decode_aux(stack, data, ty_inner, ty_id.id, visitor, types);
-                    // println!("bytes left to decode end  : {:?}", &data);
-                    stack.pop();
-                }
+                decode_items(stack, data, ty_inner, arr.type_param.id, len, visitor, types, total)?;
             }
         }
         TypeDef::BitSequence(seq) => {
-            // assert_eq!(seq.bit_order_type(), bitvec::prelude::Lsb0);
-            let ty_inner = types.resolve(seq.bit_store_type.id).unwrap();
-            match ty_inner.type_def {
-                TypeDef::Primitive(TypeDefPrimitive::U8) => {
-                    visitor.visit(&stack, &data[..1], ty, types);
-                    *data = &data[1..];
-                }
-                _ => panic!("unsupported bitvec size - send PR please."),
-            }
+            let bytes = take_bits(data, seq, types).map_err(|kind| at(stack, kind))?;
+            visitor
+                .visit(stack, bytes, ty, types)
+                .map_err(|kind| at(stack, kind))?;
         }
-        TypeDef::Compact(inner) => {
-            let ty_inner = types.resolve(inner.type_param.id).unwrap();
-
-            match ty_inner.type_def {
-                TypeDef::Primitive(TypeDefPrimitive::U32) => {
-                    visitor.visit(&stack, data, ty, types);
-                    Compact::<u32>::skip(data).unwrap();
-                }
-                TypeDef::Primitive(TypeDefPrimitive::U64) => {
-                    visitor.visit(&stack, data, ty, types);
-                    Compact::<u64>::skip(data).unwrap();
-                }
-                TypeDef::Primitive(TypeDefPrimitive::U128) => {
-                    visitor.visit(&stack, data, ty, types);
-                    Compact::<u128>::skip(data).unwrap();
-                }
-                _ => panic!(
-                    "unsupported compact size - send PR please. {:?}",
-                    ty_inner.type_def
-                ),
+        TypeDef::Compact(_) => {
+            let bytes = take_compact_of(data, ty, types).map_err(|kind| at(stack, kind))?;
+            // `Compact<()>` is nothing at all.
+            if let Some(bytes) = bytes {
+                visitor
+                    .visit(stack, bytes, ty, types)
+                    .map_err(|kind| at(stack, kind))?;
             }
-            //  panic!("don't understand a {:?}", ty_inner.type_def());
         }
         _ => {
-            panic!("don't understand a {:?}", ty.type_def);
+            let kind = ErrorKind::Unsupported(format!("{:?}", ty.type_def));
+            return Err(at(stack, kind));
         }
     }
-    assert!(data.len() < original_len, "failed to make any progress!");
-    stack
+    Ok(())
+}
+
+/// The items of a (non byte) sequence or array.
+#[allow(clippy::too_many_arguments)]
+fn decode_items<'scale, V: VisitScale<'scale>>(
+    stack: &mut Vec<(&'scale str, u32)>,
+    data: &mut &'scale [u8],
+    ty_inner: &'scale Type<PortableForm>,
+    inner_id: u32,
+    len: usize,
+    visitor: &mut V,
+    types: &'scale PortableRegistry,
+    total: usize,
+) -> Result<(), Error> {
+    // Only more names for the items actually there, not whatever the length claims.
+    let mut names: &[&str] = &[];
+    for i in 0..len {
+        if i == names.len() {
+            names = nums(i + 1);
+        }
+        stack.push((names[i], inner_id));
+        // NB: this call must move the data slice onwards.
+        semi_decode_aux(stack, data, ty_inner, inner_id, visitor, types, total)?;
+        stack.pop();
+    }
+    Ok(())
 }
 
 #[cfg(test)]
+#[allow(deprecated)] // `descale!` keeps working until it's removed.
 mod tests {
     use super::value::{Value, ValueBuilder};
-    use crate::VisitScale;
+    use crate::{ErrorKind, VisitScale};
     use parity_scale_codec::*;
     use scale_info::interner::UntrackedSymbol;
     use scale_info::prelude::any::TypeId;
@@ -370,37 +572,64 @@ mod tests {
 
         let (id, types) = make_type::<bool>();
 
-        let val = ValueBuilder::parse(&encoded, id.id(), &types);
+        let val = ValueBuilder::parse(&encoded, id.id(), &types).unwrap();
         assert_eq!(val, Value::Bool(false));
     }
 
-    // #[wasm_bindgen_test]
-    // #[test]
-    // #[cfg(feature = "bitvec")]
-    // fn bitvec_test() {
-    //     use bitvec::prelude::*;
-    //     let val = bitvec![u8, Msb0;];
-    //     let encoded = val.encode();
+    #[wasm_bindgen_test]
+    #[test]
+    #[cfg(feature = "bitvec")]
+    fn bitvec_test() {
+        use bitvec::prelude::*;
+        let val = bitvec![u8, Lsb0; 1, 0, 1, 1, 0, 0, 0, 0, 1, 1, 0];
+        let encoded = val.encode();
+        let (id, types) = make_type::<BitVec<u8, Lsb0>>();
 
-    //     let (id, types) = make_type::<BitVec<u8, bitvec::order::Lsb0>>();
+        let value = ValueBuilder::parse(&encoded, id.id(), &types).unwrap();
+        let bits: scale_value::BitSequence = val.iter().map(|bit| *bit).collect();
+        assert_eq!(value, Value::Bits(Box::new(bits)));
+        assert_eq!(value.encode_as_type(id.id(), &types).unwrap(), encoded);
 
-    //     let val = ValueBuilder::parse(&encoded, id, &types);
-    //     assert_eq!(val, Value::Bits(Box::new(bitvec![u8, Lsb0;])));
-    // }
+        // Wider stores, and the bits are followed by more data.
+        let val = (bitvec![u32, Msb0; 1; 40], true);
+        let encoded = val.encode();
+        let (id, types) = make_type::<(BitVec<u32, Msb0>, bool)>();
 
-    // #[wasm_bindgen_test]
-    // #[test]
-    // #[cfg(not(feature = "bitvec"))]
-    // fn bitvec_test2() {
-    //     // use bitvec::prelude::*;
-    //     let val = bitvec![u8, Msb0;];
-    //     let encoded = val.encode();
+        let value = ValueBuilder::parse(&encoded, id.id(), &types).unwrap();
+        let bits = vec![true; 40].into_iter().collect();
+        assert_eq!(value.get("0"), Some(&Value::Bits(Box::new(bits))));
+        assert_eq!(value.get("1"), Some(&Value::Bool(true)));
+        assert_eq!(value.encode_as_type(id.id(), &types).unwrap(), encoded);
+    }
 
-    //     let (id, types) = make_type::<BitVec<u8, bitvec::order::Lsb0>>();
+    #[wasm_bindgen_test]
+    #[test]
+    #[cfg(not(feature = "bitvec"))]
+    fn bitvec_test2() {
+        use bitvec::prelude::*;
+        let val = (bitvec![u16, Lsb0; 1; 17], true);
+        let encoded = val.encode();
+        let (id, types) = make_type::<(BitVec<u16, Lsb0>, bool)>();
 
-    //     let val = ValueBuilder::parse(&encoded, id.id(), &types);
-    //     assert_eq!(val, Value::Scale(&[0]));
-    // }
+        let value = ValueBuilder::parse(&encoded, id.id(), &types).unwrap();
+        // The compact length and two `u16`s.
+        assert_eq!(value.get("0"), Some(&Value::Scale(&encoded[..5])));
+        assert_eq!(value.get("1"), Some(&Value::Bool(true)));
+    }
+
+    #[wasm_bindgen_test]
+    #[test]
+    fn long_sequence_test() {
+        let val: Vec<u16> = (0..40_000).collect();
+        let encoded = val.encode();
+
+        let (id, types) = make_type::<Vec<u16>>();
+
+        let value = ValueBuilder::parse(&encoded, id.id(), &types).unwrap();
+        assert_eq!(value.get("39999"), Some(&Value::U16(39_999)));
+        assert_eq!(value.encode_as_type(id.id(), &types).unwrap(), encoded);
+        assert_eq!(crate::item_name(12_345), "12345");
+    }
 
     #[wasm_bindgen_test]
     #[test]
@@ -410,7 +639,7 @@ mod tests {
 
         let (id, types) = make_type::<&str>();
 
-        let value = ValueBuilder::parse(&encoded, id.id(), &types);
+        let value = ValueBuilder::parse(&encoded, id.id(), &types).unwrap();
         if let Value::Str(inner) = value {
             assert_eq!(val, inner);
         } else {
@@ -443,15 +672,15 @@ mod tests {
                 named_bool2: &'scale str,
             }
         };
-        let xx = XParse::parse(&encoded[..], id, &types);
+        let xx = XParse::parse(&encoded[..], id, &types).unwrap();
         assert_eq!(xx.named_bool, true);
         assert_eq!(xx.named_bool2, "hi val");
 
-        let val = ValueBuilder::parse(&encoded, id.id(), &types);
+        let val = ValueBuilder::parse(&encoded, id.id(), &types).unwrap();
         assert_eq!(
             val,
             Value::Object(Box::new(vec![
-                ("_ty", Value::U32(1)),
+                ("_ty", Value::U32(0)),
                 ("val", Value::Bool(true)),
                 ("name", Value::Str("hi val"))
             ]))
@@ -479,14 +708,14 @@ mod tests {
                 named_bool: bool,
             }
         };
-        let xx = XParse::parse(&encoded[..], id, &types);
+        let xx = XParse::parse(&encoded[..], id, &types).unwrap();
         assert_eq!(xx.named_bool, true);
 
-        let val = ValueBuilder::parse(&encoded, id.id(), &types);
+        let val = ValueBuilder::parse(&encoded, id.id(), &types).unwrap();
         assert_eq!(
             val,
             Value::Object(Box::new(vec![
-                ("_ty", Value::U32(3)),
+                ("_ty", Value::U32(0)),
                 (
                     "C",
                     Value::Object(Box::new(vec![
@@ -519,14 +748,14 @@ mod tests {
                 val: u32,
             }
         };
-        let xx = XParse::parse(&encoded[..], id, &types);
+        let xx = XParse::parse(&encoded[..], id, &types).unwrap();
         assert_eq!(xx.val, 10);
 
-        let val = ValueBuilder::parse(&encoded, id.id(), &types);
+        let val = ValueBuilder::parse(&encoded, id.id(), &types).unwrap();
         assert_eq!(
             val,
             Value::Object(Box::new(vec![
-                ("_ty", Value::U32(1)),
+                ("_ty", Value::U32(0)),
                 (
                     "B",
                     Value::Object(Box::new(vec![
@@ -560,14 +789,14 @@ mod tests {
                 uncopied_bytes: &'scale [u8],
             }
         };
-        let xx = XParse::parse(&encoded[..], id, &types);
+        let xx = XParse::parse(&encoded[..], id, &types).unwrap();
         assert_eq!(xx.uncopied_bytes, vec![1, 2, 3, 4].as_slice());
 
-        let val = ValueBuilder::parse(&encoded, id.id(), &types);
+        let val = ValueBuilder::parse(&encoded, id.id(), &types).unwrap();
         assert_eq!(
             val,
             Value::Object(Box::new(vec![
-                ("_ty", Value::U32(1)),
+                ("_ty", Value::U32(0)),
                 ("more_scale", Value::Scale(&[1, 2, 3, 4])),
             ]))
         );
@@ -610,18 +839,18 @@ mod tests {
                 e: u128,
             }
         };
-        let xx = XParse::parse(&encoded[..], id, &types);
+        let xx = XParse::parse(&encoded[..], id, &types).unwrap();
         assert_eq!(xx.a, 1);
         assert_eq!(xx.b, 2);
         assert_eq!(xx.c, 3);
         assert_eq!(xx.d, 4);
         assert_eq!(xx.e, 5);
 
-        let val = ValueBuilder::parse(&encoded, id.id, &types);
+        let val = ValueBuilder::parse(&encoded, id.id, &types).unwrap();
         assert_eq!(
             val,
             Value::Object(Box::new(vec![
-                ("_ty", Value::U32(1)),
+                ("_ty", Value::U32(0)),
                 ("a", Value::U8(1)),
                 ("b", Value::U16(2)),
                 ("c", Value::U32(3)),
@@ -669,15 +898,15 @@ mod tests {
                 named_bool2: &'scale str,
             }
         };
-        let xx = XParse::parse(&encoded[..], id, &types);
+        let xx = XParse::parse(&encoded[..], id, &types).unwrap();
         assert_eq!(xx.named_bool, true);
         assert_eq!(xx.named_bool2, "skip meh");
 
-        let val = ValueBuilder::parse(&encoded, id.id, &types);
+        let val = ValueBuilder::parse(&encoded, id.id, &types).unwrap();
         assert_eq!(
             val,
             Value::Object(Box::new(vec![
-                ("_ty", Value::U32(3)),
+                ("_ty", Value::U32(0)),
                 (
                     "outer",
                     Value::Object(Box::new(vec![
@@ -704,6 +933,260 @@ mod tests {
         );
     }
 
+    #[wasm_bindgen_test]
+    #[test]
+    fn metadata_types_test() {
+        // The other shapes found in runtime metadata.
+        #[derive(Decode, Encode, CompactAs, scale_info::TypeInfo)]
+        struct Perbill(u32);
+
+        #[derive(Decode, Encode, scale_info::TypeInfo)]
+        enum Kind {
+            Empty,
+            Full(u8),
+        }
+
+        #[derive(Decode, Encode, scale_info::TypeInfo)]
+        struct X {
+            pair: (u8, i16),
+            #[codec(compact)]
+            small: u16,
+            #[codec(compact)]
+            commission: Perbill,
+            kind: Kind,
+            many: Vec<i64>,
+        }
+        let val = X {
+            pair: (1, -2),
+            small: 300,
+            commission: Perbill(50),
+            kind: Kind::Empty,
+            many: (0..12).collect(),
+        };
+        let mut encoded = val.encode();
+        encoded.push(42);
+
+        let (id, types) = make_type::<X>();
+        let data = &mut &encoded[..];
+        let val = ValueBuilder::parse_prefix(data, id.id, &types).unwrap();
+        // Just what followed is left.
+        assert_eq!(*data, &[42]);
+
+        assert_eq!(val.get("pair.1"), Some(&Value::I16(-2)));
+        assert_eq!(val.get("small"), Some(&Value::U16(300)));
+        assert_eq!(val.get("commission"), Some(&Value::U32(50)));
+        assert_eq!(val.get("many.11"), Some(&Value::I64(11)));
+        // A variant without fields is there, with nothing in it.
+        let kind = val.get("kind").unwrap();
+        assert_eq!(kind.only().map(|(name, _)| name), Some("Empty"));
+        assert_eq!(kind.get("Empty").unwrap().into_iter().count(), 0);
+    }
+
+    #[wasm_bindgen_test]
+    #[test]
+    fn truncated_test() {
+        #[derive(Decode, Encode, scale_info::TypeInfo)]
+        struct Y {
+            outer: Vec<X>,
+            num: Compact<u64>,
+        }
+
+        #[derive(Decode, Encode, scale_info::TypeInfo)]
+        enum X {
+            A(u32),
+            B { val: bool, name: String },
+        }
+        let y = Y {
+            outer: vec![
+                X::A(7),
+                X::B {
+                    val: true,
+                    name: "skip me".into(),
+                },
+            ],
+            num: Compact(1 << 40),
+        };
+        let encoded = y.encode();
+        let (id, types) = make_type::<Y>();
+
+        descale! {
+            struct XParse<'scale> {
+                #[path("outer.1.B.name")]
+                name: &'scale str,
+            }
+        };
+        assert!(ValueBuilder::parse(&encoded, id.id, &types).is_ok());
+        for len in 0..encoded.len() {
+            assert!(ValueBuilder::parse(&encoded[..len], id.id, &types).is_err());
+            assert!(XParse::parse(&encoded[..len], id, &types).is_err());
+        }
+
+        // outer len, A, 7u32, B, true, then the name.
+        let err = ValueBuilder::parse(&encoded[..10], id.id, &types).unwrap_err();
+        assert_eq!(err.path, "outer.1.B.name");
+        assert_eq!(err.offset, 8);
+        assert_eq!(err.kind, ErrorKind::NotEnoughData { needed: 7, left: 1 });
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "needed 7 bytes but only 1 left at `outer.1.B.name` (type {}, offset 8)",
+                err.type_id
+            )
+        );
+    }
+
+    #[wasm_bindgen_test]
+    #[test]
+    fn bad_data_test() {
+        #[derive(Decode, Encode, scale_info::TypeInfo)]
+        enum X {
+            A(bool),
+            B(String),
+        }
+        let (id, types) = make_type::<X>();
+
+        let err = ValueBuilder::parse(&[2], id.id, &types).unwrap_err();
+        assert_eq!((err.path.as_str(), err.offset), ("", 0));
+        assert_eq!(err.kind, ErrorKind::UnknownVariant(2));
+
+        let err = ValueBuilder::parse(&[0, 2], id.id, &types).unwrap_err();
+        assert_eq!((err.path.as_str(), err.offset), ("A.0", 1));
+        assert!(matches!(err.kind, ErrorKind::Codec(_)));
+
+        let err = ValueBuilder::parse(&[1, 4, 0xff], id.id, &types).unwrap_err();
+        assert_eq!((err.path.as_str(), err.kind), ("B.0", ErrorKind::InvalidUtf8));
+
+        descale! {
+            struct XParse<'scale> {
+                #[path("B.0")]
+                name: &'scale str,
+            }
+        };
+        let Err(err) = XParse::parse(&[1, 4, 0xff], id, &types) else {
+            panic!()
+        };
+        assert_eq!((err.path.as_str(), err.kind), ("B.0", ErrorKind::InvalidUtf8));
+
+        let err = ValueBuilder::parse(&[0], 1_000, &types).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::UnknownType(1_000));
+    }
+
+    #[cfg(feature = "derive")]
+    #[wasm_bindgen_test]
+    #[test]
+    fn derive_test() {
+        use crate::Descale;
+
+        #[derive(Decode, Encode, scale_info::TypeInfo)]
+        enum Junction {
+            Parachain(#[codec(compact)] u32),
+            AccountId32 { network: u8, id: [u8; 32] },
+            Here,
+        }
+        #[derive(Decode, Encode, scale_info::TypeInfo)]
+        struct Event {
+            who: Junction,
+            memo: Option<String>,
+            amounts: Vec<u64>,
+            items: Vec<Junction>,
+        }
+        let val = Event {
+            who: Junction::AccountId32 {
+                network: 2,
+                id: [7; 32],
+            },
+            memo: Some("hi".into()),
+            amounts: vec![1, 2],
+            items: vec![Junction::Here, Junction::Parachain(2000)],
+        };
+        let encoded = val.encode();
+        let (id, types) = make_type::<Event>();
+
+        #[derive(Descale, Debug, PartialEq)]
+        enum JunctionView<'scale> {
+            Parachain(Compact<u32>),
+            AccountId32 {
+                id: &'scale [u8],
+            },
+            #[descale(path = "Here")]
+            Nowhere,
+        }
+        #[derive(Descale, Debug, PartialEq)]
+        struct EventView<'scale> {
+            who: JunctionView<'scale>,
+            #[descale(path = "memo.Some.0")]
+            memo: Option<&'scale str>,
+            amounts: Vec<u64>,
+            items: Vec<JunctionView<'scale>>,
+            #[descale(path = "items.1.Parachain.0")]
+            para: Option<Compact<u32>>,
+            #[descale(path = "who.AccountId32.network")]
+            network: u8,
+        }
+        assert_eq!(
+            EventView::parse(&encoded, id.id, &types).unwrap(),
+            EventView {
+                who: JunctionView::AccountId32 { id: &[7; 32] },
+                memo: Some("hi"),
+                amounts: vec![1, 2],
+                items: vec![JunctionView::Nowhere, JunctionView::Parachain(Compact(2000))],
+                para: Some(Compact(2000)),
+                network: 2,
+            }
+        );
+
+        #[derive(Descale, Debug, PartialEq)]
+        struct Owned(
+            #[descale(path = "amounts")] Vec<u64>,
+            #[descale(path = "who.AccountId32.network")] Option<u8>,
+        );
+        let val = Event {
+            memo: None,
+            amounts: vec![],
+            ..val
+        };
+        let encoded = val.encode();
+        assert_eq!(
+            Owned::parse(&encoded, id.id, &types).unwrap(),
+            Owned(vec![], Some(2))
+        );
+
+        // A plain `Option` over a scale one.
+        #[derive(Descale, Debug, PartialEq)]
+        struct Memo<'scale> {
+            memo: Option<&'scale str>,
+            #[descale(path = "who.AccountId32.id")]
+            id: Option<&'scale [u8]>,
+        }
+        assert_eq!(
+            Memo::parse(&encoded, id.id, &types).unwrap(),
+            Memo {
+                memo: None,
+                id: Some(&[7; 32])
+            }
+        );
+        let with_memo = Event {
+            memo: Some("hello".into()),
+            ..Event { who: Junction::Here, ..val }
+        };
+        assert_eq!(
+            Memo::parse(&with_memo.encode(), id.id, &types).unwrap(),
+            Memo {
+                memo: Some("hello"),
+                id: None
+            }
+        );
+
+        #[derive(Descale, Debug)]
+        struct Wrong {
+            #[descale(path = "who.Parachain.0")]
+            _para: Compact<u32>,
+        }
+        let err = Wrong::parse(&encoded, id.id, &types).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Missing("who.Parachain.0".into()));
+        assert_eq!(err.path, "who.Parachain.0");
+    }
+
     #[test]
     fn test_value() {
         assert_eq!(std::mem::size_of::<super::value::Value>(), 24); // 16 in wasm32
@@ -0,0 +1,237 @@
+//! Paths into a `Value` with wildcards, e.g. `"beneficiary.V*.0.**.X1.0.AccountId32.id"`.
+//!
+//! A path is field names separated by `.`, where a segment can be:
+//!
+//!    * `name` - the field (or variant) called `name`. Tuples and arrays have fields 0, 1, 2...
+//!    * `*` - any field.
+//!    * `V*`, `*Id`, `X*Y` - any field whose name matches the pattern.
+//!    * `X1|X2` - any of the alternatives (which can be patterns).
+//!    * `**` - any number of fields, including none.
+//!
+//! Wildcards never match the `_ty` fields `ValueBuilder` adds, only a `_ty` segment does.
+use crate::Value;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment<'q> {
+    /// One field, matching any of the patterns.
+    Field(Vec<&'q str>),
+    Descendants,
+}
+
+/// A parsed path, for when the same one is used over and over.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Query<'q> {
+    segments: Vec<Segment<'q>>,
+}
+
+/// A value found by a query and the names of the fields on the way to it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Match<'a, 'scale> {
+    pub path: Vec<&'scale str>,
+    pub value: &'a Value<'scale>,
+}
+
+impl<'q> Query<'q> {
+    pub fn parse(path: &'q str) -> Self {
+        let mut segments = vec![];
+        if !path.is_empty() {
+            for segment in path.split('.') {
+                let segment = match segment {
+                    "**" => Segment::Descendants,
+                    _ => Segment::Field(segment.split('|').collect()),
+                };
+                // `**.**` matches the same as `**`, just more than once.
+                if segment == Segment::Descendants && segments.last() == Some(&segment) {
+                    continue;
+                }
+                segments.push(segment);
+            }
+        }
+        Query { segments }
+    }
+
+    /// All the values in `value` matching the query, depth first.
+    pub fn matches<'a, 'scale>(&self, value: &'a Value<'scale>) -> Matches<'a, 'q, 'scale> {
+        Matches {
+            segments: self.segments.clone(),
+            stack: vec![(0, vec![], value)],
+        }
+    }
+}
+
+/// Iterator over the values a `Query` matches.
+pub struct Matches<'a, 'q, 'scale> {
+    segments: Vec<Segment<'q>>,
+    /// What's left to look at: the segment to match next, the path and value so far.
+    stack: Vec<(usize, Vec<&'scale str>, &'a Value<'scale>)>,
+}
+
+impl<'a, 'q, 'scale> Iterator for Matches<'a, 'q, 'scale> {
+    type Item = Match<'a, 'scale>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((i, path, value)) = self.stack.pop() {
+            let segment = match self.segments.get(i) {
+                Some(segment) => segment,
+                None => return Some(Match { path, value }),
+            };
+            let fields = match value {
+                Value::Object(fields) => fields,
+                _ => {
+                    // A leaf can still be matched by `**` matching nothing.
+                    if *segment == Segment::Descendants {
+                        self.stack.push((i + 1, path, value));
+                    }
+                    continue;
+                }
+            };
+            // Pushed in reverse so they come off the stack in order.
+            for (name, child) in fields.iter().rev() {
+                let next = match segment {
+                    Segment::Field(patterns) if patterns.iter().any(|p| glob(p, name)) => i + 1,
+                    Segment::Descendants if *name != "_ty" => i,
+                    _ => continue,
+                };
+                let mut child_path = path.clone();
+                child_path.push(*name);
+                self.stack.push((next, child_path, child));
+            }
+            if *segment == Segment::Descendants {
+                self.stack.push((i + 1, path, value));
+            }
+        }
+        None
+    }
+}
+
+/// Does `name` match a pattern where `*` is any run of characters?
+fn glob(pattern: &str, name: &str) -> bool {
+    if !pattern.contains('*') {
+        return pattern == name;
+    }
+    if name == "_ty" {
+        return false;
+    }
+    let mut parts = pattern.split('*');
+    // There's always a first and a last part, though they can be empty.
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match name.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let mut parts: Vec<_> = parts.collect();
+    let last = parts.pop().unwrap_or_default();
+    for part in parts {
+        match rest.find(part) {
+            Some(found) => rest = &rest[found + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+impl<'scale> Value<'scale> {
+    /// All the values matching `path` (see `query` module).
+    pub fn query<'a, 'q>(&'a self, path: &'q str) -> Matches<'a, 'q, 'scale> {
+        Query::parse(path).matches(self)
+    }
+
+    /// The first value matching `path`.
+    pub fn query_first<'a>(&'a self, path: &str) -> Option<&'a Value<'scale>> {
+        self.query(path).next().map(|found| found.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obj<'scale>(fields: Vec<(&'scale str, Value<'scale>)>) -> Value<'scale> {
+        Value::Object(Box::new(fields))
+    }
+
+    /// `{V*: {0: {<interior>: {X1: {0: {<junction>: ...}}}}}}` as in xcm `MultiLocation`s.
+    fn location<'scale>(
+        version: &'scale str,
+        junction: &'scale str,
+        field: &'scale str,
+        val: Value<'scale>,
+    ) -> Value<'scale> {
+        let x1 = obj(vec![(
+            "X1",
+            obj(vec![("0", obj(vec![(junction, obj(vec![(field, val)]))]))]),
+        )]);
+        let location = if version == "V0" {
+            x1
+        } else {
+            obj(vec![("parents", Value::U8(0)), ("interior", x1)])
+        };
+        obj(vec![(version, obj(vec![("0", location)]))])
+    }
+
+    #[test]
+    fn query_versions() {
+        for version in ["V0", "V1", "V2"] {
+            let args = obj(vec![
+                (
+                    "dest",
+                    location(version, "Parachain", "0", Value::U64(2000)),
+                ),
+                (
+                    "beneficiary",
+                    location(version, "AccountId32", "id", Value::Scale(&[7; 32])),
+                ),
+            ]);
+            let found: Vec<_> = args
+                .query("beneficiary.V*.0.**.X1.0.AccountId32.id")
+                .collect();
+            assert_eq!(found.len(), 1);
+            assert_eq!(found[0].value, &Value::Scale(&[7; 32]));
+            assert_eq!(found[0].path[1], version);
+
+            let para = args.query_first("dest.V*.0.**.X1.0.Parachain.0");
+            assert_eq!(para, Some(&Value::U64(2000)));
+            assert_eq!(args.query_first("dest.V3|V4.**.Parachain.0"), None);
+            assert_eq!(args.query("*.*.0.**.X1.0.*").count(), 2);
+        }
+    }
+
+    #[test]
+    fn query_segments() {
+        let val = obj(vec![
+            ("_ty", Value::U32(1)),
+            ("a", obj(vec![("_ty", Value::U32(2)), ("b", Value::U8(1))])),
+            ("ab", obj(vec![("b", Value::U8(2))])),
+            ("c", Value::U8(3)),
+        ]);
+        let values = |path| -> Vec<_> { val.query(path).map(|found| found.value).collect() };
+        assert_eq!(values(""), vec![&val]);
+        assert_eq!(values("*.b"), vec![&Value::U8(1), &Value::U8(2)]);
+        assert_eq!(values("a*.b"), vec![&Value::U8(1), &Value::U8(2)]);
+        assert_eq!(values("*b.b"), vec![&Value::U8(2)]);
+        assert_eq!(values("c|ab.b"), vec![&Value::U8(2)]);
+        assert_eq!(values("**.b"), vec![&Value::U8(1), &Value::U8(2)]);
+        assert_eq!(values("**.**.c"), vec![&Value::U8(3)]);
+        assert_eq!(values("_ty"), vec![&Value::U32(1)]);
+        assert_eq!(values("*").len(), 3);
+        assert_eq!(values("c.b"), Vec::<&Value>::new());
+
+        let query = Query::parse("**");
+        let paths: Vec<_> = query
+            .matches(&val)
+            .map(|found| found.path.join("."))
+            .collect();
+        assert_eq!(paths, vec!["", "a", "a.b", "ab", "ab.b", "c"]);
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob("V*", "V2"));
+        assert!(!glob("V*", "X1"));
+        assert!(glob("*", ""));
+        assert!(glob("X*Y", "XY"));
+        assert!(glob("X*Y*Z", "XaYbZ"));
+        assert!(!glob("X*X", "X"));
+        assert!(!glob("*", "_ty"));
+    }
+}
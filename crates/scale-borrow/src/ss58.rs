@@ -0,0 +1,242 @@
+//! SS58 addresses: how substrate chains show account ids, e.g.
+//! `5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY` (format 42) for Alice.
+#[cfg(any(feature = "display", feature = "serde"))]
+use crate::Value;
+use blake2::{Blake2b512, Digest};
+use core::fmt::{Display, Formatter};
+#[cfg(any(feature = "display", feature = "serde"))]
+use scale_info::PortableRegistry;
+
+const PREFIX: &[u8] = b"SS58PRE";
+
+/// Why a string isn't an SS58 address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Not base 58.
+    BadBase58,
+    /// The format takes more bytes than there are, or is one of the reserved ones (128+).
+    BadFormat,
+    /// Ids are 32 bytes (or 20 for ethereum style accounts).
+    BadLength(usize),
+    /// The last two bytes don't match: a typo, or not an address at all.
+    BadChecksum,
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::BadBase58 => write!(f, "not base58"),
+            Self::BadFormat => write!(f, "bad ss58 format"),
+            Self::BadLength(len) => write!(f, "{} byte ids aren't accounts", len),
+            Self::BadChecksum => write!(f, "bad checksum"),
+        }
+    }
+}
+
+/// The address of an account id (32 bytes, or 20 for ethereum style accounts) on a chain with
+/// the given `ss58Format` (0 is polkadot, 2 kusama, 42 generic substrate).
+pub fn encode(format: u16, id: &[u8]) -> String {
+    let mut bytes = format_bytes(format);
+    bytes.extend_from_slice(id);
+    let check = checksum(&bytes);
+    bytes.extend_from_slice(&check);
+    bs58::encode(bytes).into_string()
+}
+
+#[cfg(any(feature = "display", feature = "serde"))]
+/// A `Value` shown (with `Display`, or serialized with the `serde` feature) with account ids as
+/// addresses, from `Value::with_ss58`.
+pub struct WithSs58<'a, 'scale> {
+    pub(crate) value: &'a Value<'scale>,
+    pub(crate) format: u16,
+    pub(crate) types: &'a PortableRegistry,
+}
+
+#[cfg(any(feature = "display", feature = "serde"))]
+impl<'scale> Value<'scale> {
+    /// Show account ids as SS58 addresses of the chain's `ss58Format`. They're known by their
+    /// type (see `Value::is_account_id`) so `types` is the registry the value was decoded with.
+    pub fn with_ss58<'a>(
+        &'a self,
+        format: u16,
+        types: &'a PortableRegistry,
+    ) -> WithSs58<'a, 'scale> {
+        WithSs58 {
+            value: self,
+            format,
+            types,
+        }
+    }
+}
+
+#[cfg(feature = "display")]
+impl<'a, 'scale> Display for WithSs58<'a, 'scale> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        self.value.display(
+            f,
+            Some((self.format, self.types)),
+            self.value.is_account_id(self.types),
+        )
+    }
+}
+
+#[cfg(any(feature = "display", feature = "serde"))]
+/// The address of the bytes of an account id. 20 byte ids stay as they are as ethereum style
+/// chains show them in hex.
+pub(crate) fn account(format: u16, bytes: &[u8]) -> Option<String> {
+    (bytes.len() == 32).then(|| encode(format, bytes))
+}
+
+/// The `ss58Format` and account id of an address, checking its checksum.
+pub fn decode(address: &str) -> Result<(u16, Vec<u8>), DecodeError> {
+    let bytes = bs58::decode(address.trim())
+        .into_vec()
+        .map_err(|_| DecodeError::BadBase58)?;
+    let (format, format_len) = match bytes.as_slice() {
+        [first @ 0..=63, ..] => (*first as u16, 1),
+        [first @ 64..=127, second, ..] => {
+            let lower = (first & 0b0011_1111) << 2 | second >> 6;
+            let upper = second & 0b0011_1111;
+            (lower as u16 | (upper as u16) << 8, 2)
+        }
+        _ => return Err(DecodeError::BadFormat),
+    };
+    let id_len = bytes.len().saturating_sub(format_len + 2);
+    if id_len != 32 && id_len != 20 {
+        return Err(DecodeError::BadLength(id_len));
+    }
+    let (body, check) = bytes.split_at(format_len + id_len);
+    if checksum(body) != check {
+        return Err(DecodeError::BadChecksum);
+    }
+    Ok((format, body[format_len..].to_vec()))
+}
+
+/// The first two bytes of the hash, which go at the end of the address.
+fn checksum(bytes: &[u8]) -> [u8; 2] {
+    let hash = Blake2b512::new()
+        .chain_update(PREFIX)
+        .chain_update(bytes)
+        .finalize();
+    [hash[0], hash[1]]
+}
+
+/// Formats below 64 are one byte, the rest (up to 16383) two.
+fn format_bytes(format: u16) -> Vec<u8> {
+    let format = format & 0b0011_1111_1111_1111;
+    if format < 64 {
+        vec![format as u8]
+    } else {
+        vec![
+            ((format & 0b1111_1100) as u8 >> 2) | 0b0100_0000,
+            (format >> 8) as u8 | ((format & 0b11) as u8) << 6,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: [u8; 32] = [
+        0xd4, 0x35, 0x93, 0xc7, 0x15, 0xfd, 0xd3, 0x1c, 0x61, 0x14, 0x1a, 0xbd, 0x04, 0xa9, 0x9f,
+        0xd6, 0x82, 0x2c, 0x85, 0x58, 0x85, 0x4c, 0xcd, 0xe3, 0x9a, 0x56, 0x84, 0xe7, 0xa5, 0x6d,
+        0xa2, 0x7d,
+    ];
+
+    #[test]
+    fn alice() {
+        assert_eq!(
+            encode(42, &ALICE),
+            "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY"
+        );
+        assert_eq!(
+            encode(0, &ALICE),
+            "15oF4uVJwmo4TdGW7VfQxNLavjCXviqxT9S1MgbjMNHr6Sp5"
+        );
+        assert_eq!(
+            encode(2, &ALICE),
+            "HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        for format in [0, 2, 42, 63, 64, 1284, 16383] {
+            assert_eq!(
+                decode(&encode(format, &ALICE)),
+                Ok((format, ALICE.to_vec()))
+            );
+            assert_eq!(decode(&encode(format, &[7; 20])), Ok((format, vec![7; 20])));
+        }
+        assert_eq!(
+            decode(" 15oF4uVJwmo4TdGW7VfQxNLavjCXviqxT9S1MgbjMNHr6Sp5\n"),
+            Ok((0, ALICE.to_vec()))
+        );
+    }
+
+    #[test]
+    fn bad_addresses() {
+        // One letter changed.
+        assert_eq!(
+            decode("5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQZ"),
+            Err(DecodeError::BadChecksum)
+        );
+        assert_eq!(decode("0x1234"), Err(DecodeError::BadBase58));
+        assert_eq!(decode(""), Err(DecodeError::BadFormat));
+        assert_eq!(decode(&encode(0, &[1; 8])), Err(DecodeError::BadLength(8)));
+        let reserved = bs58::encode([128; 35]).into_string();
+        assert_eq!(decode(&reserved), Err(DecodeError::BadFormat));
+    }
+
+    #[test]
+    #[cfg(feature = "display")]
+    fn display() {
+        use parity_scale_codec::Encode;
+
+        #[derive(Encode, scale_info::TypeInfo)]
+        struct AccountId32([u8; 32]);
+        #[derive(Encode, scale_info::TypeInfo)]
+        struct Transfer {
+            who: AccountId32,
+            block_hash: [u8; 32],
+            amount: u32,
+        }
+        let mut types = scale_info::Registry::new();
+        let id = types
+            .register_type(&scale_info::MetaType::new::<Transfer>())
+            .id;
+        let types: PortableRegistry = types.into();
+        let encoded = Transfer {
+            who: AccountId32(ALICE),
+            block_hash: ALICE,
+            amount: 3,
+        }
+        .encode();
+        let val = crate::ValueBuilder::parse(&encoded, id, &types).unwrap();
+
+        // Known by the type, not by being 32 bytes.
+        assert_eq!(
+            val.with_ss58(0, &types).to_string(),
+            format!(
+                "{{who: {{0: 15oF4uVJwmo4TdGW7VfQxNLavjCXviqxT9S1MgbjMNHr6Sp5}}, block_hash: \
+                 Scale(0x{}), amount: U32(3)}}",
+                hex::encode(ALICE)
+            )
+        );
+        assert!(val.to_string().starts_with("{who: {0: Scale(0xd43593c7"));
+        let who = val.get("who").unwrap();
+        assert_eq!(
+            who.with_ss58(2, &types).to_string(),
+            "{0: HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F}"
+        );
+    }
+
+    #[test]
+    fn two_byte_formats() {
+        assert_eq!(format_bytes(63), vec![63]);
+        // 64 is the first two byte format, 1284 is moonbeam's.
+        assert_eq!(format_bytes(64), vec![0b0101_0000, 0]);
+        assert_eq!(format_bytes(1284), vec![0b0100_0001, 0b0000_0101]);
+    }
+}
@@ -1,7 +1,10 @@
 use parity_scale_codec::Compact;
 use scale_info::PortableRegistry;
 use scale_info::TypeDef;
+use scale_info::TypeDefBitSequence;
+use scale_info::form::PortableForm;
 use scale_info::TypeDefPrimitive;
+use crate::ErrorKind;
 
 #[cfg(feature = "display")]
 use core::fmt::{Display, Formatter};
@@ -44,18 +47,43 @@ pub enum Value<'scale> {
 #[cfg(feature = "display")]
 impl<'scale> Display for Value<'scale> {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        self.display(f, None, false)
+    }
+}
+
+#[cfg(feature = "display")]
+impl<'scale> Value<'scale> {
+    /// Account ids are shown as addresses of the `ss58` format (if any), knowing them by their
+    /// type. `in_account` is whether the value is the inside of one.
+    #[cfg_attr(not(feature = "ss58"), allow(unused_variables))]
+    pub(crate) fn display(
+        &self,
+        f: &mut Formatter<'_>,
+        ss58: Option<(u16, &PortableRegistry)>,
+        in_account: bool,
+    ) -> Result<(), std::fmt::Error> {
         const TRUNC_LEN: usize = 100;
+        #[cfg(feature = "ss58")]
+        if let (Some((format, _)), true) = (ss58, in_account) {
+            if let Some(address) = self
+                .as_bytes()
+                .and_then(|bytes| crate::ss58::account(format, bytes))
+            {
+                return write!(f, "{}", address);
+            }
+        }
         match self {
             Self::Object(contents) => {
                 write!(f, "{{").unwrap();
                 let mut first = true;
-                for (k, v) in contents.iter() {
+                let in_account = ss58.is_some_and(|(_, types)| self.is_account_id(types));
+                for (k, v) in contents.iter().filter(|(k, _)| *k != "_ty") {
                     if !first {
                         write!(f, ", ").unwrap();
                     }
                     k.fmt(f).unwrap();
                     write!(f, ": ").unwrap();
-                    v.fmt(f).unwrap();
+                    v.display(f, ss58, in_account).unwrap();
                     first = false;
                 }
                 write!(f, "}}").unwrap();
@@ -85,18 +113,20 @@ impl<'scale> Display for Value<'scale> {
     }
 }
 
+/// The fields of an object (other than `_ty`), nothing for anything else.
 impl<'a, 'scale> IntoIterator for &'a Value<'scale> {
     type Item = &'a (&'scale str, Value<'scale>);
-    type IntoIter = core::slice::Iter<'a, (&'scale str, Value<'scale>)>;
+    type IntoIter = core::iter::Filter<
+        core::slice::Iter<'a, (&'scale str, Value<'scale>)>,
+        fn(&&'a (&'scale str, Value<'scale>)) -> bool,
+    >;
 
     fn into_iter(self) -> Self::IntoIter {
-        if let Value::Object(ref vals) = *self {
-            vals.iter()
-        } else {
-            debug_assert!(false); // This is not a good sign.
-            todo!();
-            // vec![].iter()
-        }
+        let fields: &[_] = match self {
+            Value::Object(fields) => fields,
+            _ => &[],
+        };
+        fields.iter().filter(|(name, _)| *name != "_ty")
     }
 }
 
@@ -118,33 +148,28 @@ impl<'scale> Value<'scale> {
         Some(cur)
     }
 
-    // Assume that this is an object with just one field. TODO! rename only()
-    pub fn only(&'scale self) -> Option<(&'scale str, &'scale Self)> {
-        if let Self::Object(fields) = self {
-            if fields.len() == 1 {
-                let (name, val) = &fields[0];
-                Some((name, val))
-            } else {
-                None
-            }
-        } else {
-            None
+    // Assume that this is an object with just one field (not counting `_ty`).
+    pub fn only(&self) -> Option<(&'scale str, &Self)> {
+        let mut fields = self.into_iter();
+        match (fields.next(), fields.next()) {
+            (Some((name, val)), None) => Some((name, val)),
+            _ => None,
         }
     }
 
-    pub fn only2(&'scale self) -> Option<(&'scale str, &'scale str, &'scale Self)> {
+    pub fn only2(&self) -> Option<(&'scale str, &'scale str, &Self)> {
         self.only()
             .and_then(|(head, tail)| tail.only().map(|(second, tail)| (head, second, tail)))
     }
 
-    pub fn only3(&'scale self) -> Option<(&'scale str, &'scale str, &'scale str, &'scale Self)> {
+    pub fn only3(&self) -> Option<(&'scale str, &'scale str, &'scale str, &Self)> {
         self.only2().and_then(|(first, second, tail)| {
             tail.only()
                 .map(|(third, tail)| (first, second, third, tail))
         })
     }
 
-    pub fn expect(&'scale self, expect1: &str) -> Option<&'scale Self> {
+    pub fn expect(&self, expect1: &str) -> Option<&Self> {
         self.only().and_then(|(head, tail)| {
             if head != expect1 {
                 return None;
@@ -153,32 +178,90 @@ impl<'scale> Value<'scale> {
         })
     }
 
-    pub fn expect2(&'scale self, expect1: &str, expect2: &str) -> Option<&'scale Self> {
+    pub fn expect2(&self, expect1: &str, expect2: &str) -> Option<&Self> {
         self.expect(expect1).and_then(|tail| tail.expect(expect2))
     }
 
     pub fn expect3(
-        &'scale self,
+        &self,
         expect1: &str,
         expect2: &str,
         expect3: &str,
-    ) -> Option<&'scale Self> {
+    ) -> Option<&Self> {
         self.expect2(expect1, expect2)
             .and_then(|tail| tail.expect(expect3))
     }
 
     pub fn expect4(
-        &'scale self,
+        &self,
         expect1: &str,
         expect2: &str,
         expect3: &str,
         expect4: &str,
-    ) -> Option<&'scale Self> {
+    ) -> Option<&Self> {
         self.expect3(expect1, expect2, expect3)
             .and_then(|tail| tail.expect(expect4))
     }
 
-    pub fn find(&'scale self, find1: &str) -> Option<&'scale Self> {
+    /// Any unsigned number that fits, whatever its size in the scale.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Self::U8(val) => Some(*val as u64),
+            Self::U16(val) => Some(*val as u64),
+            Self::U32(val) => Some(*val as u64),
+            Self::U64(val) => Some(*val),
+            Self::U128(val) => u64::try_from(**val).ok(),
+            _ => None,
+        }
+    }
+
+    /// Any whole number, if it's not negative.
+    pub fn as_u128(&self) -> Option<u128> {
+        match self {
+            Self::U128(val) => Some(**val),
+            _ => self
+                .as_u64()
+                .map(u128::from)
+                .or_else(|| self.as_i128().and_then(|val| u128::try_from(val).ok())),
+        }
+    }
+
+    /// Any whole number that fits in an `i128`.
+    pub fn as_i128(&self) -> Option<i128> {
+        match self {
+            Self::I8(val) => Some(*val as i128),
+            Self::I16(val) => Some(*val as i128),
+            Self::I32(val) => Some(*val as i128),
+            Self::I64(val) => Some(*val as i128),
+            Self::I128(val) => Some(**val),
+            Self::U128(val) => i128::try_from(**val).ok(),
+            _ => self.as_u64().map(i128::from),
+        }
+    }
+
+    /// Whether this is an account id, going by the name of its type (`AccountId32` or
+    /// `AccountId`, not e.g. a hash that's also 32 bytes).
+    pub fn is_account_id(&self, types: &PortableRegistry) -> bool {
+        let ty = match self.find("_ty") {
+            Some(Self::U32(ty)) => *ty,
+            _ => return false,
+        };
+        types
+            .resolve(ty)
+            .and_then(|ty| ty.path.segments.last())
+            .is_some_and(|name| name == "AccountId32" || name == "AccountId")
+    }
+
+    /// Bytes, whether borrowed or not.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Scale(bytes) => Some(bytes),
+            Self::ScaleOwned(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn find(&self, find1: &str) -> Option<&Self> {
         if let Self::Object(fields) = self {
             for (field, val) in fields.iter() {
                 if *field == find1 {
@@ -189,7 +272,7 @@ impl<'scale> Value<'scale> {
         None
     }
 
-    pub fn find2(&'scale self, find1: &str, find2: &str) -> Option<&'scale Self> {
+    pub fn find2(&self, find1: &str, find2: &str) -> Option<&Self> {
         self.find(find1).and_then(|val| {
             if let Self::Object(fields) = val {
                 for (field, val) in fields.iter() {
@@ -206,6 +289,8 @@ impl<'scale> Value<'scale> {
 #[derive(Default)]
 pub struct ValueBuilder<'scale> {
     root: Option<Value<'scale>>,
+    /// The type of the root.
+    ty: u32,
 }
 
 impl<'scale> ValueBuilder<'scale> {
@@ -213,10 +298,46 @@ impl<'scale> ValueBuilder<'scale> {
         data: &'scale [u8],
         top_type_id: u32,
         types: &'scale scale_info::PortableRegistry,
-    ) -> Value<'scale> {
-        let mut slf = ValueBuilder::<'scale>::default();
-        crate::skeleton_decode(data, top_type_id, &mut slf, types);
-        slf.root.take().unwrap()
+    ) -> Result<Value<'scale>, crate::Error> {
+        ValueBuilder::parse_prefix(&mut &*data, top_type_id, types)
+    }
+
+    /// Parse the value at the start of `data`, moving `data` on past it.
+    pub fn parse_prefix(
+        data: &mut &'scale [u8],
+        top_type_id: u32,
+        types: &'scale scale_info::PortableRegistry,
+    ) -> Result<Value<'scale>, crate::Error> {
+        let mut slf = ValueBuilder::<'scale> {
+            root: None,
+            ty: top_type_id,
+        };
+        crate::skeleton_decode_prefix(data, top_type_id, &mut slf, types)?;
+        // Nothing visited means a struct without fields.
+        Ok(slf
+            .root
+            .take()
+            .unwrap_or_else(|| Value::Object(Box::new(vec![("_ty", Value::U32(top_type_id))]))))
+    }
+
+    /// Put a value at `path` in the root.
+    fn place(
+        &mut self,
+        path: &[(&'scale str, u32)],
+        new_val: Value<'scale>,
+    ) -> Result<(), ErrorKind> {
+        let (last, _) = match path.last() {
+            Some(last) => last,
+            None => {
+                self.root = Some(new_val);
+                return Ok(());
+            }
+        };
+        let root = self
+            .root
+            .get_or_insert_with(|| Value::Object(Box::new(vec![("_ty", Value::U32(self.ty))])));
+
+        ValueBuilder::append(&path[..path.len() - 1], root, last, new_val)
     }
 
     fn append(
@@ -224,52 +345,57 @@ impl<'scale> ValueBuilder<'scale> {
         current: &mut Value<'scale>,
         new_field: &'scale str,
         new_val: Value<'scale>,
-    ) {
+    ) -> Result<(), ErrorKind> {
         if let Value::<'scale>::Object(fields) = current {
-            if path.is_empty() {
-                // println!("appending path {:?} fin {:?}  / {:?} to {:?}",path, new_field, new_val, &fields);
-                fields.push((new_field, new_val));
-                return;
-            }
-
-            let ((head, head_ty), tail) = path.split_first().unwrap();
+            let ((head, head_ty), tail) = match path.split_first() {
+                None => {
+                    // println!("appending path {:?} fin {:?}  / {:?} to {:?}",path, new_field, new_val, &fields);
+                    fields.push((new_field, new_val));
+                    return Ok(());
+                }
+                Some(split) => split,
+            };
             for (field, child) in fields.iter_mut() {
                 if field == head {
                     // println!("appending deeper new path {:?} | {:?}  / {:?} ", &tail, new_field, new_val);
-                    ValueBuilder::append(tail, child, new_field, new_val);
-                    return;
+                    return ValueBuilder::append(tail, child, new_field, new_val);
                 }
             }
             // println!("appending path {:?} notfound {:?} adding {:?} | {:?}  / {:?} ", &tail, head, fields, new_field, new_val);
 
-            fields.push((
-                head,
-                Value::Object(Box::new(vec![("_ty", Value::U32(*head_ty))])),
-            ));
-            let (_, new_current) = fields.last_mut().unwrap();
-            ValueBuilder::append(tail, new_current, new_field, new_val);
+            let mut new_current = Value::Object(Box::new(vec![("_ty", Value::U32(*head_ty))]));
+            ValueBuilder::append(tail, &mut new_current, new_field, new_val)?;
+            fields.push((head, new_current));
+            Ok(())
         } else {
-            panic!()
+            Err(ErrorKind::Unsupported(format!(
+                "field `{}` inside a {:?}",
+                new_field, current
+            )))
         }
     }
 
     #[cfg(not(feature = "bitvec"))]
     #[inline]
-    fn parse_bitvec(data: &'scale [u8]) -> Option<Value> {
-        Some(Value::Scale(data))
+    fn parse_bitvec(
+        data: &'scale [u8],
+        _seq: &TypeDefBitSequence<PortableForm>,
+        _types: &PortableRegistry,
+    ) -> Result<Value<'scale>, ErrorKind> {
+        Ok(Value::Scale(data))
     }
 
     #[cfg(feature = "bitvec")]
     #[inline]
-    fn parse_bitvec(mut data: &'scale [u8]) -> Option<Value> {
-        assert_eq!(data.len(), 1, "bitvec size not suppored - please send pr.");
-        use parity_scale_codec::Decode;
-        Some(
-             Value::Bits(Box::new(
-                scale_value::BitSequence::decode(&mut data).unwrap())))
-                // <bitvec::prelude::BitVec<u8, bitvec::prelude::Lsb0>
-                // as
-                // parity_scale_codec::Decode>::decode(&mut data).unwrap())))
+    fn parse_bitvec(
+        data: &'scale [u8],
+        seq: &TypeDefBitSequence<PortableForm>,
+        types: &PortableRegistry,
+    ) -> Result<Value<'scale>, ErrorKind> {
+        let format = crate::bit_format(seq, types)?;
+        let bits = scale_bits::scale::decode_using_format_from(data, format)?
+            .collect::<Result<scale_value::BitSequence, _>>()?;
+        Ok(Value::Bits(Box::new(bits)))
     }
 }
 
@@ -280,80 +406,98 @@ impl<'scale> super::VisitScale<'scale> for ValueBuilder<'scale> {
         data: &'scale [u8],
         ty: &scale_info::Type<scale_info::form::PortableForm>,
         types: &PortableRegistry,
-    ) {
+    ) -> Result<(), ErrorKind> {
         let new_val = match &ty.type_def {
-            scale_info::TypeDef::Primitive(TypeDefPrimitive::Str) => Some(Value::Str(
-                <&'scale str as crate::borrow_decode::BorrowDecode>::borrow_decode(data),
-            )),
-            scale_info::TypeDef::Primitive(TypeDefPrimitive::Bool) => Some(Value::Bool(
-                <bool as crate::borrow_decode::BorrowDecode>::borrow_decode(data),
-            )),
-            scale_info::TypeDef::Primitive(TypeDefPrimitive::U8) => Some(Value::U8(
-                <u8 as crate::borrow_decode::BorrowDecode>::borrow_decode(data),
-            )),
-            scale_info::TypeDef::Primitive(TypeDefPrimitive::U16) => Some(Value::U16(
-                <u16 as crate::borrow_decode::BorrowDecode>::borrow_decode(data),
-            )),
-            scale_info::TypeDef::Primitive(TypeDefPrimitive::U32) => Some(Value::U32(
-                <u32 as crate::borrow_decode::BorrowDecode>::borrow_decode(data),
+            scale_info::TypeDef::Primitive(TypeDefPrimitive::Str) => Value::Str(
+                <&'scale str as crate::borrow_decode::BorrowDecode>::borrow_decode(data)?,
+            ),
+            scale_info::TypeDef::Primitive(TypeDefPrimitive::Bool) => Value::Bool(
+                <bool as crate::borrow_decode::BorrowDecode>::borrow_decode(data)?,
+            ),
+            scale_info::TypeDef::Primitive(TypeDefPrimitive::U8) => {
+                Value::U8(<u8 as crate::borrow_decode::BorrowDecode>::borrow_decode(data)?)
+            }
+            scale_info::TypeDef::Primitive(TypeDefPrimitive::U16) => {
+                Value::U16(<u16 as crate::borrow_decode::BorrowDecode>::borrow_decode(data)?)
+            }
+            scale_info::TypeDef::Primitive(TypeDefPrimitive::U32) => {
+                Value::U32(<u32 as crate::borrow_decode::BorrowDecode>::borrow_decode(data)?)
+            }
+            scale_info::TypeDef::Primitive(TypeDefPrimitive::U64) => {
+                Value::U64(<u64 as crate::borrow_decode::BorrowDecode>::borrow_decode(data)?)
+            }
+            scale_info::TypeDef::Primitive(TypeDefPrimitive::U128) => Value::U128(Box::new(
+                <u128 as crate::borrow_decode::BorrowDecode>::borrow_decode(data)?,
             )),
-            scale_info::TypeDef::Primitive(TypeDefPrimitive::U64) => Some(Value::U64(
-                <u64 as crate::borrow_decode::BorrowDecode>::borrow_decode(data),
+            scale_info::TypeDef::Primitive(TypeDefPrimitive::I8) => {
+                Value::I8(<i8 as crate::borrow_decode::BorrowDecode>::borrow_decode(data)?)
+            }
+            scale_info::TypeDef::Primitive(TypeDefPrimitive::I16) => {
+                Value::I16(<i16 as crate::borrow_decode::BorrowDecode>::borrow_decode(data)?)
+            }
+            scale_info::TypeDef::Primitive(TypeDefPrimitive::I32) => {
+                Value::I32(<i32 as crate::borrow_decode::BorrowDecode>::borrow_decode(data)?)
+            }
+            scale_info::TypeDef::Primitive(TypeDefPrimitive::I64) => {
+                Value::I64(<i64 as crate::borrow_decode::BorrowDecode>::borrow_decode(data)?)
+            }
+            scale_info::TypeDef::Primitive(TypeDefPrimitive::I128) => Value::I128(Box::new(
+                <i128 as crate::borrow_decode::BorrowDecode>::borrow_decode(data)?,
             )),
-            scale_info::TypeDef::Primitive(TypeDefPrimitive::U128) => Some(Value::U128(Box::new(
-                <u128 as crate::borrow_decode::BorrowDecode>::borrow_decode(data),
-            ))),
+            scale_info::TypeDef::Primitive(TypeDefPrimitive::Char) => {
+                Value::Char(<char as crate::borrow_decode::BorrowDecode>::borrow_decode(data)?)
+            }
+            scale_info::TypeDef::Primitive(TypeDefPrimitive::U256) => {
+                Value::U256(data.try_into().map_err(|_| ErrorKind::Codec("u256".into()))?)
+            }
+            scale_info::TypeDef::Primitive(TypeDefPrimitive::I256) => {
+                Value::I256(data.try_into().map_err(|_| ErrorKind::Codec("i256".into()))?)
+            }
 
             TypeDef::Sequence(_) | TypeDef::Array(_) => {
                 // Only hits here if it's u8, otherwise it's treated as an object with many fields.
-                Some(Value::Scale(data))
+                Value::Scale(data)
             }
-            TypeDef::BitSequence(_seq) => ValueBuilder::parse_bitvec(data),
-            TypeDef::Compact(inner) => {
-                let inner = types.resolve(inner.type_param.id).unwrap();
+            TypeDef::BitSequence(seq) => ValueBuilder::parse_bitvec(data, seq, types)?,
+            TypeDef::Compact(_) => {
+                let inner = crate::compact_inner(ty, types)?;
                 match inner.type_def {
-                    TypeDef::Primitive(TypeDefPrimitive::U32) => Some(Value::U32(
-                        <Compact<u32> as crate::borrow_decode::BorrowDecode>::borrow_decode(data)
+                    TypeDef::Primitive(TypeDefPrimitive::U8) => Value::U8(
+                        <Compact<u8> as crate::borrow_decode::BorrowDecode>::borrow_decode(data)?
                             .into(),
-                    )),
-                    TypeDef::Primitive(TypeDefPrimitive::U64) => Some(Value::U64(
-                        <Compact<u64> as crate::borrow_decode::BorrowDecode>::borrow_decode(data)
+                    ),
+                    TypeDef::Primitive(TypeDefPrimitive::U16) => Value::U16(
+                        <Compact<u16> as crate::borrow_decode::BorrowDecode>::borrow_decode(data)?
                             .into(),
-                    )),
-                    TypeDef::Primitive(TypeDefPrimitive::U128) => Some(Value::U128(Box::new(
-                        <Compact<u128> as crate::borrow_decode::BorrowDecode>::borrow_decode(data)
+                    ),
+                    TypeDef::Primitive(TypeDefPrimitive::U32) => Value::U32(
+                        <Compact<u32> as crate::borrow_decode::BorrowDecode>::borrow_decode(data)?
                             .into(),
-                    ))),
-                    _ => panic!("unsupported {:?}", inner),
+                    ),
+                    TypeDef::Primitive(TypeDefPrimitive::U64) => Value::U64(
+                        <Compact<u64> as crate::borrow_decode::BorrowDecode>::borrow_decode(data)?
+                            .into(),
+                    ),
+                    TypeDef::Primitive(TypeDefPrimitive::U128) => Value::U128(Box::new(
+                        <Compact<u128> as crate::borrow_decode::BorrowDecode>::borrow_decode(data)?
+                            .into(),
+                    )),
+                    _ => return Err(ErrorKind::Unsupported(format!("compact {:?}", inner))),
                 }
             }
             _ => {
-                panic!("skipping {:?}", ty);
+                return Err(ErrorKind::Unsupported(format!("{:?}", ty.type_def)));
             }
         };
 
         // place val in right location.
-        let last = if self.root.is_none() {
-            if current_path.is_empty() {
-                self.root = new_val;
-                return;
-            }
-            let (last, last_ty) = current_path.last().unwrap();
-            self.root = Some(Value::Object(Box::new(vec![("_ty", Value::U32(*last_ty))])));
-            last
-        } else {
-            let (last, _) = current_path.last().unwrap();
-            last
-        };
-
-        // println!("appending {:?}  / {:?}", current_path, new_val);
+        self.place(current_path, new_val)
+    }
 
-        ValueBuilder::append(
-            &current_path[..current_path.len() - 1],
-            self.root.as_mut().unwrap(),
-            last,
-            new_val.unwrap(),
-        );
+    // Variants without fields still need to be there.
+    fn visit_variant(&mut self, path: &[(&'scale str, u32)]) -> Result<(), ErrorKind> {
+        let enum_ty = path.last().map_or(self.ty, |(_, ty)| *ty);
+        self.place(path, Value::Object(Box::new(vec![("_ty", Value::U32(enum_ty))])))
     }
 }
 
@@ -376,6 +520,7 @@ mod tests {
     fn test_display() {
         let data = &[1, 2, 3, 4, 17, 18, 19, 20];
         let val = Value::Object(Box::new(vec![
+            ("_ty", Value::U32(7)),
             ("0", Value::U32(0)),
             ("1", Value::Scale(data)),
         ]));
@@ -1,3 +1,6 @@
+mod owned;
+pub use owned::to_owned_value;
+
 /// if optimise then 128bits put in 64bits where possible and owned slices of u8 are created.
 /// If not optimise then we try and keep exactly the same shape as scale_value.
 pub fn convert<T>(input: &'_ scale_value::Value<T>, optimise: bool) -> scale_borrow::Value<'_> {
@@ -0,0 +1,371 @@
+use scale_borrow::{Error, ErrorKind};
+use scale_info::form::PortableForm;
+use scale_info::{Field, PortableRegistry, TypeDef, TypeDefPrimitive};
+use scale_value::{Composite, Primitive, Value, ValueDef, Variant};
+
+/// The other way: a `scale_value::Value` with type ids as context, just as
+/// `scale_value::scale::decode_as_type` would have decoded it.
+///
+/// The type puts back what `convert` (even with `optimise`) and `ValueBuilder` lose: the
+/// sizes of numbers, which objects are sequences, bytes as the `u8`s they were...
+/// Errors have no offset as there are no bytes.
+pub fn to_owned_value(
+    input: &scale_borrow::Value,
+    type_id: u32,
+    types: &PortableRegistry,
+) -> Result<Value<u32>, Error> {
+    to_owned_aux(&mut vec![], Some(input), type_id, types)
+}
+
+/// `input` is `None` if it's missing (e.g. `ValueBuilder` leaves out empty sequences).
+fn to_owned_aux<'t>(
+    path: &mut Vec<&'t str>,
+    input: Option<&scale_borrow::Value>,
+    id: u32,
+    types: &'t PortableRegistry,
+) -> Result<Value<u32>, Error> {
+    let at = |path: &[&str], kind| error(path, id, kind);
+    let ty = types
+        .resolve(id)
+        .ok_or_else(|| at(path, ErrorKind::UnknownType(id)))?;
+
+    let value = match &ty.type_def {
+        TypeDef::Composite(composite) => {
+            ValueDef::Composite(fields(path, input, id, &composite.fields, types)?)
+        }
+        TypeDef::Variant(var) => {
+            let (name, values) = input
+                .and_then(|input| input.only())
+                .ok_or_else(|| at(path, mismatch("an enum variant", input)))?;
+            let variant = var
+                .variants
+                .iter()
+                .find(|variant| variant.name == name)
+                .ok_or_else(|| at(path, ErrorKind::Mismatch(format!("no variant `{}`", name))))?;
+            path.push(&variant.name);
+            let values = fields(path, Some(values), id, &variant.fields, types)?;
+            path.pop();
+            ValueDef::Variant(Variant {
+                name: variant.name.clone(),
+                values,
+            })
+        }
+        TypeDef::Sequence(seq) => {
+            let items = items(input).map_err(|kind| at(path, kind))?;
+            ValueDef::Composite(unnamed(path, &items, seq.type_param.id, types)?)
+        }
+        TypeDef::Array(arr) => {
+            let items = items(input).map_err(|kind| at(path, kind))?;
+            if items.len() != arr.len as usize {
+                let kind = ErrorKind::Mismatch(format!(
+                    "{} items for an array of {}",
+                    items.len(),
+                    arr.len
+                ));
+                return Err(at(path, kind));
+            }
+            ValueDef::Composite(unnamed(path, &items, arr.type_param.id, types)?)
+        }
+        TypeDef::Tuple(tuple) => {
+            let items = items(input).map_err(|kind| at(path, kind))?;
+            let mut values = vec![];
+            for (i, field) in tuple.fields.iter().enumerate() {
+                path.push(num(i));
+                values.push(to_owned_aux(
+                    path,
+                    items
+                        .get(i)
+                        .and_then(|item| item.as_ref())
+                        .map(|item| item.as_ref()),
+                    field.id,
+                    types,
+                )?);
+                path.pop();
+            }
+            ValueDef::Composite(Composite::Unnamed(values))
+        }
+        TypeDef::Primitive(primitive) => {
+            let input = input.ok_or_else(|| at(path, ErrorKind::Missing(String::new())))?;
+            ValueDef::Primitive(primitive_of(input, primitive).map_err(|kind| at(path, kind))?)
+        }
+        TypeDef::Compact(compact) => return compact_of(path, input, compact.type_param.id, types),
+        TypeDef::BitSequence(_) => match input {
+            Some(scale_borrow::Value::Bits(bits)) => ValueDef::BitSequence((**bits).clone()),
+            _ => return Err(at(path, mismatch("bits", input))),
+        },
+    };
+    Ok(Value { value, context: id })
+}
+
+/// The name of the `i`th unnamed field or item.
+fn num(i: usize) -> &'static str {
+    scale_borrow::item_name(i)
+}
+
+fn error(path: &[&str], type_id: u32, kind: ErrorKind) -> Error {
+    Error {
+        path: path.join("."),
+        type_id,
+        offset: 0,
+        kind,
+    }
+}
+
+/// A compact `id`. Like `decode_as_type` the number has the type it's a compact of, and if
+/// that's a newtype (e.g. `Compact<Perbill>`) a bare number is put back in it.
+fn compact_of<'t>(
+    path: &mut Vec<&'t str>,
+    input: Option<&scale_borrow::Value>,
+    id: u32,
+    types: &'t PortableRegistry,
+) -> Result<Value<u32>, Error> {
+    if input.and_then(|input| input.as_u128()).is_none() {
+        return to_owned_aux(path, input, id, types);
+    }
+    let composite = match types.resolve(id).map(|ty| &ty.type_def) {
+        Some(TypeDef::Composite(composite)) if composite.fields.len() == 1 => {
+            let field = &composite.fields[0];
+            let inner = compact_of(path, input, field.ty.id, types)?;
+            match &field.name {
+                Some(name) => Composite::Named(vec![(name.clone(), inner)]),
+                None => Composite::Unnamed(vec![inner]),
+            }
+        }
+        Some(TypeDef::Tuple(tuple)) if tuple.fields.len() == 1 => {
+            Composite::Unnamed(vec![compact_of(path, input, tuple.fields[0].id, types)?])
+        }
+        _ => return to_owned_aux(path, input, id, types),
+    };
+    Ok(Value {
+        value: ValueDef::Composite(composite),
+        context: id,
+    })
+}
+
+/// The fields of a struct or variant (of type `id`), named if they all have names.
+fn fields<'t>(
+    path: &mut Vec<&'t str>,
+    input: Option<&scale_borrow::Value>,
+    id: u32,
+    fields: &'t [Field<PortableForm>],
+    types: &'t PortableRegistry,
+) -> Result<Composite<u32>, Error> {
+    let named = !fields.is_empty() && fields.iter().all(|field| field.name.is_some());
+    if !named {
+        let items = items(input).map_err(|kind| error(path, id, kind))?;
+        let mut values = vec![];
+        for (i, field) in fields.iter().enumerate() {
+            path.push(num(i));
+            values.push(to_owned_aux(
+                path,
+                items
+                    .get(i)
+                    .and_then(|item| item.as_ref())
+                    .map(|item| item.as_ref()),
+                field.ty.id,
+                types,
+            )?);
+            path.pop();
+        }
+        return Ok(Composite::Unnamed(values));
+    }
+    let mut values = vec![];
+    for field in fields {
+        let name = field.name.as_deref().unwrap_or_default();
+        let value = input.and_then(|input| {
+            input
+                .into_iter()
+                .find(|(field, _)| *field == name)
+                .map(|(_, value)| value)
+        });
+        path.push(name);
+        values.push((
+            name.to_string(),
+            to_owned_aux(path, value, field.ty.id, types)?,
+        ));
+        path.pop();
+    }
+    Ok(Composite::Named(values))
+}
+
+fn unnamed<'t>(
+    path: &mut Vec<&'t str>,
+    items: &[Option<Item>],
+    item_ty: u32,
+    types: &'t PortableRegistry,
+) -> Result<Composite<u32>, Error> {
+    let mut values = Vec::with_capacity(items.len());
+    for (i, item) in items.iter().enumerate() {
+        path.push(num(i));
+        let item = item.as_ref().map(|item| item.as_ref());
+        values.push(to_owned_aux(path, item, item_ty, types)?);
+        path.pop();
+    }
+    Ok(Composite::Unnamed(values))
+}
+
+/// An item of a sequence-like value. Bytes are items too, as `optimise` squashes anything
+/// of small enough numbers into bytes.
+enum Item<'a, 'scale> {
+    Value(&'a scale_borrow::Value<'scale>),
+    Byte(scale_borrow::Value<'scale>),
+}
+
+impl<'a, 'scale> AsRef<scale_borrow::Value<'scale>> for Item<'a, 'scale> {
+    fn as_ref(&self) -> &scale_borrow::Value<'scale> {
+        match self {
+            Item::Value(value) => value,
+            Item::Byte(byte) => byte,
+        }
+    }
+}
+
+/// The items in order. A missing value has none, and so might an item (`ValueBuilder`
+/// leaves out empty ones).
+fn items<'a, 'scale>(
+    input: Option<&'a scale_borrow::Value<'scale>>,
+) -> Result<Vec<Option<Item<'a, 'scale>>>, ErrorKind> {
+    match input {
+        None => Ok(vec![]),
+        Some(scale_borrow::Value::Object(fields)) => {
+            let index = |name: &str| name.parse::<usize>().ok();
+            let len = fields
+                .iter()
+                .filter_map(|(name, _)| index(name))
+                .max()
+                .map_or(0, |last| last + 1);
+            let mut items: Vec<_> = (0..len).map(|_| None).collect();
+            for (name, item) in fields.iter() {
+                if let Some(i) = index(name) {
+                    items[i] = Some(Item::Value(item));
+                }
+            }
+            Ok(items)
+        }
+        Some(input) => match input.as_bytes() {
+            Some(bytes) => Ok(bytes
+                .iter()
+                .map(|byte| Some(Item::Byte(scale_borrow::Value::U8(*byte))))
+                .collect()),
+            None => Err(mismatch("items", Some(input))),
+        },
+    }
+}
+
+fn mismatch(expected: &str, input: Option<&scale_borrow::Value>) -> ErrorKind {
+    match input {
+        Some(input) => ErrorKind::Mismatch(format!("expected {}, got {:?}", expected, input)),
+        None => ErrorKind::Missing(String::new()),
+    }
+}
+
+fn primitive_of(
+    input: &scale_borrow::Value,
+    primitive: &TypeDefPrimitive,
+) -> Result<Primitive, ErrorKind> {
+    let expected = |what: &str| mismatch(what, Some(input));
+    Ok(match (primitive, input) {
+        (TypeDefPrimitive::Bool, scale_borrow::Value::Bool(val)) => Primitive::Bool(*val),
+        (TypeDefPrimitive::Char, scale_borrow::Value::Char(val)) => Primitive::Char(*val),
+        (TypeDefPrimitive::Str, scale_borrow::Value::Str(val)) => {
+            Primitive::String(val.to_string())
+        }
+        (TypeDefPrimitive::U256, scale_borrow::Value::U256(val)) => Primitive::U256(**val),
+        (TypeDefPrimitive::I256, scale_borrow::Value::I256(val)) => Primitive::I256(**val),
+        (
+            TypeDefPrimitive::U8
+            | TypeDefPrimitive::U16
+            | TypeDefPrimitive::U32
+            | TypeDefPrimitive::U64
+            | TypeDefPrimitive::U128,
+            _,
+        ) => Primitive::U128(
+            input
+                .as_u128()
+                .ok_or_else(|| expected("an unsigned number"))?,
+        ),
+        (
+            TypeDefPrimitive::I8
+            | TypeDefPrimitive::I16
+            | TypeDefPrimitive::I32
+            | TypeDefPrimitive::I64
+            | TypeDefPrimitive::I128,
+            _,
+        ) => Primitive::I128(input.as_i128().ok_or_else(|| expected("a number"))?),
+        (primitive, _) => return Err(expected(&format!("{:?}", primitive).to_lowercase())),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parity_scale_codec::{Compact, CompactAs, Encode};
+    use scale_borrow::ValueBuilder;
+
+    #[derive(Encode, CompactAs, scale_info::TypeInfo)]
+    struct Perbill(u32);
+
+    #[derive(Encode, scale_info::TypeInfo)]
+    enum Kind {
+        Empty,
+        Full(u8),
+        Named { text: String, flag: bool },
+    }
+
+    #[derive(Encode, scale_info::TypeInfo)]
+    struct X {
+        pair: (u8, i16),
+        #[codec(compact)]
+        commission: Perbill,
+        big: Compact<u128>,
+        kinds: Vec<Kind>,
+        none: Vec<u32>,
+        bytes: Vec<u8>,
+        hash: [u8; 4],
+        small: [u16; 3],
+        maybe: Option<u64>,
+        proxies: (Vec<u32>, u128),
+        nested: Vec<Vec<u32>>,
+    }
+
+    #[test]
+    fn to_owned_test() {
+        let x = X {
+            pair: (1, -2),
+            commission: Perbill(50),
+            big: Compact(u128::MAX),
+            kinds: vec![
+                Kind::Empty,
+                Kind::Full(7),
+                Kind::Named {
+                    text: "hi".into(),
+                    flag: true,
+                },
+            ],
+            none: vec![],
+            bytes: vec![9; 20],
+            hash: [1, 2, 3, 4],
+            small: [1, 2, 3],
+            maybe: Some(1 << 40),
+            proxies: (vec![], 0),
+            nested: vec![vec![], vec![1]],
+        };
+        let encoded = x.encode();
+        let mut types = scale_info::Registry::new();
+        let id = types.register_type(&scale_info::MetaType::new::<X>()).id;
+        let types: PortableRegistry = types.into();
+        let expected = scale_value::scale::decode_as_type(&mut &encoded[..], id, &types).unwrap();
+
+        let borrowed = ValueBuilder::parse(&encoded, id, &types).unwrap();
+        assert_eq!(to_owned_value(&borrowed, id, &types).unwrap(), expected);
+        // `optimise` made bytes of `small` and `pair` but the type says what they were.
+        for optimise in [false, true] {
+            let converted = crate::convert(&expected, optimise);
+            assert_eq!(to_owned_value(&converted, id, &types).unwrap(), expected);
+        }
+
+        let err = to_owned_value(&scale_borrow::Value::Bool(true), id, &types).unwrap_err();
+        assert_eq!(err.path, "pair.0");
+        assert_eq!(err.kind, ErrorKind::Missing(String::new()));
+    }
+}
@@ -1,6 +1,13 @@
-use crate::datasource::{raw_source::AgnosticBlock, Source};
+use crate::{
+	datasource::{
+		raw_source::{AgnosticBlock, BlockBundle},
+		Source,
+	},
+	ui::details::Verification,
+};
 use futures::TryFutureExt;
 use primitive_types::H256;
+use std::collections::BTreeMap;
 
 #[derive(Clone)]
 pub struct CachedDataSource<S: Source> {
@@ -27,14 +34,28 @@ where
 		let urlhash = super::please_hash(&underlying_source.url());
 		Self { underlying_source, urlhash }
 	}
+
+	fn cache_file(&self, datatype: &str, keybytes: impl AsRef<[u8]>) -> String {
+		let path = format!("target/{}.data", self.urlhash);
+		let _ = std::fs::create_dir(&path);
+		format!("{}/{}.{}", path, hex::encode(keybytes), datatype)
+	}
+}
+
+/// Empty file means `None`.
+fn read_cached(filename: &str) -> Option<Option<Vec<u8>>> {
+	let contents = std::fs::read(filename).ok()?;
+	Some(if contents.is_empty() { None } else { Some(contents) })
+}
+
+fn write_cached(filename: &str, bytes: Option<&[u8]>) {
+	std::fs::write(filename, bytes.unwrap_or_default())
+		.unwrap_or_else(|_| panic!("Couldn't write output to {}", filename));
 }
 
 macro_rules! memoise {
 	($datatype:expr, $self:expr, $keybytes:expr, $fetch:expr) => {{
-		let path = format!("target/{}.data", $self.urlhash);
-		let _ = std::fs::create_dir(&path);
-
-		let filename = format!("{}/{}.{}", path, hex::encode($keybytes), $datatype);
+		let filename = $self.cache_file($datatype, $keybytes);
 
 		if let Ok(contents) = std::fs::read(&filename) {
 			// println!("cache hit events!");
@@ -140,6 +161,55 @@ where
 		)
 	}
 
+	/// Cached as the same files `fetch_block` and `fetch_storage` use.
+	async fn fetch_block_bundle(
+		&mut self,
+		block_hash: H256,
+		keys: &[&[u8]],
+	) -> Result<BlockBundle, BError> {
+		let block_file = self.cache_file("block", block_hash.as_bytes());
+		let storage_files: Vec<_> = keys
+			.iter()
+			.map(|key| self.cache_file("storage", [*key, block_hash.as_bytes()].concat()))
+			.collect();
+		let verification_files: Vec<_> = keys
+			.iter()
+			.map(|key| self.cache_file("verification", [*key, block_hash.as_bytes()].concat()))
+			.collect();
+
+		if let Some(block) = read_cached(&block_file) {
+			let storage: Option<Vec<_>> = storage_files.iter().map(|f| read_cached(f)).collect();
+			if let Some(storage) = storage {
+				let block = block.map(|bytes| AgnosticBlock::from_bytes(bytes.as_slice()).unwrap());
+				// What's in the cache isn't checked again, it stands as it was checked when cached.
+				let verification = verification_files
+					.iter()
+					.map(|f| {
+						read_cached(f)
+							.flatten()
+							.and_then(|json| serde_json::from_slice(&json).ok())
+							.unwrap_or_default()
+					})
+					.collect();
+				return Ok(BlockBundle { block, storage, verification })
+			}
+		}
+
+		let bundle = self.underlying_source.fetch_block_bundle(block_hash, keys).await?;
+		// Don't keep what the state root doesn't back up.
+		if bundle.verification.contains(&Verification::Mismatch) {
+			return Ok(bundle)
+		}
+		write_cached(&block_file, bundle.block.as_ref().map(|block| block.to_vec()).as_deref());
+		for (filename, value) in storage_files.iter().zip(&bundle.storage) {
+			write_cached(filename, value.as_deref());
+		}
+		for (filename, verification) in verification_files.iter().zip(&bundle.verification) {
+			write_cached(filename, serde_json::to_vec(verification).ok().as_deref());
+		}
+		Ok(bundle)
+	}
+
 	async fn fetch_metadata(&mut self, as_of: Option<H256>) -> Result<Option<Vec<u8>>, ()> {
 		memoise!(
 			"metadata",
@@ -151,7 +221,58 @@ where
 		)
 	}
 
+	async fn fetch_metadata_at_version(
+		&mut self,
+		version: u32,
+		as_of: Option<H256>,
+	) -> Result<Option<Vec<u8>>, ()> {
+		let mut cache_key = version.to_le_bytes().to_vec();
+		cache_key.extend(as_of.unwrap_or_default().as_bytes());
+		memoise!(
+			"metadata_at_version",
+			self,
+			cache_key.as_slice(),
+			self.underlying_source.fetch_metadata_at_version(version, as_of)
+		)
+	}
+
+	async fn fetch_runtime_api(
+		&mut self,
+		api: &str,
+		method: &str,
+		args: &[u8],
+		as_of: Option<H256>,
+	) -> Result<Option<Vec<u8>>, BError> {
+		if let Some(as_of) = as_of {
+			let mut cache_key = format!("{}_{}", api, method).into_bytes();
+			cache_key.extend(args);
+			cache_key.extend(as_of.as_bytes());
+			memoise!(
+				"runtime_api",
+				self,
+				super::please_hash(&cache_key).to_le_bytes(),
+				self.underlying_source.fetch_runtime_api(api, method, args, Some(as_of))
+			)
+		} else {
+			// The answer could change with the next block.
+			self.underlying_source.fetch_runtime_api(api, method, args, None).await
+		}
+	}
+
+	async fn submit(&mut self, extrinsic: &[u8]) -> Result<(), BError> {
+		// Never cached.
+		self.underlying_source.submit(extrinsic).await
+	}
+
 	fn url(&self) -> &str {
 		self.underlying_source.url()
 	}
+
+	fn throttling(&self) -> polkapipe::Throttling {
+		self.underlying_source.throttling()
+	}
+
+	fn rpc_metrics(&self) -> BTreeMap<String, polkapipe::metrics::MethodMetrics> {
+		self.underlying_source.rpc_metrics()
+	}
 }
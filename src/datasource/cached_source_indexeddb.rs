@@ -205,4 +205,12 @@ where
 	fn url(&self) -> &str {
 		&self.underlying_source.url()
 	}
+
+	fn throttling(&self) -> polkapipe::Throttling {
+		self.underlying_source.throttling()
+	}
+
+	fn rpc_metrics(&self) -> std::collections::BTreeMap<String, polkapipe::metrics::MethodMetrics> {
+		self.underlying_source.rpc_metrics()
+	}
 }
@@ -0,0 +1,311 @@
+use super::raw_source::{AgnosticBlock, BlockBundle, RawDataSource, Source};
+use parity_scale_codec::{Compact, Decode};
+use polkapipe::{ChainHead, FollowEvent};
+use primitive_types::H256;
+use std::collections::BTreeMap;
+
+type BError = polkapipe::Error;
+
+/// How many finalized blocks we keep pinned on the node at once.
+const MAX_PINNED: usize = 16;
+
+/// A source on the new JSON-RPC spec. Live mode follows the finalized blocks with
+/// `chainHead_v1_follow` (and queries them while they're pinned), history uses
+/// `archive_v1_*`. Whatever the node doesn't support goes the legacy way.
+pub struct ChainHeadDataSource {
+	legacy: RawDataSource,
+	/// `rpc_methods`, once we've asked.
+	methods: Option<Vec<String>>,
+	head: Option<ChainHead>,
+	/// Finalized blocks we're holding pinned, by block number.
+	pinned: BTreeMap<u32, H256>,
+}
+
+/// Where to go for a block.
+enum Via {
+	ChainHead(u32, H256),
+	Archive(H256),
+	Legacy,
+}
+
+impl ChainHeadDataSource {
+	pub fn new(legacy: RawDataSource) -> Self {
+		ChainHeadDataSource { legacy, methods: None, head: None, pinned: BTreeMap::new() }
+	}
+
+	async fn supports(&mut self, method: &str) -> bool {
+		if self.methods.is_none() {
+			self.methods = Some(match self.legacy.client().await {
+				Some(client) => client.rpc_methods().await.unwrap_or_default(),
+				None => vec![],
+			});
+		}
+		self.methods.iter().flatten().any(|m| m == method)
+	}
+
+	/// Catch up with the follow subscription: pin newly finalized blocks and unpin pruned
+	/// ones and the oldest once there's more than `MAX_PINNED`.
+	async fn catch_up(&mut self) {
+		if self.head.is_none() && !self.supports("chainHead_v1_follow").await {
+			return
+		}
+		let client = match self.legacy.client().await {
+			Some(client) => client,
+			None => return,
+		};
+		if self.head.is_none() {
+			self.head = client.chain_head_follow().await.ok();
+		}
+		let head = match self.head.as_mut() {
+			Some(head) => head,
+			None => return,
+		};
+
+		let mut events = vec![];
+		if self.pinned.is_empty() {
+			// Wait for the blocks we start from.
+			events.extend(head.next_event().await);
+		}
+		while let Some(event) = head.try_next_event() {
+			events.push(event);
+		}
+
+		let mut stopped = false;
+		let mut unpin = vec![];
+		for event in events {
+			let finalized = match event {
+				FollowEvent::Initialized(finalized) => finalized,
+				FollowEvent::Finalized { finalized, pruned } => {
+					unpin.extend(pruned);
+					finalized
+				},
+				FollowEvent::Stop => {
+					stopped = true;
+					break
+				},
+				_ => continue,
+			};
+			for hash in finalized {
+				let header = client.chain_head_header(head, &hash).await.ok().flatten();
+				match header.as_deref().and_then(block_number) {
+					Some(number) if hash.len() == 32 => {
+						self.pinned.insert(number, H256::from_slice(&hash));
+					},
+					_ => unpin.push(hash),
+				}
+			}
+		}
+		while self.pinned.len() > MAX_PINNED {
+			if let Some((_, hash)) = self.pinned.pop_first() {
+				unpin.push(hash.as_bytes().to_vec());
+			}
+		}
+		if !unpin.is_empty() {
+			let unpin: Vec<&[u8]> = unpin.iter().map(|hash| hash.as_slice()).collect();
+			let _ = client.chain_head_unpin(head, &unpin).await;
+		}
+
+		if stopped {
+			// Everything got unpinned, start following again next time.
+			self.head = None;
+			self.pinned.clear();
+		}
+	}
+
+	/// `None` means the latest finalized block.
+	async fn via(&mut self, as_of: Option<H256>) -> Via {
+		self.catch_up().await;
+		let found = match as_of {
+			Some(hash) => self.pinned.iter().find(|(_, pinned)| **pinned == hash),
+			None => self.pinned.iter().next_back(),
+		};
+		if let Some((number, hash)) = found {
+			return Via::ChainHead(*number, *hash)
+		}
+		match as_of {
+			Some(hash) if self.supports("archive_v1_body").await => Via::Archive(hash),
+			_ => Via::Legacy,
+		}
+	}
+
+	/// Call a runtime function, `None` if it has to go the legacy way.
+	async fn call(
+		&mut self,
+		function: &str,
+		args: &[u8],
+		as_of: Option<H256>,
+	) -> Option<Result<Vec<u8>, BError>> {
+		let via = self.via(as_of).await;
+		let client = self.legacy.client().await?;
+		Some(match via {
+			Via::ChainHead(_, hash) => match self.head.as_mut() {
+				Some(head) => client.chain_head_call(head, hash.as_bytes(), function, args).await,
+				None => Err(BError::ChainUnavailable),
+			},
+			Via::Archive(hash) => client.archive_call(hash.as_bytes(), function, args).await,
+			Via::Legacy => return None,
+		})
+	}
+}
+
+/// Headers start with the parent hash then the compact encoded block number.
+fn block_number(header: &[u8]) -> Option<u32> {
+	let mut number = header.get(32..)?;
+	<Compact<u32>>::decode(&mut number).ok().map(|number| number.0)
+}
+
+impl Source for ChainHeadDataSource {
+	async fn fetch_block_hash(&mut self, block_number: u32) -> Result<Option<H256>, BError> {
+		self.catch_up().await;
+		if let Some(hash) = self.pinned.get(&block_number) {
+			return Ok(Some(*hash))
+		}
+		if let Some((latest, _)) = self.pinned.iter().next_back() {
+			if block_number > *latest {
+				// An error rather than None so that it's not cached.
+				return Err(BError::Node(format!("block {} isn't finalized yet", block_number)))
+			}
+		}
+		if self.supports("archive_v1_hashByHeight").await {
+			let client = self.legacy.client().await.ok_or(BError::ChainUnavailable)?;
+			let hashes = client.archive_hash_by_height(block_number).await?;
+			match hashes.first() {
+				Some(hash) if hash.len() == 32 => Ok(Some(H256::from_slice(hash))),
+				_ => Err(BError::Node(format!("no block {} yet", block_number))),
+			}
+		} else {
+			self.legacy.fetch_block_hash(block_number).await
+		}
+	}
+
+	async fn fetch_block(
+		&mut self,
+		block_hash: Option<H256>,
+	) -> Result<Option<AgnosticBlock>, BError> {
+		match self.via(block_hash).await {
+			Via::ChainHead(block_number, hash) => {
+				let client = self.legacy.client().await.ok_or(BError::ChainUnavailable)?;
+				let head = self.head.as_mut().ok_or(BError::ChainUnavailable)?;
+				let extrinsics = client.chain_head_body(head, hash.as_bytes()).await?;
+				Ok(Some(AgnosticBlock { block_number, extrinsics }))
+			},
+			Via::Archive(hash) => {
+				let client = self.legacy.client().await.ok_or(BError::ChainUnavailable)?;
+				let header = client.archive_header(hash.as_bytes()).await?;
+				let extrinsics = client.archive_body(hash.as_bytes()).await?;
+				Ok(header.as_deref().and_then(block_number).zip(extrinsics).map(
+					|(block_number, extrinsics)| AgnosticBlock { block_number, extrinsics },
+				))
+			},
+			Via::Legacy => self.legacy.fetch_block(block_hash).await,
+		}
+	}
+
+	async fn fetch_storage(
+		&mut self,
+		key: &[u8],
+		as_of: Option<H256>,
+	) -> Result<Option<Vec<u8>>, BError> {
+		let values = match self.via(as_of).await {
+			Via::ChainHead(_, hash) => {
+				let client = self.legacy.client().await.ok_or(BError::ChainUnavailable)?;
+				let head = self.head.as_mut().ok_or(BError::ChainUnavailable)?;
+				client.chain_head_storage(head, hash.as_bytes(), &[key]).await?
+			},
+			Via::Archive(hash) => {
+				let client = self.legacy.client().await.ok_or(BError::ChainUnavailable)?;
+				client.archive_storage(hash.as_bytes(), &[key]).await?
+			},
+			Via::Legacy => return self.legacy.fetch_storage(key, as_of).await,
+		};
+		Ok(values.into_iter().next().flatten())
+	}
+
+	async fn fetch_block_bundle(
+		&mut self,
+		block_hash: H256,
+		keys: &[&[u8]],
+	) -> Result<BlockBundle, BError> {
+		// Read proofs are only to be had the legacy way.
+		if self.legacy.verifies() {
+			return self.legacy.fetch_block_bundle(block_hash, keys).await
+		}
+		let storage = match self.via(Some(block_hash)).await {
+			Via::ChainHead(_, hash) => {
+				let client = self.legacy.client().await.ok_or(BError::ChainUnavailable)?;
+				let head = self.head.as_mut().ok_or(BError::ChainUnavailable)?;
+				client.chain_head_storage(head, hash.as_bytes(), keys).await?
+			},
+			Via::Archive(hash) => {
+				let client = self.legacy.client().await.ok_or(BError::ChainUnavailable)?;
+				client.archive_storage(hash.as_bytes(), keys).await?
+			},
+			Via::Legacy => return self.legacy.fetch_block_bundle(block_hash, keys).await,
+		};
+		let block = self.fetch_block(Some(block_hash)).await?;
+		Ok(BlockBundle { block, storage, verification: vec![] })
+	}
+
+	async fn fetch_metadata(&mut self, as_of: Option<H256>) -> Result<Option<Vec<u8>>, ()> {
+		match self.call("Metadata_metadata", &[], as_of).await {
+			// OpaqueMetadata
+			Some(Ok(output)) => Vec::<u8>::decode(&mut output.as_slice()).map(Some).map_err(|_| ()),
+			Some(Err(_)) => Err(()),
+			None => self.legacy.fetch_metadata(as_of).await,
+		}
+	}
+
+	async fn fetch_metadata_at_version(
+		&mut self,
+		version: u32,
+		as_of: Option<H256>,
+	) -> Result<Option<Vec<u8>>, ()> {
+		match self.call("Metadata_metadata_at_version", &version.to_le_bytes(), as_of).await {
+			// Option<OpaqueMetadata>
+			Some(Ok(output)) => Option::<Vec<u8>>::decode(&mut output.as_slice()).map_err(|_| ()),
+			Some(Err(_)) => Err(()),
+			None => self.legacy.fetch_metadata_at_version(version, as_of).await,
+		}
+	}
+
+	async fn fetch_runtime_api(
+		&mut self,
+		api: &str,
+		method: &str,
+		args: &[u8],
+		as_of: Option<H256>,
+	) -> Result<Option<Vec<u8>>, BError> {
+		match self.call(&format!("{}_{}", api, method), args, as_of).await {
+			Some(output) => output.map(Some),
+			None => self.legacy.fetch_runtime_api(api, method, args, as_of).await,
+		}
+	}
+
+	async fn submit(&mut self, extrinsic: &[u8]) -> Result<(), BError> {
+		self.legacy.submit(extrinsic).await
+	}
+
+	fn url(&self) -> &str {
+		self.legacy.url()
+	}
+
+	fn throttling(&self) -> polkapipe::Throttling {
+		self.legacy.throttling()
+	}
+
+	fn rpc_metrics(&self) -> BTreeMap<String, polkapipe::metrics::MethodMetrics> {
+		self.legacy.rpc_metrics()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	#[test]
+	fn block_number_from_header() {
+		// parent hash, compact 10504599, ...
+		let mut header = vec![0; 32];
+		header.extend(hex::decode("5e268102").unwrap());
+		header.extend([0; 64]);
+		assert_eq!(super::block_number(&header), Some(10504599));
+	}
+}
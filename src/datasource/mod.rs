@@ -1,17 +1,23 @@
-use self::raw_source::AgnosticBlock;
+use self::raw_source::{AgnosticBlock, BlockBundle};
 use crate::{
-	default, render_block, ui::DotUrl, ChainInfo, DataEntity, DataEvent, Details, LinkType,
+	default, render_block,
+	ui::{details::Verification, DotUrl},
+	ChainInfo, DataEntity, DataEvent, Details, LinkType,
 	RenderDetails, RenderUpdate, BASETIME, CHAIN_STATS, DATASOURCE_EPOC, PAUSE_DATA_FETCH,
 };
 use core::future::Future;
+use lazy_static::lazy_static;
 use log::warn;
 use serde::{Deserialize, Serialize};
-use parity_scale_codec::Decode;
+use parity_scale_codec::{Compact, Decode};
+use polkadyn::MetadataIndex;
 use primitive_types::H256;
+use scale_info::PortableRegistry;
+use scale_value::At;
 use std::{
-	collections::{hash_map::DefaultHasher, HashMap},
+	collections::{hash_map::DefaultHasher, HashMap, VecDeque},
 	hash::Hash,
-	sync::atomic::Ordering,
+	sync::{atomic::Ordering, Arc, Mutex},
 	time::Duration,
 };
 use crate::LINKS;
@@ -24,6 +30,7 @@ mod time_predictor;
 
 #[cfg(not(target_arch = "wasm32"))]
 mod cached_source;
+mod chain_head_source;
 mod raw_source;
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -32,6 +39,7 @@ pub use cached_source::CachedDataSource;
 
 // #[cfg(target_arch = "wasm32")]
 // pub use cached_source_indexeddb::CachedDataSource;
+pub use chain_head_source::ChainHeadDataSource;
 pub use raw_source::{RawDataSource, Source};
 
 macro_rules! log {
@@ -47,10 +55,137 @@ fn please_hash<T: Hash>(val: &T) -> u64 {
 	hasher.finish()
 }
 
+/// How many runtimes' indexed metadata to keep per chain. Replaying history usually only
+/// crosses a few runtime upgrades at a time.
+const MAX_METADATA_INDEXES_PER_CHAIN: usize = 4;
+
+lazy_static! {
+	/// Indexed metadata by source url, most recently used runtime version first, so that
+	/// replaying history only decodes and indexes each runtime's metadata once.
+	static ref METADATA_INDEXES: Mutex<HashMap<String, VecDeque<(String, Arc<MetadataIndex>)>>> =
+		default();
+}
+
+fn cached_metadata(url: &str, version: &str) -> Option<Arc<MetadataIndex>> {
+	let mut indexes = METADATA_INDEXES.lock().unwrap();
+	let chain = indexes.get_mut(url)?;
+	let pos = chain.iter().position(|(v, _)| v == version)?;
+	let entry = chain.remove(pos)?;
+	let index = entry.1.clone();
+	chain.push_front(entry);
+	Some(index)
+}
+
+fn cache_metadata(url: &str, version: String, index: Arc<MetadataIndex>) {
+	let mut indexes = METADATA_INDEXES.lock().unwrap();
+	let chain = indexes.entry(url.to_string()).or_default();
+	chain.retain(|(v, _)| *v != version);
+	chain.push_front((version, index));
+	chain.truncate(MAX_METADATA_INDEXES_PER_CHAIN);
+}
+
+lazy_static! {
+	/// `Core_version` labels by (source url, runtime version).
+	static ref RUNTIME_VERSIONS: Mutex<HashMap<(String, String), Option<String>>> = default();
+}
+
+/// A label for the runtime such as "polkadot v9430" from the `Core_version` runtime API.
+async fn get_runtime_version<S: Source>(
+	source: &mut S,
+	metad: &MetadataIndex,
+	version: &str,
+	as_of: H256,
+) -> Option<String> {
+	let cache_key = (source.url().to_string(), version.to_string());
+	if let Some(label) = RUNTIME_VERSIONS.lock().unwrap().get(&cache_key) {
+		return label.clone()
+	}
+	let label = match source.fetch_runtime_api("Core", "version", &[], Some(as_of)).await {
+		Ok(Some(output)) => polkadyn::decode_runtime_api_output(metad, "Core", "version", &output)
+			.ok()
+			.and_then(|version| {
+				let spec_name = version.at("spec_name")?.as_str()?.to_string();
+				let spec_version = version.at("spec_version")?.as_u128()?;
+				Some(format!("{} v{}", spec_name, spec_version))
+			}),
+		_ => None,
+	};
+	RUNTIME_VERSIONS.lock().unwrap().insert(cache_key, label.clone());
+	label
+}
+
+/// Estimated fee and weight of a signed extrinsic from `TransactionPaymentApi_query_info`.
+async fn estimate_fee<S: Source>(
+	source: &mut S,
+	metad: &MetadataIndex,
+	extrinsic: &[u8],
+	as_of: H256,
+) -> Option<String> {
+	// `uxt` is the extrinsic as is (its type only describes it as opaque bytes), followed by
+	// its length.
+	let (method, types) = metad.runtime_api("TransactionPaymentApi", "query_info")?;
+	let uxt = method.inputs.first()?.ty.id;
+	let uxt = scale_value::scale::decode_as_type(&mut &extrinsic[..], uxt, types).ok()?;
+	let args = [uxt.remove_context(), scale_value::Value::u128(extrinsic.len() as u128)];
+	let args =
+		polkadyn::encode_runtime_api_args(metad, "TransactionPaymentApi", "query_info", &args)
+			.ok()?;
+	let output = source
+		.fetch_runtime_api("TransactionPaymentApi", "query_info", &args, Some(as_of))
+		.await
+		.ok()??;
+	let info =
+		polkadyn::decode_runtime_api_output(metad, "TransactionPaymentApi", "query_info", &output)
+			.ok()?;
+	let fee = info.at("partial_fee")?.as_u128()?;
+	let weight = info.at("weight")?;
+	// Weights v2 has ref_time and proof_size.
+	let weight = weight.at("ref_time").unwrap_or(weight).as_u128()?;
+	Some(format!("{} planck (weight {})", fee, weight))
+}
+
+/// Whether an extrinsic is signed (and so pays a fee).
+pub(crate) fn is_signed(extrinsic: &[u8]) -> bool {
+	let mut cursor = extrinsic;
+	<Compact<u32>>::decode(&mut cursor).is_ok() &&
+		cursor.first().map_or(false, |version| version & 0b1000_0000 != 0)
+}
+
+/// The estimated fee of a signed extrinsic as of the block it's in. Only asked for when the
+/// extrinsic is selected, as it's a runtime call per extrinsic.
+pub async fn estimate_fee_at(
+	chain_ws: Vec<String>,
+	block_number: u32,
+	extrinsic: &[u8],
+) -> Result<String, String> {
+	let mut source = RawDataSource::new(chain_ws);
+	let url = source.url().to_string();
+	let block_hash = get_block_hash(&mut source, block_number)
+		.await
+		.ok_or_else(|| format!("no block {} on {}", block_number, url))?;
+	// System.LastRuntimeUpgrade, to know which metadata the block needs.
+	let key =
+		hex::decode("26aa394eea5630e07c48ae0c9558cef7f9cce9c888469bb1a0dceaa129672ef8").unwrap();
+	let version = source.fetch_storage(&key, Some(block_hash)).await.ok().flatten();
+	let metad =
+		get_metadata(&mut source, version.map(|version| (hex::encode(version), block_hash)))
+			.await
+			.ok_or_else(|| format!("can't get metadata from {}", url))?;
+	estimate_fee(&mut source, &metad, extrinsic, block_hash)
+		.await
+		.ok_or_else(|| format!("{} can't estimate the fee", url))
+}
+
 async fn get_metadata<S: Source>(
 	source: &mut S,
 	version: Option<(String, H256)>,
-) -> Option<frame_metadata::RuntimeMetadataPrefixed> {
+) -> Option<Arc<MetadataIndex>> {
+	if let Some((version, _)) = &version {
+		if let Some(index) = cached_metadata(source.url(), version) {
+			return Some(index)
+		}
+	}
+	let cache_key = version.as_ref().map(|(version, _)| version.clone());
 	let as_of = if let Some((_version, hash)) = version { Some(hash) } else { None };
 
 	let metadata_bytes = {
@@ -81,33 +216,72 @@ async fn get_metadata<S: Source>(
 	if result.is_err() {
 		log!("WARN: should be able to get metadata from {}, {:?}", &source.url(), &result);
 	}
-	result.ok()
+	let mut index = MetadataIndex::new(result.ok()?);
+	// Runtime APIs are only described by V15 metadata, which older runtimes don't have.
+	if let Ok(Some(v15)) = source.fetch_metadata_at_version(15, as_of).await {
+		if let Ok(v15) = polkadyn::decode_metadata(&v15) {
+			index.set_runtime_apis(v15);
+		}
+	}
+	let index = Arc::new(index);
+	if let Some(version) = cache_key {
+		cache_metadata(source.url(), version, index.clone());
+	}
+	Some(index)
 }
 
-async fn get_metadata_version(
-	source: &mut impl Source,
-	hash: primitive_types::H256,
-) -> Option<String> {
-	let storage_key =
-		hex::decode("26aa394eea5630e07c48ae0c9558cef7f9cce9c888469bb1a0dceaa129672ef8").unwrap();
-	let call = source.fetch_storage(&storage_key[..], Some(hash)).await;
-	let call = match call {
-		Ok(call) => call,
-		Err(_err) => {
-			// let err = err.to_string();
-			// happens if not hitting archive nodes.
-			// let needle = "State already discarded for BlockId::Hash(";
-
-			// let pos = err.find(needle);
-			// if let Some(_pos) = pos {
-			// 	return None // If you get this error you need to point to an archive node.
-			// } else {
-			//panic!("could not recover from error2 {:?}", err);
-			return None
-			// }
+/// The chain's `ss58Format` to show account ids in (or the generic substrate one if the
+/// runtime doesn't say), and the types to know them by.
+fn ss58_format(metad: &MetadataIndex) -> Option<(u16, &PortableRegistry)> {
+	let types = &metad.metadata()?.types;
+	Some((polkadyn::ss58_format(metad).unwrap_or(42), types))
+}
+
+/// A value as JSON for the details panel, account ids as addresses.
+fn to_json(
+	value: &scale_borrow::Value,
+	ss58: Option<(u16, &PortableRegistry)>,
+) -> Option<serde_json::Value> {
+	match ss58 {
+		Some((format, types)) => serde_json::to_value(value.with_ss58(format, types)).ok(),
+		None => serde_json::to_value(value).ok(),
+	}
+}
+
+/// A value as text, account ids as addresses.
+fn to_text(value: &scale_borrow::Value, ss58: Option<(u16, &PortableRegistry)>) -> String {
+	match ss58 {
+		Some((format, types)) => value.with_ss58(format, types).to_string(),
+		None => value.to_string(),
+	}
+}
+
+/// Expected block time in ms from the chain's metadata (BABE or Aura based chains).
+fn expected_block_time(metad: &MetadataIndex) -> Option<u64> {
+	if let Ok(block_time) = polkadyn::constant(metad, "Babe", "ExpectedBlockTime")
+		.or_else(|_| polkadyn::constant(metad, "Aura", "SlotDuration"))
+	{
+		return block_time.as_u128().map(|ms| ms as u64)
+	}
+	// Older Aura chains: slot duration is twice the minimum period.
+	polkadyn::constant(metad, "Timestamp", "MinimumPeriod")
+		.ok()?
+		.as_u128()
+		.map(|ms| ms as u64 * 2)
+}
+
+/// `System.BlockWeights.max_block` (ref time) from the chain's metadata.
+fn max_block_weight(metad: &MetadataIndex) -> Option<u64> {
+	let weights = polkadyn::constant(metad, "System", "BlockWeights").ok()?;
+	let weights = scale_value_to_borrowed::convert(&weights, true);
+	match weights.find("max_block")? {
+		scale_borrow::Value::U64(weight) => Some(*weight),
+		// Weights v2
+		max_block => match max_block.find("ref_time")? {
+			scale_borrow::Value::U64(ref_time) => Some(*ref_time),
+			_ => None,
 		},
-	};
-	call.map(hex::encode)
+	}
 }
 
 async fn get_block_hash<S: Source>(
@@ -134,6 +308,8 @@ where
 	pub receive_channel: Option<async_std::channel::Receiver<(RelayBlockNumber, i64, H256)>>,
 	pub sender: Option<HashMap<u32, async_std::channel::Sender<(RelayBlockNumber, i64, H256)>>>,
 	pub forwards: bool,
+	/// Check storage against the state root of its block (`RawDataSource::with_verification`).
+	pub verify: bool,
 }
 
 impl<F, R> BlockWatcher<F, R>
@@ -155,10 +331,11 @@ where
 		// log!("listening to as of {:?}", as_of);
 
 		let url = &chain_info.chain_ws;
+		let raw_source = RawDataSource::new(url.clone()).with_verification(self.verify);
 		#[cfg(not(target_arch = "wasm32"))]
-		let mut source = CachedDataSource::new(RawDataSource::new(url.clone()));
+		let mut source = CachedDataSource::new(ChainHeadDataSource::new(raw_source));
 		#[cfg(target_arch = "wasm32")]
-		let mut source = RawDataSource::new(url.clone());
+		let mut source = ChainHeadDataSource::new(raw_source);
 
 		let para_id = chain_info.chain_url.para_id;
 		
@@ -172,7 +349,15 @@ where
 		if metad.is_some() {
 			let metad = metad.unwrap();
 
-			if let frame_metadata::RuntimeMetadata::V14(ref m) = metad.1 {
+			{
+				let mut stats = CHAIN_STATS.lock().unwrap();
+				let entry = stats.entry(chain_info.chain_index).or_insert_with(default);
+				entry.chain_name = chain_info.chain_name.clone();
+				entry.max_block_weight = max_block_weight(&metad);
+				entry.block_time_ms = expected_block_time(&metad);
+			}
+
+			if let Some(m) = metad.metadata() {
 				let mut pallets = crate::PALLETS.lock().unwrap();
 				for p in &m.pallets {
 					if p.name == "Sudo" {
@@ -296,6 +481,8 @@ where
 								extrinsics: vec![],
 								events: vec![],
 								weight: None,
+								runtime: None,
+								verification: Verification::Unchecked,
 							}),
 							&chain_info,
 							&mut vec![],
@@ -417,16 +604,21 @@ where
 {
 	// log!("processing extrinsics {:?}", block_hash);
 	let mut timestamp = None;
-	if let Ok(Some(block)) = get_extrinsics(source, block_hash).await {
-		let weights = source
-			.fetch_storage(
-				&hex::decode("26aa394eea5630e07c48ae0c9558cef734abf5cb34d6244378cddbf18e849d96")
-					.unwrap()[..],
-				Some(block_hash),
-			)
-			.await
-			.unwrap()
-			.unwrap();
+	// System.BlockWeight, System.LastRuntimeUpgrade and System.Events, fetched along with the
+	// block in one round trip.
+	let keys = [
+		hex::decode("26aa394eea5630e07c48ae0c9558cef734abf5cb34d6244378cddbf18e849d96").unwrap(),
+		hex::decode("26aa394eea5630e07c48ae0c9558cef7f9cce9c888469bb1a0dceaa129672ef8").unwrap(),
+		hex::decode("26aa394eea5630e07c48ae0c9558cef780d41e5e16056765bc8461851072c9d7").unwrap(),
+	];
+	let keys: Vec<&[u8]> = keys.iter().map(|key| key.as_slice()).collect();
+	let bundle = source.fetch_block_bundle(block_hash, &keys).await;
+	if let Ok(BlockBundle { block: Some(block), mut storage, verification }) = bundle {
+		if verification.contains(&Verification::Mismatch) {
+			log!("{}: state root of {:?} doesn't back up its storage", source.url(), block_hash);
+		}
+		let verified = |i: usize| verification.get(i).copied().unwrap_or_default();
+		let weights = storage[0].take().unwrap_or_default();
 		// let d: FrameSupportDispatchPerDispatchClassWeight;
 
 		// for skip in 0..weights.len() {
@@ -455,9 +647,9 @@ where
 		blockurl.block_number = Some(got_block_num);
 		// let block_number = blockurl.block_number.unwrap();
 
-		let version = get_metadata_version(source, block_hash).await;
+		let version = storage[1].take().map(hex::encode);
 
-		let metad = if let Some(version) = version {
+		let metad = if let Some(version) = version.clone() {
 			get_metadata(source, Some((version, block_hash))).await
 		} else {
 			//TODO: This is unlikely to work. we should try the oldest metadata we have instead...
@@ -468,6 +660,11 @@ where
 			return Err(())
 		}
 		let metad = metad.unwrap();
+		let runtime = if let Some(version) = &version {
+			get_runtime_version(source, &metad, version, block_hash).await
+		} else {
+			None
+		};
 
 		let mut exts = vec![];
 		for (i, encoded_extrinsic) in extrinsics.into_iter().enumerate() {
@@ -489,54 +686,84 @@ where
 						variant: "?".to_string(),
 						raw: encoded_extrinsic,
 						value: None,
+						error: None,
+						runtime: None,
+						verification: Verification::Unchecked,
 						links: vec![]
 					},
 				},
 			};
 			let ex_slice = &the_extrinsic.raw[..];
 			// let ex_slice = &ext_bytes.0;
-			let decode_result = polkadyn::decode_extrinsic(&metad, ex_slice);
-			
+			// Straight from the bytes to borrowed values, no `scale_value` in between.
+			let borrowed = polkadyn::decode_extrinsic_borrowed(&metad, ex_slice);
+			// Anything scale_borrow can't decode yet still goes through `scale_value`.
+			let fallback = match &borrowed {
+				Ok(_) => None,
+				Err(err) => {
+					log!("extrinsic {} falling back to scale_value: {}", i, err);
+					polkadyn::decode_extrinsic(&metad, ex_slice).ok()
+				},
+			};
+			let decode_result = borrowed.or_else(|_| {
+				let value = fallback.as_ref().ok_or(())?;
+				Ok::<_, ()>(scale_value_to_borrowed::convert(value, true))
+			});
+			let ss58 = ss58_format(&metad);
 
-			if let Ok(extrinsic2) = decode_result {
-				
-				// if let frame_metadata::RuntimeMetadata::V14(metadd) = &metad.1 {
-				// 	let id = extrinsic2.context;
-				// 	let r = (metadd).types.resolve(unsafe { std::mem::transmute(id) }).unwrap();
-				// 	log!("got docs {:?}", r.docs()); -- docs are always empty...
-				// }
+			if let Ok(extrinsic) = decode_result {
 				// log!("an extrinsics  decoded {:?}", i);
-				let extrinsic = scale_value_to_borrowed::convert(&extrinsic2, true);
 				let entity = process_extrinsic(
 					&metad,
 					ex_slice,
 					&extrinsic,
 					DotUrl { extrinsic: Some(i as u32), ..blockurl.clone() },
 					// source.url(),
-					&extrinsic2,
 				)
 				.await;
 				if let Some(mut entity) = entity {
 					// log!("an extrinsic processed {:?}", i);
-					entity.details_mut().value = Some(scale_value::stringify::to_string(
-						&extrinsic2.clone().remove_context(),
-					));
-					if let Some(scale_borrow::Value::U64(time)) =
-						extrinsic.expect4("Timestamp", "0", "set", "now")
+					let details = entity.details_mut();
+					details.value = to_json(&extrinsic, ss58);
+					details.call =
+						polkadyn::extrinsic_call(&metad, ex_slice).ok().map(|call| call.to_vec());
+					if let Some(time) =
+						extrinsic.expect4("Timestamp", "0", "set", "now").and_then(|now| now.as_u64())
 					{
-						timestamp = Some(*time as i64);
+						timestamp = Some(time as i64);
 					}
 					exts.push(entity);
+				} else {
+					exts.push(the_extrinsic.derived);
 				}
 			} else {
 				log!("can't decode block extrinsic  {} http://127.0.0.1:8080#q={}", i, &blockurl);
 				exts.push(the_extrinsic.derived);
 			}
 		}
-		let (events, _start_links) =
-			get_events_for_block(source, block_hash, sender, &blockurl, &metad, timestamp)
+		let (mut events, _start_links) =
+			get_events_for_block(storage[2].take(), sender, &blockurl, &metad, timestamp)
 				.await
 				.or(Err(()))?;
+		for event in &mut events {
+			event.details.verification = verified(2);
+		}
+
+		// The outcome of an extrinsic is only known from its events.
+		for event in &events {
+			let details = &event.details;
+			if let ("System", "ExtrinsicFailed", Some(extrinsic_num)) =
+				(details.pallet.as_str(), details.variant.as_str(), details.parent)
+			{
+				if let Some(ext) = exts
+					.iter_mut()
+					.find(|ext| ext.details().doturl.extrinsic == Some(extrinsic_num))
+				{
+					ext.details_mut().success = crate::ui::details::Success::Sad;
+					ext.details_mut().error = details.error.clone();
+				}
+			}
+		}
 
 		//let mut handle = tx.lock().unwrap();
 
@@ -553,6 +780,13 @@ where
 			extrinsics: exts,
 			events,
 			weight: block_weight,
+			runtime,
+			// As good as the worst of its storage.
+			verification: if verification.contains(&Verification::Mismatch) {
+				Verification::Mismatch
+			} else {
+				verified(0)
+			},
 		};
 
 		//FYI: blocks sometimes have no events in them.
@@ -560,6 +794,7 @@ where
 		let mut render_details = RenderDetails::default();
 		// let mut links = vec![]; //TODO should be global?
 		let weight = current.weight;
+		let runtime = current.blockurl.block_number.zip(current.runtime.clone());
 		{
 			let mut links = &mut *LINKS.lock().unwrap();
 			render_block(
@@ -576,6 +811,18 @@ where
 			//ChainStats {// start: Utc::now().timestamp(),
 			default, // }
 		);
+		let throttling = source.throttling();
+		if throttling.throttled > entry.throttling.throttled {
+			log!("{} is throttling us: {:?}", source.url(), throttling);
+		}
+		entry.throttling = throttling;
+		entry.rpc_metrics = source.rpc_metrics();
+		// Lanes show the runtime of the newest block seen.
+		if let Some((block_number, runtime)) = runtime {
+			if entry.runtime.as_ref().map_or(true, |(newest, _)| block_number >= *newest) {
+				entry.runtime = Some((block_number, runtime));
+			}
+		}
 		if let Some(weight) = weight {
 			// entry.block_count += rend.block_instances.len() as u32;
 			let non_boring_tx =
@@ -598,21 +845,18 @@ where
 async fn find_timestamp<S: Source>(
 	block_hash: H256,
 	source: &mut S,
-	metad: &frame_metadata::RuntimeMetadataPrefixed,
+	metad: &MetadataIndex,
 ) -> Option<i64> {
 	if let Ok(Some(block)) = get_extrinsics(source, block_hash).await {
 		for (i, encoded_extrinsic) in block.extrinsics.iter().enumerate() {
-			let result = polkadyn::decode_extrinsic(metad, encoded_extrinsic.as_slice());
+			let result = polkadyn::decode_extrinsic_borrowed(metad, encoded_extrinsic.as_slice());
 			if let Ok(extrinsic) = result {
-
-			//let docs = registry. id.docs();
-				let extrinsic = scale_value_to_borrowed::convert(&extrinsic, true);
-				if let Some(scale_borrow::Value::U64(val)) =
-					extrinsic.expect4("Timestamp", "0", "set", "now")
+				if let Some(val) =
+					extrinsic.expect4("Timestamp", "0", "set", "now").and_then(|now| now.as_u64())
 				{
 					// Timestamps are usually represented as i64
 					// I'm sure i64 time will be enough for a while.
-					return Some(*val as i64)
+					return Some(val as i64)
 				}
 			} else {
 				println!(
@@ -629,6 +873,31 @@ async fn find_timestamp<S: Source>(
 	None
 }
 
+/// Re-submit a call (as shown in the details panel) to the same chain on the local dev network
+/// (`Env::Local`). The call is submitted unsigned so it's only going to be accepted by the node
+/// if it needs no origin.
+pub async fn replay_call_locally(para_id: Option<u32>, call: &[u8]) -> Result<(), String> {
+	let url = crate::networks::get_network(&crate::Env::Local)
+		.into_iter()
+		.flatten()
+		.find(|(_, local_para_id, _, _)| *local_para_id == para_id)
+		.and_then(|(_, _, _, urls)| urls.first().copied())
+		.ok_or_else(|| format!("no local chain for para id {:?}", para_id))?;
+
+	let mut source = RawDataSource::new(vec![url.to_string()]);
+	let metad = get_metadata(&mut source, None)
+		.await
+		.ok_or_else(|| format!("can't get metadata from {}", url))?;
+	// The call as it was, as long as the local runtime still understands it.
+	polkadyn::decode_call_borrowed(&metad, call)
+		.map_err(|e| format!("{} doesn't know the call: {}", url, e))?;
+
+	source
+		.submit(&polkadyn::encode_unsigned_extrinsic(call))
+		.await
+		.map_err(|e| format!("{} rejected call: {}", url, e))
+}
+
 // fetches extrinsics from node for a block number (wrapped by a file cache).
 async fn get_extrinsics(
 	source: &mut impl Source,
@@ -638,11 +907,10 @@ async fn get_extrinsics(
 }
 
 async fn process_extrinsic<'a, 'scale>(
-	meta: &frame_metadata::RuntimeMetadataPrefixed,
+	meta: &MetadataIndex,
 	ex_slice: &'scale [u8],
 	ext: &scale_borrow::Value<'scale>,
 	extrinsic_url: DotUrl,
-	ext2: &scale_value::Value<u32>,
 	// url: &str,
 ) -> Option<DataEntity> {
 	let mut children = vec![];
@@ -650,14 +918,15 @@ async fn process_extrinsic<'a, 'scale>(
 	let end_link: Vec<(String, LinkType)> = vec![];
 	let mut msg_count = 0;
 	let mut value = None;
+	let ss58 = ss58_format(meta);
 
 	let (pallet, variant) = if let Some((pallet, "0", variant, payload)) = ext.only3() {
 		match (pallet, variant) {
 			("System", "remark") => {
 				// log!("REMARK {:?}", payload);
-				if let Some(scale_borrow::Value::ScaleOwned(own)) = payload.get("remark") {
-					log!("REMARK {:?}", String::from_utf8((**own).clone()));
-					value = Some(String::from_utf8((**own).clone()).unwrap());
+				if let Some(remark) = payload.get("remark").and_then(|remark| remark.as_bytes()) {
+					log!("REMARK {:?}", String::from_utf8_lossy(remark));
+					value = Some(String::from_utf8_lossy(remark).to_string());
 				}
 				// match &payload.get("0") {
 				// 	scale_value::ValueDef::Primitive(scale_value::Composite::Unnamed(
@@ -713,8 +982,8 @@ async fn process_extrinsic<'a, 'scale>(
 									if rows.len() > 1 {
 										msg_count += 1;
 										log!("found horiz {}", inner_val);
-										if let Some(scale_borrow::Value::U64(para_id)) =
-											inner_val.get("0")
+										if let Some(para_id) =
+											inner_val.get("0").and_then(|para| para.as_u64())
 										{
 											log!(
 												"TODO: do something with horiz to para {}",
@@ -736,22 +1005,17 @@ async fn process_extrinsic<'a, 'scale>(
 					for (msg_index, msg) in channels {
 						msg_count += 1;
 						log!("found {} downward_message is, {}", msg_index, &msg);
-						if let (Some(scale_borrow::Value::ScaleOwned(bytes)), Some(_sent_at)) =
-							(msg.get("msg"), msg.get("sent_at"))
+						if let (Some(bytes), Some(_sent_at)) =
+							(msg.get("msg").and_then(|msg| msg.as_bytes()), msg.get("sent_at"))
 						{
-							let v = polkadyn::decode_xcm(meta, &bytes[..]).unwrap_or_else(|_| {
-								panic!(
-									"{}",
-									&format!(
-										"expect to be able to decode {}",
-										&hex::encode(&bytes[..])
-									)
-								)
-							});
-							log!("xcm msgv= {}", v);
-							let msg_decoded = scale_value_to_borrowed::convert(&v, true);
-							// msg.set("msg_decoded", msg_decoded);
-							let msg = msg_decoded;
+							let msg = match polkadyn::decode_xcm_borrowed(meta, bytes) {
+								Ok(msg) => msg,
+								Err(err) => {
+									let bytes = hex::encode(bytes);
+									log!("can't decode xcm msg {} ({})", bytes, err);
+									continue
+								},
+							};
 							log!("xcm msg = {}", msg);
 
 							if let Some(instructions) = msg.get("V0") {
@@ -1087,37 +1351,12 @@ async fn process_extrinsic<'a, 'scale>(
 				if variant.contains("batch") {
 					// log!("found batch {:?}", payload);
 					if let Some(args) = payload.get("calls") {
-						let v = ext2;
-						// log!("BATCH {:?}", v);
-
-						let uuv = unwrap_value(unwrap_value(v).unwrap()).unwrap();
-
-						// log!("BATCH UNWRAPPED {:?}", uuv);
-						// if let scale_value::ValueDef::Variant(scale_value::Variant { name: _,
-						// values}) = &v.value {
-
-						/*
-						Value { value: Variant(
-							Variant {
-								name: "Utility",
-								values: Unnamed(
-									[
-										Value {
-											value: Variant(
-												Variant { name: "batch_all", values: Named([("calls", Value { value: Composite(Unnamed([Value { value: Variant(Variant { name: "Balances", values: Unnamed([Value { value: Variant(Variant { name: "transfer", values: Named([("dest", Value { value: Variant(Variant { name: "Id", values: Unnamed([Value { value:
-						*/
-						// }
-
-						let mut i = 0;
-						for (_, instruction) in args {
-							let val = if let scale_value::ValueDef::Composite(
-								scale_value::Composite::Unnamed(uuv),
-							) = &uuv.value
-							{
-								uuv.iter().nth(i)
-							} else {
-								None
-							};
+						// Each call as encoded, so it can be replayed on its own.
+						let calls = polkadyn::extrinsic_call(meta, ex_slice)
+							.and_then(|call| polkadyn::batch_calls(meta, call))
+							.unwrap_or_default();
+
+						for (i, (_, instruction)) in args.into_iter().enumerate() {
 							if let Some((inner_pallet, "0", inner_variant, extrinsic_payload)) =
 								instruction.only3()
 							{
@@ -1128,17 +1367,14 @@ async fn process_extrinsic<'a, 'scale>(
 								// 	instruction
 								// );
 								children.push(DataEntity::Extrinsic {
-									args: vec![format!("{}", instruction)],
+									args: vec![to_text(instruction, ss58)],
 									contains: vec![],
 									start_link: vec![],
 									end_link: vec![],
 									msg_count: 0,
 									details: Details {
-										value: val.map(|v| {
-											scale_value::stringify::to_string(
-												&v.clone().remove_context(),
-											)
-										}),
+										value: to_json(instruction, ss58),
+										call: calls.get(i).map(|call| call.to_vec()),
 										// raw: ex_slice.to_vec(), //TODO reference not own.
 										pallet: inner_pallet.to_string(),
 										variant: inner_variant.to_string(),
@@ -1159,7 +1395,6 @@ async fn process_extrinsic<'a, 'scale>(
 									.await;
 								}
 							}
-							i += 1;
 						}
 					}
 				}
@@ -1170,13 +1405,13 @@ async fn process_extrinsic<'a, 'scale>(
 						{
 							//TODO: if the sudo is a batch...
 							children.push(DataEntity::Extrinsic {
-								args: vec![format!("{}", instruction)],
+								args: vec![to_text(instruction, ss58)],
 								contains: vec![],
 								start_link: vec![],
 								end_link: vec![],
 								msg_count: 0,
 								details: Details {
-									value: Some(extrinsic_payload.to_string()),
+									value: to_json(instruction, ss58),
 									pallet: inner_pallet.to_string(),
 									variant: inner_variant.to_string(),
 									doturl: extrinsic_url.clone(),
@@ -1191,7 +1426,8 @@ async fn process_extrinsic<'a, 'scale>(
 
 		(pallet, variant)
 	} else {
-		panic!("could not find call_data.pallet_name in {:#?}", &ext);
+		log!("extrinsic {} is not a pallet call", extrinsic_url);
+		return None
 	};
 
 	// log!("found {pallet} / {variant}");
@@ -1244,6 +1480,9 @@ async fn process_extrinsic<'a, 'scale>(
 			variant: variant.to_string(),
 			raw: ex_slice.to_vec(),
 			value,
+			error: None,
+			runtime: None,
+			verification: Verification::Unchecked,
 			links: vec![]
 		},
 	})
@@ -1252,117 +1491,27 @@ async fn process_extrinsic<'a, 'scale>(
 	// extrinsic");
 }
 
-fn unwrap_value<T>(v: &scale_value::Value<T>) -> Option<&scale_value::Value<T>> {
-	if let scale_value::ValueDef::Variant(scale_value::Variant { name: _, values }) = &v.value {
-		if let scale_value::Composite::Unnamed(vals) = values {
-			for v in vals {
-				return Some(v) //return first
-			}
-		}
-		if let scale_value::Composite::Named(vals) = values {
-			for (_name, v) in vals {
-				return Some(v) //return first
-			}
-		}
-	}
-	None
-}
-
 async fn check_reserve_asset<'scale, 'b>(
 	args: &scale_borrow::Value<'scale>,
 	extrinsic_url: &DotUrl,
 	start_link: &'b mut Vec<(String, LinkType)>,
 ) {
-	if let Some(beneficiary) = args.find2("beneficiary", "V2") {
-		// println!("check reserve assetben {}", beneficiary);
-		if let Some(rest) = beneficiary.find2("0", "interior") {
-			if let Some(("X1", "0", rest)) = rest.only2() {
-				if let Some(scale_borrow::Value::ScaleOwned(to)) = rest.find2("AccountId32", "id") {
-					// println!("GOT benefactor address is {:?}", *to);
-					if let Some(dest) = args.find2("dest", "V2") {
-						if let Some(rest) = dest.find2("0", "interior") {
-							if let Some(("X1", "0", rest)) = rest.only2() {
-								if let Some(("Parachain", "0", scale_borrow::Value::U64(para_id))) =
-									rest.only2()
-								{
-									log!("GOT PARA ID dest is {}", para_id);
-									// let dest = NonZeroU32::try_from(*para_id as u32).unwrap();
-									let msg_id = format!(
-										"{}-{}",
-										extrinsic_url.block_number.unwrap(),
-										please_hash(to)
-									);
-									log!("FIRST TIME 2 SEND MSG v2 hash {}", msg_id);
-									start_link.push((msg_id, LinkType::ReserveTransfer));
-								}
-							}
-							// TODO: parent 1 - upwards
-						}
-					}
-				}
-			}
-		}
-	}
-
-	if let Some(beneficiary) = args.find2("beneficiary", "V1") {
-		// println!("check reserve assetben {}", beneficiary);
-		if let Some(rest) = beneficiary.find2("0", "interior") {
-			if let Some(("X1", "0", rest)) = rest.only2() {
-				if let Some(scale_borrow::Value::ScaleOwned(to)) = rest.find2("AccountId32", "id") {
-					// println!("GOT benefactor address is {:?}", *to);
-					if let Some(dest) = args.find2("dest", "V1") {
-						if let Some(rest) = dest.find2("0", "interior") {
-							if let Some(("X1", "0", rest)) = rest.only2() {
-								if let Some(("Parachain", "0", scale_borrow::Value::U64(para_id))) =
-									rest.only2()
-								{
-									log!("GOT PARA ID dest is {}", para_id);
-									// let dest = NonZeroU32::try_from(*para_id as u32).unwrap();
-									let msg_id = format!(
-										"{}-{}",
-										extrinsic_url.block_number.unwrap(),
-										please_hash(to)
-									);
-									log!("SEND MSG v1 hash {}", msg_id);
-									start_link.push((msg_id, LinkType::ReserveTransfer));
-								}
-							}
-							// TODO: parent 1 - upwards
-						}
-					}
-				}
-			}
-		}
-	}
-
-	if let Some(beneficiary) = args.find2("beneficiary", "V0") {
-		// println!("check reserve assetben {}", beneficiary);
-		if let Some(rest) = beneficiary.find("0") {
-			if let Some(("X1", "0", rest)) = rest.only2() {
-				if let Some(scale_borrow::Value::ScaleOwned(to)) = rest.find2("AccountId32", "id") {
-					// println!("GOT benefactor address is {:?}", *to);
-					if let Some(dest) = args.find2("dest", "V0") {
-						if let Some(rest) = dest.find("0") {
-							if let Some(("X1", "0", rest)) = rest.only2() {
-								if let Some(("Parachain", "0", scale_borrow::Value::U64(para_id))) =
-									rest.only2()
-								{
-									log!("GOT PARA ID dest is {}", para_id);
-									// let dest = NonZeroU32::try_from(*para_id as u32).unwrap();
-									let msg_id = format!(
-										"{}-{}",
-										extrinsic_url.block_number.unwrap(),
-										please_hash(to)
-									);
-									log!("SEND MSG v0 hash {}", msg_id);
-									start_link.push((msg_id, LinkType::ReserveTransfer));
-								}
-							}
-							// TODO: parent 1 - upwards
-						}
-					}
-				}
-			}
+	// V0 locations are just the junctions, later ones have `parents` and an `interior`.
+	for beneficiary in args.query("beneficiary.V*.0.**.X1.0.AccountId32.id") {
+		let to = match beneficiary.value.as_bytes() {
+			Some(to) => to,
+			None => continue,
+		};
+		// println!("GOT benefactor address is {:?}", *to);
+		let version = beneficiary.path[1];
+		let dest = format!("dest.{}.0.**.X1.0.Parachain.0", version);
+		// TODO: parent 1 - upwards
+		if let Some(para_id) = args.query_first(&dest).and_then(|para_id| para_id.as_u64()) {
+			log!("GOT PARA ID dest is {}", para_id);
+			// let dest = NonZeroU32::try_from(*para_id as u32).unwrap();
+			let msg_id = format!("{}-{}", extrinsic_url.block_number.unwrap(), please_hash(&to));
+			log!("SEND MSG {} hash {}", version, msg_id);
+			start_link.push((msg_id, LinkType::ReserveTransfer));
 		}
 	}
 
@@ -1422,15 +1571,36 @@ pub struct PolkaBlock {
 	pub extrinsics: Vec<DataEntity>,
 	pub events: Vec<DataEvent>,
 	pub weight: Option<u64>,
+	/// Runtime version label, e.g. "polkadot v9430".
+	pub runtime: Option<String>,
+	/// Whether its weights and events were checked against its state root.
+	pub verification: Verification,
 }
 
+/// Events that send a message on to another chain, keeping the message's topic.
+const TOPIC_SENDERS: &[(&str, &str)] = &[
+	("XcmPallet", "Sent"),
+	("PolkadotXcm", "Sent"),
+	("XcmpQueue", "XcmpMessageSent"),
+	("ParachainSystem", "UpwardMessageSent"),
+];
+
+/// Events that process a message from another chain, keeping the message's topic.
+const TOPIC_RECEIVERS: &[(&str, &str)] = &[
+	("MessageQueue", "Processed"),
+	("MessageQueue", "ProcessingFailed"),
+	("XcmpQueue", "Success"),
+	("XcmpQueue", "Fail"),
+	("DmpQueue", "ExecutedDownward"),
+	("Ump", "ExecutedUpward"),
+];
+
 // Timestamp only needs to be provided when relay chain.
 async fn get_events_for_block(
-	source: &mut impl Source,
-	blockhash: H256,
+	events: Option<Vec<u8>>,
 	sender: &Option<HashMap<u32, async_std::channel::Sender<(RelayBlockNumber, i64, H256)>>>,
 	block_url: &DotUrl,
-	metad: &frame_metadata::RuntimeMetadataPrefixed,
+	metad: &MetadataIndex,
 	timestamp: Option<i64>,
 ) -> Result<(Vec<DataEvent>, Vec<(Vec<u8>, LinkType)>), Box<dyn std::error::Error>> {
 	let mut start_links: Vec<(Vec<u8>, LinkType)> = vec![];
@@ -1438,42 +1608,27 @@ async fn get_events_for_block(
 
 	let blocknum = block_url.block_number.unwrap();
 
-	// let storage = decoder::decode_events(&metad, );
-	let events_key = "26aa394eea5630e07c48ae0c9558cef780d41e5e16056765bc8461851072c9d7";
-	let storage_key = hex::decode(events_key).unwrap();
-	// let events_entry = storage
-	// 	.decode_key(&metad, &mut storage_key.as_slice())
-	// 	.expect("can decode storage");
-
-	let bytes = source
-		.fetch_storage(&storage_key[..], Some(blockhash))
-		.await
-		.map_err(|_| "oops num 332")?;
-
-	if let Some(events_raw) = bytes {
-		if let Ok(events) = polkadyn::decode_events(metad, &events_raw[..]) {
+	if let Some(events_raw) = events {
+		if let Ok(events) = polkadyn::decode_events_borrowed(metad, &events_raw[..]) {
+			let ss58 = ss58_format(metad);
 			// if let ValueDef::Composite(Composite::Unnamed(events)) = val.value {
 			let mut inclusions = vec![];
 			let mut ext_count_map = HashMap::new();
 			let _events: Vec<_> = events
 				.iter()
-				.map(|(phase, event_val, raw_event)| {
-					let event = scale_value_to_borrowed::convert(event_val, true);
-
-					// let event = scale_value_to_borrowed::convert(&event2);
+				.map(|record| {
+					let event = &record.value;
 					let mut start_link = vec![];
 					let mut end_link = vec![];
 					let mut details = Details {
 						// url: source.url().to_string(),
 						doturl: DotUrl { ..block_url.clone() },
-						value: Some(scale_value::stringify::to_string(
-							&event_val.clone().remove_context(),
-						)),
-						raw: raw_event.to_vec(),
+						value: to_json(event, ss58),
+						raw: record.raw.to_vec(),
 						..Default::default()
 					};
 
-					if let polkadyn::Phase::ApplyExtrinsic(extrinsic_num) = phase {
+					if let polkadyn::Phase::ApplyExtrinsic(extrinsic_num) = &record.phase {
 						details.parent = Some(*extrinsic_num);
 						let count = ext_count_map.entry(extrinsic_num).or_insert(0);
 						*count += 1;
@@ -1487,24 +1642,39 @@ async fn get_events_for_block(
 						details.doturl.event = Some(*count);
 					}
 
-					let (pallet, variant, contents) =
-						if let Some((pallet, "0", variant, contents)) = event.only3() {
-							(pallet, variant, contents)
-						} else {
-							panic!("could not find call_data.pallet_name in {:#?}", &event)
-						};
+					details.pallet = record.pallet.clone();
+					details.variant = record.variant.clone();
 
-					details.pallet = pallet.to_string();
-					details.variant = variant.to_string();
+					// ExtrinsicSuccess needs no more than the default Happy.
+					if let Some(error) = polkadyn::extrinsic_failed(metad, record) {
+						details.success = crate::ui::details::Success::Sad;
+						details.error = Some(error.to_string());
+					}
+
+					// Topics (e.g. XCM `SetTopic` message ids) tie together events on different
+					// chains: the sending side starts the link, the receiving side finishes it.
+					let side = (record.pallet.as_str(), record.variant.as_str());
+					let links = if TOPIC_SENDERS.contains(&side) {
+						Some(&mut start_link)
+					} else if TOPIC_RECEIVERS.contains(&side) {
+						Some(&mut end_link)
+					} else {
+						None
+					};
+					if let Some(links) = links {
+						for topic in &record.topics {
+							links.push((hex::encode(topic), LinkType::Topic));
+						}
+					}
 
 					match (details.pallet.as_str(),details.variant.as_str()) {
 						("Balances","Deposit") => {
 							if let Some(("Balances", "0", "Deposit", tail)) = event.only3() {
 								if let Some(tail) = tail.get("who") {
-									if let Some(scale_borrow::Value::ScaleOwned(scale)) = tail.get("0") {
+									if let Some(scale) = tail.get("0").and_then(|who| who.as_bytes()) {
 										// log!("got here readddit {:?}", scale);
-										// start_link.push((hex::encode((*scale).as_slice()), LinkType::Balances));
-										end_link.push((hex::encode((*scale).as_slice()), LinkType::Balances));
+										// start_link.push((hex::encode(scale), LinkType::Balances));
+										end_link.push((hex::encode(scale), LinkType::Balances));
 									}
 								}
 								// if let Some(tail) = tail.get("amount") {
@@ -1517,9 +1687,9 @@ async fn get_events_for_block(
 						("Balances","Withdraw") => {
 							if let Some(("Balances", "0", "Withdraw", tail)) = event.only3() {
 								if let Some(tail) = tail.get("who") {
-									if let Some(scale_borrow::Value::ScaleOwned(scale)) = tail.get("0") {
+									if let Some(scale) = tail.get("0").and_then(|who| who.as_bytes()) {
 										log!("got here readddit2 {:?}", scale);
-										start_link.push((hex::encode((*scale).as_slice()), LinkType::Balances));
+										start_link.push((hex::encode(scale), LinkType::Balances));
 									}
 								}
 								// if let Some(tail) = tail.get("amount") {
@@ -1532,15 +1702,16 @@ async fn get_events_for_block(
 						_ => {}
 					}
 
-					if details.pallet == "ParaInclusion" && details.variant == "CandidateIncluded" {
+					if let Some(("ParaInclusion", "0", "CandidateIncluded", contents)) =
+						event.only3()
+					{
 						if let Some(inner) = contents.find2("0", "descriptor") {
-							if let (
-								Some(scale_borrow::Value::U64(parachain_id)),
-								Some(scale_borrow::Value::ScaleOwned(para_head)),
-							) = (inner.find2("para_id", "0"), inner.find2("para_head", "0"))
-							{
-								let para_id = *parachain_id as u32;
-								inclusions.push((para_id, para_head.as_slice().to_vec()));
+							if let (Some(parachain_id), Some(para_head)) = (
+								inner.find2("para_id", "0").and_then(|para_id| para_id.as_u64()),
+								inner.find2("para_head", "0").and_then(|para_head| para_head.as_bytes()),
+							) {
+								let para_id = parachain_id as u32;
+								inclusions.push((para_id, para_head.to_vec()));
 							}
 						}
 					} else {
@@ -1,5 +1,10 @@
-use crate::log;
+use crate::{log, ui::details::Verification};
+use polkapipe::{
+	metrics::{MethodMetrics, Metrics, Observed},
+	proof::{self, Header},
+};
 use primitive_types::H256;
+use std::{collections::BTreeMap, sync::Arc};
 #[cfg(not(target_arch = "wasm32"))]
 use {
 	async_tungstenite::{tungstenite::Message, WebSocketStream},
@@ -24,6 +29,15 @@ impl AgnosticBlock {
 	}
 }
 
+/// A block along with some of its storage, fetched together.
+pub struct BlockBundle {
+	pub block: Option<AgnosticBlock>,
+	/// As of the block, in the order the keys were asked for.
+	pub storage: Vec<Option<Vec<u8>>>,
+	/// How each of `storage` stood up to the block's state root, empty if it wasn't checked.
+	pub verification: Vec<Verification>,
+}
+
 /// A way to source untransformed raw data.
 pub trait Source {
 	async fn fetch_block_hash(&mut self, block_number: u32) -> Result<Option<H256>, BError>;
@@ -39,10 +53,40 @@ pub trait Source {
 		as_of: Option<H256>,
 	) -> Result<Option<Vec<u8>>, BError>;
 
+	/// The block and storage items as of it in one round trip (where the backend can batch).
+	async fn fetch_block_bundle(
+		&mut self,
+		block_hash: H256,
+		keys: &[&[u8]],
+	) -> Result<BlockBundle, BError>;
+
 	async fn fetch_metadata(&mut self, as_of: Option<H256>) -> Result<Option<Vec<u8>>, ()>;
 
+	/// e.g. version 15 for the runtime API descriptions.
+	async fn fetch_metadata_at_version(
+		&mut self,
+		version: u32,
+		as_of: Option<H256>,
+	) -> Result<Option<Vec<u8>>, ()>;
+
+	async fn fetch_runtime_api(
+		&mut self,
+		api: &str,
+		method: &str,
+		args: &[u8],
+		as_of: Option<H256>,
+	) -> Result<Option<Vec<u8>>, BError>;
+
+	async fn submit(&mut self, extrinsic: &[u8]) -> Result<(), BError>;
+
 	fn url(&self) -> &str;
 
+	/// How much the node has been holding us back.
+	fn throttling(&self) -> polkapipe::Throttling;
+
+	/// Latency, errors and bytes of the calls made so far, by method.
+	fn rpc_metrics(&self) -> BTreeMap<String, MethodMetrics>;
+
 	// #[cfg(target_arch="wasm32")]
 	// async fn process_incoming_messages(&mut self) -> WSBackend;
 }
@@ -225,8 +269,15 @@ pub trait Source {
 #[cfg(target_arch = "wasm32")]
 pub(crate) type WSBackend = polkapipe::ws_web::Backend;
 
+#[cfg(all(not(test), not(target_arch = "wasm32")))]
+pub(crate) type WSBackend = LiveBackend;
+
+// Tests replay what the node said the last time they were run.
+#[cfg(all(test, not(target_arch = "wasm32")))]
+pub(crate) type WSBackend = polkapipe::mock::Node<LiveBackend>;
+
 #[cfg(not(target_arch = "wasm32"))]
-pub(crate) type WSBackend = polkapipe::ws::Backend<
+type LiveBackend = polkapipe::ws::Backend<
 	SinkErrInto<
 		SplitSink<
 			WebSocketStream<
@@ -242,10 +293,16 @@ pub(crate) type WSBackend = polkapipe::ws::Backend<
 	>,
 >;
 
+/// Every call is counted in the source's `Metrics`.
+pub(crate) type Client = polkapipe::PolkaPipe<Observed<WSBackend, Arc<Metrics>>>;
+
 //#[derive(Clone)]
 pub struct RawDataSource {
 	ws_url: Vec<String>,
-	client: Option<polkapipe::PolkaPipe::<WSBackend>>,
+	client: Option<Client>,
+	// Kept across reconnections.
+	metrics: Arc<Metrics>,
+	verify: bool,
 }
 
 type BError = polkapipe::Error;
@@ -254,31 +311,72 @@ type BError = polkapipe::Error;
 /// This is the only type that should know about subxt
 impl RawDataSource {
 	pub fn new(url: Vec<String>) -> Self {
-		RawDataSource { ws_url: url, client: None }
+		RawDataSource { ws_url: url, client: None, metrics: Default::default(), verify: false }
+	}
+
+	/// Rather than taking the node's word for it, check the storage of block bundles against
+	/// a read proof from the state root of the block (whose header has to hash to the block
+	/// hash). It costs another round trip a block.
+	pub fn with_verification(mut self, verify: bool) -> Self {
+		self.verify = verify;
+		self
+	}
+
+	pub(super) fn verifies(&self) -> bool {
+		self.verify
 	}
 
 	#[cfg(target_arch = "wasm32")]
-	async fn client(&mut self) -> Option<&mut polkapipe::PolkaPipe::<WSBackend>> {
+	pub(super) async fn client(&mut self) -> Option<&mut Client> {
 		if self.client.is_none() {
 			let urls: Vec<_> = self.ws_url.iter().map(|s| s.as_ref()).collect();
 			if let Ok(client) = polkapipe::ws_web::Backend::new(urls.as_slice()).await  {
-				self.client = Some(polkapipe::PolkaPipe::<polkapipe::ws_web::Backend>{rpc:client});
+				self.client =
+					Some(polkapipe::PolkaPipe { rpc: Observed::new(client, self.metrics.clone()) });
 			}
 		}
 		self.client.as_mut()
 	}
 
 	#[cfg(not(target_arch = "wasm32"))]
-	async fn client(&mut self) -> Option<&mut polkapipe::PolkaPipe::<WSBackend>> {
+	pub(super) async fn client(&mut self) -> Option<&mut Client> {
 		if self.client.is_none() {
-			if let Ok(client) = polkapipe::ws::Backend::new(&self.ws_url[0]).await {
-				self.client = Some(polkapipe::PolkaPipe::<WSBackend>{rpc:client});
+			if let Ok(client) = connect(&self.ws_url[0]).await {
+				self.client =
+					Some(polkapipe::PolkaPipe { rpc: Observed::new(client, self.metrics.clone()) });
 			}
 		}
 		self.client.as_mut()
 	}
 }
 
+#[cfg(all(not(test), not(target_arch = "wasm32")))]
+async fn connect(url: &str) -> Result<WSBackend, BError> {
+	Ok(polkapipe::ws::Backend::new(url).await?)
+}
+
+/// Replays `tests/transcripts/<test name>.json`, see `polkapipe::mock::Node::replay`.
+#[cfg(all(test, not(target_arch = "wasm32")))]
+async fn connect(url: &str) -> Result<WSBackend, BError> {
+	thread_local! {
+		static CONNECTIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+	}
+	// Tests that connect more than once get a transcript for each connection.
+	let connection = CONNECTIONS.with(|connections| connections.replace(connections.get() + 1));
+	let test = std::thread::current().name().unwrap_or("main").replace("::", "_");
+	let transcript = match connection {
+		0 => test,
+		n => format!("{}_{}", test, n + 1),
+	};
+	let path = format!("{}/tests/transcripts/{}.json", env!("CARGO_MANIFEST_DIR"), transcript);
+	let node = polkapipe::mock::Node::replay(path, || async {
+		polkapipe::ws::Backend::new(url).await.map_err(BError::from)
+	})
+	.await;
+	// Fail the test rather than have it look like the node is down.
+	Ok(node.unwrap_or_else(|err| panic!("{}", err)))
+}
+
 // #[async_trait(?Send)]
 // trait ProcessIncoming {
 // 	async fn process_incoming_messages(&self);
@@ -335,57 +433,9 @@ impl Source for RawDataSource {
 			let opt = block_hash.map(|b| hex::encode(b.as_bytes()));
 			let result = client.query_block(opt.as_deref()).await;
 
-			if let Ok(serde_json::value::Value::Object(map)) = &result {
-				// log!("block = {:?}", map);
-				if let Some(serde_json::value::Value::Object(map)) = map.get("block") {
-					let mut res = AgnosticBlock { block_number: 0, extrinsics: vec![] };
-					if let Some(serde_json::value::Value::Object(m)) = map.get("header") {
-						// log!("header = {:?}", m);
-						if let Some(serde_json::value::Value::String(num_original)) = m.get("number") {
-							 let mut num = num_original.trim_start_matches("0x").to_string();
-							if num.len() % 2 == 1 {
-								// println!("odd found {}", num_original);
-								num = format!("0{}", num);
-							}
-
-							let _bytes = hex::decode(&num).unwrap();
-
-						//	bytes.reverse(); //TODO: why is this needed? it gets the right number but...
-							/* while bytes.len() < 4 {
-								bytes.insert(0, 0);
-							} */
-							// println!("bytes or {}", num_original);
-							// println!("bytes is {}", hex::encode(&bytes));
-							// use parity_scale_codec::Decode; 
-
-						   let number: u32 = u32::from_str_radix(num_original.trim_start_matches("0x"), 16).unwrap();
-//							let number = u32::decode(&mut &bytes[..]).unwrap();
-
-							/* let mut b = [0,0,0,0];
-							for (i, bb) in bytes.iter().enumerate() {
-								b[i] = *bb;
-							} */
-							/* use parity_scale_codec::Compact;
-					/* 		 */let number = Compact::<u32>::decode(&mut &bytes[..]).unwrap(); */
-						/* 	/*  */let re : u32 = number.into(); */
-					// println!("bytes {} -> {}",&num_original, number);   
-							res.block_number = number;
-						}
-					}
-					if let Some(serde_json::value::Value::Array(extrinsics)) = map.get("extrinsics")
-					{
-						for ex in extrinsics {
-							if let serde_json::value::Value::String(val) = ex {
-								/* println!("about to decode '{}'", &val); */
-								res.extrinsics
-									.push(hex::decode(val.trim_start_matches("0x")).unwrap());
-							} else {
-								panic!()
-							}
-						}
-						// println!("got 4here aa{}", extrinsics.len());
-					}
-					return Ok(Some(res))
+			if let Ok(block) = &result {
+				if let Some(block) = agnostic_block(block) {
+					return Ok(Some(block))
 				}
 			}
 			result.map(|_| None)
@@ -427,6 +477,33 @@ impl Source for RawDataSource {
 		}
 	}
 
+	async fn fetch_block_bundle(
+		&mut self,
+		block_hash: H256,
+		keys: &[&[u8]],
+	) -> Result<BlockBundle, BError> {
+		let verify = self.verify;
+		if let Some(client) = self.client().await {
+			let (block, storage) =
+				client.query_block_with_storage(block_hash.as_bytes(), keys).await?;
+			let verification = if verify {
+				match client.query_read_proof(keys, Some(block_hash.as_bytes())).await {
+					Ok(read_proof) =>
+						verify_storage(&block, block_hash, &read_proof.proof, keys, &storage),
+					Err(err) => {
+						log!("no read proof for {:?}: {}", block_hash, err);
+						vec![Verification::Unverifiable; keys.len()]
+					},
+				}
+			} else {
+				vec![]
+			};
+			Ok(BlockBundle { block: agnostic_block(&block), storage, verification })
+		} else {
+			Err(polkapipe::Error::Node(format!("can't get client for {}", self.ws_url[0])))
+		}
+	}
+
 	async fn fetch_metadata(&mut self, as_of: Option<H256>) -> Result<Option<Vec<u8>>, ()> {
 		if let Some(client) = self.client().await {
 			if let Some(as_of) = as_of {
@@ -439,15 +516,138 @@ impl Source for RawDataSource {
 		}
 	}
 
+	async fn fetch_metadata_at_version(
+		&mut self,
+		version: u32,
+		as_of: Option<H256>,
+	) -> Result<Option<Vec<u8>>, ()> {
+		if let Some(client) = self.client().await {
+			let as_of = as_of.as_ref().map(|as_of| as_of.as_bytes());
+			client.query_metadata_at_version(version, as_of).await.map_err(|_e| ())
+		} else {
+			Err(())
+		}
+	}
+
+	async fn fetch_runtime_api(
+		&mut self,
+		api: &str,
+		method: &str,
+		args: &[u8],
+		as_of: Option<H256>,
+	) -> Result<Option<Vec<u8>>, BError> {
+		if let Some(client) = self.client().await {
+			let as_of = as_of.as_ref().map(|as_of| as_of.as_bytes());
+			client.query_runtime_api(api, method, args, as_of).await.map(Some)
+		} else {
+			Err(polkapipe::Error::Node(format!("can't get client for {}", self.ws_url[0])))
+		}
+	}
+
+	async fn submit(&mut self, extrinsic: &[u8]) -> Result<(), BError> {
+		if let Some(client) = self.client().await {
+			client.submit(extrinsic).await
+		} else {
+			Err(polkapipe::Error::Node(format!("can't get client for {}", self.ws_url[0])))
+		}
+	}
+
 	fn url(&self) -> &str {
 		&self.ws_url[0]
 	}
+
+	fn throttling(&self) -> polkapipe::Throttling {
+		self.client.as_ref().map(|client| client.throttling()).unwrap_or_default()
+	}
+
+	fn rpc_metrics(&self) -> BTreeMap<String, MethodMetrics> {
+		self.metrics.snapshot()
+	}
+}
+
+/// Check storage values against the state root in the header of a block (`chain_getBlock` JSON),
+/// once the header is shown to be the one of `block_hash`.
+fn verify_storage(
+	block: &serde_json::Value,
+	block_hash: H256,
+	proof: &[Vec<u8>],
+	keys: &[&[u8]],
+	storage: &[Option<Vec<u8>>],
+) -> Vec<Verification> {
+	let header = block.get("block").and_then(|block| block.get("header"));
+	let state_root = match header.and_then(Header::from_json) {
+		Some(header) if header.hash() == block_hash.as_bytes() => header.state_root,
+		_ => return vec![Verification::Mismatch; keys.len()],
+	};
+	keys.iter()
+		.zip(storage)
+		.map(|(key, value)| match proof::read(&state_root, proof, key) {
+			Ok(proven) if proven == *value => Verification::Verified,
+			_ => Verification::Mismatch,
+		})
+		.collect()
+}
+
+/// Pull the block number and extrinsics out of `chain_getBlock` JSON.
+fn agnostic_block(block: &serde_json::value::Value) -> Option<AgnosticBlock> {
+	if let Some(serde_json::value::Value::Object(map)) = block.get("block") {
+		let mut res = AgnosticBlock { block_number: 0, extrinsics: vec![] };
+		if let Some(serde_json::value::Value::Object(m)) = map.get("header") {
+			if let Some(serde_json::value::Value::String(num_original)) = m.get("number") {
+				let number: u32 =
+					u32::from_str_radix(num_original.trim_start_matches("0x"), 16).unwrap();
+				res.block_number = number;
+			}
+		}
+		if let Some(serde_json::value::Value::Array(extrinsics)) = map.get("extrinsics") {
+			for ex in extrinsics {
+				if let serde_json::value::Value::String(val) = ex {
+					res.extrinsics.push(hex::decode(val.trim_start_matches("0x")).unwrap());
+				} else {
+					panic!()
+				}
+			}
+		}
+		return Some(res)
+	}
+	None
 }
 
 #[cfg(test)]
 mod tests {
+	use super::*;
 	use parity_scale_codec::Encode;
 
+	#[test]
+	fn storage_is_checked_against_the_state_root() {
+		// A trie of just 0x1234 => "value": a leaf with a partial key of 4 nibbles.
+		let leaf = hex::decode(format!("441234{}", hex::encode((*b"value").to_vec().encode())))
+			.unwrap();
+		let header = serde_json::json!({
+			"parentHash": format!("0x{}", "11".repeat(32)),
+			"number": "0x10",
+			"stateRoot": format!("0x{}", hex::encode(proof::blake2_256(&leaf))),
+			"extrinsicsRoot": format!("0x{}", "33".repeat(32)),
+			"digest": { "logs": [] }
+		});
+		let block_hash = H256::from_slice(&Header::from_json(&header).unwrap().hash());
+		let block = serde_json::json!({ "block": { "header": header, "extrinsics": [] } });
+		let keys: [&[u8]; 2] = [&[0x12, 0x34], &[0x12, 0x35]];
+		let proof = [leaf];
+
+		let honest = [Some(b"value".to_vec()), None];
+		let verification = verify_storage(&block, block_hash, &proof, &keys, &honest);
+		assert_eq!(verification, vec![Verification::Verified, Verification::Verified]);
+
+		let lies = [Some(b"other".to_vec()), Some(vec![])];
+		let verification = verify_storage(&block, block_hash, &proof, &keys, &lies);
+		assert_eq!(verification, vec![Verification::Mismatch, Verification::Mismatch]);
+
+		// Not the header of the block asked for.
+		let verification = verify_storage(&block, H256::zero(), &proof, &keys, &honest);
+		assert_eq!(verification, vec![Verification::Mismatch, Verification::Mismatch]);
+	}
+
 	#[test]
 	fn testit() {
 		/* let  hex::decode("03ee6c")/*  */.unwrap(); */
@@ -1,4 +1,4 @@
-use crate::datasource::{find_timestamp, get_block_hash, Source};
+use crate::datasource::{expected_block_time, find_timestamp, get_block_hash, Source};
 use async_recursion::async_recursion;
 use std::convert::TryInto;
 
@@ -11,9 +11,11 @@ pub async fn get_block_number_near_timestamp(
 	source: &mut impl Source,
 	//&mut impl FnMut(u32) -> Option<Time>,
 	average_blocktime_in_ms: Option<u64>,
-	metad_current: &frame_metadata::RuntimeMetadataPrefixed,
+	metad_current: &polkadyn::MetadataIndex,
 ) -> Option<u32> {
 	debug_assert!(search_timestamp > 9_654_602_493, "you were meant to multiply that by 1000");
+	let average_blocktime_in_ms =
+		average_blocktime_in_ms.or_else(|| expected_block_time(metad_current));
 	get_block_number_near_timestamp_helper(
 		search_timestamp,
 		start_block as i64,
@@ -31,7 +33,7 @@ async fn get_block_number_near_timestamp_helper<S: Source>(
 	start_block: i64,
 	source: &mut S,
 	average_blocktime_in_ms: Option<i64>,
-	metad_current: &frame_metadata::RuntimeMetadataPrefixed,
+	metad_current: &polkadyn::MetadataIndex,
 ) -> Option<i64> {
 	let average_blocktime_in_ms = average_blocktime_in_ms.unwrap_or(12_000);
 
@@ -18,7 +18,7 @@ use lazy_static::lazy_static;
 use primitive_types::H256;
 use serde::{Deserialize, Serialize};
 use std::{
-	collections::{HashMap, HashSet},
+	collections::{BTreeMap, HashMap, HashSet},
 	convert::AsRef,
 	f32::consts::PI,
 	iter,
@@ -112,6 +112,9 @@ const CUBE_WIDTH: f32 = 0.8;
 
 static FREE_TXS: AtomicU64 = AtomicU64::new(0);
 
+/// How many extrinsics and events mention the account last searched for.
+static FOUND: AtomicU32 = AtomicU32::new(0);
+
 // The time by which all times should be placed relative to each other on the x axis.
 lazy_static! {
 	static ref BASETIME: Arc<Mutex<i64>> = default();
@@ -150,6 +153,11 @@ lazy_static! {
 	static ref PALLETS: Arc<std::sync::Mutex<HashSet<String>>> = default();
 }
 
+/// What the diagnostics panel shows.
+lazy_static! {
+	static ref RPC_HEALTH: Arc<std::sync::Mutex<Vec<RpcHealth>>> = default();
+}
+
 /// Bump this to tell the current datasources to stop.
 static DATASOURCE_EPOC: AtomicU32 = AtomicU32::new(0);
 
@@ -174,17 +182,28 @@ use core::sync::atomic::AtomicU64;
 
 #[derive(Default)]
 pub struct ChainStats {
+	chain_name: String,
 	/// weight of blocks containing non-boring extrinsics (with base block already subtracted)
 	total_block_weight: u64,
 	/// number of non-boring extrinsics in blocks
 	total_extrinsics: u32,
+	/// `System.BlockWeights.max_block` from the chain's metadata.
+	max_block_weight: Option<u64>,
+	/// Expected block time from the chain's metadata.
+	block_time_ms: Option<u64>,
+	/// Runtime version (e.g. "polkadot v9430") of the newest block seen, by block number.
+	runtime: Option<(u32, String)>,
+	/// How much the chain's node has been holding us back.
+	throttling: polkapipe::Throttling,
+	/// Calls to the chain's node so far, by method.
+	rpc_metrics: BTreeMap<String, polkapipe::metrics::MethodMetrics>,
 }
 
 impl ChainStats {
 	fn avg_free_transactions(&self) -> Option<u64> {
 							 
-		//currently max ever seen + 100m
-		let max_block_size = 500_227_690_912u64; //todo: get from system state call
+		//currently max ever seen + 100m (if the metadata doesn't tell us)
+		let max_block_size = self.max_block_weight.unwrap_or(500_227_690_912u64);
 										 // let min_block_weight = 5_000_000_000u64;
 
 		if self.total_extrinsics == 0 {
@@ -202,6 +221,132 @@ impl ChainStats {
 		//round down
 		free_weight.checked_div(weight_per_extrinsic)
 	}
+
+	fn avg_free_tps(&self) -> Option<u64> {
+		// Assume relay chain block times if the metadata doesn't tell us.
+		let block_time_ms = self.block_time_ms.unwrap_or(12_000).max(1);
+		self.avg_free_transactions().map(|free| free * 1000 / block_time_ms)
+	}
+}
+
+/// How a lane's node has been answering, for the diagnostics panel.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct RpcHealth {
+	pub chain_name: String,
+	/// Runtime version of the newest block seen.
+	pub runtime: Option<String>,
+	pub calls: u64,
+	pub errors: u64,
+	pub bytes_sent: u64,
+	pub bytes_received: u64,
+	pub mean_ms: Option<u64>,
+	pub p95_ms: Option<u64>,
+	pub throttled: u64,
+	pub gave_up: u64,
+	pub in_flight: usize,
+	/// Slowest (by 95th percentile) first.
+	pub methods: Vec<MethodHealth>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MethodHealth {
+	pub method: String,
+	pub calls: u64,
+	pub errors: u64,
+	pub p95_ms: Option<u64>,
+}
+
+impl RpcHealth {
+	fn new(stats: &ChainStats) -> Self {
+		let mut latency = polkapipe::metrics::Histogram::default();
+		let mut health = RpcHealth {
+			chain_name: stats.chain_name.clone(),
+			runtime: stats.runtime.as_ref().map(|(_, runtime)| runtime.clone()),
+			throttled: stats.throttling.throttled,
+			gave_up: stats.throttling.gave_up,
+			in_flight: stats.throttling.in_flight,
+			..default()
+		};
+		for (method, metrics) in &stats.rpc_metrics {
+			health.calls += metrics.calls;
+			health.errors += metrics.errors;
+			health.bytes_sent += metrics.bytes_sent;
+			health.bytes_received += metrics.bytes_received;
+			latency.merge(&metrics.latency);
+			health.methods.push(MethodHealth {
+				method: method.clone(),
+				calls: metrics.calls,
+				errors: metrics.errors,
+				p95_ms: metrics.latency.quantile(0.95).map(|p95| p95.as_millis() as u64),
+			});
+		}
+		health.mean_ms = latency.mean().map(|mean| mean.as_millis() as u64);
+		health.p95_ms = latency.quantile(0.95).map(|p95| p95.as_millis() as u64);
+		health.methods.sort_by_key(|method| core::cmp::Reverse(method.p95_ms));
+		health
+	}
+}
+
+/// The RPC health of each lane, in lane order.
+fn rpc_health() -> Vec<RpcHealth> {
+	let stats = CHAIN_STATS.lock().unwrap();
+	let mut lanes: Vec<_> = stats.iter().collect();
+	lanes.sort_by_key(|(chain_index, _)| **chain_index);
+	lanes.into_iter().map(|(_, stats)| RpcHealth::new(stats)).collect()
+}
+
+/// Refresh `RPC_HEALTH`, straight away natively or when the webworker answers.
+fn request_rpc_health() {
+	#[cfg(not(target_arch = "wasm32"))]
+	{
+		*RPC_HEALTH.lock().unwrap() = rpc_health();
+	}
+	#[cfg(target_arch = "wasm32")]
+	{
+		let mut requests = REQUESTS.lock().unwrap();
+		if !requests.iter().any(|request| matches!(request, BridgeMessage::GetRpcHealth)) {
+			requests.push(BridgeMessage::GetRpcHealth);
+		}
+	}
+}
+
+/// Select the extrinsics and events that mention the account, straight away natively or when
+/// the webworker answers.
+fn request_find_account(account: Vec<u8>) {
+	#[cfg(not(target_arch = "wasm32"))]
+	{
+		let (found, selected) = find_account(&account);
+		FOUND.store(found, Ordering::Relaxed);
+		*SELECTED.lock().unwrap() = selected;
+	}
+	#[cfg(target_arch = "wasm32")]
+	REQUESTS.lock().unwrap().push(BridgeMessage::FindAccount(account));
+}
+
+/// How many of the extrinsics and events mention the account, and the first hundred of them.
+fn find_account(account: &[u8]) -> (u32, Vec<(u32, Details, ChainInfo)>) {
+	const MAX_SELECTED: usize = 100;
+	let details = DETAILS.lock().unwrap();
+	let sovereigns = SOVEREIGNS.lock().unwrap();
+	let mut search = ui::details::AccountSearch::new(account);
+	let found: Vec<_> = details
+		.extrinsic_instances
+		.iter()
+		.enumerate()
+		.chain(details.event_instances.iter().enumerate())
+		.filter(|(_, details)| search.mentioned_in(details))
+		.collect();
+	let selected = match sovereigns.as_ref() {
+		Some(sovereigns) => found
+			.iter()
+			.take(MAX_SELECTED)
+			.map(|(index, details)| {
+				(*index as u32, (*details).clone(), sovereigns.chain_info(&details.doturl))
+			})
+			.collect(),
+		None => vec![],
+	};
+	(found.len() as u32, selected)
 }
 
 pub struct DataSourceChangedEvent {
@@ -648,7 +793,12 @@ async fn run(event_loop: EventLoop<()>, window: Window, params: HashMap<String,
 
 	//"dotsama:/1//10504599".to_string()
 	let mut urlbar = ui::UrlBar::new(q.clone(), Utc::now().naive_utc(), Env::Local);
-	let sovereigns = Sovereigns { relays: vec![], default_track_speed: 1. };
+	// ?verify checks what the nodes say against the state roots of their blocks.
+	let sovereigns = Sovereigns {
+		relays: vec![],
+		default_track_speed: 1.,
+		verify_storage: params.contains_key("verify"),
+	};
 
 	let mut anchor = Anchor::default();
 	let mut destination = movement::Destination::default();
@@ -1116,10 +1266,14 @@ async fn run(event_loop: EventLoop<()>, window: Window, params: HashMap<String,
 		// TODO: avoid doing this every frame...
 		selected_instance_data.clear();
 		for (index, details, _chain_info) in &selected_details {
-			if details.doturl.event.is_some() {
-				selected_instance_data.push(create_selected_instance(&event_instance_data[*index as usize].clone()));
+			// Search results can be ahead of what's been rendered.
+			let instance = if details.doturl.event.is_some() {
+				event_instance_data.get(*index as usize)
 			} else {
-				selected_instance_data.push(create_selected_instance(&extrinsic_instance_data[*index as usize].clone()));
+				extrinsic_instance_data.get(*index as usize)
+			};
+			if let Some(instance) = instance {
+				selected_instance_data.push(create_selected_instance(instance));
 			}
 		}
 
@@ -2988,6 +3142,13 @@ fn source_data(
 					log!("got selected from backend");
 					*SELECTED.lock().unwrap() = selected_details;
 				},
+				WorkerResponse::RpcHealth(health) => {
+					*RPC_HEALTH.lock().unwrap() = health;
+				},
+				WorkerResponse::Found(found, selected) => {
+					FOUND.store(found, Ordering::Relaxed);
+					*SELECTED.lock().unwrap() = selected;
+				},
 			})
 			.spawn("./worker.js");
 
@@ -3015,6 +3176,7 @@ fn source_data(
 
 #[cfg(not(target_arch = "wasm32"))]
 fn do_datasources(sovereigns: Sovereigns, as_of: Option<DotUrl>) {
+	let verify = sovereigns.verify_storage;
 	for relay in sovereigns.relays.into_iter() {
 		let mut relay2: Vec<(ChainInfo, _)> = vec![];
 		let mut send_map: HashMap<
@@ -3045,7 +3207,8 @@ fn do_datasources(sovereigns: Sovereigns, as_of: Option<DotUrl>) {
 				as_of,
 				receive_channel: Some(rc),
 				sender: maybe_sender,
-				forwards: true
+				forwards: true,
+				verify,
 			};
 
 			std::thread::spawn(
@@ -3098,6 +3261,7 @@ async fn do_datasources<F, R>(
 				receive_channel: Some(rc),
 				sender: maybe_sender,
 				forwards: true,
+				verify: sovereigns.verify_storage,
 			};
 
 			#[cfg(target_arch = "wasm32")]
@@ -3255,6 +3419,7 @@ pub enum LinkType {
 	ReserveTransferMintDerivative,
 	ParaInclusion,
 	Balances,
+	Topic,
 }
 
 static EMPTY_SLICE: Vec<DataEntity> = vec![];
@@ -3335,6 +3500,8 @@ pub struct Sovereigns {
 	//                            name    para_id             url
 	pub relays: Vec<Vec<ChainInfo>>,
 	pub default_track_speed: f32,
+	/// Check storage against read proofs from the state root of its block.
+	pub verify_storage: bool,
 }
 
 impl Sovereigns {
@@ -3539,6 +3706,8 @@ fn render_block(
 
 			let details = Details {
 				doturl: DotUrl { extrinsic: None, event: None, ..block.blockurl.clone() },
+				runtime: block.runtime.clone(),
+				verification: block.verification,
 
 				// url: format!(
 				// 	"https://polkadot.js.org/apps/?{}#/explorer/query/{}",
@@ -4327,6 +4496,8 @@ pub struct Inspector {
 	// #[inspectable(deletable = false)]
 	// selected: Option<Details>,
 	hovered: Option<String>,
+	/// Whether the RPC health of each lane is showing.
+	show_diagnostics: bool,
 	// texture: Option<egui::TextureHandle>,
 }
 
@@ -4398,4 +4569,7 @@ pub enum BridgeMessage {
 	GetNewBlocks,
 	GetExtrinsicDetails(u32),
 	GetEventDetails(u32),
+	GetRpcHealth,
+	/// Select whatever mentions this account id.
+	FindAccount(Vec<u8>),
 }
@@ -63,6 +63,12 @@ pub fn style_event(entry: &DataEntity) -> ExStyle {
 		//         color: Color::hex("000000").unwrap(),
 		//     },
 		// }
+		DataEntity::Extrinsic { details, .. } if details.success == Success::Sad => {
+			let emoji =
+				emojidot::extrinsic_emoji(details.pallet.as_str(), details.variant.as_str());
+			let alpha = if let Some(emoji) = emoji { emoji_index(emoji) } else { 255 };
+			ExStyle { color: as_rgbemoji_u32(1., 0., 0., alpha) }
+		},
 		DataEntity::Extrinsic { details, .. } => {
 			let color = palette::Lchuv::new(
 				if darkside { 30. } else { 50. },
@@ -4,6 +4,7 @@ use super::DotUrl;
 // use bevy_ecs::prelude::Component;
 // use bevy_egui::EguiSettings;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 // use bevy_inspector_egui::{
 // 	options::{NumberAttributes, StringAttributes},
 // 	Context, Inspectable,
@@ -17,6 +18,19 @@ pub enum Success {
 	Sad,
 }
 
+/// Whether the storage something was decoded from was checked against its block's state root.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Verification {
+	#[default]
+	Unchecked,
+	/// The read proof backs up what the node said.
+	Verified,
+	/// The node said something the read proof doesn't back up.
+	Mismatch,
+	/// The node wouldn't give a read proof to check it against.
+	Unverifiable,
+}
+
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct Details
 //<'scale> - would require details being stored somewhere with ids.
@@ -33,12 +47,71 @@ pub struct Details
 	// pub url: String,
 	// pub chain_name: String,
 	pub raw: Vec<u8>,
-	pub value: Option<String>, //scale Value as string
+	/// The decoded call or event, as `scale_borrow::Value` JSON.
+	pub value: Option<serde_json::Value>,
+	/// The call as encoded, to replay it.
+	pub call: Option<Vec<u8>>,
+	/// Why it failed (pallet, error and docs) if it did.
+	pub error: Option<String>,
+	/// Runtime version of the block, e.g. "polkadot v9430".
+	pub runtime: Option<String>,
+	pub verification: Verification,
 
 	// pointers to other details that are linked together.
 	pub links: Vec<usize>,
 }
 
+/// Looks for an account id in the values of details. They show account ids as addresses of
+/// their chain's format, so the first address seen on a chain is decoded to find out which, and
+/// after that it's comparing strings with the account encoded in that format.
+pub struct AccountSearch<'a> {
+	account: &'a [u8],
+	hex: String,
+	/// The account as an address of each chain (sovereign, para id) seen so far.
+	addresses: HashMap<(Option<i32>, Option<u32>), String>,
+}
+
+impl<'a> AccountSearch<'a> {
+	pub fn new(account: &'a [u8]) -> Self {
+		Self { account, hex: format!("0x{}", hex::encode(account)), addresses: HashMap::new() }
+	}
+
+	/// Whether the value has the account id in it, as an address or hex.
+	pub fn mentioned_in(&mut self, details: &Details) -> bool {
+		let chain = (details.doturl.sovereign, details.doturl.para_id);
+		details.value.as_ref().map_or(false, |value| self.mentions(chain, value))
+	}
+
+	fn mentions(&mut self, chain: (Option<i32>, Option<u32>), value: &serde_json::Value) -> bool {
+		match value {
+			serde_json::Value::String(s) => {
+				if s.eq_ignore_ascii_case(&self.hex) {
+					return true
+				}
+				if let Some(address) = self.addresses.get(&chain) {
+					return s == address
+				}
+				// Hex is never base58.
+				if s.starts_with("0x") {
+					return false
+				}
+				match scale_borrow::ss58::decode(s) {
+					Ok((format, id)) => {
+						let address = scale_borrow::ss58::encode(format, self.account);
+						self.addresses.insert(chain, address);
+						id == self.account
+					},
+					Err(_) => false,
+				}
+			},
+			serde_json::Value::Array(items) => items.iter().any(|item| self.mentions(chain, item)),
+			serde_json::Value::Object(fields) =>
+				fields.values().any(|field| self.mentions(chain, field)),
+			_ => false,
+		}
+	}
+}
+
 // use egui::Grid;
 // impl Inspectable for Details {
 // 	type Attributes = ();
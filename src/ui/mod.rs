@@ -2,12 +2,16 @@
 
 pub mod details;
 pub mod doturl;
-use crate::{log, Anchor, ChainInfo, Destination, Env, Inspector, FREE_TXS};
+use crate::{log, Anchor, ChainInfo, Destination, Env, Inspector, FOUND, FREE_TXS};
 use cgmath::Point3;
 use chrono::{DateTime, NaiveDateTime, Utc};
 pub use details::Details;
+use details::Verification;
 pub use doturl::DotUrl;
-use std::{collections::HashMap, sync::atomic::Ordering};
+use std::{
+	collections::HashMap,
+	sync::{atomic::Ordering, Mutex},
+};
 // use std::num::NonZeroU32;
 use egui::ComboBox;
 // use egui_datepicker::DatePicker;
@@ -76,6 +80,9 @@ pub fn ui_bars_system(
 				if let Some((_cube_index, selected, chain_info)) = selected_details.first() {
 					ui.heading(&selected.variant);
 					ui.heading(&selected.pallet);
+					if let Some(error) = &selected.error {
+						ui.colored_label(egui::Color32::RED, format!("Failed: {}", error));
+					}
 					ui.separator();
 					let chain_tuple = (
 						selected.doturl.souverign_index(),
@@ -94,54 +101,73 @@ pub fn ui_bars_system(
 					// 		log!("Error opening link {:?}", e);
 					// 	}
 					// }
-					if let Some(vall) = &selected.value {
-						let (val, _s) = scale_value::stringify::from_str(vall);
-						if let Ok(val) = val {
-							let val_decoded = scale_value_to_borrowed::convert(&val, true);
-
-							if let Some(v) = val_decoded.expect3("Ethereum", "0", "Executed") {
-								if let Some(tx_hash) = v.find2("transaction_hash", "0") {
-									if let scale_borrow::Value::ScaleOwned(tx) = tx_hash {
-										let mut eth_tx_map = HashMap::new();
-										eth_tx_map.insert(
-											(1, 2006),
-											"https://blockscout.com/astar//tx/0x{}",
-										);
-										eth_tx_map.insert((1, 2004), "https://moonscan.io/tx/0x{}");
-										eth_tx_map.insert(
-											(0, 2023),
-											"https://moonriver.moonscan.io/tx/0x{}",
-										);
-
-										if let Some(url) = eth_tx_map.get(&chain_tuple) {
-											let tx_hash = hex::encode(&tx[..]);
-											if ui
-												.add(Link::new(format!(
-													"Transaction Hash #: 0x{}",
-													tx_hash
-												)))
-												.clicked()
-											{
-												let url = url.replace("{}", &tx_hash);
-
-												open_url(&url);
-											}
-										}
-									}
+					if let Some(val) = &selected.value {
+						if let Some(serde_json::Value::String(tx_hash)) =
+							val.pointer("/Ethereum/0/Executed/transaction_hash/0")
+						{
+							let mut eth_tx_map = HashMap::new();
+							eth_tx_map.insert((1, 2006), "https://blockscout.com/astar//tx/0x{}");
+							eth_tx_map.insert((1, 2004), "https://moonscan.io/tx/0x{}");
+							eth_tx_map.insert((0, 2023), "https://moonriver.moonscan.io/tx/0x{}");
+
+							if let Some(url) = eth_tx_map.get(&chain_tuple) {
+								let tx_hash = tx_hash.trim_start_matches("0x");
+								if ui
+									.add(Link::new(format!("Transaction Hash #: 0x{}", tx_hash)))
+									.clicked()
+								{
+									let url = url.replace("{}", tx_hash);
+
+									open_url(&url);
 								}
 							}
-
-							funk(ui, &val_decoded);
-						} else {
-							ui.heading(vall);
 						}
+
+						funk(ui, val);
 						//             .default_open(depth < 1)
-						// ui.label(&val.to_string());
-						// ui.label(&scale_value_to_borrowed::convert(&val,true).to_string());
 					}
 					// ui.add(egui::TextEdit::multiline(&mut  selected.url.as_ref()));
 					// ui.label("RAW Scale:");
 
+					if let (None, Some(_), Some(call)) =
+						(selected.doturl.event, selected.doturl.extrinsic, &selected.call)
+					{
+						if ui.button("Replay on local node").clicked() {
+							replay_locally(selected.doturl.para_id, call.clone());
+						}
+					}
+
+					if let (None, Some(block_number)) =
+						(selected.doturl.event, selected.doturl.block_number)
+					{
+						if selected.doturl.extrinsic.is_some() &&
+							crate::datasource::is_signed(&selected.raw)
+						{
+							estimated_fee(ui, &selected, &chain_info.chain_ws, block_number);
+						}
+					}
+					if let Some(runtime) = &selected.runtime {
+						ui.label(format!("Runtime: {}", runtime));
+					}
+					match selected.verification {
+						Verification::Verified => {
+							ui.colored_label(egui::Color32::GREEN, "Verified against state root");
+						},
+						Verification::Mismatch => {
+							ui.colored_label(
+								egui::Color32::RED,
+								"Mismatch: the node's answer isn't backed by the state root!",
+							);
+						},
+						Verification::Unverifiable => {
+							ui.colored_label(
+								egui::Color32::YELLOW,
+								"Unverifiable: the node gave no read proof",
+							);
+						},
+						Verification::Unchecked => {},
+					}
+
 					if let Some(event) = selected.doturl.event {
 						ui.label(format!("Event #: {}", event));
 					}
@@ -305,8 +331,13 @@ pub fn ui_bars_system(
 
 				//TODO: location = alpha blend to 10% everything but XXXX
 				let response = ui.text_edit_singleline(&mut spec.find);
-				let found = 0;
+				let found = FOUND.load(Ordering::Relaxed);
 				if response.lost_focus() && ui.input().key_pressed(egui::Key::Enter) {
+					// An address (of any chain's format) selects whatever mentions its account.
+					match scale_borrow::ss58::decode(&spec.find) {
+						Ok((_format, account)) => crate::request_find_account(account),
+						Err(_) => FOUND.store(0, Ordering::Relaxed),
+					}
 					if spec.find.len() <= 4 {
 						// if let Ok(para_id) = spec.find.parse() {
 						// for (loc, details) in entities.iter() {
@@ -341,6 +372,7 @@ pub fn ui_bars_system(
 				}
 				ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
 					ui.checkbox(&mut anchor.deref_mut().follow_chain, "Follow");
+					ui.checkbox(&mut inspector.show_diagnostics, "RPC health");
 					// spec.location.deref_mut().ui(ui, StringAttributes { multiline: false },
 					// egui_context);
 				});
@@ -349,6 +381,9 @@ pub fn ui_bars_system(
 		.response
 		.rect
 		.height();
+	if inspector.show_diagnostics {
+		rpc_health_window(egui_context, &mut inspector.show_diagnostics);
+	}
 	occupied_screen_space.bottom = egui::TopBottomPanel::bottom("bottom_panel")
 		.resizable(false)
 		.show(egui_context, |ui| {
@@ -382,6 +417,125 @@ pub fn ui_bars_system(
 }
 use egui::Ui;
 
+/// Each lane's calls to its node: how many, how many failed, how slow and whether the node is
+/// throttling us. Hover over a lane for its slowest methods.
+fn rpc_health_window(egui_context: &egui::Context, open: &mut bool) {
+	crate::request_rpc_health();
+	egui::Window::new("RPC health").open(open).show(egui_context, |ui| {
+		let health = crate::RPC_HEALTH.lock().unwrap();
+		if health.is_empty() {
+			ui.label("No calls made yet.");
+			return
+		}
+		let ms = |ms: Option<u64>| ms.map_or("-".to_string(), |ms| format!("{} ms", ms));
+		egui::Grid::new("rpc_health").striped(true).show(ui, |ui| {
+			let headings = [
+				"Lane",
+				"Runtime",
+				"Calls",
+				"In flight",
+				"Errors",
+				"Mean",
+				"p95",
+				"kB out / in",
+				"Throttled",
+			];
+			for heading in headings {
+				ui.strong(heading);
+			}
+			ui.end_row();
+			for lane in health.iter() {
+				let methods: Vec<_> = lane
+					.methods
+					.iter()
+					.take(5)
+					.map(|method| {
+						format!(
+							"{}: {} calls, {} errors, p95 {}",
+							method.method,
+							method.calls,
+							method.errors,
+							ms(method.p95_ms)
+						)
+					})
+					.collect();
+				ui.label(&lane.chain_name).on_hover_text(methods.join("\n"));
+				ui.label(lane.runtime.as_deref().unwrap_or("-"));
+				ui.label(lane.calls.to_string());
+				ui.label(lane.in_flight.to_string());
+				if lane.errors > 0 {
+					ui.colored_label(egui::Color32::RED, lane.errors.to_string());
+				} else {
+					ui.label("0");
+				}
+				ui.label(ms(lane.mean_ms));
+				ui.label(ms(lane.p95_ms));
+				ui.label(format!("{} / {}", lane.bytes_sent / 1024, lane.bytes_received / 1024));
+				if lane.throttled > 0 {
+					ui.colored_label(
+						egui::Color32::YELLOW,
+						format!("{} ({} gave up)", lane.throttled, lane.gave_up),
+					);
+				} else {
+					ui.label("no");
+				}
+				ui.end_row();
+			}
+		});
+	});
+}
+
+lazy_static::lazy_static! {
+	/// Estimated fees by extrinsic url, `None` while still being estimated.
+	static ref ESTIMATED_FEES: Mutex<HashMap<String, Option<Result<String, String>>>> =
+		Mutex::new(HashMap::new());
+}
+
+/// Show the extrinsic's estimated fee, asking the chain for it in the background the first
+/// time it's selected.
+fn estimated_fee(ui: &mut Ui, selected: &Details, chain_ws: &[String], block_number: u32) {
+	let key = selected.doturl.to_string();
+	let mut fees = ESTIMATED_FEES.lock().unwrap();
+	match fees.get(&key) {
+		Some(Some(Ok(fee))) => {
+			ui.label(format!("Estimated fee: {}", fee));
+		},
+		Some(Some(Err(err))) => {
+			ui.label(format!("Can't estimate fee: {}", err));
+		},
+		Some(None) => {
+			ui.label("Estimating fee...");
+		},
+		None => {
+			fees.insert(key.clone(), None);
+			let (chain_ws, extrinsic) = (chain_ws.to_vec(), selected.raw.clone());
+			let estimate = async move {
+				let fee =
+					crate::datasource::estimate_fee_at(chain_ws, block_number, &extrinsic).await;
+				ESTIMATED_FEES.lock().unwrap().insert(key, Some(fee));
+			};
+			#[cfg(target_family = "wasm")]
+			wasm_bindgen_futures::spawn_local(estimate);
+			#[cfg(not(target_family = "wasm"))]
+			std::thread::spawn(move || async_std::task::block_on(estimate));
+		},
+	}
+}
+
+/// Submit the call to the `Env::Local` version of the chain in the background.
+fn replay_locally(para_id: Option<u32>, call: Vec<u8>) {
+	let replay = async move {
+		match crate::datasource::replay_call_locally(para_id, &call).await {
+			Ok(()) => log!("replayed call on local node"),
+			Err(err) => log!("could not replay call on local node: {}", err),
+		}
+	};
+	#[cfg(target_family = "wasm")]
+	wasm_bindgen_futures::spawn_local(replay);
+	#[cfg(not(target_family = "wasm"))]
+	std::thread::spawn(move || async_std::task::block_on(replay));
+}
+
 fn open_url(url: &str) {
 	#[cfg(target_family = "wasm")]
 	{
@@ -400,62 +554,37 @@ fn open_url(url: &str) {
 	}
 }
 
-fn funk<'r>(ui: &'r mut Ui, val: &scale_borrow::Value) {
+fn funk<'r>(ui: &'r mut Ui, val: &serde_json::Value) {
 	match &val {
-		scale_borrow::Value::Object(ref pairs) => {
-			if pairs.len() == 1 {
-				let mut header = String::new();
-				let (mut k, v) = &pairs[0];
-				let mut v: &scale_borrow::Value = v;
-
-				while matches!(&v, scale_borrow::Value::Object(_)) {
-					if let scale_borrow::Value::Object(nested_pairs) = &v {
-						if nested_pairs.len() != 1 {
-							break;
-						}
-						header.push_str(k);
-						header.push('.');
-						let (nk, nv) = &nested_pairs[0];
-						k = nk;
-						v = nv;
-					}
+		serde_json::Value::Object(pairs) => {
+			for (k, v) in pairs.iter() {
+				if k == "_ty" {
+					continue
+				}
+				// Collapse chains of single fields into one header (e.g. `Balances.transfer`).
+				let mut header = k.clone();
+				let mut v = v;
+				while let serde_json::Value::Object(nested_pairs) = v {
+					let mut fields = nested_pairs.iter().filter(|(nk, _)| *nk != "_ty");
+					let (nk, nv) = match (fields.next(), fields.next()) {
+						(Some(field), None) => field,
+						_ => break,
+					};
+					header.push('.');
+					header.push_str(nk);
+					v = nv;
 				}
-				header.push_str(k);
-				// use egui::CollapsingHeader;
 				ui.collapsing(header, |ui| {
 					funk(ui, v);
 				});
-			} else {
-				for (mut k, v) in pairs.iter() {
-					if let scale_borrow::Value::Object(_nested_pairs) = &v {
-						let mut header = String::new();
-						let mut v: &scale_borrow::Value = v;
-
-						while matches!(&v, scale_borrow::Value::Object(_)) {
-							if let scale_borrow::Value::Object(nested_pairs) = &v {
-								if nested_pairs.len() != 1 { break; }
-								header.push_str(k);
-								header.push('.');
-								let (nk, nv) = &nested_pairs[0];
-								k = nk;
-								v = nv;
-							}
-						}
-						header.push_str(k);
-						// use egui::CollapsingHeader;
-						ui.collapsing(header, |ui| {
-							funk(ui, v);
-						});
-					} else {
-						ui.collapsing(k, |ui| {
-							funk(ui, v);
-						});
-					}
-				}
 			}
 		},
-		scale_borrow::Value::ScaleOwned(bytes) => {
-			ui.label(format!("0x{}", hex::encode(bytes.as_slice())));
+		serde_json::Value::String(s) => {
+			// Addresses show which account id they are on hover.
+			let label = ui.label(s);
+			if let Ok((_format, account)) = scale_borrow::ss58::decode(s) {
+				label.on_hover_text(format!("0x{}", hex::encode(account)));
+			}
 		},
 		_ => {
 			ui.label(val.to_string());
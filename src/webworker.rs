@@ -1,6 +1,6 @@
 use crate::{
-	do_datasources, log, BridgeMessage, ChainInfo, Details, RenderDetails, RenderUpdate,
-	DATASOURCE_EPOC, DETAILS, SOVEREIGNS, UPDATE_QUEUE,
+	do_datasources, find_account, log, rpc_health, BridgeMessage, ChainInfo, Details,
+	RenderDetails, RenderUpdate, RpcHealth, DATASOURCE_EPOC, DETAILS, SOVEREIGNS, UPDATE_QUEUE,
 };
 use core::sync::atomic::Ordering;
 
@@ -37,6 +37,9 @@ impl IOWorker {
 pub enum WorkerResponse {
 	RenderUpdate(RenderUpdate, u64), //free transactions
 	Details(Vec<(u32, Details, ChainInfo)>),
+	RpcHealth(Vec<RpcHealth>),
+	/// How many extrinsics and events mention the account, and (some of) them to select.
+	Found(u32, Vec<(u32, Details, ChainInfo)>),
 }
 
 impl Worker for IOWorker {
@@ -73,10 +76,9 @@ impl Worker for IOWorker {
 					// log!("chains with no tx: {}", chains_with_no_tx);
 					let mut free_tx = chains
 						.values()
-						.map(|v| v.avg_free_transactions())
+						.map(|v| v.avg_free_tps())
 						.filter_map(|s| s)
-						.sum::<u64>() / 12;
-					//TODO 12 seconds per block assumed.
+						.sum::<u64>();
 
 					//For chains with no transactions assume average
 					free_tx += free_tx * chains_with_no_tx / chain_count as u64;
@@ -114,6 +116,13 @@ impl Worker for IOWorker {
 					(*SOVEREIGNS.lock().unwrap()).as_ref().unwrap().chain_info(&details.doturl);
 				scope.respond(id, WorkerResponse::Details(vec![(cube_index, details, chain_info)]));
 			},
+			BridgeMessage::GetRpcHealth => {
+				scope.respond(id, WorkerResponse::RpcHealth(rpc_health()));
+			},
+			BridgeMessage::FindAccount(account) => {
+				let (found, selected) = find_account(&account);
+				scope.respond(id, WorkerResponse::Found(found, selected));
+			},
 		}
 	}
 }